@@ -0,0 +1,77 @@
+// Counts heap allocations per StreamSink::send with a single listener, and fails unless the
+// steady state allocates nothing.
+//
+//   cargo bench --bench transaction_allocs
+
+extern crate sodium_rust;
+
+use sodium_rust::sodium::IsStream;
+use sodium_rust::sodium::SodiumCtx;
+use sodium_rust::sodium::StreamSink;
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::cell::RefCell;
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const SENDS: usize = 100000;
+
+fn main() {
+    let sodium_ctx = SodiumCtx::new();
+    let sink: StreamSink<u32> = sodium_ctx.new_stream_sink();
+    let total = Rc::new(RefCell::new(0u64));
+    let l;
+    {
+        let total = total.clone();
+        l = sink.listen(move |x: &u32| *total.borrow_mut() += *x as u64);
+    }
+    // Warm up so buffers have reached their steady state capacity.
+    for i in 0..100 {
+        sink.send(&i);
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..SENDS {
+        sink.send(&(i as u32));
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    l.unlisten();
+    println!(
+        "{} sends: {:.2} allocations per send, {:.0} ns per send",
+        SENDS,
+        allocations as f64 / SENDS as f64,
+        elapsed.as_secs_f64() * 1e9 / SENDS as f64
+    );
+    if allocations > 0 {
+        eprintln!("expected no allocations per send once warmed up, got {}", allocations);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,327 @@
+// Summarises a heap dump written by GcCtx::heap_dump.
+//
+//   sodium-heapview dump.json
+//   sodium-heapview < dump.json
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::process;
+
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String,Json>)
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            &Json::Object(ref fields) => fields.get(key),
+            _ => None
+        }
+    }
+
+    fn as_array(&self) -> &[Json] {
+        match self {
+            &Json::Array(ref items) => items,
+            _ => &[]
+        }
+    }
+
+    fn as_usize(&self) -> Option<usize> {
+        match self {
+            &Json::Number(n) if n >= 0.0 => Some(n as usize),
+            _ => None
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            &Json::Bool(b) => Some(b),
+            _ => None
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            &Json::Str(ref s) => Some(s),
+            _ => None
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>
+}
+
+impl<'a> Parser<'a> {
+    fn skip_ws(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c.is_whitespace() {
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_ws();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}' but found '{}'", expected, c)),
+            None => Err(format!("expected '{}' but found end of input", expected))
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(format!("invalid literal, expected {}", word));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_ws();
+        match self.chars.peek().cloned() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::Str),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some(_) => self.number(),
+            None => Err(String::from("unexpected end of input"))
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = BTreeMap::new();
+        self.skip_ws();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_ws();
+            let key = self.string()?;
+            self.expect(':')?;
+            let value = self.value()?;
+            fields.insert(key, value);
+            self.skip_ws();
+            match self.chars.next() {
+                Some(',') => (),
+                Some('}') => return Ok(Json::Object(fields)),
+                _ => return Err(String::from("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_ws();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_ws();
+            match self.chars.next() {
+                Some(',') => (),
+                Some(']') => return Ok(Json::Array(items)),
+                _ => return Err(String::from("expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut out = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(out),
+                Some('\\') =>
+                    match self.chars.next() {
+                        Some('n') => out.push('\n'),
+                        Some('r') => out.push('\r'),
+                        Some('t') => out.push('\t'),
+                        Some('u') => {
+                            let hex: String = self.chars.by_ref().take(4).collect();
+                            let code = u32::from_str_radix(&hex, 16).map_err(|err| err.to_string())?;
+                            out.push(::std::char::from_u32(code).unwrap_or('?'));
+                        },
+                        Some(c) => out.push(c),
+                        None => return Err(String::from("unterminated string"))
+                    },
+                Some(c) => out.push(c),
+                None => return Err(String::from("unterminated string"))
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let mut text = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' || c.is_ascii_digit() {
+                text.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        text.parse::<f64>().map(Json::Number).map_err(|_| format!("invalid number '{}'", text))
+    }
+}
+
+struct HeapNode {
+    label: String,
+    strong: usize,
+    buffered: bool
+}
+
+// Tarjan's algorithm, returns the strongly connected components that form a cycle.
+fn cycles(node_count: usize, adjacency: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    struct State<'a> {
+        adjacency: &'a [Vec<usize>],
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        next_index: usize,
+        result: Vec<Vec<usize>>
+    }
+    fn visit(state: &mut State, v: usize) {
+        state.index[v] = Some(state.next_index);
+        state.low[v] = state.next_index;
+        state.next_index = state.next_index + 1;
+        state.stack.push(v);
+        state.on_stack[v] = true;
+        for &w in &state.adjacency[v] {
+            match state.index[w] {
+                None => {
+                    visit(state, w);
+                    state.low[v] = state.low[v].min(state.low[w]);
+                },
+                Some(w_index) if state.on_stack[w] => state.low[v] = state.low[v].min(w_index),
+                _ => ()
+            }
+        }
+        if Some(state.low[v]) == state.index[v] {
+            let mut component = Vec::new();
+            loop {
+                let w = state.stack.pop().unwrap();
+                state.on_stack[w] = false;
+                component.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            if component.len() > 1 || state.adjacency[v].contains(&v) {
+                state.result.push(component);
+            }
+        }
+    }
+    let mut state = State {
+        adjacency,
+        index: vec![None; node_count],
+        low: vec![0; node_count],
+        on_stack: vec![false; node_count],
+        stack: Vec::new(),
+        next_index: 0,
+        result: Vec::new()
+    };
+    for v in 0..node_count {
+        if state.index[v].is_none() {
+            visit(&mut state, v);
+        }
+    }
+    state.result
+}
+
+fn run() -> Result<(), String> {
+    let mut text = String::new();
+    match env::args().nth(1) {
+        Some(path) => text = fs::read_to_string(&path).map_err(|err| format!("{}: {}", path, err))?,
+        None => { io::stdin().read_to_string(&mut text).map_err(|err| err.to_string())?; }
+    }
+    let dump = Parser { chars: text.chars().peekable() }.value()?;
+    let mut nodes: HashMap<usize,HeapNode> = HashMap::new();
+    for node in dump.get("nodes").map(|nodes| nodes.as_array()).unwrap_or(&[]) {
+        let id = node.get("id").and_then(|id| id.as_usize()).ok_or("node without an id")?;
+        let type_name = node.get("type_name").and_then(|t| t.as_str()).unwrap_or("?");
+        let label =
+            match node.get("desc").and_then(|desc| desc.as_str()) {
+                Some(desc) => format!("{} ({})", type_name, desc),
+                None => String::from(type_name)
+            };
+        let strong = node.get("strong").and_then(|strong| strong.as_usize()).unwrap_or(0);
+        let buffered = node.get("buffered").and_then(|buffered| buffered.as_bool()).unwrap_or(false);
+        nodes.insert(id, HeapNode { label, strong, buffered });
+    }
+    let node_count = nodes.keys().max().map(|max| max + 1).unwrap_or(0);
+    let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); node_count];
+    let mut in_degree: Vec<usize> = vec![0; node_count];
+    let mut edge_count = 0;
+    for edge in dump.get("edges").map(|edges| edges.as_array()).unwrap_or(&[]) {
+        let edge = edge.as_array();
+        if let (Some(from), Some(to)) = (edge.get(0).and_then(|e| e.as_usize()), edge.get(1).and_then(|e| e.as_usize())) {
+            if from < node_count && to < node_count {
+                adjacency[from].push(to);
+                in_degree[to] = in_degree[to] + 1;
+                edge_count = edge_count + 1;
+            }
+        }
+    }
+    println!("{} objects, {} edges, {} buffered as possible cycle roots", nodes.len(), edge_count, nodes.values().filter(|node| node.buffered).count());
+    let mut by_type: HashMap<&str,usize> = HashMap::new();
+    for node in nodes.values() {
+        *by_type.entry(&node.label).or_insert(0) += 1;
+    }
+    let mut by_type: Vec<(&str,usize)> = by_type.into_iter().collect();
+    by_type.sort_by(|&(label1, count1), &(label2, count2)| count2.cmp(&count1).then(label1.cmp(label2)));
+    println!();
+    println!("objects by type:");
+    for (label, count) in by_type {
+        println!("  {:>6}  {}", count, label);
+    }
+    let mut ids: Vec<&usize> = nodes.keys().collect();
+    ids.sort();
+    println!();
+    println!("held from outside the heap:");
+    for id in ids {
+        let node = &nodes[id];
+        if node.strong > in_degree[*id] {
+            println!("  #{} {} (+{})", id, node.label, node.strong - in_degree[*id]);
+        }
+    }
+    let cycles = cycles(node_count, &adjacency);
+    println!();
+    println!("{} cycles:", cycles.len());
+    for cycle in cycles {
+        let labels: Vec<String> = cycle.iter().map(|id| match nodes.get(id) {
+            Some(node) => format!("#{} {}", id, node.label),
+            None => format!("#{}", id)
+        }).collect();
+        println!("  {}", labels.join(" -> "));
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("sodium-heapview: {}", err);
+        process::exit(1);
+    }
+}
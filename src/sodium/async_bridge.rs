@@ -0,0 +1,321 @@
+use sodium::Backpressure;
+use sodium::BoundedQueue;
+use sodium::Cell;
+use sodium::CellSink;
+use sodium::IsStream;
+use sodium::SodiumCtx;
+use sodium::Stream;
+use sodium::StreamSink;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::cell::Cell as StdCell;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Wake;
+use std::task::Waker;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Discard new events while the queue is full.
+    Drop,
+    // Discard the oldest queued events so the consumer always sees the latest ones.
+    Latest,
+    // Hold up the propagating thread until the consumer catches up.
+    Block
+}
+
+impl OverflowPolicy {
+    pub fn backpressure(self, capacity: usize) -> Backpressure {
+        match self {
+            OverflowPolicy::Drop => Backpressure::DropNewest(capacity),
+            OverflowPolicy::Latest => Backpressure::DropOldest(capacity),
+            OverflowPolicy::Block => Backpressure::Block(capacity)
+        }
+    }
+}
+
+struct Shared<A> {
+    queue: BoundedQueue<A>,
+    waker: Mutex<Option<Waker>>,
+    closed: AtomicBool
+}
+
+impl<A> Shared<A> {
+    fn wake(&self) {
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+// Held by the listener, the async stream ends once the listener has gone.
+pub struct AsyncSender<A> {
+    shared: Arc<Shared<A>>
+}
+
+impl<A> AsyncSender<A> {
+    pub fn send(&self, a: A) {
+        self.shared.queue.push(a);
+        self.shared.wake();
+    }
+}
+
+impl<A> Drop for AsyncSender<A> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.wake();
+    }
+}
+
+pub struct AsyncStream<A> {
+    shared: Arc<Shared<A>>
+}
+
+pub fn async_channel<A>(capacity: usize, policy: OverflowPolicy) -> (AsyncSender<A>, AsyncStream<A>) {
+    let shared = Arc::new(Shared {
+        queue: BoundedQueue::new(policy.backpressure(capacity)),
+        waker: Mutex::new(None),
+        closed: AtomicBool::new(false)
+    });
+    (AsyncSender { shared: shared.clone() }, AsyncStream { shared })
+}
+
+impl<A> AsyncStream<A> {
+    // Same contract as futures::Stream::poll_next.
+    pub fn poll_next(&self, cx: &mut Context) -> Poll<Option<A>> {
+        if let Some(a) = self.shared.queue.try_pop() {
+            return Poll::Ready(Some(a));
+        }
+        *self.shared.waker.lock().unwrap() = Some(cx.waker().clone());
+        // closed is read before popping, so an item pushed just before the sender closed
+        // can't be missed by an empty pop that only then sees closed.
+        let closed = self.shared.closed.load(Ordering::SeqCst);
+        match self.shared.queue.try_pop() {
+            Some(a) => Poll::Ready(Some(a)),
+            None if closed => Poll::Ready(None),
+            None => Poll::Pending
+        }
+    }
+
+    pub fn next(&self) -> Next<'_, A> {
+        Next {
+            stream: self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.is_empty()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.shared.queue.dropped()
+    }
+}
+
+pub struct Next<'a, A: 'a> {
+    stream: &'a AsyncStream<A>
+}
+
+impl<'a, A> Future for Next<'a, A> {
+    type Output = Option<A>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<A>> {
+        self.stream.poll_next(cx)
+    }
+}
+
+struct TokenState {
+    sodium_ctx: SodiumCtx,
+    cancelled: StdCell<bool>,
+    sink_op: RefCell<Option<CellSink<bool>>>
+}
+
+// Handed to each future started by map_async_cancellable, it is cancelled once a newer event
+// replaces the operation or the output stream goes away.
+pub struct CancellationToken {
+    state: Rc<TokenState>
+}
+
+impl CancellationToken {
+    fn new(sodium_ctx: &SodiumCtx) -> CancellationToken {
+        CancellationToken {
+            state: Rc::new(TokenState {
+                sodium_ctx: sodium_ctx.clone(),
+                cancelled: StdCell::new(false),
+                sink_op: RefCell::new(None)
+            })
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.get()
+    }
+
+    // Turns true in the transaction after the one that cancelled the operation.
+    pub fn cell(&self) -> Cell<bool> {
+        let mut sink_op = self.state.sink_op.borrow_mut();
+        if sink_op.is_none() {
+            *sink_op = Some(self.state.sodium_ctx.new_cell_sink(self.is_cancelled()));
+        }
+        sink_op.as_ref().unwrap().to_cell()
+    }
+
+    fn cancel(&self) {
+        if self.state.cancelled.replace(true) {
+            return;
+        }
+        if let Some(sink) = self.state.sink_op.borrow().clone() {
+            self.state.sodium_ctx.post(move || sink.send(&true));
+        }
+    }
+}
+
+impl Clone for CancellationToken {
+    fn clone(&self) -> Self {
+        CancellationToken {
+            state: self.state.clone()
+        }
+    }
+}
+
+struct MapTask<FUT: Future, B> {
+    future: Pin<Box<FUT>>,
+    token: CancellationToken,
+    sink: StreamSink<B>
+}
+
+impl<FUT: Future<Output=B>, B: Clone + Trace + Finalize + 'static> Future for MapTask<FUT,B> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let self_ = unsafe { self.get_unchecked_mut() };
+        if self_.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        match self_.future.as_mut().poll(cx) {
+            Poll::Ready(b) => {
+                if !self_.token.is_cancelled() {
+                    self_.sink.send(&b);
+                }
+                Poll::Ready(())
+            },
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
+struct WakeFlag {
+    woken: AtomicBool
+}
+
+impl Wake for WakeFlag {
+    fn wake(self: Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+    }
+}
+
+struct RunnerTask {
+    future: Pin<Box<dyn Future<Output=()>>>,
+    token: CancellationToken
+}
+
+// Runs the futures started by map_async_cancellable on the FRP thread. Nothing happens on its
+// own, call poll() from the main loop (outside a transaction) whenever is_woken() says a
+// future can make progress. A cancelled future is dropped on the next poll().
+pub struct AsyncRunner {
+    sodium_ctx: SodiumCtx,
+    tasks: Rc<RefCell<Vec<RunnerTask>>>,
+    wake_flag: Arc<WakeFlag>
+}
+
+impl AsyncRunner {
+    pub fn new(sodium_ctx: &SodiumCtx) -> AsyncRunner {
+        AsyncRunner {
+            sodium_ctx: sodium_ctx.clone(),
+            tasks: Rc::new(RefCell::new(Vec::new())),
+            wake_flag: Arc::new(WakeFlag {
+                woken: AtomicBool::new(true)
+            })
+        }
+    }
+
+    pub fn is_woken(&self) -> bool {
+        self.wake_flag.woken.load(Ordering::SeqCst)
+    }
+
+    // Polls every task once and returns how many are still pending.
+    pub fn poll(&self) -> usize {
+        self.wake_flag.woken.store(false, Ordering::SeqCst);
+        let waker = Waker::from(self.wake_flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut tasks: Vec<RunnerTask> = self.tasks.borrow_mut().drain(..).collect();
+        tasks.retain(|task| !task.token.is_cancelled());
+        tasks.retain_mut(|task| task.future.as_mut().poll(&mut cx) == Poll::Pending);
+        let mut pending = self.tasks.borrow_mut();
+        // Futures started while polling go after the ones that were already running.
+        tasks.extend(pending.drain(..));
+        *pending = tasks;
+        pending.len()
+    }
+
+    // Starts f(a, token) for each event and fires with its result once the future completes.
+    // A newer event cancels the operation still in flight, so its result is never delivered.
+    pub fn map_async_cancellable<A, B, SA, F, FUT>(&self, sa: SA, f: F) -> Stream<B>
+        where A: Clone + Trace + Finalize + 'static,
+              B: Clone + Trace + Finalize + 'static,
+              SA: IsStream<A>,
+              F: Fn(&A, CancellationToken) -> FUT + 'static,
+              FUT: Future<Output=B> + 'static
+    {
+        let sa = sa.to_stream();
+        let sink: StreamSink<B> = self.sodium_ctx.new_stream_sink();
+        let results = sink.to_stream();
+        let current: Rc<RefCell<Option<CancellationToken>>> = Rc::new(RefCell::new(None));
+        let current2 = current.clone();
+        let tasks = self.tasks.clone();
+        let wake_flag = self.wake_flag.clone();
+        let sodium_ctx = self.sodium_ctx.clone();
+        let sa2 = sa.clone();
+        self.sodium_ctx
+            .new_node_builder("AsyncRunner::map_async_cancellable")
+            .depends_on(&sa)
+            .depends_on(&results)
+            .on_update(move |inputs| {
+                if let Some(a) = inputs.value(&sa2) {
+                    let token = CancellationToken::new(&sodium_ctx);
+                    if let Some(previous) = current.borrow_mut().replace(token.clone()) {
+                        previous.cancel();
+                    }
+                    tasks.borrow_mut().push(RunnerTask {
+                        future: Box::pin(MapTask {
+                            future: Box::pin(f(&a, token.clone())),
+                            token: token.clone(),
+                            sink: sink.clone()
+                        }),
+                        token
+                    });
+                    wake_flag.woken.store(true, Ordering::SeqCst);
+                }
+                inputs.value(&results)
+            })
+            .on_cleanup(move || {
+                if let Some(token) = current2.borrow_mut().take() {
+                    token.state.cancelled.set(true);
+                }
+            })
+            .build()
+            .stream()
+    }
+}
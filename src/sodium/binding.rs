@@ -0,0 +1,65 @@
+use sodium::CellSink;
+use sodium::Listener;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Equality<A> = dyn Fn(&A, &A) -> bool;
+
+// Keeps a CellSink and some external state (e.g. a GUI widget) equal. Changes to the cell are
+// pushed out through external_set, call pull() when the external state changes. A value is
+// never written back to the side it came from.
+pub struct Binding<A> {
+    cell_sink: CellSink<A>,
+    external_get: Rc<dyn Fn() -> A>,
+    eq: Rc<Equality<A>>,
+    pulling: Rc<RefCell<bool>>,
+    listener: Listener
+}
+
+pub fn bind_bidirectional<A, GET, SET, EQ>(cell_sink: &CellSink<A>, external_get: GET, external_set: SET, eq: EQ) -> Binding<A>
+    where A: Clone + Trace + Finalize + 'static,
+          GET: Fn() -> A + 'static,
+          SET: Fn(&A) + 'static,
+          EQ: Fn(&A, &A) -> bool + 'static
+{
+    let external_get: Rc<dyn Fn() -> A> = Rc::new(external_get);
+    let eq: Rc<Equality<A>> = Rc::new(eq);
+    let pulling = Rc::new(RefCell::new(false));
+    let listener;
+    {
+        let external_get = external_get.clone();
+        let eq = eq.clone();
+        let pulling = pulling.clone();
+        listener = cell_sink.to_cell().listen(move |a: &A| {
+            if !*pulling.borrow() && !eq(a, &external_get()) {
+                external_set(a);
+            }
+        });
+    }
+    Binding {
+        cell_sink: cell_sink.clone(),
+        external_get,
+        eq,
+        pulling,
+        listener
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> Binding<A> {
+    pub fn pull(&self) {
+        let a = (self.external_get)();
+        if !(self.eq)(&a, &self.cell_sink.to_cell().sample()) {
+            *self.pulling.borrow_mut() = true;
+            self.cell_sink.send(&a);
+            *self.pulling.borrow_mut() = false;
+        }
+    }
+}
+
+impl<A> Drop for Binding<A> {
+    fn drop(&mut self) {
+        self.listener.unlisten();
+    }
+}
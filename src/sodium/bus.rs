@@ -0,0 +1,129 @@
+use sodium::IsStream;
+use sodium::PlugHandle;
+use sodium::SodiumCtx;
+use sodium::Stream;
+use sodium::StreamJunction;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::any::Any;
+use std::any::type_name;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::rc::Weak;
+
+// Why a Bus refused to publish or subscribe.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BusError {
+    // The topic already carries events of another type.
+    TypeMismatch { topic: String, expected: &'static str, found: &'static str }
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BusError::TypeMismatch { ref topic, expected, found } =>
+                write!(f, "topic '{}' carries {}, not {}", topic, expected, found)
+        }
+    }
+}
+
+struct Topic {
+    type_name: &'static str,
+    // An Rc<StreamJunction<T>> for the topic's type.
+    junction: Box<dyn Any>,
+    inputs: Box<dyn Inputs>
+}
+
+// Streams shared by topic name, so modules can talk to each other without having stream
+// handles passed down to them. A topic is typed by whichever of publish and subscribe
+// first uses it. Subscribers may come before or after the publishers, and get the events
+// of every publisher, the first one published winning when several fire together.
+#[derive(Clone)]
+pub struct Bus {
+    sodium_ctx: SodiumCtx,
+    topics: Rc<RefCell<HashMap<String,Topic>>>
+}
+
+impl Bus {
+    pub fn new(sodium_ctx: &SodiumCtx) -> Bus {
+        Bus {
+            sodium_ctx: sodium_ctx.clone(),
+            topics: Rc::new(RefCell::new(HashMap::new()))
+        }
+    }
+
+    // Events on sa go out on the topic for as long as the returned Publication lives. The
+    // Publication only holds the topic weakly, it does not keep the bus alive.
+    pub fn publish<T, SA>(&self, topic: &str, sa: SA) -> Result<Publication,BusError>
+        where T: Clone + Trace + Finalize + 'static,
+              SA: IsStream<T>
+    {
+        let junction = self.junction::<T>(topic)?;
+        let handle = junction.plug(sa);
+        Ok(Publication {
+            inputs: Box::new(Rc::downgrade(&junction)),
+            handle
+        })
+    }
+
+    pub fn subscribe<T: Clone + Trace + Finalize + 'static>(&self, topic: &str) -> Result<Stream<T>,BusError> {
+        Ok(self.junction::<T>(topic)?.stream())
+    }
+
+    pub fn publisher_count(&self, topic: &str) -> usize {
+        self.topics.borrow().get(topic).map_or(0, |topic| topic.inputs.input_count())
+    }
+
+    fn junction<T: Clone + Trace + Finalize + 'static>(&self, topic: &str) -> Result<Rc<StreamJunction<T>>,BusError> {
+        let mut topics = self.topics.borrow_mut();
+        let entry = topics.entry(String::from(topic)).or_insert_with(|| {
+            let junction: Rc<StreamJunction<T>> = Rc::new(StreamJunction::new(&self.sodium_ctx, |a: &T, _: &T| a.clone()));
+            Topic {
+                type_name: type_name::<T>(),
+                inputs: Box::new(Rc::downgrade(&junction)),
+                junction: Box::new(junction)
+            }
+        });
+        match entry.junction.downcast_ref::<Rc<StreamJunction<T>>>() {
+            Some(junction) => Ok(junction.clone()),
+            None => Err(BusError::TypeMismatch {
+                topic: String::from(topic),
+                expected: entry.type_name,
+                found: type_name::<T>()
+            })
+        }
+    }
+}
+
+// A topic's junction without its type.
+trait Inputs {
+    fn unplug(&self, handle: PlugHandle);
+
+    fn input_count(&self) -> usize;
+}
+
+impl<T: Clone + Trace + Finalize + 'static> Inputs for Weak<StreamJunction<T>> {
+    fn unplug(&self, handle: PlugHandle) {
+        if let Some(junction) = self.upgrade() {
+            junction.unplug(handle);
+        }
+    }
+
+    fn input_count(&self) -> usize {
+        self.upgrade().map_or(0, |junction| junction.input_count())
+    }
+}
+
+// Keeps one stream published on a Bus, dropping it takes the stream off the topic.
+pub struct Publication {
+    inputs: Box<dyn Inputs>,
+    handle: PlugHandle
+}
+
+impl Drop for Publication {
+    fn drop(&mut self) {
+        self.inputs.unplug(self.handle);
+    }
+}
@@ -9,10 +9,14 @@ use sodium::IsLambda5;
 use sodium::IsLambda6;
 use sodium::Listener;
 use sodium::Stream;
+#[cfg(feature = "debug-history")]
+use sodium::TxId;
 use sodium::gc::Finalize;
 use sodium::gc::GcDep;
+use sodium::gc::OomError;
 use sodium::gc::Trace;
 use sodium::impl_;
+use std::hash::Hash;
 
 pub struct Cell<A> {
     pub impl_: impl_::Cell<A>
@@ -28,6 +32,22 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
         self.impl_.sample()
     }
 
+    // Listeners attached to this cell itself, not to cells derived from it.
+    pub fn listener_count(&self) -> usize {
+        self.impl_._node().listener_count()
+    }
+
+    // Keeps the last n values together with the id of the transaction that set them.
+    #[cfg(feature = "debug-history")]
+    pub fn record_history(&self, n: usize) {
+        self.impl_.record_history(n)
+    }
+
+    #[cfg(feature = "debug-history")]
+    pub fn history(&self) -> Vec<(TxId,A)> {
+        self.impl_.history()
+    }
+
     pub fn map<B: Clone + Trace + Finalize + 'static,F:IsLambda1<A,B> + 'static>(
         &self,
         f: F
@@ -37,6 +57,14 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
         }
     }
 
+    // See Stream::try_map.
+    pub fn try_map<B: Clone + Trace + Finalize + 'static,F:IsLambda1<A,B> + 'static>(
+        &self,
+        f: F
+    ) -> Result<Cell<B>,OomError> {
+        self.impl_._node().sodium_ctx().gc_ctx().fallible(|| self.map(f))
+    }
+
     pub fn apply<B,F: IsLambda1<A,B> + Trace + Finalize + Clone + 'static,CF:IsCell<F>>(&self, cf: CF) -> Cell<B> where B: Trace + Finalize + Clone + 'static {
         Cell {
             impl_: self.impl_.apply(cf.to_cell().impl_)
@@ -49,6 +77,10 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
         }
     }
 
+    pub fn try_lift2<B,C,CB:IsCell<B>,F: IsLambda2<A,B,C> + 'static>(&self, cb: CB, f: F) -> Result<Cell<C>,OomError> where B: Clone + Trace + Finalize + 'static, C: Clone + Trace + Finalize + 'static {
+        self.impl_._node().sodium_ctx().gc_ctx().fallible(|| self.lift2(cb, f))
+    }
+
     pub fn lift3<B,C,D,CB:IsCell<B>,CC:IsCell<C>,F: IsLambda3<A,B,C,D> + 'static>(&self, cb: CB, cc: CC, f: F) -> Cell<D> where B: Clone + Trace + Finalize + 'static, C: Clone + Trace + Finalize + 'static, D: Clone + Trace + Finalize + 'static {
         Cell {
             impl_: self.impl_.lift3(cb.to_cell().impl_, cc.to_cell().impl_, f)
@@ -73,6 +105,21 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
         }
     }
 
+    pub fn lift_all<B: Clone + Trace + Finalize + 'static, CA: IsCell<A>, F: Fn(&[A])->B + 'static>(cells: &[CA], f: F) -> Cell<B> {
+        let cells: Vec<impl_::Cell<A>> = cells.iter().map(|ca| ca.to_cell().impl_).collect();
+        Cell {
+            impl_: impl_::Cell::lift_all(&cells, f)
+        }
+    }
+
+    // A cell over state kept outside of sodium, e.g. a cache or an expensive query. f is
+    // called once now and then again only in transactions where invalidate fires, so it must
+    // not be relied on to see changes that nothing announced.
+    pub fn from_fn<F: Fn()->A + 'static>(f: F, invalidate: &Stream<()>) -> Cell<A> {
+        let initial = f();
+        invalidate.map(move |_: &()| f()).hold(initial)
+    }
+
     pub fn switch_s<SA:IsStream<A> + Trace + Finalize + Clone + 'static,CSA:IsCell<SA>>(csa: CSA) -> Stream<A> {
         Stream {
             impl_: impl_::Cell::switch_s(csa.to_cell().impl_.map(|sa:&SA| sa.to_stream().impl_))
@@ -85,6 +132,12 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
         }
     }
 
+    pub fn switch_vec<CA:IsCell<A> + Trace + Finalize + Clone + 'static,CCA:IsCell<Vec<CA>>>(cca: CCA) -> Cell<Vec<A>> {
+        Cell {
+            impl_: impl_::Cell::switch_vec(cca.to_cell().impl_.map(|cas:&Vec<CA>| cas.iter().map(|ca| ca.to_cell().impl_).collect()))
+        }
+    }
+
     pub fn listen<CALLBACK:FnMut(&A)+'static>(
         &self,
         callback: CALLBACK
@@ -100,7 +153,77 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
     }
 }
 
-impl<A: Clone + Trace + Finalize + 'static> Clone for Cell<A> {
+impl<A: Clone + PartialEq + Trace + Finalize + 'static> Cell<A> {
+    // Fires when the cell changes from some other value to a, setting it to the value it already
+    // has does not count.
+    pub fn changes_to(&self, a: A) -> Stream<()> {
+        Stream {
+            impl_: self.impl_.changes_to(a)
+        }
+    }
+}
+
+impl<A: Clone + Eq + Hash + Trace + Finalize + 'static> Cell<A> {
+    // Like map, but remembers the results for the last capacity distinct inputs, so switching
+    // between a few values does not call f again.
+    pub fn map_memo<B: Clone + Trace + Finalize + 'static, F: Fn(&A)->B + 'static>(&self, f: F, capacity: usize) -> Cell<B> {
+        Cell {
+            impl_: self.impl_.map_memo(f, capacity)
+        }
+    }
+}
+
+impl Cell<bool> {
+    pub fn when_true(&self) -> Stream<()> {
+        Stream {
+            impl_: self.impl_.when_true()
+        }
+    }
+
+    pub fn when_false(&self) -> Stream<()> {
+        Stream {
+            impl_: self.impl_.when_false()
+        }
+    }
+
+    pub fn not(&self) -> Cell<bool> {
+        Cell {
+            impl_: self.impl_.not()
+        }
+    }
+
+    pub fn and<CB:IsCell<bool>>(&self, other: CB) -> Cell<bool> {
+        Cell {
+            impl_: self.impl_.and(other.to_cell().impl_)
+        }
+    }
+
+    pub fn or<CB:IsCell<bool>>(&self, other: CB) -> Cell<bool> {
+        Cell {
+            impl_: self.impl_.or(other.to_cell().impl_)
+        }
+    }
+
+    // True while every cell is. Unlike lift_all, a transaction only looks at the cells that
+    // changed in it, and the result only updates when it flips.
+    pub fn all<CA:IsCell<bool>>(cells: &[CA]) -> Cell<bool> {
+        let cells: Vec<impl_::Cell<bool>> = cells.iter().map(|ca| ca.to_cell().impl_).collect();
+        Cell {
+            impl_: impl_::Cell::all(&cells)
+        }
+    }
+
+    // True while at least one cell is, see all.
+    pub fn any<CA:IsCell<bool>>(cells: &[CA]) -> Cell<bool> {
+        let cells: Vec<impl_::Cell<bool>> = cells.iter().map(|ca| ca.to_cell().impl_).collect();
+        Cell {
+            impl_: impl_::Cell::any(&cells)
+        }
+    }
+}
+
+// Cloning a handle only bumps a reference count, both handles share the same graph node.
+impl<A> Clone for Cell<A> {
     fn clone(&self) -> Self {
         Cell {
             impl_: self.impl_.clone()
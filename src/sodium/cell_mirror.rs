@@ -0,0 +1,95 @@
+use sodium::Cell;
+use sodium::Listener;
+use sodium::Operational;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::atomic::fence;
+
+// A seqlock. The only writer is the listener on the thread that owns the SodiumCtx, the
+// sequence is odd while it is part way through a write and readers go round again if it
+// moved under them.
+struct Mirror<A> {
+    seq: AtomicUsize,
+    value: UnsafeCell<A>
+}
+
+unsafe impl<A: Copy + Send> Send for Mirror<A> {}
+
+unsafe impl<A: Copy + Send> Sync for Mirror<A> {}
+
+impl<A: Copy> Mirror<A> {
+    fn write(&self, a: A) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        unsafe { ptr::write_volatile(self.value.get(), a); }
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+
+    fn read(&self) -> (A,usize) {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 1 {
+                ::std::hint::spin_loop();
+                continue;
+            }
+            // Copied as MaybeUninit, a read torn by a write need not be a valid A and is
+            // only treated as one once seq shows no write overlapped it.
+            let a = unsafe { ptr::read_volatile(self.value.get() as *const MaybeUninit<A>) };
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return (unsafe { a.assume_init() }, seq / 2);
+            }
+        }
+    }
+}
+
+// A copy of a cell's value that any thread can read without going near the SodiumCtx, e.g. an
+// audio or render thread. Reads never block, they spin only while a write is in progress.
+pub struct ArcCellMirror<A> {
+    mirror: Arc<Mirror<A>>
+}
+
+impl<A: Copy + Send> ArcCellMirror<A> {
+    // The value as of the end of the last transaction that changed the cell.
+    pub fn get(&self) -> A {
+        self.mirror.read().0
+    }
+
+    // How many times the mirror has been written to since it was made, for readers that
+    // only want to do work when the value has moved on.
+    pub fn version(&self) -> usize {
+        self.mirror.read().1
+    }
+}
+
+impl<A> Clone for ArcCellMirror<A> {
+    fn clone(&self) -> Self {
+        ArcCellMirror {
+            mirror: self.mirror.clone()
+        }
+    }
+}
+
+impl<A: Copy + Send + Trace + Finalize + 'static> Cell<A> {
+    // Keeps an ArcCellMirror up to date with the cell, it is written once at the end of each
+    // transaction the cell changes in. Unlisten to stop, the mirror keeps the last value.
+    pub fn mirror_atomic(&self) -> (ArcCellMirror<A>, Listener) {
+        let mirror = Arc::new(Mirror {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(self.sample())
+        });
+        let listener;
+        {
+            let mirror = mirror.clone();
+            listener = Operational::updates(self).listen(move |a: &A| mirror.write(*a));
+        }
+        (ArcCellMirror { mirror }, listener)
+    }
+}
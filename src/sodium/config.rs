@@ -0,0 +1,50 @@
+use sodium::Cell;
+use sodium::Stream;
+use sodium::gc::Finalize;
+use sodium::gc::GcDep;
+use sodium::gc::Trace;
+use sodium::os::FsEvent;
+use sodium::os::OsEvents;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    Io(PathBuf, String),
+    Parse(PathBuf, String)
+}
+
+impl Trace for ConfigError {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for ConfigError {}
+
+fn load<T, PARSE: Fn(&str) -> Result<T, String>>(path: &Path, parse: &PARSE) -> Result<T, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err.to_string()))?;
+    parse(&text).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+}
+
+// The format is given as a parse function, e.g. |s| serde_json::from_str(s).map_err(|e| e.to_string()).
+// The file must load when called, after that a failed reload keeps the last good value and
+// reports on the error stream. Reloads happen from OsEvents::poll().
+pub fn cell_from_file<T, P, PARSE>(os_events: &mut OsEvents, path: P, parse: PARSE) -> Result<(Cell<T>, Stream<ConfigError>), ConfigError>
+    where T: Clone + Trace + Finalize + 'static,
+          P: AsRef<Path>,
+          PARSE: Fn(&str) -> Result<T, String> + 'static
+{
+    let path = path.as_ref().to_path_buf();
+    let initial = load(&path, &parse)?;
+    let (oks, errs) =
+        os_events
+            .fs_watch(&path)
+            .map(move |event: &FsEvent| {
+                match *event {
+                    FsEvent::Created(ref path) | FsEvent::Modified(ref path) => load(path, &parse),
+                    FsEvent::Removed(ref path) => Err(ConfigError::Io(path.clone(), String::from("file was removed")))
+                }
+            })
+            .split();
+    Ok((oks.hold(initial), errs))
+}
@@ -0,0 +1,156 @@
+use sodium::Cell;
+use sodium::IsCell;
+use sodium::IsStream;
+use sodium::Operational;
+use sodium::Stream;
+use sodium::gc::Finalize;
+use sodium::gc::GcDep;
+use sodium::gc::Trace;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// A value that changes can be described by, for moving between whole values and patches.
+// apply(diff(old, new)) must give back new.
+pub trait Diff: Clone + Trace + Finalize + 'static {
+    type Patch: Clone + Trace + Finalize + 'static;
+
+    // None when nothing changed.
+    fn diff(&self, new: &Self) -> Option<Self::Patch>;
+
+    fn apply(&self, patch: &Self::Patch) -> Self;
+}
+
+// The cell a stream of patches builds up from initial.
+pub fn deltas_to_cell<C: Diff, SP: IsStream<C::Patch>>(initial: C, patches: SP) -> Cell<C> {
+    patches.accum(initial, |patch: &C::Patch, c: &C| c.apply(patch))
+}
+
+// The patch for each change to the cell, updates that leave it as it was are left out.
+pub fn cell_to_deltas<C: Diff, CC: IsCell<C>>(cc: CC) -> Stream<C::Patch> {
+    let c = cc.to_cell();
+    Operational::updates(&c)
+        .snapshot2(&c, |new: &C, old: &C| old.diff(new))
+        .filter_option()
+}
+
+// Replaces removed elements from start on with inserted. Vec's diff keeps the common prefix and
+// suffix, so a single insertion, removal or replacement makes a patch of just that.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VecPatch<A> {
+    pub start: usize,
+    pub removed: usize,
+    pub inserted: Vec<A>
+}
+
+impl<A: Trace> Trace for VecPatch<A> {
+    fn trace(&self, f: &mut dyn FnMut(&GcDep)) {
+        self.inserted.trace(f);
+    }
+}
+
+impl<A: Finalize> Finalize for VecPatch<A> {
+    fn finalize(&mut self) {
+        self.inserted.finalize();
+    }
+}
+
+impl<A: Clone + PartialEq + Trace + Finalize + 'static> Diff for Vec<A> {
+    type Patch = VecPatch<A>;
+
+    fn diff(&self, new: &Vec<A>) -> Option<VecPatch<A>> {
+        let prefix = self.iter().zip(new.iter()).take_while(|&(a, b)| a == b).count();
+        if prefix == self.len() && prefix == new.len() {
+            return None;
+        }
+        let suffix = self[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|&(a, b)| a == b).count();
+        Some(VecPatch {
+            start: prefix,
+            removed: self.len() - prefix - suffix,
+            inserted: new[prefix..new.len() - suffix].to_vec()
+        })
+    }
+
+    fn apply(&self, patch: &VecPatch<A>) -> Vec<A> {
+        let mut result = Vec::with_capacity(self.len() - patch.removed + patch.inserted.len());
+        result.extend_from_slice(&self[..patch.start]);
+        result.extend_from_slice(&patch.inserted);
+        result.extend_from_slice(&self[patch.start + patch.removed..]);
+        result
+    }
+}
+
+// Keys to set, to a new value or for the first time, and keys to remove.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapPatch<K,V> {
+    pub inserted: Vec<(K,V)>,
+    pub removed: Vec<K>
+}
+
+impl<K: Trace, V: Trace> Trace for MapPatch<K,V> {
+    fn trace(&self, f: &mut dyn FnMut(&GcDep)) {
+        self.inserted.trace(f);
+        self.removed.trace(f);
+    }
+}
+
+impl<K: Finalize, V: Finalize> Finalize for MapPatch<K,V> {
+    fn finalize(&mut self) {
+        self.inserted.finalize();
+        self.removed.finalize();
+    }
+}
+
+impl<K: Clone + Ord + Trace + Finalize + 'static, V: Clone + PartialEq + Trace + Finalize + 'static> Diff for BTreeMap<K,V> {
+    type Patch = MapPatch<K,V>;
+
+    fn diff(&self, new: &BTreeMap<K,V>) -> Option<MapPatch<K,V>> {
+        let patch = MapPatch {
+            inserted: new.iter().filter(|&(k, v)| self.get(k) != Some(v)).map(|(k, v)| (k.clone(), v.clone())).collect(),
+            removed: self.keys().filter(|k| !new.contains_key(k)).cloned().collect()
+        };
+        if patch.inserted.is_empty() && patch.removed.is_empty() {
+            None
+        } else {
+            Some(patch)
+        }
+    }
+
+    fn apply(&self, patch: &MapPatch<K,V>) -> BTreeMap<K,V> {
+        let mut result = self.clone();
+        for k in &patch.removed {
+            result.remove(k);
+        }
+        for (k, v) in &patch.inserted {
+            result.insert(k.clone(), v.clone());
+        }
+        result
+    }
+}
+
+impl<K: Clone + Eq + Hash + Trace + Finalize + 'static, V: Clone + PartialEq + Trace + Finalize + 'static> Diff for HashMap<K,V> {
+    type Patch = MapPatch<K,V>;
+
+    fn diff(&self, new: &HashMap<K,V>) -> Option<MapPatch<K,V>> {
+        let patch = MapPatch {
+            inserted: new.iter().filter(|&(k, v)| self.get(k) != Some(v)).map(|(k, v)| (k.clone(), v.clone())).collect(),
+            removed: self.keys().filter(|k| !new.contains_key(k)).cloned().collect()
+        };
+        if patch.inserted.is_empty() && patch.removed.is_empty() {
+            None
+        } else {
+            Some(patch)
+        }
+    }
+
+    fn apply(&self, patch: &MapPatch<K,V>) -> HashMap<K,V> {
+        let mut result = self.clone();
+        for k in &patch.removed {
+            result.remove(k);
+        }
+        for (k, v) in &patch.inserted {
+            result.insert(k.clone(), v.clone());
+        }
+        result
+    }
+}
@@ -0,0 +1,122 @@
+use sodium::IsCell;
+use sodium::IsStream;
+use sodium::Listener;
+use sodium::SodiumCtx;
+use sodium::Stream;
+use sodium::StreamSink;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::array;
+use std::cell::RefCell;
+use std::ops::Add;
+use std::rc::Rc;
+
+// Fires one block of N samples per transaction instead of one transaction per sample.
+pub struct BlockStream<T, const N: usize> {
+    pub stream: Stream<[T; N]>
+}
+
+impl<T: Copy + Trace + Finalize + 'static, const N: usize> BlockStream<T, N> {
+    pub fn new<SA: IsStream<[T; N]>>(sa: SA) -> BlockStream<T, N> {
+        BlockStream {
+            stream: sa.to_stream()
+        }
+    }
+
+    pub fn map_block<U: Copy + Trace + Finalize + 'static, F: Fn(&[T; N]) -> [U; N] + 'static>(&self, f: F) -> BlockStream<U, N> {
+        BlockStream {
+            stream: self.stream.map(move |block: &[T; N]| f(block))
+        }
+    }
+
+    pub fn map_samples<U: Copy + Trace + Finalize + 'static, F: Fn(T) -> U + 'static>(&self, f: F) -> BlockStream<U, N> {
+        self.map_block(move |block: &[T; N]| array::from_fn(|i| f(block[i])))
+    }
+
+    pub fn mix(&self, other: &BlockStream<T, N>) -> BlockStream<T, N> where T: Add<Output=T> {
+        BlockStream {
+            stream: self.stream.merge(&other.stream, |l: &[T; N], r: &[T; N]| {
+                let mut out = *l;
+                for i in 0..N {
+                    out[i] = l[i] + r[i];
+                }
+                out
+            })
+        }
+    }
+
+    pub fn hold_to_block<U: Copy + Trace + Finalize + 'static, CU: IsCell<U>>(&self, cu: CU) -> BlockStream<U, N> {
+        BlockStream {
+            stream: self.stream.snapshot2(cu, |_block: &[T; N], u: &U| [*u; N])
+        }
+    }
+}
+
+impl<T: Copy + Trace + Finalize + 'static, const N: usize> IsStream<[T; N]> for BlockStream<T, N> {
+    fn to_stream(&self) -> Stream<[T; N]> {
+        self.stream.clone()
+    }
+}
+
+impl<T: Copy + Trace + Finalize + 'static, const N: usize> Clone for BlockStream<T, N> {
+    fn clone(&self) -> Self {
+        BlockStream {
+            stream: self.stream.clone()
+        }
+    }
+}
+
+// Call render() from an audio output callback (e.g. cpal's data callback). Each block of N
+// samples fires one tick, blocks the graph does not produce are rendered as silence.
+pub struct BlockDriver<const N: usize> {
+    tick: StreamSink<()>,
+    latest: Rc<RefCell<Option<[f32; N]>>>,
+    listener: Listener
+}
+
+impl<const N: usize> BlockDriver<N> {
+    pub fn new<BUILD: FnOnce(&Stream<()>) -> BlockStream<f32, N>>(sodium_ctx: &SodiumCtx, build: BUILD) -> BlockDriver<N> {
+        let tick = sodium_ctx.new_stream_sink();
+        let latest = Rc::new(RefCell::new(None));
+        let output = build(&tick.to_stream());
+        let listener;
+        {
+            let latest = latest.clone();
+            listener = output.stream.listen(move |block: &[f32; N]| {
+                *latest.borrow_mut() = Some(*block);
+            });
+        }
+        BlockDriver {
+            tick,
+            latest,
+            listener
+        }
+    }
+
+    pub fn render(&self, out: &mut [f32]) {
+        // Empty blocks carry no samples, so there's nothing to tick for.
+        if N == 0 {
+            for sample in out.iter_mut() {
+                *sample = 0.0;
+            }
+            return;
+        }
+        for chunk in out.chunks_mut(N) {
+            self.tick.send(&());
+            match self.latest.borrow_mut().take() {
+                Some(block) => chunk.copy_from_slice(&block[..chunk.len()]),
+                None => {
+                    for sample in chunk.iter_mut() {
+                        *sample = 0.0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<const N: usize> Drop for BlockDriver<N> {
+    fn drop(&mut self) {
+        self.listener.unlisten();
+    }
+}
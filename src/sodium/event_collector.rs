@@ -0,0 +1,54 @@
+use sodium::IsStream;
+use sodium::Listener;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::cell::RefCell;
+use std::mem::swap;
+use std::rc::Rc;
+
+// Buffers the events of a stream between calls to drain_into(), e.g. once per frame of a game
+// loop. Stops listening when dropped.
+pub struct EventCollector<A> {
+    events: Rc<RefCell<Vec<A>>>,
+    listener: Listener
+}
+
+impl<A: Clone + Trace + Finalize + 'static> EventCollector<A> {
+    pub fn new<SA: IsStream<A>>(sa: &SA) -> EventCollector<A> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let listener;
+        {
+            let events = events.clone();
+            listener = sa.to_stream().listen(move |a: &A| events.borrow_mut().push(a.clone()));
+        }
+        EventCollector {
+            events,
+            listener
+        }
+    }
+
+    // Appends the buffered events to out. When out is empty the buffers are swapped instead, so
+    // reusing the same Vec every frame settles into no allocations at all.
+    pub fn drain_into(&self, out: &mut Vec<A>) {
+        let mut events = self.events.borrow_mut();
+        if out.is_empty() {
+            swap(&mut *events, out);
+        } else {
+            out.extend(events.drain(..));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.borrow().is_empty()
+    }
+}
+
+impl<A> Drop for EventCollector<A> {
+    fn drop(&mut self) {
+        self.listener.unlisten();
+    }
+}
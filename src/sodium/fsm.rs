@@ -0,0 +1,88 @@
+use sodium::Cell;
+use sodium::IsStream;
+use sodium::Stream;
+use sodium::gc::Finalize;
+use sodium::gc::GcDep;
+use sodium::gc::Trace;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition<S> {
+    pub from: S,
+    pub to: S
+}
+
+impl<S: Trace> Trace for Transition<S> {
+    fn trace(&self, f: &mut dyn FnMut(&GcDep)) {
+        self.from.trace(f);
+        self.to.trace(f);
+    }
+}
+
+impl<S: Finalize> Finalize for Transition<S> {
+    fn finalize(&mut self) {
+        self.from.finalize();
+        self.to.finalize();
+    }
+}
+
+// Works out the next state from the event and the state being left.
+pub type Step<E,S> = fn(&E,&S) -> S;
+
+pub struct StateMachine<S> {
+    state: Cell<S>,
+    transitions: Stream<Transition<S>>
+}
+
+impl<S: Clone + PartialEq + Trace + Finalize + 'static> StateMachine<S> {
+    // Each transition is the state it leaves, the stream that sets it off and what the next
+    // state is. When several could be taken in one transaction the first declared wins, and
+    // at most one is taken per transaction. There has to be at least one transition.
+    pub fn new<E: Clone + Trace + Finalize + 'static>(initial: S, transitions: &[(S, &Stream<E>, Step<E,S>)]) -> StateMachine<S> {
+        let mut decls: Vec<(S,Step<E,S>)> = Vec::new();
+        let mut events_op: Option<Stream<Vec<(usize,E)>>> = None;
+        for (i, &(ref from, stream, f)) in transitions.iter().enumerate() {
+            decls.push((from.clone(), f));
+            let tagged = stream.map(move |e: &E| vec![(i, e.clone())]);
+            events_op = Some(
+                match events_op {
+                    Some(events) => events.merge(&tagged, |a: &Vec<(usize,E)>, b: &Vec<(usize,E)>| {
+                        let mut a = a.clone();
+                        a.extend(b.iter().cloned());
+                        a
+                    }),
+                    None => tagged
+                }
+            );
+        }
+        let transitions =
+            match events_op {
+                Some(events) => events
+                    .collect(initial.clone(), move |events: &Vec<(usize,E)>, state: &S| {
+                        let taken_op = events.iter().find(|&&(i, _)| decls[i].0 == *state);
+                        match taken_op {
+                            Some(&(i, ref e)) => {
+                                let to = (decls[i].1)(e, state);
+                                (Some(Transition { from: state.clone(), to: to.clone() }), to)
+                            },
+                            None => (None, state.clone())
+                        }
+                    })
+                    .filter_option(),
+                None => panic!("StateMachine::new requires at least one transition.")
+            };
+        let state = transitions.map(|t: &Transition<S>| t.to.clone()).hold(initial);
+        StateMachine {
+            state,
+            transitions
+        }
+    }
+
+    pub fn state(&self) -> Cell<S> {
+        self.state.clone()
+    }
+
+    // Fires with each transition taken, including ones back to the same state.
+    pub fn transitions(&self) -> Stream<Transition<S>> {
+        self.transitions.clone()
+    }
+}
@@ -0,0 +1,66 @@
+use sodium::Stream;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::borrow::Cow;
+use std::rc::Rc;
+
+// One stage and everything before it. Values are borrowed from the event until a map makes
+// a new one, so a filter never clones and only a chain with no map clones, once, at the end.
+type Stages<A,B> = dyn Fn(&A) -> Option<Cow<B>>;
+
+// Builds a chain of maps and filters as a single node, where the same chain on Stream makes
+// a node per step. It only saves nodes: each step is still its own closure, called through
+// the one before it for every event. Nothing in between can be listened to, so no step's
+// output is needed by anything else. The functions are plain closures, anything they
+// capture is not seen by the GC.
+pub struct FusedStream<A,B: Clone> {
+    source: Stream<A>,
+    f: Rc<Stages<A,B>>,
+    stages: usize
+}
+
+impl<A: Clone + Trace + Finalize + 'static, B: Clone + Trace + Finalize + 'static> FusedStream<A,B> {
+    pub(crate) fn new(source: &Stream<A>, f: Rc<Stages<A,B>>, stages: usize) -> FusedStream<A,B> {
+        FusedStream {
+            source: source.clone(),
+            f,
+            stages
+        }
+    }
+
+    pub fn map<C: Clone + Trace + Finalize + 'static, F: Fn(&B) -> C + 'static>(&self, g: F) -> FusedStream<A,C> {
+        let f = self.f.clone();
+        FusedStream::new(&self.source, Rc::new(move |a: &A| f(a).map(|b| Cow::Owned(g(&b)))), self.stages + 1)
+    }
+
+    pub fn filter<PRED: Fn(&B) -> bool + 'static>(&self, pred: PRED) -> FusedStream<A,B> {
+        let f = self.f.clone();
+        FusedStream::new(&self.source, Rc::new(move |a: &A| f(a).filter(|b| pred(b))), self.stages + 1)
+    }
+
+    // The same as map followed by filter_option.
+    pub fn map_option<C: Clone + Trace + Finalize + 'static, F: Fn(&B) -> Option<C> + 'static>(&self, g: F) -> FusedStream<A,C> {
+        let f = self.f.clone();
+        FusedStream::new(&self.source, Rc::new(move |a: &A| f(a).and_then(|b| g(&b)).map(Cow::Owned)), self.stages + 2)
+    }
+
+    // How many nodes the chain would have made on Stream. build() always makes one.
+    pub fn unfused_node_count(&self) -> usize {
+        self.stages
+    }
+
+    pub fn build(&self) -> Stream<B> {
+        let f = self.f.clone();
+        Stream {
+            impl_: self.source.impl_.map_filter(move |a: &A| f(a).map(Cow::into_owned), "Stream::fused")
+        }
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
+    // Starts a FusedStream, e.g. s.fuse().map(f).map(g).filter(p).build() for one node
+    // instead of three.
+    pub fn fuse(&self) -> FusedStream<A,A> {
+        FusedStream::new(self, Rc::new(|a: &A| Some(Cow::Borrowed(a))), 0)
+    }
+}
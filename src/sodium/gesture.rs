@@ -0,0 +1,145 @@
+use sodium::IsStream;
+use sodium::Stream;
+use sodium::gc::Finalize;
+use sodium::gc::GcDep;
+use sodium::gc::Trace;
+use sodium::time::TimerSystem;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64
+}
+
+impl Point {
+    pub fn new(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    pub fn distance(&self, other: &Point) -> f64 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2)).sqrt()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ButtonEvent {
+    Down(Point),
+    Up(Point)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyEvent<K> {
+    Down(K),
+    Up(K)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Drag {
+    // Where the button went down.
+    pub start: Point,
+    pub current: Point
+}
+
+impl Trace for Point {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for Point {}
+
+impl Trace for ButtonEvent {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for ButtonEvent {}
+
+impl<K: Trace> Trace for KeyEvent<K> {
+    fn trace(&self, f: &mut dyn FnMut(&GcDep)) {
+        match *self {
+            KeyEvent::Down(ref k) | KeyEvent::Up(ref k) => k.trace(f)
+        }
+    }
+}
+
+impl<K: Finalize> Finalize for KeyEvent<K> {
+    fn finalize(&mut self) {
+        match *self {
+            KeyEvent::Down(ref mut k) | KeyEvent::Up(ref mut k) => k.finalize()
+        }
+    }
+}
+
+impl Trace for Drag {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for Drag {}
+
+// Fires with the second of two clicks no more than window apart. A third click starts the
+// next pair rather than counting again. Clicks are timed by the timer's time(), so poll it
+// before handing them in.
+pub fn double_click<A: Clone + Trace + Finalize + 'static, SA: IsStream<A>>(clicks: SA, timer_system: &TimerSystem, window: Duration) -> Stream<A> {
+    clicks
+        .to_stream()
+        .snapshot2(timer_system.time(), |a: &A, t: &Duration| (a.clone(), *t))
+        .collect(None, move |&(ref a, t): &(A,Duration), last: &Option<Duration>| {
+            match *last {
+                Some(last) if t.checked_sub(last).is_some_and(|gap| gap <= window) => (Some(a.clone()), None),
+                _ => (None, Some(t))
+            }
+        })
+        .filter_option()
+}
+
+// Fires for each move while a button is held, from the first that gets further than px from
+// where it went down. Moving back inside px doesn't stop the drag, releasing does. A press in
+// the same transaction as a move is taken to come first.
+pub fn drag_threshold<SM: IsStream<Point>, SB: IsStream<ButtonEvent>>(moves: SM, presses: SB, px: f64) -> Stream<Drag> {
+    let presses = presses.to_stream().map(|press: &ButtonEvent| (Some(*press), None));
+    let moves = moves.to_stream().map(|p: &Point| (None, Some(*p)));
+    presses
+        .merge(&moves, |&(press, _): &(Option<ButtonEvent>,Option<Point>), &(_, p): &(Option<ButtonEvent>,Option<Point>)| (press, p))
+        .collect(None, move |&(press, p): &(Option<ButtonEvent>,Option<Point>), held: &Option<(Point,bool)>| {
+            let held =
+                match press {
+                    Some(ButtonEvent::Down(start)) => Some((start, false)),
+                    Some(ButtonEvent::Up(_)) => None,
+                    None => *held
+                };
+            match (held, p) {
+                (Some((start, dragging)), Some(current)) => {
+                    let dragging = dragging || current.distance(&start) > px;
+                    let drag_op = if dragging { Some(Drag { start, current }) } else { None };
+                    (drag_op, Some((start, dragging)))
+                },
+                _ => (None, held)
+            }
+        })
+        .filter_option()
+}
+
+// Fires when the key that completes the chord goes down while the others are held. Holding
+// the chord and pressing an extra key doesn't fire it again, releasing one of its keys and
+// pressing it again does.
+pub fn key_chord<K: Clone + PartialEq + Trace + Finalize + 'static, SK: IsStream<KeyEvent<K>>>(keys: SK, chord: &[K]) -> Stream<()> {
+    let chord = chord.to_vec();
+    keys.to_stream()
+        .collect(Vec::new(), move |key: &KeyEvent<K>, held: &Vec<K>| {
+            let mut held = held.clone();
+            match *key {
+                KeyEvent::Down(ref k) => {
+                    if held.contains(k) {
+                        return (None, held);
+                    }
+                    held.push(k.clone());
+                    let completes = !chord.is_empty() && chord.contains(k) && chord.iter().all(|k| held.contains(k));
+                    (if completes { Some(()) } else { None }, held)
+                },
+                KeyEvent::Up(ref k) => {
+                    held.retain(|k2| k2 != k);
+                    (None, held)
+                }
+            }
+        })
+        .filter_option()
+}
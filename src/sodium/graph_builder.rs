@@ -0,0 +1,88 @@
+use sodium::Cell;
+use sodium::CellSink;
+use sodium::SodiumCtx;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+type Command = Box<dyn FnOnce(&SodiumCtx)>;
+
+pub struct GraphBuilder {
+    sodium_ctx: SodiumCtx,
+    commands: VecDeque<Command>,
+    total: usize,
+    applied: usize,
+    progress: CellSink<f32>
+}
+
+impl GraphBuilder {
+    pub fn new(sodium_ctx: &SodiumCtx) -> GraphBuilder {
+        GraphBuilder {
+            sodium_ctx: sodium_ctx.clone(),
+            commands: VecDeque::new(),
+            total: 0,
+            applied: 0,
+            progress: sodium_ctx.new_cell_sink(1.0)
+        }
+    }
+
+    // Only queues the command, so it can be called from inside a callback. progress moves
+    // when the next step applies it.
+    pub fn add<F: FnOnce(&SodiumCtx) + 'static>(&mut self, command: F) {
+        self.commands.push_back(Box::new(command));
+        self.total += 1;
+    }
+
+    // Updated by step and abort.
+    pub fn progress(&self) -> Cell<f32> {
+        self.progress.to_cell()
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.applied as f32 / self.total as f32
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn step(&mut self, budget: usize) -> bool {
+        self.step_while(|applied| applied < budget)
+    }
+
+    pub fn step_for(&mut self, budget: Duration) -> bool {
+        let start = Instant::now();
+        self.step_while(move |applied| applied == 0 || start.elapsed() < budget)
+    }
+
+    pub fn abort(&mut self) {
+        self.commands.clear();
+        self.total = self.applied;
+        self.progress.send(&self.fraction());
+    }
+
+    fn step_while<COND: Fn(usize) -> bool>(&mut self, cond: COND) -> bool {
+        let sodium_ctx = self.sodium_ctx.clone();
+        sodium_ctx.transaction(|sodium_ctx| {
+            let mut applied = 0;
+            while cond(applied) {
+                match self.commands.pop_front() {
+                    Some(command) => command(sodium_ctx),
+                    None => break
+                }
+                applied += 1;
+            }
+            self.applied += applied;
+            self.progress.send(&self.fraction());
+        });
+        self.is_done()
+    }
+}
@@ -0,0 +1,413 @@
+use sodium::Cell;
+use sodium::CellSink;
+use sodium::IsStream;
+use sodium::SodiumCtx;
+use sodium::Stream;
+use sodium::StreamSink;
+use std::collections::HashMap;
+use std::fmt;
+
+// Experimental. A graph over f64 described by data rather than code, so a new description can
+// be loaded while the program runs and patched into the live graph. Combinator logic is
+// referred to by name and looked up in Functions.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeSpec {
+    // A stream fed from outside through HotGraph::input.
+    Input,
+    Constant(f64),
+    // Stream or cell, whichever the input is.
+    Map { f: String, input: String },
+    Filter { pred: String, input: String },
+    Merge { f: String, left: String, right: String },
+    Hold { init: f64, input: String },
+    // f is called with the event and the state.
+    Accum { init: f64, f: String, input: String },
+    // f is called with the event and the cell's value.
+    Snapshot { f: String, stream: String, cell: String },
+    Lift { f: String, left: String, right: String }
+}
+
+// Named nodes in the order they are defined, each can only refer to those before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphSpec {
+    nodes: Vec<(String,NodeSpec)>
+}
+
+impl Default for GraphSpec {
+    fn default() -> GraphSpec {
+        GraphSpec::new()
+    }
+}
+
+impl GraphSpec {
+    pub fn new() -> GraphSpec {
+        GraphSpec {
+            nodes: Vec::new()
+        }
+    }
+
+    pub fn node(mut self, name: &str, node: NodeSpec) -> GraphSpec {
+        self.nodes.retain(|(name2, _)| name2 != name);
+        self.nodes.push((String::from(name), node));
+        self
+    }
+
+    pub fn input(self, name: &str) -> GraphSpec {
+        self.node(name, NodeSpec::Input)
+    }
+
+    pub fn constant(self, name: &str, value: f64) -> GraphSpec {
+        self.node(name, NodeSpec::Constant(value))
+    }
+
+    pub fn map(self, name: &str, f: &str, input: &str) -> GraphSpec {
+        self.node(name, NodeSpec::Map { f: String::from(f), input: String::from(input) })
+    }
+
+    pub fn filter(self, name: &str, pred: &str, input: &str) -> GraphSpec {
+        self.node(name, NodeSpec::Filter { pred: String::from(pred), input: String::from(input) })
+    }
+
+    pub fn merge(self, name: &str, f: &str, left: &str, right: &str) -> GraphSpec {
+        self.node(name, NodeSpec::Merge { f: String::from(f), left: String::from(left), right: String::from(right) })
+    }
+
+    pub fn hold(self, name: &str, init: f64, input: &str) -> GraphSpec {
+        self.node(name, NodeSpec::Hold { init, input: String::from(input) })
+    }
+
+    pub fn accum(self, name: &str, init: f64, f: &str, input: &str) -> GraphSpec {
+        self.node(name, NodeSpec::Accum { init, f: String::from(f), input: String::from(input) })
+    }
+
+    pub fn snapshot(self, name: &str, f: &str, stream: &str, cell: &str) -> GraphSpec {
+        self.node(name, NodeSpec::Snapshot { f: String::from(f), stream: String::from(stream), cell: String::from(cell) })
+    }
+
+    pub fn lift(self, name: &str, f: &str, left: &str, right: &str) -> GraphSpec {
+        self.node(name, NodeSpec::Lift { f: String::from(f), left: String::from(left), right: String::from(right) })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NodeSpec> {
+        self.nodes.iter().find(|&(name2, _)| name2 == name).map(|(_, node)| node)
+    }
+
+    // The text form, one node per line as "name = kind args...", the inverse of Display.
+    // Blank lines and lines starting with # are skipped.
+    pub fn parse(text: &str) -> Result<GraphSpec,String> {
+        let mut spec = GraphSpec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {}", i + 1, msg);
+            let mut halves = line.splitn(2, '=');
+            let name = halves.next().unwrap().trim();
+            let rhs = halves.next().ok_or_else(|| err("expected name = kind args"))?;
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(err("bad node name"));
+            }
+            let words: Vec<&str> = rhs.split_whitespace().collect();
+            let num = |word: &str| word.parse::<f64>().map_err(|_| err(&format!("bad number {}", word)));
+            let s = |at: usize| String::from(words[at]);
+            let node =
+                match (words.first().cloned(), words.len()) {
+                    (Some("input"), 1) => NodeSpec::Input,
+                    (Some("constant"), 2) => NodeSpec::Constant(num(words[1])?),
+                    (Some("map"), 3) => NodeSpec::Map { f: s(1), input: s(2) },
+                    (Some("filter"), 3) => NodeSpec::Filter { pred: s(1), input: s(2) },
+                    (Some("merge"), 4) => NodeSpec::Merge { f: s(1), left: s(2), right: s(3) },
+                    (Some("hold"), 3) => NodeSpec::Hold { init: num(words[1])?, input: s(2) },
+                    (Some("accum"), 4) => NodeSpec::Accum { init: num(words[1])?, f: s(2), input: s(3) },
+                    (Some("snapshot"), 4) => NodeSpec::Snapshot { f: s(1), stream: s(2), cell: s(3) },
+                    (Some("lift"), 4) => NodeSpec::Lift { f: s(1), left: s(2), right: s(3) },
+                    _ => return Err(err(&format!("can't make sense of {}", rhs.trim())))
+                };
+            spec = spec.node(name, node);
+        }
+        Ok(spec)
+    }
+
+    // What it takes to get from self to to. A node counts as changed when its own definition
+    // is different, not when something upstream of it is.
+    pub fn diff(&self, to: &GraphSpec) -> SpecDiff {
+        let mut diff = SpecDiff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+            unchanged: Vec::new()
+        };
+        for (name, node) in &to.nodes {
+            match self.get(name) {
+                None => diff.added.push(name.clone()),
+                Some(old) if old == node => diff.unchanged.push(name.clone()),
+                Some(_) => diff.changed.push(name.clone())
+            }
+        }
+        for (name, _) in &self.nodes {
+            if to.get(name).is_none() {
+                diff.removed.push(name.clone());
+            }
+        }
+        diff
+    }
+}
+
+impl fmt::Display for GraphSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, node) in &self.nodes {
+            match *node {
+                NodeSpec::Input => writeln!(f, "{} = input", name)?,
+                NodeSpec::Constant(value) => writeln!(f, "{} = constant {:?}", name, value)?,
+                NodeSpec::Map { f: ref g, ref input } => writeln!(f, "{} = map {} {}", name, g, input)?,
+                NodeSpec::Filter { ref pred, ref input } => writeln!(f, "{} = filter {} {}", name, pred, input)?,
+                NodeSpec::Merge { f: ref g, ref left, ref right } => writeln!(f, "{} = merge {} {} {}", name, g, left, right)?,
+                NodeSpec::Hold { init, ref input } => writeln!(f, "{} = hold {:?} {}", name, init, input)?,
+                NodeSpec::Accum { init, f: ref g, ref input } => writeln!(f, "{} = accum {:?} {} {}", name, init, g, input)?,
+                NodeSpec::Snapshot { f: ref g, ref stream, ref cell } => writeln!(f, "{} = snapshot {} {} {}", name, g, stream, cell)?,
+                NodeSpec::Lift { f: ref g, ref left, ref right } => writeln!(f, "{} = lift {} {} {}", name, g, left, right)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpecDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+    pub unchanged: Vec<String>
+}
+
+// The functions a GraphSpec can name.
+pub struct Functions {
+    unary: HashMap<String,fn(f64) -> f64>,
+    binary: HashMap<String,fn(f64,f64) -> f64>,
+    predicates: HashMap<String,fn(f64) -> bool>
+}
+
+impl Default for Functions {
+    fn default() -> Functions {
+        Functions::new()
+    }
+}
+
+impl Functions {
+    pub fn new() -> Functions {
+        Functions {
+            unary: HashMap::new(),
+            binary: HashMap::new(),
+            predicates: HashMap::new()
+        }
+    }
+
+    // For map.
+    pub fn unary(mut self, name: &str, f: fn(f64) -> f64) -> Functions {
+        self.unary.insert(String::from(name), f);
+        self
+    }
+
+    // For merge, accum, snapshot and lift.
+    pub fn binary(mut self, name: &str, f: fn(f64,f64) -> f64) -> Functions {
+        self.binary.insert(String::from(name), f);
+        self
+    }
+
+    // For filter.
+    pub fn predicate(mut self, name: &str, pred: fn(f64) -> bool) -> Functions {
+        self.predicates.insert(String::from(name), pred);
+        self
+    }
+
+    fn get_unary(&self, name: &str) -> Result<fn(f64) -> f64,String> {
+        self.unary.get(name).cloned().ok_or_else(|| format!("no unary function {}", name))
+    }
+
+    fn get_binary(&self, name: &str) -> Result<fn(f64,f64) -> f64,String> {
+        self.binary.get(name).cloned().ok_or_else(|| format!("no binary function {}", name))
+    }
+
+    fn get_predicate(&self, name: &str) -> Result<fn(f64) -> bool,String> {
+        self.predicates.get(name).cloned().ok_or_else(|| format!("no predicate {}", name))
+    }
+}
+
+#[derive(Clone)]
+enum Built {
+    Stream(Stream<f64>),
+    Cell(Cell<f64>)
+}
+
+impl Built {
+    fn stream(&self, name: &str) -> Result<Stream<f64>,String> {
+        match *self {
+            Built::Stream(ref sa) => Ok(sa.clone()),
+            Built::Cell(_) => Err(format!("{} is a cell where a stream is needed", name))
+        }
+    }
+
+    fn cell(&self, name: &str) -> Result<Cell<f64>,String> {
+        match *self {
+            Built::Cell(ref ca) => Ok(ca.clone()),
+            Built::Stream(_) => Err(format!("{} is a stream where a cell is needed", name))
+        }
+    }
+}
+
+// Handed out outputs follow whatever their node is after a patch.
+enum Output {
+    Stream(CellSink<Stream<f64>>),
+    Cell(CellSink<Cell<f64>>)
+}
+
+// A live graph built from a GraphSpec. Input sinks, and the streams and cells handed out by
+// stream() and cell(), stay the same across patches.
+pub struct HotGraph {
+    sodium_ctx: SodiumCtx,
+    functions: Functions,
+    spec: GraphSpec,
+    inputs: HashMap<String,StreamSink<f64>>,
+    built: HashMap<String,Built>,
+    outputs: HashMap<String,Output>
+}
+
+impl HotGraph {
+    pub fn new(sodium_ctx: &SodiumCtx, functions: Functions, spec: GraphSpec) -> Result<HotGraph,String> {
+        let mut graph = HotGraph {
+            sodium_ctx: sodium_ctx.clone(),
+            functions,
+            spec: GraphSpec::new(),
+            inputs: HashMap::new(),
+            built: HashMap::new(),
+            outputs: HashMap::new()
+        };
+        graph.patch(spec)?;
+        Ok(graph)
+    }
+
+    pub fn spec(&self) -> &GraphSpec {
+        &self.spec
+    }
+
+    pub fn input(&self, name: &str) -> Option<StreamSink<f64>> {
+        self.inputs.get(name).cloned()
+    }
+
+    pub fn stream(&mut self, name: &str) -> Result<Stream<f64>,String> {
+        if let Some(Output::Stream(output)) = self.outputs.get(name) {
+            return Ok(Cell::switch_s(output));
+        }
+        let sa = self.built.get(name).ok_or_else(|| format!("no node {}", name))?.stream(name)?;
+        let output = self.sodium_ctx.new_cell_sink(sa);
+        let sa = Cell::switch_s(&output);
+        self.outputs.insert(String::from(name), Output::Stream(output));
+        Ok(sa)
+    }
+
+    pub fn cell(&mut self, name: &str) -> Result<Cell<f64>,String> {
+        if let Some(Output::Cell(output)) = self.outputs.get(name) {
+            return Ok(Cell::switch_c(output));
+        }
+        let ca = self.built.get(name).ok_or_else(|| format!("no node {}", name))?.cell(name)?;
+        let output = self.sodium_ctx.new_cell_sink(ca);
+        let ca = Cell::switch_c(&output);
+        self.outputs.insert(String::from(name), Output::Cell(output));
+        Ok(ca)
+    }
+
+    // Rebuilds the graph from spec in one transaction. Hold and accum nodes whose definition
+    // hasn't changed start from the value they have now, everything else starts afresh. On
+    // an error, e.g. a node that was handed out going missing, the graph is left as it was.
+    pub fn patch(&mut self, spec: GraphSpec) -> Result<SpecDiff,String> {
+        let diff = self.spec.diff(&spec);
+        let sodium_ctx = self.sodium_ctx.clone();
+        let inputs: HashMap<String,StreamSink<f64>> = spec.nodes
+            .iter()
+            .filter(|&(_, node)| *node == NodeSpec::Input)
+            .map(|(name, _)| (name.clone(), self.inputs.get(name).cloned().unwrap_or_else(|| sodium_ctx.new_stream_sink())))
+            .collect();
+        let built = sodium_ctx.transaction(|_| self.build(&spec, &inputs))?;
+        for (name, output) in &self.outputs {
+            let fits = matches!(
+                (output, built.get(name)),
+                (Output::Stream(_), Some(Built::Stream(_))) | (Output::Cell(_), Some(Built::Cell(_)))
+            );
+            if !fits {
+                return Err(format!("{} is in use and is missing or changed kind", name));
+            }
+        }
+        sodium_ctx.transaction(|_| {
+            for (name, output) in &self.outputs {
+                match (output, &built[name]) {
+                    (Output::Stream(output), Built::Stream(sa)) => output.send(sa),
+                    (Output::Cell(output), Built::Cell(ca)) => output.send(ca),
+                    _ => {}
+                }
+            }
+        });
+        self.inputs = inputs;
+        self.built = built;
+        self.spec = spec;
+        Ok(diff)
+    }
+
+    fn build(&self, spec: &GraphSpec, inputs: &HashMap<String,StreamSink<f64>>) -> Result<HashMap<String,Built>,String> {
+        let mut built: HashMap<String,Built> = HashMap::new();
+        for (name, node) in &spec.nodes {
+            let get = |name2: &String| built.get(name2).cloned().ok_or_else(|| format!("{} refers to {}, which isn't defined before it", name, name2));
+            // State carried over when the node's definition is the same as before.
+            let kept_op =
+                if self.spec.get(name) == Some(node) {
+                    match self.built.get(name) {
+                        Some(Built::Cell(ca)) => Some(ca.sample()),
+                        _ => None
+                    }
+                } else {
+                    None
+                };
+            let b =
+                match *node {
+                    NodeSpec::Input => Built::Stream(inputs[name].to_stream()),
+                    NodeSpec::Constant(value) => Built::Cell(self.sodium_ctx.new_cell(value)),
+                    NodeSpec::Map { ref f, ref input } => {
+                        let f = self.functions.get_unary(f)?;
+                        match get(input)? {
+                            Built::Stream(sa) => Built::Stream(sa.map(move |a: &f64| f(*a))),
+                            Built::Cell(ca) => Built::Cell(ca.map(move |a: &f64| f(*a)))
+                        }
+                    },
+                    NodeSpec::Filter { ref pred, ref input } => {
+                        let pred = self.functions.get_predicate(pred)?;
+                        Built::Stream(get(input)?.stream(input)?.filter(move |a: &f64| pred(*a)))
+                    },
+                    NodeSpec::Merge { ref f, ref left, ref right } => {
+                        let f = self.functions.get_binary(f)?;
+                        let right = get(right)?.stream(right)?;
+                        Built::Stream(get(left)?.stream(left)?.merge(&right, move |a: &f64, b: &f64| f(*a, *b)))
+                    },
+                    NodeSpec::Hold { init, ref input } => {
+                        Built::Cell(get(input)?.stream(input)?.hold(kept_op.unwrap_or(init)))
+                    },
+                    NodeSpec::Accum { init, ref f, ref input } => {
+                        let f = self.functions.get_binary(f)?;
+                        Built::Cell(get(input)?.stream(input)?.accum(kept_op.unwrap_or(init), move |a: &f64, s: &f64| f(*a, *s)))
+                    },
+                    NodeSpec::Snapshot { ref f, ref stream, ref cell } => {
+                        let f = self.functions.get_binary(f)?;
+                        let cell = get(cell)?.cell(cell)?;
+                        Built::Stream(get(stream)?.stream(stream)?.snapshot2(&cell, move |a: &f64, b: &f64| f(*a, *b)))
+                    },
+                    NodeSpec::Lift { ref f, ref left, ref right } => {
+                        let f = self.functions.get_binary(f)?;
+                        let right = get(right)?.cell(right)?;
+                        Built::Cell(get(left)?.cell(left)?.lift2(&right, move |a: &f64, b: &f64| f(*a, *b)))
+                    }
+                };
+            built.insert(name.clone(), b);
+        }
+        Ok(built)
+    }
+}
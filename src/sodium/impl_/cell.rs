@@ -1,3 +1,5 @@
+#[cfg(feature = "debug-history")]
+use sodium::impl_::CellHistory;
 use sodium::impl_::Dep;
 use sodium::impl_::Lambda;
 use sodium::impl_::IsLambda0;
@@ -15,21 +17,37 @@ use sodium::impl_::Operational;
 use sodium::impl_::SodiumCtx;
 use sodium::impl_::Stream;
 use sodium::impl_::StreamData;
+#[cfg(feature = "debug-history")]
+use sodium::impl_::TxId;
 use sodium::gc::Finalize;
 use sodium::gc::Gc;
 use sodium::gc::GcDep;
+use sodium::gc::GcWeak;
 use sodium::gc::Trace;
+use std::cell::RefCell;
 use std::cell::UnsafeCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::hash::Hash;
 use std::rc::Rc;
 
 pub struct Cell<A> {
     pub data: Gc<UnsafeCell<CellData<A>>>
 }
 
+pub struct WeakCell<A> {
+    data: GcWeak<UnsafeCell<CellData<A>>>
+}
+
 pub struct CellData<A> {
     pub value: Gc<UnsafeCell<MemoLazy<A>>>,
     pub next_value: Gc<UnsafeCell<MemoLazy<A>>>,
-    pub node: Node
+    pub node: Node,
+    // Set on cells made by Cell::new, which never change, for SodiumCtx::set_optimize_graph.
+    pub constant: bool,
+    #[cfg(feature = "debug-history")]
+    pub history: Gc<UnsafeCell<CellHistory<A>>>
 }
 
 impl<A: Trace> Trace for CellData<A> {
@@ -37,6 +55,8 @@ impl<A: Trace> Trace for CellData<A> {
         self.value.trace(f);
         self.next_value.trace(f);
         self.node.trace(f);
+        #[cfg(feature = "debug-history")]
+        self.history.trace(f);
     }
 }
 
@@ -45,19 +65,35 @@ impl<A: Finalize> Finalize for CellData<A> {
         self.value.finalize();
         self.next_value.finalize();
         self.node.finalize();
+        #[cfg(feature = "debug-history")]
+        self.history.finalize();
     }
 }
 
 impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
     pub fn new(sodium_ctx: &SodiumCtx, value: A) -> Cell<A> {
-        Cell::_new(
+        Cell::_new_constant(sodium_ctx, sodium_ctx.new_lazy(move || value.clone()), "Cell::new")
+    }
+
+    fn _new_constant(sodium_ctx: &SodiumCtx, value: MemoLazy<A>, desc: &'static str) -> Cell<A> {
+        let cell = Cell::_new(
             sodium_ctx,
-            sodium_ctx.new_lazy(move || value.clone()),
+            value,
             || None,
             Vec::new(),
             || {},
-            "Cell::new"
-        )
+            desc
+        );
+        {
+            let data = unsafe { &mut *(*cell.data).get() };
+            data.constant = true;
+        }
+        cell
+    }
+
+    pub fn _is_constant(&self) -> bool {
+        let data = unsafe { &*(*self.data).get() };
+        data.constant
     }
 
     pub fn new_lazy(sodium_ctx: &SodiumCtx, value: MemoLazy<A>) -> Cell<A> {
@@ -79,15 +115,22 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
         cleanup: CLEANUP,
         desc: &'static str
     ) -> Cell<A> {
-        let mut gc_ctx = sodium_ctx.gc_ctx();
+        let gc_ctx = sodium_ctx.gc_ctx();
         let value = gc_ctx.new_gc_with_desc(UnsafeCell::new(init_value.clone()), String::from(desc) + "_value");
         let next_value = gc_ctx.new_gc_with_desc(UnsafeCell::new(init_value), String::from(desc) + "_next_value");
+        #[cfg(feature = "debug-history")]
+        let history = gc_ctx.new_gc_with_desc(UnsafeCell::new(CellHistory::new()), String::from(desc) + "_history");
+        #[cfg(feature = "debug-history")]
+        let history2 = history.clone();
         let update_deps = update.deps();
         let sodium_ctx2 = sodium_ctx.clone();
         Cell {
             data: gc_ctx.new_gc_with_desc(UnsafeCell::new(CellData {
                 value: value.clone(),
                 next_value: next_value.clone(),
+                constant: false,
+                #[cfg(feature = "debug-history")]
+                history: history2,
                 node: Node::new(
                     sodium_ctx,
                     move || {
@@ -100,10 +143,19 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
                             *next_value2 = val;
                             let value = value.clone();
                             let next_value = next_value.clone();
+                            #[cfg(feature = "debug-history")]
+                            let history = history.clone();
+                            #[cfg(feature = "debug-history")]
+                            let tx_id = sodium_ctx.current_tx_id();
                             sodium_ctx.post(move || {
                                 let value = unsafe { &mut *(*value).get() };
                                 let next_value = unsafe { &mut *(*next_value).get() };
                                 *value = next_value.clone();
+                                #[cfg(feature = "debug-history")]
+                                {
+                                    let history = unsafe { &mut *(*history).get() };
+                                    history.record(tx_id, value.clone());
+                                }
                             });
                         }
                         val_op_is_some
@@ -141,6 +193,11 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
         thunk.get().clone()
     }
 
+    pub fn _value_thunk(&self) -> MemoLazy<A> {
+        let thunk = unsafe { &*(self._value()).get() };
+        thunk.clone()
+    }
+
     pub fn _next_value_thunk(&self) -> MemoLazy<A> {
         let thunk_op = unsafe { &*(self._next_value()).get() };
         thunk_op.clone()
@@ -168,6 +225,10 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
                 f.apply(&self_.sample_no_trans())
             });
         }
+        if sodium_ctx.optimize_graph() && self._is_constant() && f.deps().is_empty() {
+            sodium_ctx.record_folded_cell();
+            return Cell::_new_constant(sodium_ctx, init_value, "Cell::map");
+        }
         let node_deps = vec![self_._node().clone()];
         let sodium_ctx2 = sodium_ctx.clone();
         let mut update_deps = f.deps();
@@ -207,6 +268,10 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
                 f.apply(&ca.sample_no_trans(), &cb.sample_no_trans())
             })
         }
+        if sodium_ctx.optimize_graph() && ca._is_constant() && cb._is_constant() && update_deps.is_empty() {
+            sodium_ctx.record_folded_cell();
+            return Cell::_new_constant(sodium_ctx, init_value, "Cell::lift2");
+        }
         let sodium_ctx2 = sodium_ctx.clone();
         let update = Lambda::new(
             move || {
@@ -312,6 +377,60 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
             )
     }
 
+    pub fn lift_all<B: Clone + Trace + Finalize + 'static, F: Fn(&[A])->B + 'static>(cells: &[Cell<A>], f: F) -> Cell<B> {
+        let sodium_ctx = cells.first().expect("Cell::lift_all needs at least one cell")._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let cells: Rc<Vec<Cell<A>>> = Rc::new(cells.to_vec());
+        let node_deps = cells.iter().map(|cell| cell._node().clone()).collect();
+        let update_deps = cells.iter().map(|cell| cell.to_dep()).collect();
+        // The argument buffer is reused between recomputes and emptied afterwards so it never
+        // holds values the GC can not see.
+        let buffer: Rc<RefCell<Vec<A>>> = Rc::new(RefCell::new(Vec::with_capacity(cells.len())));
+        let f = Rc::new(move |values: &mut dyn Iterator<Item=A>| {
+            let result_op = buffer.try_borrow_mut().ok().map(|mut buffer| {
+                buffer.extend(&mut *values);
+                let result = f(&buffer);
+                buffer.clear();
+                result
+            });
+            match result_op {
+                Some(result) => result,
+                None => {
+                    let values: Vec<A> = values.collect();
+                    f(&values)
+                }
+            }
+        });
+        let init_value;
+        {
+            let f = f.clone();
+            let cells = cells.clone();
+            init_value = sodium_ctx.new_lazy(move || {
+                f(&mut cells.iter().map(|cell| cell.sample_no_trans()))
+            })
+        }
+        let sodium_ctx2 = sodium_ctx.clone();
+        let update = Lambda::new(
+            move || {
+                let sodium_ctx = &sodium_ctx2;
+                let thunks: Vec<MemoLazy<A>> = cells.iter().map(|cell| cell._next_value_thunk()).collect();
+                let f = f.clone();
+                Some(sodium_ctx.new_lazy(move || {
+                    f(&mut thunks.iter().map(|thunk| thunk.get().clone()))
+                }))
+            },
+            update_deps
+        );
+        Cell::_new(
+            sodium_ctx,
+            init_value,
+            update,
+            node_deps,
+            || {},
+            "Cell::lift_all"
+        )
+    }
+
     pub fn switch_s(csa: Cell<Stream<A>>) -> Stream<A> {
         let sodium_ctx = csa._node().sodium_ctx();
         let sodium_ctx = &sodium_ctx;
@@ -355,7 +474,9 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
         let result = Stream {
             data: gc_ctx.new_gc_with_desc(UnsafeCell::new(StreamData {
                 value: value.clone(),
-                node: node2.clone()
+                node: node2.clone(),
+                replay_op: None,
+                stage_op: None
             }), String::from("Cell::switch_s"))
         };
         let node1_deps = vec![csa._node().clone()];
@@ -406,10 +527,127 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
             .hold(cca.sample_no_trans().sample_no_trans())
     }
 
+    pub fn switch_vec(cca: Cell<Vec<Cell<A>>>) -> Cell<Vec<A>> {
+        let sodium_ctx = cca._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let cas_init = cca.sample_no_trans();
+        let init_value;
+        {
+            let cas_init = cas_init.clone();
+            init_value = sodium_ctx.new_lazy(move || {
+                cas_init.iter().map(|ca| ca.sample_no_trans()).collect()
+            });
+        }
+        let mut node_deps = vec![cca._node().clone()];
+        let inner_ids: Rc<UnsafeCell<HashSet<u32>>> = Rc::new(UnsafeCell::new(HashSet::new()));
+        for ca in &cas_init {
+            let inner_ids = unsafe { &mut *(*inner_ids).get() };
+            if inner_ids.insert(ca._node().id()) {
+                node_deps.push(ca._node().clone());
+            }
+        }
+        let sodium_ctx2 = sodium_ctx.clone();
+        let update;
+        {
+            let update_deps = vec![cca.to_dep()];
+            let cca = cca.clone();
+            update = Lambda::new(
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    let thunks: Vec<MemoLazy<A>> =
+                        cca._next_value_thunk().get().iter().map(|ca| ca._next_value_thunk()).collect();
+                    Some(sodium_ctx.new_lazy(move || thunks.iter().map(|thunk| thunk.get().clone()).collect()))
+                },
+                update_deps
+            );
+        }
+        let result = Cell::_new(
+            sodium_ctx,
+            init_value,
+            update,
+            node_deps,
+            || {},
+            "Cell::switch_vec"
+        );
+        let result_node = result._node().clone();
+        let node1;
+        {
+            let sodium_ctx2 = sodium_ctx.clone();
+            let node1_update_deps = vec![cca.to_dep(), result_node.to_dep()];
+            let node1_deps = vec![cca._node().clone()];
+            let cca = cca.clone();
+            let result_node = result_node.clone();
+            node1 = Node::new(
+                sodium_ctx,
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    let cca = cca.clone();
+                    let result_node = result_node.clone();
+                    let inner_ids = inner_ids.clone();
+                    sodium_ctx.post(move || {
+                        let inner_ids = unsafe { &mut *(*inner_ids).get() };
+                        let cas = cca.sample_no_trans();
+                        let mut new_ids = HashSet::new();
+                        for ca in &cas {
+                            let node = ca._node();
+                            if new_ids.insert(node.id()) && !inner_ids.contains(&node.id()) {
+                                result_node.ensure_bigger_than(node.rank());
+                                result_node.add_dependencies(vec![node.clone()]);
+                            }
+                        }
+                        let removed: Vec<u32> = inner_ids.difference(&new_ids).cloned().collect();
+                        if !removed.is_empty() {
+                            let old_deps: Vec<Node> = result_node.dependencies().into_iter().filter(|node| removed.contains(&node.id())).collect();
+                            for node in old_deps {
+                                result_node.remove_dependency(&node);
+                            }
+                        }
+                        *inner_ids = new_ids;
+                    });
+                    false
+                },
+                node1_update_deps,
+                node1_deps,
+                || {},
+                String::from("Cell::switch_vec_node1")
+            );
+        }
+        result_node.ensure_bigger_than(node1.rank());
+        result_node.add_dependencies(vec![node1]);
+        result
+    }
+
     pub fn add_cleanup<CLEANUP:IsLambdaMut0<()>+'static>(&self, cleanup: CLEANUP) {
         self._node().add_cleanup(cleanup);
     }
 
+    pub fn set_name(&self, name: &str) {
+        self._node().set_name(name);
+    }
+
+    #[cfg(feature = "debug-history")]
+    pub fn record_history(&self, n: usize) {
+        let data = unsafe { &*(*self.data).get() };
+        let history = unsafe { &mut *(*data.history).get() };
+        let was_recording = history.is_recording();
+        history.set_capacity(n);
+        if !was_recording {
+            let value = unsafe { &*(*data.value).get() };
+            history.record(self._node().sodium_ctx().current_tx_id(), value.clone());
+        }
+    }
+
+    #[cfg(feature = "debug-history")]
+    pub fn history(&self) -> Vec<(TxId,A)> {
+        let data = unsafe { &*(*self.data).get() };
+        let history = unsafe { &*(*data.history).get() };
+        history.entries()
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self._node().name()
+    }
+
     pub fn listen<CALLBACK:FnMut(&A)+'static>(
         &self,
         callback: CALLBACK
@@ -432,23 +670,24 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
         let sodium_ctx = self._node().sodium_ctx();
         let sodium_ctx = &sodium_ctx;
         let callback = Rc::new(UnsafeCell::new(callback));
+        let key = sodium_ctx.new_id();
         let self_ = self.clone();
         {
             let self_ = self_.clone();
             let callback = callback.clone();
+            let sodium_ctx2 = sodium_ctx.clone();
             sodium_ctx.pre(move || {
-                let callback = unsafe { &mut *(*callback).get() };
                 let val = self_.sample_no_trans();
-                (*callback)(&val);
+                sodium_ctx2.deliver(key, self_._node().name(), &callback, &val);
             });
         }
+        let sodium_ctx2 = sodium_ctx.clone();
         Listener::new(Node::new(
             sodium_ctx,
             move || {
-                let callback = unsafe { &mut *(*callback).get() };
                 let thunk = self_._next_value_thunk();
                 let val = thunk.get();
-                (*callback)(val);
+                sodium_ctx2.deliver(key, self_._node().name(), &callback, val);
                 return true;
             },
             Vec::new(),
@@ -459,7 +698,146 @@ impl<A: Clone + Trace + Finalize + 'static> Cell<A> {
     }
 }
 
-impl<A: Clone + 'static> Clone for Cell<A> {
+impl<A: Clone + PartialEq + Trace + Finalize + 'static> Cell<A> {
+    pub fn changes_to(&self, a: A) -> Stream<()> {
+        Operational::updates(self.clone())
+            .snapshot2(
+                self.clone(),
+                move |new_value: &A, old_value: &A| {
+                    if *new_value == a && *old_value != a {
+                        Some(())
+                    } else {
+                        None
+                    }
+                }
+            )
+            .filter_option()
+    }
+}
+
+impl<A: Clone + Eq + Hash + Trace + Finalize + 'static> Cell<A> {
+    pub fn map_memo<B: Clone + Trace + Finalize + 'static, F: Fn(&A)->B + 'static>(&self, f: F, capacity: usize) -> Cell<B> {
+        if capacity == 0 {
+            panic!("Cell::map_memo requires a non-zero capacity.");
+        }
+        let cache: RefCell<(HashMap<A,B>,VecDeque<A>)> = RefCell::new((HashMap::new(), VecDeque::new()));
+        self.map(move |a: &A| {
+            let mut cache = cache.borrow_mut();
+            let &mut (ref mut results, ref mut recent) = &mut *cache;
+            if let Some(b) = results.get(a) {
+                recent.retain(|a2| a2 != a);
+                recent.push_back(a.clone());
+                return b.clone();
+            }
+            let b = f(a);
+            if recent.len() == capacity {
+                if let Some(oldest) = recent.pop_front() {
+                    results.remove(&oldest);
+                }
+            }
+            results.insert(a.clone(), b.clone());
+            recent.push_back(a.clone());
+            b
+        })
+    }
+}
+
+impl Cell<bool> {
+    pub fn when_true(&self) -> Stream<()> {
+        self.changes_to(true)
+    }
+
+    pub fn when_false(&self) -> Stream<()> {
+        self.changes_to(false)
+    }
+
+    pub fn not(&self) -> Cell<bool> {
+        self.map(|a: &bool| !*a)
+    }
+
+    pub fn and(&self, other: Cell<bool>) -> Cell<bool> {
+        Cell::all(&[self.clone(), other])
+    }
+
+    pub fn or(&self, other: Cell<bool>) -> Cell<bool> {
+        Cell::any(&[self.clone(), other])
+    }
+
+    pub fn all(cells: &[Cell<bool>]) -> Cell<bool> {
+        Cell::count_true(cells, |trues, n| trues == n, "Cell::all")
+    }
+
+    pub fn any(cells: &[Cell<bool>]) -> Cell<bool> {
+        Cell::count_true(cells, |trues, _| trues > 0, "Cell::any")
+    }
+
+    // Keeps how many of the cells are true, so a transaction only looks at the inputs that
+    // changed in it, and only updates when decide gives a different answer.
+    fn count_true(cells: &[Cell<bool>], decide: fn(usize,usize) -> bool, desc: &'static str) -> Cell<bool> {
+        let n = cells.len();
+        if n == 0 {
+            panic!("{} needs at least one cell", desc);
+        }
+        let sodium_ctx = cells[0]._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let cells: Vec<Cell<bool>> = cells.to_vec();
+        let node_deps = cells.iter().map(|cell| cell._node().clone()).collect();
+        let update_deps = cells.iter().map(|cell| cell.to_dep()).collect();
+        let init_value;
+        {
+            let cells = cells.clone();
+            init_value = sodium_ctx.new_lazy(move || {
+                let trues = cells.iter().filter(|cell| cell.sample_no_trans()).count();
+                decide(trues, n)
+            });
+        }
+        // The inputs last seen, how many of them were true and what that decided. Filled in
+        // from the settled values the first time anything changes.
+        let state: RefCell<Option<(Vec<bool>,usize,bool)>> = RefCell::new(None);
+        let sodium_ctx2 = sodium_ctx.clone();
+        let update = Lambda::new(
+            move || {
+                let sodium_ctx = &sodium_ctx2;
+                let mut state = state.borrow_mut();
+                if state.is_none() {
+                    let last: Vec<bool> = cells.iter().map(|cell| cell.sample_no_trans()).collect();
+                    let trues = last.iter().filter(|a| **a).count();
+                    *state = Some((last, trues, decide(trues, n)));
+                }
+                let &mut (ref mut last, ref mut trues, ref mut result) = state.as_mut().unwrap();
+                for (i, cell) in cells.iter().enumerate() {
+                    let value = unsafe { &*(*cell._value()).get() };
+                    let next_value = unsafe { &*(*cell._next_value()).get() };
+                    if next_value.same(value) {
+                        continue;
+                    }
+                    let a = *next_value.get();
+                    if a != last[i] {
+                        last[i] = a;
+                        if a { *trues = *trues + 1; } else { *trues = *trues - 1; }
+                    }
+                }
+                let next_result = decide(*trues, n);
+                if next_result == *result {
+                    return None;
+                }
+                *result = next_result;
+                Some(sodium_ctx.new_lazy(move || next_result))
+            },
+            update_deps
+        );
+        Cell::_new(
+            sodium_ctx,
+            init_value,
+            update,
+            node_deps,
+            || {},
+            desc
+        )
+    }
+}
+
+impl<A> Clone for Cell<A> {
     fn clone(&self) -> Self {
         Cell {
             data: self.data.clone()
@@ -467,6 +845,20 @@ impl<A: Clone + 'static> Clone for Cell<A> {
     }
 }
 
+impl<A> Cell<A> {
+    pub fn downgrade(&self) -> WeakCell<A> {
+        WeakCell {
+            data: self.data.downgrade()
+        }
+    }
+}
+
+impl<A> WeakCell<A> {
+    pub fn upgrade(&self) -> Option<Cell<A>> {
+        self.data.upgrade().map(|data| Cell { data })
+    }
+}
+
 impl<A: Trace> Trace for Cell<A> {
     fn trace(&self, f: &mut FnMut(&GcDep)) {
         self.data.trace(f);
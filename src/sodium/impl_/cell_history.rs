@@ -0,0 +1,59 @@
+use sodium::impl_::MemoLazy;
+use sodium::impl_::TxId;
+use sodium::gc::Finalize;
+use sodium::gc::GcDep;
+use sodium::gc::Trace;
+use std::collections::VecDeque;
+
+pub struct CellHistory<A> {
+    capacity: usize,
+    entries: VecDeque<(TxId,MemoLazy<A>)>
+}
+
+impl<A: Trace> Trace for CellHistory<A> {
+    fn trace(&self, f: &mut dyn FnMut(&GcDep)) {
+        for (_, value) in &self.entries {
+            value.trace(f);
+        }
+    }
+}
+
+impl<A: Finalize> Finalize for CellHistory<A> {
+    fn finalize(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> CellHistory<A> {
+    pub fn new() -> CellHistory<A> {
+        CellHistory {
+            capacity: 0,
+            entries: VecDeque::new()
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn record(&mut self, tx_id: TxId, value: MemoLazy<A>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((tx_id, value));
+    }
+
+    pub fn entries(&self) -> Vec<(TxId,A)> {
+        self.entries.iter().map(|&(tx_id, ref value)| (tx_id, value.get().clone())).collect()
+    }
+}
@@ -2,6 +2,7 @@ use sodium::impl_::Dep;
 use sodium::impl_::MemoLazy;
 use sodium::impl_::Node;
 use sodium::impl_::SodiumCtx;
+use sodium::impl_::SodiumError;
 use sodium::impl_::Cell;
 use sodium::impl_::gc::Finalize;
 use sodium::impl_::gc::Gc;
@@ -41,7 +42,8 @@ impl<A: Trace + Finalize + Clone + 'static> CellLoop<A> {
     pub fn loop_(&self, ca: Cell<A>) {
         let init_value = unsafe { &mut *(*self.init_value).get() };
         if init_value.is_some() {
-            panic!("CellLoop looped more than once.");
+            self.cell._node().sodium_ctx().fail(SodiumError::LoopedTwice(self.cell._node().describe("CellLoop")));
+            return;
         }
         *init_value = Some(ca.sample_no_trans());
         let value = self.cell._value().clone();
@@ -3,6 +3,7 @@ use sodium::impl_::Dep;
 use sodium::impl_::Lambda;
 use sodium::impl_::MemoLazy;
 use sodium::impl_::SodiumCtx;
+use sodium::impl_::SodiumError;
 use sodium::gc::Finalize;
 use sodium::gc::Gc;
 use sodium::gc::Trace;
@@ -15,7 +16,7 @@ pub struct CellSink<A> {
 
 impl<A: Trace + Finalize + Clone + 'static> CellSink<A> {
     pub fn new(sodium_ctx: &SodiumCtx, value: A) -> CellSink<A> {
-        let mut gc_ctx = sodium_ctx.gc_ctx();
+        let gc_ctx = sodium_ctx.gc_ctx();
         let next_value_op = gc_ctx.new_gc_with_desc(UnsafeCell::new(None), String::from("CellSink::new_next_value"));
         let deps = vec![Dep { gc_dep: next_value_op.to_dep() }];
         CellSink {
@@ -40,7 +41,13 @@ impl<A: Trace + Finalize + Clone + 'static> CellSink<A> {
     pub fn send(&self, value: A) {
         let sodium_ctx = self.cell._node().sodium_ctx();
         if sodium_ctx.callback_depth() > 0 {
-            panic!("StreamSink::send / CellSink::send can not be called from a sodium callback, consider using SodiumCtx::post to send after the end of transaction.")
+            sodium_ctx.fail(SodiumError::SendFromCallback(self.cell._node().describe("CellSink")));
+            return;
+        }
+        if sodium_ctx.is_suspended() {
+            let self_ = self.clone();
+            sodium_ctx.buffer_send(move || self_.send(value.clone()));
+            return;
         }
         sodium_ctx.transaction(|| {
             let next_value_op = unsafe { &mut *(*self.next_value_op).get() };
@@ -3,9 +3,17 @@
  * DAVID F. BACON, CLEMENT R. ATTANASIO, V.T. RAJAN, STEPHEN E. SMITH
  */
 
+use std::any::TypeId;
+use std::any::type_name;
+use std::alloc::Layout;
+use std::alloc::alloc;
 use std::ptr;
 use std::ops::Deref;
 use std::ops::DerefMut;
+use std::pin::Pin;
+use std::marker::PhantomData;
+use std::mem::forget;
+use std::mem::size_of;
 use std::mem::transmute;
 use std::mem::swap;
 use std::cell::Cell;
@@ -13,6 +21,9 @@ use std::cell::RefCell;
 use std::cell::UnsafeCell;
 use std::rc::Rc;
 use std::hash::Hash;
+use std::io;
+use std::io::Write;
+use std::time::Duration;
 use std::collections::{BinaryHeap, BTreeMap, BTreeSet, HashMap, HashSet, LinkedList, VecDeque};
 
 pub struct GcCtx {
@@ -30,7 +41,103 @@ impl Clone for GcCtx {
 struct GcCtxData {
     roots: Vec<*mut Node>,
     collecting_cycles: bool,
-    to_be_freed: Vec<*mut Node>
+    to_be_freed: Vec<*mut Node>,
+    live: HashSet<*mut Node>,
+    // Only filled in while track_ids is set, see set_track_ids.
+    by_id: HashMap<GcNodeId,*mut Node>,
+    track_ids: bool,
+    next_id: u64,
+    // Scratch space for collect_cycles, kept between collections so they do not allocate.
+    spare_nodes: Vec<*mut Node>,
+    spare_white: HashSet<*mut Node>,
+    // Bytes held by live values and their headers, checked against memory_limit_op.
+    memory_in_use: usize,
+    memory_limit_op: Option<usize>,
+    fallible_depth: u32,
+    over_budget_op: Option<OomError>,
+    oom_handler_op: Option<Rc<dyn Fn(&OomEvent)>>,
+    // Handles dropped while the collector was running, by finalizers or by the values it
+    // frees. Each holds a weak count on its node until collect_cycles gets to it.
+    deferred: Vec<*mut Node>,
+    policy: GcPolicy,
+    // Root buffer entries pushed, removed or walked past, see root_buffer_steps.
+    root_buffer_steps: u64
+}
+
+impl GcCtxData {
+    fn push_root(&mut self, s: *mut Node) {
+        unsafe { (*s).set_root_index(self.roots.len()) };
+        self.roots.push(s);
+        self.root_buffer_steps += 1;
+    }
+
+    // O(1) by swapping the last root into the removed one's place.
+    fn remove_root(&mut self, s: *mut Node) {
+        let index = unsafe { (*s).root_index() };
+        if index < self.roots.len() && ptr::eq(self.roots[index], s) {
+            self.roots.swap_remove(index);
+            self.root_buffer_steps += 1;
+            if index < self.roots.len() {
+                let moved = self.roots[index];
+                unsafe { (*moved).set_root_index(index) };
+            }
+        } else if index == NO_ROOT_INDEX && unsafe { (*s).buffered() } {
+            self.root_buffer_steps += 2 * self.roots.len() as u64;
+            self.roots.retain(|n| !ptr::eq(*n, s));
+            for (index, n) in self.roots.iter().enumerate() {
+                unsafe { (**n).set_root_index(index) };
+            }
+        }
+    }
+}
+
+// When dropping a handle sets off a search for garbage cycles. Values nothing refers to any
+// more are freed straight away either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GcPolicy {
+    // On every drop that could have left a cycle behind.
+    Eager,
+    // Once this many possible cycle roots have built up, or on an explicit collect_cycles.
+    // Garbage cycles live longer, but drops are cheaper.
+    Deferred(usize)
+}
+
+// Identifies one allocation for as long as the context lives, ids are never reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GcNodeId(u64);
+
+// Why an allocation could not be made. limit_op is None when it was the system allocator
+// that refused rather than the budget set with GcCtx::set_memory_limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OomError {
+    pub requested: usize,
+    pub in_use: usize,
+    pub limit_op: Option<usize>,
+    pub type_name: &'static str
+}
+
+// Handed to the oom handler for every allocation over budget. refused is false when the
+// allocation went ahead anyway, because the code asking for it had no way to fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OomEvent {
+    pub error: OomError,
+    pub refused: bool
+}
+
+pub struct LeakedNode {
+    pub type_name: &'static str,
+    pub desc_op: Option<String>,
+    pub strong: i32
+}
+
+pub struct LeakReport {
+    pub leaks: Vec<LeakedNode>
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.leaks.is_empty()
+    }
 }
 
 pub struct GcDep {
@@ -56,6 +163,10 @@ impl Drop for GcDep {
     }
 }
 
+// The value is boxed on its own when allocated and stays at that address until it is
+// dropped in place, whether by the last handle going or by the cycle collector, which
+// calls finalize() on it first. The only way to move it out is try_unwrap, which needs an
+// unpinned handle, so a value from Gc::pin really is pinned and may point into itself.
 pub struct Gc<A: ?Sized> {
     ctx: GcCtx,
     value: *mut A,
@@ -65,6 +176,7 @@ pub struct Gc<A: ?Sized> {
 impl<A: ?Sized> Clone for Gc<A> {
     fn clone(&self) -> Self {
         self.ctx.increment(self.node);
+        unsafe { &mut *self.node }.add_handle();
         Gc {
             ctx: self.ctx.clone(),
             value: self.value,
@@ -75,8 +187,19 @@ impl<A: ?Sized> Clone for Gc<A> {
 
 impl<A: ?Sized> Drop for Gc<A> {
     fn drop(&mut self) {
-        self.ctx.decrement(self.node);
-        self.ctx.collect_cycles();
+        if !self.ctx.defer_decrement(self.node) {
+            self.ctx.decrement(self.node);
+            self.ctx.collect_cycles_if_due();
+        }
+        // Debug builds keep the node allocated while handles to it exist, see Node::handles.
+        #[cfg(debug_assertions)]
+        {
+            let node = unsafe { &mut *self.node };
+            node.handles = node.handles - 1;
+            if node.freed() && node.unreferenced() {
+                unsafe { drop(Box::from_raw(self.node)); }
+            }
+        }
     }
 }
 
@@ -84,6 +207,16 @@ impl<A: ?Sized> Deref for Gc<A> {
     type Target = A;
 
     fn deref(&self) -> &A {
+        #[cfg(debug_assertions)]
+        {
+            let node = unsafe { &*self.node };
+            if node.freed() {
+                panic!(
+                    "{} was used after the cycle collector freed it, check the Trace impls of the objects pointing to it",
+                    node.desc_op.as_ref().map(|desc| desc.to_string()).unwrap_or_else(|| format!("Gc<{}>", node.type_name()))
+                );
+            }
+        }
         unsafe { &*self.value }
     }
 }
@@ -108,6 +241,17 @@ impl<A: ?Sized> Gc<A> {
         node.strong
     }
 
+    pub fn id(&self) -> GcNodeId {
+        let node = unsafe { &*self.node };
+        node.id
+    }
+
+    // As given to new_gc_with_desc.
+    pub fn desc(&self) -> Option<&str> {
+        let node = unsafe { &*self.node };
+        node.desc_op.as_ref().map(|desc| &**desc)
+    }
+
     pub fn weak_count(&self) -> i32 {
         let node = unsafe { &*self.node };
         node.weak
@@ -123,9 +267,47 @@ impl<A: ?Sized> Gc<A> {
         }
     }
 
+    // For values that must not move, e.g. futures or anything self-referential. Only shared
+    // references can be had from the handle, so anything that needs Pin<&mut A>, like
+    // polling a future, goes through a cell inside A with the usual unsafe projection.
+    // finalize() must not move out of a pinned value either.
+    pub fn pin(gc_ctx: &GcCtx, value: A) -> Pin<Gc<A>> where A: Sized + Trace + Finalize + 'static {
+        // No unpinned handle to the new value ever exists, and see the note on Gc for why
+        // the value does not move.
+        unsafe { Pin::new_unchecked(gc_ctx.new_gc(value)) }
+    }
+
+    // Moves the value out when this is the only reference to it, strong or weak. Objects the
+    // value points to stay alive, they are now referenced from the returned value.
+    pub fn try_unwrap(self) -> Result<A,Gc<A>> where A: Sized + 'static {
+        let node = unsafe { &*self.node };
+        if node.strong != 1 || node.weak != 1 || node.dying() || node.value != self.value as *mut () || node.type_id() != TypeId::of::<A>() {
+            return Err(self);
+        }
+        let id = node.id;
+        let size = node.vtable.size;
+        let node = self.node;
+        let value = self.value;
+        let ctx = unsafe { ptr::read(&self.ctx) };
+        forget(self);
+        ctx.with_data(|data| {
+            data.remove_root(node);
+            data.live.remove(&node);
+            if !data.by_id.is_empty() {
+                data.by_id.remove(&id);
+            }
+            data.memory_in_use = data.memory_in_use - size;
+        });
+        unsafe {
+            drop(Box::from_raw(node));
+            Ok(*Box::from_raw(value))
+        }
+    }
+
     pub fn upcast<F,B:?Sized>(&self, f: F) -> Gc<B> where F: FnOnce(&A)->&B {
         let s = unsafe { &mut *self.node };
         s.strong = s.strong + 1;
+        s.add_handle();
         Gc {
             ctx: self.ctx.clone(),
             value: unsafe { transmute(f(&mut *self.value)) },
@@ -157,7 +339,7 @@ impl<A: ?Sized> Drop for GcWeak<A> {
         let node = unsafe { &mut *self.node };
         if node.weak > 0 {
             node.weak = node.weak - 1;
-            if node.weak == 0 {
+            if node.unreferenced() {
                 unsafe { Box::from_raw(node); }
             }
         }
@@ -165,12 +347,18 @@ impl<A: ?Sized> Drop for GcWeak<A> {
 }
 
 impl<A: ?Sized> GcWeak<A> {
+    pub fn id(&self) -> GcNodeId {
+        let node = unsafe { &*self.node };
+        node.id
+    }
+
     pub fn upgrade(&self) -> Option<Gc<A>> {
         let node = unsafe { &mut *self.node };
         if node.strong == 0 {
             None
         } else {
             node.strong = node.strong + 1;
+            node.add_handle();
             Some(
                 Gc {
                     ctx: self.ctx.clone(),
@@ -256,11 +444,23 @@ macro_rules! mk_empty_finalize_trace {
 }
 
 mk_empty_finalize_trace![(), isize, usize, bool, i8, u8, i16, u16, i32,
-    u32, i64, u64, f32, f64, char, String];
+    u32, i64, u64, f32, f64, char, String, Duration];
 
 #[cfg(feature = "nightly")]
 mk_empty_finalize_trace![i128, u128];
 
+impl Trace for OomError {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for OomError {}
+
+impl Trace for OomEvent {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for OomEvent {}
+
 impl<A: Trace> Trace for Gc<A> {
     fn trace(&self, f: &mut FnMut(&GcDep)) {
         f(&self.to_dep());
@@ -292,12 +492,40 @@ impl<A: Trace, E: Trace> Trace for Result<A,E> {
     }
 }
 
-impl<A: Trace> Trace for Box<A> {
+impl<A: Trace + ?Sized> Trace for Box<A> {
     fn trace(&self, tracer: &mut FnMut(&GcDep)) {
         (**self).trace(tracer);
     }
 }
 
+impl<A: Trace> Trace for [A] {
+    fn trace(&self, f: &mut dyn FnMut(&GcDep)) {
+        for a in self {
+            a.trace(f);
+        }
+    }
+}
+
+// A RefCell that is mutably borrowed while the collector runs is skipped. Missing edges only
+// make the collector keep things, so the worst case is a cycle that lives until the next
+// collection.
+impl<A: Trace> Trace for RefCell<A> {
+    fn trace(&self, f: &mut dyn FnMut(&GcDep)) {
+        if let Ok(a) = self.try_borrow() {
+            a.trace(f);
+        }
+    }
+}
+
+// Gc handles aren't Copy, so there is nothing to trace in a Cell.
+impl<A: Copy> Trace for Cell<A> {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl<A> Trace for PhantomData<A> {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
 impl<A: Trace> Trace for Vec<A> {
     fn trace(&self, f: &mut FnMut(&GcDep)) {
         for a in self {
@@ -306,6 +534,14 @@ impl<A: Trace> Trace for Vec<A> {
     }
 }
 
+impl<A: Trace, const N: usize> Trace for [A; N] {
+    fn trace(&self, f: &mut dyn FnMut(&GcDep)) {
+        for a in self {
+            a.trace(f);
+        }
+    }
+}
+
 impl <A: Ord + Trace> Trace for BinaryHeap<A> {
     fn trace(&self, tracer: &mut FnMut(&GcDep)) {
         for v in self.into_iter() {
@@ -457,12 +693,30 @@ impl<A: Finalize, E: Finalize> Finalize for Result<A,E> {
     }
 }
 
-impl<A: Finalize> Finalize for Box<A> {
+impl<A: Finalize + ?Sized> Finalize for Box<A> {
     fn finalize(&mut self) {
         (**self).finalize();
     }
 }
 
+impl<A: Finalize> Finalize for [A] {
+    fn finalize(&mut self) {
+        for a in self {
+            a.finalize();
+        }
+    }
+}
+
+impl<A: Finalize> Finalize for RefCell<A> {
+    fn finalize(&mut self) {
+        self.get_mut().finalize();
+    }
+}
+
+impl<A: Copy> Finalize for Cell<A> {}
+
+impl<A> Finalize for PhantomData<A> {}
+
 impl<A: Finalize> Finalize for Vec<A> {
     fn finalize(&mut self) {
         for a in self {
@@ -471,6 +725,14 @@ impl<A: Finalize> Finalize for Vec<A> {
     }
 }
 
+impl<A: Finalize, const N: usize> Finalize for [A; N] {
+    fn finalize(&mut self) {
+        for a in self {
+            a.finalize();
+        }
+    }
+}
+
 impl<A: Ord + Finalize> Finalize for BinaryHeap<A> {
     fn finalize(&mut self) {
         for mut a in self.drain() {
@@ -740,30 +1002,190 @@ impl<'a, T: ?Sized> Drop for GcCellRefMut<'a, T> {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum Colour {
-    Black,
-    Purple,
-    White,
-    Gray
+    Black = 0,
+    Purple = 1,
+    White = 2,
+    Gray = 3
+}
+
+// Node::flags holds the colour in its low two bits and these above it, and the node's position
+// in GcCtxData::roots in its top 24 bits while it is buffered.
+const COLOUR_MASK: u32 = 0b11;
+const BUFFERED: u32 = 0b100;
+const DYING: u32 = 0b1000;
+const FREED: u32 = 0b10000;
+const ROOT_INDEX_SHIFT: u32 = 8;
+// Stored for positions that do not fit, those nodes are searched for when they are removed.
+const NO_ROOT_INDEX: usize = (1 << (32 - ROOT_INDEX_SHIFT)) - 1;
+
+// Everything about a node that only depends on the type it was allocated with, shared by all
+// nodes of that type.
+struct NodeVTable {
+    type_id: fn() -> TypeId,
+    type_name: fn() -> &'static str,
+    trace: unsafe fn(*mut (), &mut FnMut(*mut Node)),
+    finalize: unsafe fn(*mut ()),
+    drop_value: unsafe fn(*mut ()),
+    // Of the value plus its header, what the allocation was charged against the memory limit.
+    size: usize
+}
+
+struct VTableOf<A>(PhantomData<A>);
+
+impl<A: Trace + Finalize + 'static> VTableOf<A> {
+    const VTABLE: NodeVTable = NodeVTable {
+        type_id: TypeId::of::<A>,
+        type_name: type_name::<A>,
+        trace: VTableOf::<A>::trace,
+        finalize: VTableOf::<A>::finalize,
+        drop_value: VTableOf::<A>::drop_value,
+        size: size_of::<A>() + size_of::<Node>()
+    };
+
+    unsafe fn trace(value: *mut (), f: &mut FnMut(*mut Node)) {
+        (&*(value as *mut A)).trace(&mut |dep: &GcDep| f(dep.node))
+    }
+
+    unsafe fn finalize(value: *mut ()) {
+        (&mut *(value as *mut A)).finalize()
+    }
+
+    unsafe fn drop_value(value: *mut ()) {
+        drop(Box::from_raw(value as *mut A));
+    }
+}
+
+// Box::new, but handing the value back instead of aborting when the allocator has nothing.
+fn try_box<T>(t: T) -> Result<*mut T,T> {
+    let layout = Layout::new::<T>();
+    if layout.size() == 0 {
+        return Ok(Box::into_raw(Box::new(t)));
+    }
+    let p = unsafe { alloc(layout) } as *mut T;
+    if p.is_null() {
+        return Err(t);
+    }
+    unsafe { ptr::write(p, t); }
+    Ok(p)
+}
+
+// The header every Gc allocation carries, for measuring what a graph costs.
+pub fn size_of_node() -> usize {
+    size_of::<Node>()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
 }
 
 struct Node {
-    desc_op: Option<String>,
+    id: GcNodeId,
+    value: *mut (),
+    vtable: &'static NodeVTable,
+    desc_op: Option<Box<str>>,
     strong: i32,
     weak: i32,
-    colour: Colour,
-    buffered: bool,
-    trace: Box<Fn(&mut FnMut(*mut Node))>,
-    finalize: Box<Fn()>,
-    freed: bool,
-    cleanup: Box<Fn()>
+    flags: u32,
+    // Gc handles pointing at the node. Debug builds keep a freed node allocated until the last
+    // one goes, so a handle left dangling by a bad Trace impl panics on deref instead of
+    // reading freed memory.
+    #[cfg(debug_assertions)]
+    handles: i32
 }
 
 impl Node {
+    fn type_id(&self) -> TypeId {
+        (self.vtable.type_id)()
+    }
+
+    fn type_name(&self) -> &'static str {
+        (self.vtable.type_name)()
+    }
+
+    fn colour(&self) -> Colour {
+        match self.flags & COLOUR_MASK {
+            0 => Colour::Black,
+            1 => Colour::Purple,
+            2 => Colour::White,
+            _ => Colour::Gray
+        }
+    }
+
+    fn set_colour(&mut self, colour: Colour) {
+        self.flags = (self.flags & !COLOUR_MASK) | colour as u32;
+    }
+
+    fn flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u32, on: bool) {
+        if on {
+            self.flags = self.flags | flag;
+        } else {
+            self.flags = self.flags & !flag;
+        }
+    }
+
+    fn buffered(&self) -> bool {
+        self.flag(BUFFERED)
+    }
+
+    fn root_index(&self) -> usize {
+        (self.flags >> ROOT_INDEX_SHIFT) as usize
+    }
+
+    fn set_root_index(&mut self, index: usize) {
+        let index = if index < NO_ROOT_INDEX { index } else { NO_ROOT_INDEX };
+        self.flags = (self.flags & ((1 << ROOT_INDEX_SHIFT) - 1)) | ((index as u32) << ROOT_INDEX_SHIFT);
+    }
+
+    fn dying(&self) -> bool {
+        self.flag(DYING)
+    }
+
+    fn freed(&self) -> bool {
+        self.flag(FREED)
+    }
+
+    fn unreferenced(&self) -> bool {
+        #[cfg(debug_assertions)]
+        {
+            self.weak == 0 && self.handles == 0
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            self.weak == 0
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn add_handle(&mut self) {
+        self.handles = self.handles + 1;
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn add_handle(&mut self) {
+    }
+
     fn trace(&self, f: &mut FnMut(*mut Node)) {
-        if !self.freed {
-            (self.trace)(f);
+        if !self.freed() {
+            unsafe { (self.vtable.trace)(self.value, f) };
         }
     }
 
@@ -816,48 +1238,316 @@ impl GcCtx {
                 GcCtxData {
                     roots: Vec::new(),
                     collecting_cycles: false,
-                    to_be_freed: Vec::new()
+                    to_be_freed: Vec::new(),
+                    live: HashSet::new(),
+                    by_id: HashMap::new(),
+                    track_ids: false,
+                    next_id: 0,
+                    spare_nodes: Vec::new(),
+                    spare_white: HashSet::new(),
+                    memory_in_use: 0,
+                    memory_limit_op: None,
+                    fallible_depth: 0,
+                    over_budget_op: None,
+                    oom_handler_op: None,
+                    deferred: Vec::new(),
+                    policy: GcPolicy::Eager,
+                    root_buffer_steps: 0
                 }
             ))
         }
     }
 
-    pub fn new_gc<A: Trace + Finalize + 'static>(&mut self, value: A) -> Gc<A> {
+    pub fn new_gc<A: Trace + Finalize + 'static>(&self, value: A) -> Gc<A> {
         self._new_gc(value, None)
     }
 
-    pub fn new_gc_with_desc<A: Trace + Finalize + 'static>(&mut self, value: A, desc: String) -> Gc<A> {
+    pub fn new_gc_with_desc<A: Trace + Finalize + 'static>(&self, value: A, desc: String) -> Gc<A> {
         self._new_gc(value, Some(desc))
     }
 
-    fn _new_gc<A: Trace + Finalize + 'static>(&mut self, value: A, desc_op: Option<String>) -> Gc<A> {
+    fn _new_gc<A: Trace + Finalize + 'static>(&self, value: A, desc_op: Option<String>) -> Gc<A> {
+        let size = size_of::<A>() + size_of::<Node>();
+        if let Err(error) = self.charge(size, type_name::<A>()) {
+            let refused = self.with_data(|data| {
+                data.memory_in_use = data.memory_in_use + size;
+                if data.fallible_depth > 0 && data.over_budget_op.is_none() {
+                    data.over_budget_op = Some(error);
+                }
+                data.fallible_depth > 0
+            });
+            self.report_oom(OomEvent { error, refused });
+        }
         let value = Box::into_raw(Box::new(value));
-        let value2 = value.clone();
-        let value3 = value.clone();
+        let node = Box::into_raw(Box::new(self.new_node::<A>(value, desc_op)));
+        self.adopt(value, node)
+    }
+
+    // Fails instead of going over the memory limit, or when the system allocator is out of
+    // memory, which Box::new would abort on.
+    pub fn try_new_gc<A: Trace + Finalize + 'static>(&self, value: A) -> Result<Gc<A>,OomError> {
+        let size = size_of::<A>() + size_of::<Node>();
+        if let Err(error) = self.charge(size, type_name::<A>()) {
+            self.report_oom(OomEvent { error, refused: true });
+            return Err(error);
+        }
+        let value = match try_box(value) {
+            Ok(value) => value,
+            Err(_) => return Err(self.refused_by_system(size, type_name::<A>()))
+        };
+        match try_box(self.new_node::<A>(value, None)) {
+            Ok(node) => Ok(self.adopt(value, node)),
+            Err(_) => {
+                unsafe { drop(Box::from_raw(value)); }
+                Err(self.refused_by_system(size, type_name::<A>()))
+            }
+        }
+    }
+
+    fn new_node<A: Trace + Finalize + 'static>(&self, value: *mut A, desc_op: Option<String>) -> Node {
+        let id = self.with_data(|data| {
+            data.next_id = data.next_id + 1;
+            GcNodeId(data.next_id)
+        });
+        Node {
+            id,
+            value: value as *mut (),
+            vtable: &VTableOf::<A>::VTABLE,
+            desc_op: desc_op.map(String::into_boxed_str),
+            strong: 1,
+            weak: 1,
+            flags: Colour::Black as u32,
+            #[cfg(debug_assertions)]
+            handles: 1
+        }
+    }
+
+    fn adopt<A: Trace + Finalize + 'static>(&self, value: *mut A, node: *mut Node) -> Gc<A> {
         let r = Gc {
             ctx: self.clone(),
             value: value,
-            node: Box::into_raw(Box::new(Node {
-                desc_op: desc_op,
-                strong: 1,
-                weak: 1,
-                colour: Colour::Black,
-                buffered: false,
-                trace: Box::new(move |f: &mut FnMut(*mut Node)| {
-                    unsafe { &*value2 }.trace(&mut |dep: &GcDep| f(dep.node))
-                }),
-                finalize: Box::new(move || {
-                    unsafe { &mut *value3 }.finalize()
-                }),
-                freed: false,
-                cleanup: Box::new(move || {
-                    unsafe { Box::from_raw(value); }
-                })
-            }))
+            node: node
         };
+        self.with_data(|data| {
+            data.live.insert(r.node);
+            if data.track_ids {
+                data.by_id.insert(unsafe { &*node }.id, r.node);
+            }
+        });
         r
     }
 
+    fn charge(&self, size: usize, type_name: &'static str) -> Result<(),OomError> {
+        self.with_data(|data| {
+            if let Some(limit) = data.memory_limit_op {
+                if data.memory_in_use + size > limit {
+                    return Err(OomError { requested: size, in_use: data.memory_in_use, limit_op: Some(limit), type_name });
+                }
+            }
+            data.memory_in_use = data.memory_in_use + size;
+            Ok(())
+        })
+    }
+
+    fn refused_by_system(&self, size: usize, type_name: &'static str) -> OomError {
+        let error = self.with_data(|data| {
+            data.memory_in_use = data.memory_in_use - size;
+            OomError { requested: size, in_use: data.memory_in_use, limit_op: None, type_name }
+        });
+        self.report_oom(OomEvent { error, refused: true });
+        error
+    }
+
+    fn report_oom(&self, event: OomEvent) {
+        if let Some(handler) = self.with_data(|data| data.oom_handler_op.clone()) {
+            handler(&event);
+        }
+    }
+
+    // A budget in bytes for the live Gc values and their headers. Going over it makes
+    // try_new_gc and everything run under fallible() fail, other allocations go ahead and are
+    // only reported to the oom handler.
+    pub fn set_memory_limit(&self, limit_op: Option<usize>) {
+        self.with_data(|data| data.memory_limit_op = limit_op);
+    }
+
+    pub fn memory_in_use(&self) -> usize {
+        self.with_data(|data| data.memory_in_use)
+    }
+
+    // How much work buffering and unbuffering possible cycle roots has taken, as entries
+    // pushed, removed or walked past. Lets a test check it stays linear without timing it.
+    pub fn root_buffer_steps(&self) -> u64 {
+        self.with_data(|data| data.root_buffer_steps)
+    }
+
+    pub fn set_policy(&self, policy: GcPolicy) {
+        self.with_data(|data| data.policy = policy);
+    }
+
+    pub fn policy(&self) -> GcPolicy {
+        self.with_data(|data| data.policy)
+    }
+
+    // Room for capacity values in the collector's bookkeeping, so a graph of about that size
+    // is built without it growing its tables.
+    pub fn reserve(&self, capacity: usize) {
+        self.with_data(|data| {
+            data.live.reserve(capacity);
+            data.by_id.reserve(capacity);
+            data.roots.reserve(capacity);
+            data.spare_nodes.reserve(capacity);
+            data.spare_white.reserve(capacity);
+        });
+    }
+
+    pub fn set_oom_handler<F: Fn(&OomEvent) + 'static>(&self, handler: F) {
+        self.with_data(|data| data.oom_handler_op = Some(Rc::new(handler)));
+    }
+
+    // Runs f, failing with the first allocation it made over the memory limit. Whatever f
+    // built is dropped again in that case, so no half built graph stays behind. Calls nest.
+    pub fn fallible<R, F: FnOnce() -> R>(&self, f: F) -> Result<R,OomError> {
+        let outer_op = self.with_data(|data| {
+            data.fallible_depth = data.fallible_depth + 1;
+            data.over_budget_op.take()
+        });
+        let r = f();
+        let error_op = self.with_data(|data| {
+            data.fallible_depth = data.fallible_depth - 1;
+            let error_op = data.over_budget_op.take();
+            data.over_budget_op = outer_op.or(error_op);
+            error_op
+        });
+        match error_op {
+            Some(error) => {
+                drop(r);
+                Err(error)
+            },
+            None => Ok(r)
+        }
+    }
+
+    // Makes weak_from_id find the objects allocated from now on. Off by default, as it costs a
+    // table insert per allocation.
+    pub fn set_track_ids(&self, track: bool) {
+        self.with_data(|data| data.track_ids = track);
+    }
+
+    // Weak references are a pointer and a count on the shared node, so this allocates nothing.
+    // Returns None once the object is freed, when A is not the type it was allocated with or
+    // when it was allocated while ids were not tracked.
+    pub fn weak_from_id<A: 'static>(&self, id: GcNodeId) -> Option<GcWeak<A>> {
+        let node = self.with_data(|data| data.by_id.get(&id).cloned())?;
+        let node = unsafe { &mut *node };
+        if node.type_id() != TypeId::of::<A>() {
+            return None;
+        }
+        node.weak = node.weak + 1;
+        Some(GcWeak {
+            ctx: self.clone(),
+            node: node as *mut Node,
+            value: node.value as *mut A
+        })
+    }
+
+    pub fn collect_all(&self) -> LeakReport {
+        let live: Vec<*mut Node> = self.with_data(|data| data.live.iter().cloned().collect());
+        for s in live {
+            let s = unsafe { &mut *s };
+            if s.strong > 0 {
+                self.possible_root(s);
+            }
+        }
+        self.collect_cycles();
+        self.leak_report()
+    }
+
+    pub fn leak_report(&self) -> LeakReport {
+        let live: Vec<*mut Node> = self.with_data(|data| data.live.iter().cloned().collect());
+        let mut internal: HashMap<*mut Node,i32> = HashMap::new();
+        for s in &live {
+            let s = unsafe { &**s };
+            s.trace(&mut |t| *internal.entry(t).or_insert(0) += 1);
+        }
+        let mut reachable: HashSet<*mut Node> = HashSet::new();
+        let mut stack: Vec<*mut Node> = live
+            .iter()
+            .cloned()
+            .filter(|s| {
+                let s2 = unsafe { &**s };
+                s2.strong > *internal.get(s).unwrap_or(&0)
+            })
+            .collect();
+        while let Some(s) = stack.pop() {
+            if reachable.insert(s) {
+                let s = unsafe { &*s };
+                s.trace(&mut |t| stack.push(t));
+            }
+        }
+        LeakReport {
+            leaks: live
+                .iter()
+                .filter(|s| !reachable.contains(*s))
+                .map(|s| {
+                    let s = unsafe { &**s };
+                    LeakedNode {
+                        type_name: s.type_name(),
+                        desc_op: s.desc_op.as_ref().map(|desc| desc.to_string()),
+                        strong: s.strong
+                    }
+                })
+                .collect()
+        }
+    }
+
+    // Writes every live object and the edges between them as JSON. Ids are only meaningful
+    // within one dump.
+    pub fn heap_dump<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut live: Vec<*mut Node> = self.with_data(|data| data.live.iter().cloned().collect());
+        live.sort();
+        let ids: HashMap<*mut Node,usize> = live.iter().cloned().enumerate().map(|(id, s)| (s, id)).collect();
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"nodes\": [")?;
+        for (id, s) in live.iter().enumerate() {
+            let s = unsafe { &**s };
+            let desc =
+                match s.desc_op {
+                    Some(ref desc) => json_string(desc),
+                    None => String::from("null")
+                };
+            let colour =
+                match s.colour() {
+                    Colour::Black => "black",
+                    Colour::Purple => "purple",
+                    Colour::White => "white",
+                    Colour::Gray => "gray"
+                };
+            writeln!(
+                w,
+                "    {{\"id\": {}, \"type_name\": {}, \"desc\": {}, \"strong\": {}, \"weak\": {}, \"colour\": \"{}\", \"buffered\": {}}}{}",
+                id, json_string(s.type_name()), desc, s.strong, s.weak, colour, s.buffered(),
+                if id + 1 < live.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(w, "  ],")?;
+        let mut edges: Vec<(usize,usize)> = Vec::new();
+        for (id, s) in live.iter().enumerate() {
+            let s = unsafe { &**s };
+            s.trace(&mut |t| {
+                if let Some(t_id) = ids.get(&t) {
+                    edges.push((id, *t_id));
+                }
+            });
+        }
+        writeln!(w, "  \"edges\": [")?;
+        for (i, &(from, to)) in edges.iter().enumerate() {
+            writeln!(w, "    [{}, {}]{}", from, to, if i + 1 < edges.len() { "," } else { "" })?;
+        }
+        writeln!(w, "  ]")?;
+        writeln!(w, "}}")
+    }
+
     fn with_data<F,A>(&self, f: F)->A where F: FnOnce(&mut GcCtxData)->A {
         f(&mut self.data.borrow_mut())
     }
@@ -865,7 +1555,7 @@ impl GcCtx {
     fn increment(&self, s: *mut Node) {
         let s = unsafe { &mut *s };
         s.strong = s.strong + 1;
-        s.colour = Colour::Black;
+        s.set_colour(Colour::Black);
     }
 
     fn decrement(&self, s: *mut Node) {
@@ -883,21 +1573,28 @@ impl GcCtx {
     fn release(&self, s: *mut Node) {
         let s = unsafe { &mut *s };
         debug_assert!(s.strong == 0);
-        s.colour = Colour::Black;
-        if !s.buffered {
-            self.finalize_and_mark_to_be_freed(s);
+        s.set_colour(Colour::Black);
+        if !s.buffered() && !s.dying() {
+            self.mark_to_be_freed(s);
         }
     }
 
     fn system_free(&self, s: *mut Node) {
-        self.with_data(|data| data.roots.retain(|n| !ptr::eq(*n, s)));
+        self.with_data(|data| {
+            data.remove_root(s);
+            data.live.remove(&s);
+            if !data.by_id.is_empty() {
+                data.by_id.remove(&unsafe { &*s }.id);
+            }
+            data.memory_in_use = data.memory_in_use - unsafe { &*s }.vtable.size;
+        });
         let s = unsafe { &mut *s };
         debug_assert!(s.strong == 0);
-        (s.cleanup)();
-        s.freed = true;
+        unsafe { (s.vtable.drop_value)(s.value) };
+        s.set_flag(FREED, true);
         if s.weak > 0 {
             s.weak = s.weak - 1;
-            if s.weak == 0 {
+            if s.unreferenced() {
                 unsafe { Box::from_raw(s); }
             }
         }
@@ -906,22 +1603,17 @@ impl GcCtx {
     fn possible_root(&self, s: *mut Node) {
         let s = unsafe { &mut *s };
         debug_assert!(s.strong > 0);
-        if s.colour != Colour::Purple {
-            s.colour = Colour::Purple;
-            if !s.buffered {
-                s.buffered = true;
-                self.with_data(|data| {
-                    let s2: *mut Node = s;
-                    if !data.roots.contains(&s2) {
-                        data.roots.push(s2);
-                    }
-                });
+        if s.colour() != Colour::Purple {
+            s.set_colour(Colour::Purple);
+            if !s.buffered() {
+                s.set_flag(BUFFERED, true);
+                self.with_data(|data| data.push_root(s));
             }
         }
     }
 
     pub fn collect_cycles(&self) {
-        if self.with_data(|data| data.collecting_cycles) {
+        if self.with_data(|data| data.collecting_cycles || (data.roots.is_empty() && data.to_be_freed.is_empty())) {
             return;
         }
         self.with_data(|data| data.collecting_cycles = true);
@@ -936,57 +1628,127 @@ impl GcCtx {
 
         self.with_data(|data| data.collecting_cycles = false);
 
-        if again {
+        let drained = self.drain_deferred();
+
+        if again || drained {
+            self.collect_cycles();
+        }
+    }
+
+    fn collect_cycles_if_due(&self) {
+        let due = self.with_data(|data| {
+            match data.policy {
+                GcPolicy::Eager => true,
+                GcPolicy::Deferred(roots) => !data.to_be_freed.is_empty() || data.roots.len() >= roots
+            }
+        });
+        if due {
             self.collect_cycles();
         }
     }
 
+    // Dropping a handle mid collection would change counts and colours under the collector's
+    // feet, so it is queued instead. True if it was. Handles to objects already being freed
+    // still count down at once, the resurrection check in free_to_be_freed depends on it.
+    fn defer_decrement(&self, s: *mut Node) -> bool {
+        self.with_data(|data| {
+            let node = unsafe { &mut *s };
+            if !data.collecting_cycles || node.dying() {
+                return false;
+            }
+            node.weak = node.weak + 1;
+            data.deferred.push(s);
+            true
+        })
+    }
+
+    fn drain_deferred(&self) -> bool {
+        let mut deferred = Vec::new();
+        self.with_data(|data| swap(&mut deferred, &mut data.deferred));
+        let drained = !deferred.is_empty();
+        for s in deferred {
+            let node = unsafe { &mut *s };
+            if !node.freed() {
+                self.decrement(s);
+            }
+            node.weak = node.weak - 1;
+            if node.unreferenced() {
+                unsafe { drop(Box::from_raw(s)); }
+            }
+        }
+        drained
+    }
+
+    fn take_roots(&self) -> Vec<*mut Node> {
+        self.with_data(|data| {
+            let mut roots = Vec::new();
+            swap(&mut roots, &mut data.spare_nodes);
+            swap(&mut roots, &mut data.roots);
+            roots
+        })
+    }
+
+    fn return_spare_nodes(&self, mut nodes: Vec<*mut Node>) {
+        nodes.clear();
+        self.with_data(|data| data.spare_nodes = nodes);
+    }
+
     fn mark_roots(&self) {
-        let roots = self.with_data(|data| data.roots.clone());
-        let mut new_roots = Vec::new();
-        for s in roots {
+        let mut roots = self.take_roots();
+        for s in roots.drain(..) {
             let s2 = s;
             let s = unsafe { &mut *s };
-            if s.colour == Colour::Purple && s.strong > 0 {
+            if s.colour() == Colour::Purple && s.strong > 0 {
                 self.mark_gray(s);
-                new_roots.push(s2);
+                self.with_data(|data| data.push_root(s2));
             } else {
-                s.buffered = false;
-                self.with_data(|data| data.roots.retain(|s2| !ptr::eq(s, *s2)));
-                if s.colour == Colour::Black && s.strong == 0 {
-                    self.finalize_and_mark_to_be_freed(s);
+                s.set_flag(BUFFERED, false);
+                if s.colour() == Colour::Black && s.strong == 0 && !s.dying() {
+                    self.mark_to_be_freed(s);
                 }
             }
         }
-        self.with_data(|data| {
-            data.roots = new_roots;
-        });
+        self.return_spare_nodes(roots);
     }
 
     fn scan_roots(&self) {
-        let roots = self.with_data(|data| data.roots.clone());
-        for s in roots {
-            self.scan(s);
+        let roots = self.take_roots();
+        for s in &roots {
+            self.scan(*s);
         }
+        self.with_data(|data| {
+            swap(&mut data.spare_nodes, &mut data.roots);
+            data.roots = roots;
+        });
     }
 
     fn collect_roots(&self) {
-        let roots = self.with_data(|data| {
-            let roots = data.roots.clone();
-            data.roots.clear();
-            roots
-        });
-        for s in roots {
+        let mut roots = self.take_roots();
+        let mut white = HashSet::new();
+        self.with_data(|data| swap(&mut white, &mut data.spare_white));
+        for s in roots.drain(..) {
             let s = unsafe { &mut *s };
-            s.buffered = false;
-            self.collect_white(s);
+            s.set_flag(BUFFERED, false);
+            self.collect_white(s, &mut white);
         }
+        self.return_spare_nodes(roots);
+        for s in &white {
+            let s = unsafe { &**s };
+            s.trace(&mut |t| {
+                if !white.contains(&t) {
+                    let t = unsafe { &mut *t };
+                    t.strong = t.strong + 1;
+                }
+            });
+        }
+        white.clear();
+        self.with_data(|data| data.spare_white = white);
     }
 
     fn mark_gray(&self, s: *mut Node) {
         let s = unsafe { &mut *s };
-        if s.colour != Colour::Gray {
-            s.colour = Colour::Gray;
+        if s.colour() != Colour::Gray {
+            s.set_colour(Colour::Gray);
             s.trace(&mut |t| {
                 let t = unsafe { &mut *t };
                 t.strong = t.strong - 1;
@@ -997,11 +1759,11 @@ impl GcCtx {
 
     fn scan(&self, s: *mut Node) {
         let s = unsafe { &mut *s };
-        if s.colour == Colour::Gray {
+        if s.colour() == Colour::Gray {
             if s.strong > 0 {
                 self.scan_black(s);
             } else {
-                s.colour = Colour::White;
+                s.set_colour(Colour::White);
                 s.trace(&mut |t| {
                     let t = unsafe { &mut *t };
                     self.scan(t);
@@ -1012,37 +1774,74 @@ impl GcCtx {
 
     fn scan_black(&self, s: *mut Node) {
         let s = unsafe { &mut *s };
-        s.colour = Colour::Black;
+        s.set_colour(Colour::Black);
         s.trace(&mut |t| {
             let t = unsafe { &mut *t };
             t.strong = t.strong + 1;
-            if t.colour != Colour::Black {
+            if t.colour() != Colour::Black {
                 self.scan_black(t);
             }
         });
     }
 
-    fn collect_white(&self, s: *mut Node) {
+    fn collect_white(&self, s: *mut Node, white: &mut HashSet<*mut Node>) {
         let s = unsafe { &mut *s };
-        if s.colour == Colour::White && !s.buffered {
-            s.colour = Colour::Black;
+        if s.colour() == Colour::White && !s.buffered() {
+            s.set_colour(Colour::Black);
+            white.insert(s as *mut Node);
             s.trace(&mut |t| {
-                self.collect_white(t);
+                self.collect_white(t, white);
             });
-            self.finalize_and_mark_to_be_freed(s);
+            self.mark_to_be_freed(s);
         }
     }
 
-    fn finalize_and_mark_to_be_freed(&self, s: *mut Node) {
-        unsafe { ((*s).finalize)() };
+    fn mark_to_be_freed(&self, s: *mut Node) {
+        unsafe { (*s).set_flag(DYING, true) };
         self.with_data(|data| data.to_be_freed.push(s));
     }
 
+    // Each batch is freed in two passes: every finalizer runs while all of the batch is still
+    // allocated, then everything is freed. Objects released by a finalizer form the next batch.
     fn free_to_be_freed(&self) {
-        let mut to_be_freed = Vec::new();
-        self.with_data(|data| swap(&mut data.to_be_freed, &mut to_be_freed));
-        for node in to_be_freed {
-            self.system_free(node);
+        loop {
+            let mut to_be_freed = Vec::new();
+            self.with_data(|data| {
+                swap(&mut to_be_freed, &mut data.spare_nodes);
+                swap(&mut data.to_be_freed, &mut to_be_freed);
+            });
+            if to_be_freed.is_empty() {
+                self.return_spare_nodes(to_be_freed);
+                break;
+            }
+            for node in &to_be_freed {
+                let node = unsafe { &mut **node };
+                node.weak = node.weak + 1;
+            }
+            for node in &to_be_freed {
+                unsafe { ((**node).vtable.finalize)((**node).value) };
+            }
+            for node in &to_be_freed {
+                let node = unsafe { &**node };
+                if node.strong > 0 {
+                    self.with_data(|data| data.collecting_cycles = false);
+                    panic!(
+                        "{} was resurrected by a finalizer, finalizers must not keep new references to objects being collected",
+                        node.desc_op.as_ref().map(|desc| desc.to_string()).unwrap_or_else(|| format!("Gc<{}>", node.type_name()))
+                    );
+                }
+            }
+            for node in &to_be_freed {
+                self.system_free(*node);
+            }
+            for node in to_be_freed.drain(..) {
+                let node = unsafe { &mut *node };
+                node.weak = node.weak - 1;
+                if node.unreferenced() {
+                    unsafe { drop(Box::from_raw(node)); }
+                }
+            }
+            self.return_spare_nodes(to_be_freed);
         }
     }
 }
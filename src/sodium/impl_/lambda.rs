@@ -1,4 +1,6 @@
 use sodium::Dep;
+use sodium::gc::NoGc;
+use std::rc::Rc;
 
 pub struct Lambda<FN: ?Sized> {
     apply: Box<FN>,
@@ -99,6 +101,26 @@ impl<A,R,FN:Fn(&A)->R> IsLambda1<A,R> for FN {
     }
 }
 
+// Lets a Cell<NoGc<Rc<dyn Fn(&A)->R>>> be used with Cell::apply.
+impl<A,R,FN:Fn(&A)->R+?Sized> IsLambda1<A,R> for NoGc<Rc<FN>> {
+    fn apply(&self, a: &A) -> R {
+        (***self)(a)
+    }
+    fn deps(&self) -> Vec<Dep> {
+        Vec::new()
+    }
+}
+
+// Lets a Cell<NoGc<F>> of plain closures or fn pointers be used with Cell::apply.
+impl<A,R,FN:Fn(&A)->R> IsLambda1<A,R> for NoGc<FN> {
+    fn apply(&self, a: &A) -> R {
+        (**self)(a)
+    }
+    fn deps(&self) -> Vec<Dep> {
+        Vec::new()
+    }
+}
+
 impl<A,B,R,FN:Fn(&A,&B)->R> IsLambda2<A,B,R> for FN {
     fn apply(&self, a: &A, b: &B) -> R {
         self(a, b)
@@ -17,7 +17,8 @@ impl Listener {
 
     pub fn new(node: Node, weak: bool) -> Listener {
         let sodium_ctx = node.sodium_ctx();
-        let mut gc_ctx = sodium_ctx.gc_ctx();
+        let gc_ctx = sodium_ctx.gc_ctx();
+        node.mark_listener();
         if !weak {
             sodium_ctx.add_keep_alive(node.clone());
         }
@@ -39,6 +40,35 @@ impl Listener {
         }
         *node_op = None;
     }
+
+    // pred runs after every call to the callback. Once it returns true the listener is removed
+    // at the end of the transaction and the callback, with everything it captured, is dropped.
+    pub fn unlisten_after<PRED: FnMut()->bool + 'static>(&self, mut pred: PRED) {
+        let node_op = unsafe { &*(*self.node_op).get() };
+        if let &Some(ref node) = node_op {
+            let sodium_ctx = node.sodium_ctx();
+            let node_op = self.node_op.downgrade();
+            let weak_node = node.downgrade();
+            let mut done = false;
+            node.after_update(move || {
+                if done || !pred() {
+                    return;
+                }
+                done = true;
+                let node_op = node_op.clone();
+                let weak_node = weak_node.clone();
+                sodium_ctx.post(move || {
+                    if let Some(node_op) = node_op.upgrade() {
+                        let node_op = unsafe { &mut *(*node_op).get() };
+                        *node_op = None;
+                    }
+                    if let Some(node) = weak_node.upgrade() {
+                        node.dispose();
+                    }
+                });
+            });
+        }
+    }
 }
 
 impl Finalize for Listener {
@@ -6,6 +6,7 @@ use sodium::impl_::gc::GcCtx;
 use sodium::impl_::gc::GcDep;
 use sodium::impl_::gc::Trace;
 use std::cell::UnsafeCell;
+use std::mem::swap;
 
 pub struct MemoLazy<A> {
     data: Gc<MemoLazyData<A>>
@@ -56,6 +57,39 @@ impl<A: Trace + Finalize + 'static> MemoLazy<A> {
         }
     }
 
+    // Already forced, so the thunk never runs and holds on to nothing.
+    pub fn evaluated(gc_ctx: &mut GcCtx, value: A) -> MemoLazy<A> {
+        let lazy = MemoLazy::new(gc_ctx, || -> A { unreachable!("MemoLazy::evaluated thunk forced") });
+        unsafe {
+            *lazy.data.val_op.get() = Some(value);
+        }
+        lazy
+    }
+
+    // No other handle or dep on it anywhere, so its value can be swapped for reuse. A weak
+    // count of one is the one every Gc starts with.
+    pub fn is_unique(&self) -> bool {
+        self.data.strong_count() == 1 && self.data.weak_count() == 1
+    }
+
+    // Swaps the forced value of an evaluated, unique handle. Taking the value out leaves the
+    // handle unusable until one is put back.
+    pub fn replace(&self, value_op: Option<A>) -> Option<A> {
+        let val_op = unsafe { &mut *self.data.val_op.get() };
+        let mut value_op = value_op;
+        swap(val_op, &mut value_op);
+        value_op
+    }
+
+    pub fn to_dep(&self) -> Dep {
+        Dep { gc_dep: self.data.to_dep() }
+    }
+
+    // Whether both are handles on the same thunk, without forcing either.
+    pub fn same(&self, other: &MemoLazy<A>) -> bool {
+        self.data.id() == other.data.id()
+    }
+
     pub fn get(&self) -> &A {
         let self_ = &*self.data;
         let val_op = unsafe { &*self_.val_op.get() };
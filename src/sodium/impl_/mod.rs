@@ -1,4 +1,7 @@
 pub use self::cell::Cell;
+pub use self::cell::WeakCell;
+#[cfg(feature = "debug-history")]
+pub use self::cell_history::CellHistory;
 pub use self::cell_loop::CellLoop;
 pub use self::cell_sink::CellSink;
 pub use self::dep::Dep;
@@ -14,17 +17,35 @@ pub use self::lambda::IsLambda6;
 pub use self::latch::Latch;
 pub use self::listener::Listener;
 pub use self::memo_lazy::MemoLazy;
+pub use self::node::EdgeError;
 pub use self::node::Node;
+pub use self::node::WeakNode;
 pub use self::operational::Operational;
+pub use self::sodium_ctx::ListenerInfo;
+pub use self::sodium_ctx::ListenerMiddleware;
+pub use self::sodium_ctx::OptimizerStats;
+pub use self::sodium_ctx::SampleReader;
 pub use self::sodium_ctx::SodiumCtx;
 pub use self::sodium_ctx::SodiumCtxData;
+pub use self::sodium_ctx::SodiumError;
+pub use self::sodium_ctx::SodiumScope;
+pub use self::sodium_ctx::TxId;
+pub use self::sodium_ctx::TxObserver;
+pub use self::sodium_ctx::TxSummary;
 pub use self::sodium_ctx::WeakSodiumCtx;
 pub use self::stream::Stream;
 pub use self::stream::StreamData;
+pub use self::stream::WeakStream;
 pub use self::stream_loop::StreamLoop;
+pub use self::stream_sink::SinkHandle;
 pub use self::stream_sink::StreamSink;
+pub use self::stream_sink::WeakStreamSink;
 
 mod cell;
+
+#[cfg(feature = "debug-history")]
+mod cell_history;
+
 mod cell_loop;
 mod cell_sink;
 mod dep;
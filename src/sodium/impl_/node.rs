@@ -1,6 +1,7 @@
 use sodium::impl_::Dep;
 use sodium::impl_::IsLambdaMut0;
 use sodium::impl_::SodiumCtx;
+use sodium::impl_::SodiumError;
 use sodium::gc::Finalize;
 use sodium::gc::Gc;
 use sodium::gc::GcDep;
@@ -12,8 +13,10 @@ use std::cmp::Ordering;
 use std::cmp::PartialEq;
 use std::cmp::PartialOrd;
 use std::collections::HashSet;
+use std::fmt;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::mem::swap;
 use std::rc::Rc;
 use std::vec::Vec;
 
@@ -21,6 +24,31 @@ pub struct Node {
     data: Gc<UnsafeCell<NodeData>>
 }
 
+// Why Node::try_add_dependency refused an edge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeError {
+    // A node that depends on itself would be updated forever.
+    SelfEdge,
+    // The edge is there already, wiring it twice would update the node twice.
+    Duplicate,
+    // The nodes belong to different SodiumCtxs, so no transaction could update both.
+    CrossContext,
+    // The dependency already depends on the node. Only detected in debug builds, where it is
+    // also reported as SodiumError::DependencyCycle.
+    Cycle
+}
+
+impl fmt::Display for EdgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EdgeError::SelfEdge => write!(f, "a node can not depend on itself"),
+            EdgeError::Duplicate => write!(f, "the dependency is already there"),
+            EdgeError::CrossContext => write!(f, "the dependency belongs to another SodiumCtx"),
+            EdgeError::Cycle => write!(f, "the dependency already depends on the node")
+        }
+    }
+}
+
 pub struct WeakNode {
     data: GcWeak<UnsafeCell<NodeData>>
 }
@@ -28,26 +56,47 @@ pub struct WeakNode {
 pub struct NodeData {
     id: u32,
     rank: u32,
+    priority: i32,
     update: Box<FnMut()->bool>,
     update_dependencies: Vec<Dep>,
     dependencies: Vec<Node>,
+    // Ids of dependencies, so try_add_dependency can spot duplicates without a scan.
+    dependency_ids: HashSet<u32>,
     dependents: Vec<WeakNode>,
     cleanup: Box<FnMut()>,
     additional_cleanups: Vec<Box<IsLambdaMut0<()>>>,
+    name_op: Option<String>,
+    // Set on the nodes behind sinks and listeners, for the inspection functions.
+    source: bool,
+    listener: bool,
     sodium_ctx: SodiumCtx
 }
 
 impl Node {
     pub fn new<UPDATE: FnMut()->bool + 'static, CLEANUP: FnMut() + 'static>(
+        sodium_ctx: &SodiumCtx,
+        update: UPDATE,
+        update_dependencies: Vec<Dep>,
+        dependencies: Vec<Node>,
+        cleanup: CLEANUP,
+        desc: String
+    ) -> Node {
+        Node::new_with_priority(sodium_ctx, update, update_dependencies, dependencies, cleanup, desc, 0)
+    }
+
+    // Priority is part of the ordering of to_be_updated, so it is only ever set here, before
+    // the node can be queued.
+    pub(crate) fn new_with_priority<UPDATE: FnMut()->bool + 'static, CLEANUP: FnMut() + 'static>(
         sodium_ctx: &SodiumCtx,
         mut update: UPDATE,
         update_dependencies: Vec<Dep>,
         dependencies: Vec<Node>,
         mut cleanup: CLEANUP,
-        desc: String
+        desc: String,
+        priority: i32
     ) -> Node {
         let id = sodium_ctx.new_id();
-        sodium_ctx.inc_node_count();
+        sodium_ctx.register_node(id, &desc);
         let mut rank = 0;
         for dependency in &dependencies {
             let dependency = unsafe { &*(*dependency.data).get() };
@@ -98,18 +147,23 @@ impl Node {
                 }
             };
         }
-        let mut gc_ctx = sodium_ctx.gc_ctx();
+        let gc_ctx = sodium_ctx.gc_ctx();
         let node = Node {
             data: gc_ctx.new_gc_with_desc(UnsafeCell::new(
                 NodeData {
                     id,
                     rank,
+                    priority,
                     update: Box::new(update2),
                     update_dependencies,
                     dependencies: dependencies.clone(),
+                    dependency_ids: dependencies.iter().map(|dependency| dependency.id()).collect(),
                     dependents: Vec::new(),
                     cleanup: Box::new(cleanup2),
                     additional_cleanups: Vec::new(),
+                    name_op: None,
+                    source: false,
+                    listener: false,
                     sodium_ctx: sodium_ctx.clone()
                 }
             ), desc)
@@ -122,6 +176,7 @@ impl Node {
             let dependency = unsafe { &mut *(*dependency.data).get() };
             dependency.dependents.push(weak_node.clone());
         }
+        sodium_ctx.track_in_scope(&node);
         node
     }
 
@@ -136,6 +191,17 @@ impl Node {
         data.update_dependencies = update_deps;
     }
 
+    pub fn after_update<AFTER: FnMut() + 'static>(&self, mut after: AFTER) {
+        let data = unsafe { &mut *(*self.data).get() };
+        let mut update: Box<dyn FnMut()->bool> = Box::new(|| false);
+        swap(&mut data.update, &mut update);
+        data.update = Box::new(move || {
+            let result = update();
+            after();
+            result
+        });
+    }
+
     pub fn add_update_deps(&self, update_deps: Vec<Dep>) {
         let data = unsafe { &mut *(*self.data).get() };
         for dep in update_deps {
@@ -161,20 +227,191 @@ impl Node {
             }
         }
         data.dependencies.clear();
+        data.dependency_ids.clear();
     }
 
-    pub fn add_dependencies(&self, dependencies: Vec<Node>) {
+    pub fn remove_dependency(&self, dependency: &Node) {
         let data = unsafe { &mut *(*self.data).get() };
-        let weak_node = self.downgrade();
+        let self_id = data.id.clone();
+        let dependency_id = dependency.id();
+        {
+            let dependency = unsafe { &mut *(*dependency.data).get() };
+            dependency.dependents.retain(|weak_node| {
+                match weak_node.upgrade() {
+                    Some(node2) => {
+                        let node2_data = unsafe { &mut *(*node2.data).get() };
+                        node2_data.id != self_id
+                    },
+                    None => false
+                }
+            });
+        }
+        data.dependencies.retain(|dependency2| dependency2.id() != dependency_id);
+        data.dependency_ids.remove(&dependency_id);
+    }
+
+    // Edges that are already there are skipped, self edges and edges to another context fail
+    // with SodiumError::InvalidEdge. Cycles have been reported by try_add_dependency already.
+    pub fn add_dependencies(&self, dependencies: Vec<Node>) {
         for dependency in dependencies {
-            {
-                let dependency = unsafe { &mut *(*dependency.data).get() };
-                dependency.dependents.push(weak_node.clone());
+            match self.try_add_dependency(dependency.clone()) {
+                Ok(()) | Err(EdgeError::Duplicate) | Err(EdgeError::Cycle) => {},
+                Err(error) => {
+                    let sodium_ctx = self.sodium_ctx();
+                    let edge = format!("{} -> {}", self.label(), dependency.label());
+                    sodium_ctx.fail(SodiumError::InvalidEdge(error, edge));
+                }
+            }
+        }
+    }
+
+    pub fn try_add_dependency(&self, dependency: Node) -> Result<(), EdgeError> {
+        if !Rc::ptr_eq(&dependency.sodium_ctx().data, &self.sodium_ctx().data) {
+            return Err(EdgeError::CrossContext);
+        }
+        if dependency.id() == self.id() {
+            return Err(EdgeError::SelfEdge);
+        }
+        let data = unsafe { &mut *(*self.data).get() };
+        if data.dependency_ids.contains(&dependency.id()) {
+            return Err(EdgeError::Duplicate);
+        }
+        #[cfg(debug_assertions)]
+        {
+            if !self.check_not_reachable_from(&dependency) {
+                return Err(EdgeError::Cycle);
+            }
+        }
+        {
+            let dependency = unsafe { &mut *(*dependency.data).get() };
+            dependency.dependents.push(self.downgrade());
+        }
+        data.dependency_ids.insert(dependency.id());
+        data.dependencies.push(dependency);
+        Ok(())
+    }
+
+    // Wiring in a dependency that already depends on this node would make propagation go
+    // round forever, so debug builds fail here with the offending path instead. False when
+    // the dependency must be left out, in panic-free mode.
+    #[cfg(debug_assertions)]
+    fn check_not_reachable_from(&self, dependency: &Node) -> bool {
+        fn find_path(node: &Node, target: u32, visited: &mut HashSet<u32>, path: &mut Vec<Node>) -> bool {
+            let id = node.id();
+            if !visited.insert(id) {
+                return false;
+            }
+            path.push(node.clone());
+            if id == target {
+                return true;
+            }
+            for dependency in node.dependencies() {
+                if find_path(&dependency, target, visited, path) {
+                    return true;
+                }
             }
-            data.dependencies.push(dependency);
+            path.pop();
+            false
+        }
+        let mut path = Vec::new();
+        if find_path(dependency, self.id(), &mut HashSet::new(), &mut path) {
+            let sodium_ctx = self.sodium_ctx();
+            let mut labels: Vec<String> = path.iter().rev().map(|node| node.label()).collect();
+            labels.push(self.label());
+            sodium_ctx.fail(SodiumError::DependencyCycle(labels.join(" -> ")));
+            return false;
+        }
+        true
+    }
+
+    // Cuts the node out of the graph in both directions and drops everything its update
+    // captured, so it is freed by reference counting alone once outside handles go away.
+    pub fn dispose(&self) {
+        let dependents: Vec<Node> = {
+            let data = unsafe { &*(*self.data).get() };
+            data.dependents.iter().flat_map(|dependent| dependent.upgrade()).collect()
+        };
+        for dependent in dependents {
+            dependent.remove_dependency(self);
+        }
+        self.remove_all_dependencies();
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.remove_keep_alive(self);
+        let data = unsafe { &mut *(*self.data).get() };
+        data.dependents.clear();
+        data.update = Box::new(|| false);
+        data.update_dependencies.clear();
+        data.additional_cleanups.clear();
+    }
+
+    pub fn set_name(&self, name: &str) {
+        let data = unsafe { &mut *(*self.data).get() };
+        data.name_op = Some(String::from(name));
+        data.sodium_ctx.set_node_name(data.id, name);
+    }
+
+    pub fn name(&self) -> Option<String> {
+        let data = unsafe { &*(*self.data).get() };
+        data.name_op.clone()
+    }
+
+    // "kind 'name'" when the node has been named, otherwise just "kind".
+    pub fn describe(&self, kind: &str) -> String {
+        match self.name() {
+            Some(name) => format!("{} '{}'", kind, name),
+            None => String::from(kind)
         }
     }
 
+    // What made the node, e.g. "Stream::map_node 'doubled'", for error messages.
+    pub fn label(&self) -> String {
+        self.describe(self.data.desc().unwrap_or("Node"))
+    }
+
+    pub fn mark_source(&self) {
+        let data = unsafe { &mut *(*self.data).get() };
+        data.source = true;
+    }
+
+    pub fn mark_listener(&self) {
+        let data = unsafe { &mut *(*self.data).get() };
+        data.listener = true;
+    }
+
+    pub fn is_listener(&self) -> bool {
+        let data = unsafe { &*(*self.data).get() };
+        data.listener
+    }
+
+    // Listeners attached to this node directly.
+    pub fn listener_count(&self) -> usize {
+        let data = unsafe { &*(*self.data).get() };
+        data.dependents.iter().flat_map(|dependent| dependent.upgrade()).filter(|dependent| dependent.is_listener()).count()
+    }
+
+    // Whether anything depends on this node, listeners included.
+    pub fn has_dependents(&self) -> bool {
+        let data = unsafe { &*(*self.data).get() };
+        data.dependents.iter().any(|dependent| dependent.upgrade().is_some())
+    }
+
+    // Whether a sink feeds this node, through any number of dependencies.
+    pub fn reaches_source(&self) -> bool {
+        fn search(node: &Node, visited: &mut HashSet<u32>) -> bool {
+            if !visited.insert(node.id()) {
+                return false;
+            }
+            let data = unsafe { &*(*node.data).get() };
+            data.source || data.dependencies.iter().any(|dependency| search(dependency, visited))
+        }
+        search(self, &mut HashSet::new())
+    }
+
+    pub fn dependencies(&self) -> Vec<Node> {
+        let data = unsafe { &*(*self.data).get() };
+        data.dependencies.clone()
+    }
+
     pub fn to_dep(&self) -> Dep {
         Dep {
             gc_dep: self.data.to_dep()
@@ -215,6 +452,11 @@ impl Node {
         sodium_ctx.to_be_updated_set.contains(self)
     }
 
+    pub fn id(&self) -> u32 {
+        let data = unsafe { &*(*self.data).get() };
+        data.id.clone()
+    }
+
     pub fn rank(&self) -> u32 {
         let data = unsafe { &*(*self.data).get() };
         data.rank.clone()
@@ -264,7 +506,7 @@ impl Node {
 
 impl Drop for NodeData {
     fn drop(&mut self) {
-        self.sodium_ctx.dec_node_count();
+        self.sodium_ctx.unregister_node(self.id);
     }
 }
 
@@ -324,7 +566,11 @@ impl Ord for Node {
     fn cmp(&self, other: &Node) -> Ordering {
         let self_ = unsafe { &*(*self).data.get() };
         let other = unsafe { &*(*other).data.get() };
-        self_.rank.cmp(&other.rank).reverse()
+        // Lowest rank first, then highest priority, then creation order.
+        self_.rank.cmp(&other.rank)
+            .then(other.priority.cmp(&self_.priority))
+            .then(self_.id.cmp(&other.id))
+            .reverse()
     }
 }
 
@@ -98,7 +98,9 @@ impl Operational {
         let result = Stream {
             data: gc_ctx.new_gc_with_desc(UnsafeCell::new(StreamData {
                 value: value.clone(),
-                node: node2.clone()
+                node: node2.clone(),
+                replay_op: None,
+                stage_op: None
             }), String::from("Operational::split"))
         };
         let node2_dep = node2.to_dep();
@@ -1,15 +1,39 @@
 use sodium::gc::Finalize;
 use sodium::gc::GcCtx;
+use sodium::gc::GcDep;
+use sodium::gc::OomEvent;
 use sodium::gc::Trace;
+use sodium::impl_::Cell;
+use sodium::impl_::EdgeError;
 use sodium::impl_::IsLambda0;
 use sodium::impl_::MemoLazy;
 use sodium::impl_::Node;
+use sodium::impl_::Stream;
+use sodium::impl_::StreamSink;
+use sodium::impl_::WeakCell;
+use sodium::impl_::WeakNode;
+use sodium::impl_::WeakStreamSink;
+use sodium::metrics::MonitorMetrics;
+use std::any::Any;
+use std::any::TypeId;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
 use std::cell::UnsafeCell;
 use std::collections::BinaryHeap;
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::mem::swap;
+use std::panic::AssertUnwindSafe;
+use std::panic::catch_unwind;
 use std::rc::Rc;
 use std::rc::Weak;
+use std::time::Duration;
+use std::time::Instant;
 
 pub struct SodiumCtx {
     pub data: Rc<UnsafeCell<SodiumCtxData>>
@@ -19,35 +43,277 @@ pub struct WeakSodiumCtx {
     pub data: Weak<UnsafeCell<SodiumCtxData>>
 }
 
+// Ids increase by one for every transaction, including ones nested in another's post callbacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TxId(pub u64);
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "tx{}", self.0)
+    }
+}
+
+impl Trace for TxId {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for TxId {}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TxSummary {
+    // The name given to SodiumCtx::transaction_named, for the outer transaction and those run
+    // from its post callbacks.
+    pub name: Option<&'static str>,
+    pub nodes_fired: u32,
+    pub listeners_fired: u32,
+    pub duration: Duration
+}
+
+// What SodiumCtx::set_optimize_graph has done so far. Each fused step is a node the graph
+// would otherwise have had.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OptimizerStats {
+    pub fused_steps: u64,
+    pub folded_cells: u64
+}
+
+pub struct TxObserver {
+    _observer: Rc<dyn Fn(TxSummary)>
+}
+
+// Which listener a listener middleware is wrapping.
+#[derive(Clone, Debug)]
+pub struct ListenerInfo {
+    pub listener_id: u32,
+    // The name of the stream or cell listened to, if it was given one.
+    pub name: Option<String>,
+    pub tx_id: TxId
+}
+
+pub struct ListenerMiddleware {
+    _middleware: Rc<dyn Fn(&ListenerInfo, &dyn Fn())>
+}
+
+// Cells commit their new values one by one while post callbacks run, so a plain sample() there
+// can see some cells before and some after the transaction.
+pub struct SampleReader {
+    committed: bool
+}
+
+pub struct SodiumScope {
+    sodium_ctx: SodiumCtx,
+    nodes: Rc<UnsafeCell<Vec<WeakNode>>>
+}
+
+pub struct NodeRecord {
+    pub desc: String,
+    pub name_op: Option<String>,
+    pub backtrace_op: Option<String>
+}
+
 pub struct SodiumCtxData {
     pub gc_ctx: GcCtx,
     pub next_id: u32,
+    pub transaction_id: u64,
+    pub outer_transaction_id: u64,
     pub transaction_depth: u32,
+    pub in_post_trans: bool,
     pub callback_depth: u32,
     pub to_be_updated: BinaryHeap<Node>,
     pub to_be_updated_set: HashSet<Node>,
     pub resort_required: bool,
     pub pre_trans: Vec<Box<FnMut()>>,
     pub post_trans: Vec<Box<FnMut()>>,
+    // Emptied buffers swapped back in after running pre_trans and post_trans, so a steady
+    // stream of transactions reuses the same allocations.
+    pub spare_trans: Vec<Box<dyn FnMut()>>,
+    // Post callbacks that are queued over and over, e.g. clearing a sink's value, kept alive
+    // by their owner so queuing one doesn't allocate.
+    pub post_shared_trans: Vec<Rc<dyn Fn()>>,
+    pub after_outer_trans: Vec<Box<dyn FnMut()>>,
+    pub running_after_outer_trans: bool,
     pub node_count: u32,
-    pub keep_alive: HashSet<Node>
+    pub node_registry: HashMap<u32,NodeRecord>,
+    pub node_limit_op: Option<u32>,
+    pub node_limit_handler_op: Option<Rc<dyn Fn(u32)>>,
+    pub track_node_sites: bool,
+    pub optimize_graph: bool,
+    pub optimizer_stats: OptimizerStats,
+    pub keep_alive: HashSet<Node>,
+    pub scope_stack: Vec<Rc<UnsafeCell<Vec<WeakNode>>>>,
+    pub listener_errors: Vec<String>,
+    pub freeze_depth: u32,
+    pub frozen_deliveries: Vec<Box<dyn FnMut()>>,
+    pub frozen_index: HashMap<u32,usize>,
+    pub tx_observers: Vec<Weak<dyn Fn(TxSummary)>>,
+    pub listener_middlewares: Vec<Weak<dyn Fn(&ListenerInfo, &dyn Fn())>>,
+    // An observer owned by the context, for one set up along with it.
+    pub tx_hook_op: Option<TxObserver>,
+    pub tx_start_op: Option<Instant>,
+    pub tx_name_op: Option<&'static str>,
+    pub tx_nodes_fired: u32,
+    pub tx_listeners_fired: u32,
+    pub oom_events: Diagnostics<OomEvent>,
+    pub errors: Diagnostics<SodiumError>,
+    // For take_errors, whether or not anything listens to errors.
+    pub unread_errors: VecDeque<SodiumError>,
+    pub panic_free: bool,
+    pub aborting: bool,
+    pub batching: bool,
+    // Sends made while suspended, each with the outer transaction it was made in if any, so
+    // those made together are replayed together.
+    pub suspended_sends_op: Option<Vec<(Option<u64>,Box<dyn FnMut()>)>>,
+    pub resumes: u64,
+    pub resumed_dropping_elapsed: bool,
+    // Interned constant cells by type and hash of their value, each entry an
+    // InternedConstant<A>.
+    pub constants: HashMap<(TypeId,u64),Vec<Box<dyn InternedEntry>>>,
+    // Values set with set_resource by type, each a Resource<A>.
+    pub resources: HashMap<TypeId,Box<dyn Any>>,
+    pub monitors: HashMap<String,MonitorMetrics>
+}
+
+// Only the weak cell is kept, the value is compared by sampling it, so the table holds
+// nothing alive once the cells are gone.
+struct InternedConstant<A> {
+    cell: WeakCell<A>
+}
+
+pub trait InternedEntry {
+    fn is_alive(&self) -> bool;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<A: Clone + Trace + Finalize + 'static> InternedEntry for InternedConstant<A> {
+    fn is_alive(&self) -> bool {
+        self.cell.upgrade().is_some()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// The cell is only made when asked for and only kept while something else holds on to it.
+// value isn't traced, a Gc handle in it stays a root for as long as the context lives.
+struct Resource<A> {
+    value: Option<A>,
+    sink_op: Option<WeakStreamSink<Option<A>>>,
+    cell_op: Option<WeakCell<Option<A>>>
+}
+
+// Reports for streams like oom_events and errors, sent at the end of the outer transaction
+// they were made in, or of the next one when none was running.
+pub struct Diagnostics<A> {
+    sinks: Vec<WeakStreamSink<A>>,
+    pending: Vec<A>,
+    delivering: bool
+}
+
+impl<A> Diagnostics<A> {
+    fn new() -> Diagnostics<A> {
+        Diagnostics {
+            sinks: Vec::new(),
+            pending: Vec::new(),
+            delivering: false
+        }
+    }
 }
 
+fn oom_events_of(data: &mut SodiumCtxData) -> &mut Diagnostics<OomEvent> {
+    &mut data.oom_events
+}
+
+// The most errors take_errors holds on to, older ones are dropped first.
+const MAX_UNREAD_ERRORS: usize = 256;
+
+fn errors_of(data: &mut SodiumCtxData) -> &mut Diagnostics<SodiumError> {
+    &mut data.errors
+}
+
+// What would have been a panic, see SodiumCtx::set_panic_free. Each carries the description
+// of the node involved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SodiumError {
+    LoopedTwice(String),
+    SendFromCallback(String),
+    DependencyCycle(String),
+    // With the edge that was refused, as dependent -> dependency.
+    InvalidEdge(EdgeError, String),
+    // Everything else, with the message it would have panicked with.
+    Misuse(String)
+}
+
+impl fmt::Display for SodiumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SodiumError::LoopedTwice(ref desc) => write!(f, "{} looped more than once.", desc),
+            SodiumError::SendFromCallback(ref desc) => write!(f, "{}::send can not be called from a sodium callback, consider using SodiumCtx::post to send after the end of transaction.", desc),
+            SodiumError::DependencyCycle(ref path) => write!(f, "instantaneous dependency cycle: {}", path),
+            SodiumError::InvalidEdge(error, ref edge) => write!(f, "invalid dependency {}: {}", edge, error),
+            SodiumError::Misuse(ref msg) => write!(f, "{}", msg)
+        }
+    }
+}
+
+impl Trace for SodiumError {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for SodiumError {}
+
 impl SodiumCtx {
     pub fn new() -> SodiumCtx {
         SodiumCtx {
             data: Rc::new(UnsafeCell::new(SodiumCtxData {
                 gc_ctx: GcCtx::new(),
                 next_id: 0,
+                transaction_id: 0,
+                outer_transaction_id: 0,
                 transaction_depth: 0,
+                in_post_trans: false,
                 callback_depth: 0,
                 to_be_updated: BinaryHeap::new(),
                 to_be_updated_set: HashSet::new(),
                 resort_required: false,
                 pre_trans: Vec::new(),
                 post_trans: Vec::new(),
+                spare_trans: Vec::new(),
+                post_shared_trans: Vec::new(),
+                after_outer_trans: Vec::new(),
+                running_after_outer_trans: false,
                 node_count: 0,
-                keep_alive: HashSet::new()
+                node_registry: HashMap::new(),
+                node_limit_op: None,
+                node_limit_handler_op: None,
+                track_node_sites: false,
+                optimize_graph: false,
+                optimizer_stats: OptimizerStats::default(),
+                keep_alive: HashSet::new(),
+                scope_stack: Vec::new(),
+                listener_errors: Vec::new(),
+                freeze_depth: 0,
+                frozen_deliveries: Vec::new(),
+                frozen_index: HashMap::new(),
+                tx_observers: Vec::new(),
+                listener_middlewares: Vec::new(),
+                tx_hook_op: None,
+                tx_start_op: None,
+                tx_name_op: None,
+                tx_nodes_fired: 0,
+                tx_listeners_fired: 0,
+                oom_events: Diagnostics::new(),
+                errors: Diagnostics::new(),
+                unread_errors: VecDeque::new(),
+                panic_free: false,
+                aborting: false,
+                batching: false,
+                suspended_sends_op: None,
+                resumes: 0,
+                resumed_dropping_elapsed: false,
+                constants: HashMap::new(),
+                resources: HashMap::new(),
+                monitors: HashMap::new()
             }))
         }
     }
@@ -76,6 +342,46 @@ impl SodiumCtx {
         id
     }
 
+    // Id of the running transaction, or of the last one to run when called outside a transaction.
+    pub fn current_tx_id(&self) -> TxId {
+        let self_ = unsafe { &*(*self.data).get() };
+        TxId(self_.transaction_id)
+    }
+
+    // Transactions started from post callbacks (e.g. by Operational::split) are nested in the
+    // outer transaction and share its id here.
+    pub fn outer_tx_id(&self) -> TxId {
+        let self_ = unsafe { &*(*self.data).get() };
+        TxId(self_.outer_transaction_id)
+    }
+
+    // Runs f once the outer transaction, including everything started from its post callbacks,
+    // has finished.
+    pub fn after_outer_transaction<F: FnMut() + 'static>(&self, f: F) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.after_outer_trans.push(Box::new(f));
+    }
+
+    pub fn sample_consistent<R, F: FnOnce(&SampleReader) -> R>(&self, f: F) -> R {
+        let self_ = unsafe { &*(*self.data).get() };
+        f(&SampleReader { committed: self_.in_post_trans })
+    }
+
+    pub fn create_scope(&self) -> SodiumScope {
+        SodiumScope {
+            sodium_ctx: self.clone(),
+            nodes: Rc::new(UnsafeCell::new(Vec::new()))
+        }
+    }
+
+    pub fn track_in_scope(&self, node: &Node) {
+        let self_ = unsafe { &*(*self.data).get() };
+        if let Some(nodes) = self_.scope_stack.last() {
+            let nodes = unsafe { &mut *(**nodes).get() };
+            nodes.push(node.downgrade());
+        }
+    }
+
     pub fn add_keep_alive(&self, node: Node) {
         let self_ = unsafe { &mut *(*self.data).get() };
         self_.keep_alive.insert(node);
@@ -86,14 +392,121 @@ impl SodiumCtx {
         self_.keep_alive.remove(node);
     }
 
-    pub fn inc_node_count(&self) {
+    // Backtraces are only captured while track_node_sites is set.
+    pub fn register_node(&self, id: u32, desc: &str) {
         let self_ = unsafe { &mut *(*self.data).get() };
         self_.node_count = self_.node_count + 1;
+        let backtrace_op =
+            if self_.track_node_sites {
+                Some(format!("{}", Backtrace::force_capture()))
+            } else {
+                None
+            };
+        self_.node_registry.insert(id, NodeRecord { desc: String::from(desc), name_op: None, backtrace_op });
+        if let Some(limit) = self_.node_limit_op {
+            if self_.node_count > limit {
+                match self_.node_limit_handler_op.clone() {
+                    Some(handler) => handler(self_.node_count),
+                    None => panic!("node limit of {} exceeded, {} nodes are alive", limit, self_.node_count)
+                }
+            }
+        }
     }
 
-    pub fn dec_node_count(&self) {
+    pub fn unregister_node(&self, id: u32) {
         let self_ = unsafe { &mut *(*self.data).get() };
         self_.node_count = self_.node_count - 1;
+        self_.node_registry.remove(&id);
+    }
+
+    pub fn set_node_name(&self, id: u32, name: &str) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if let Some(record) = self_.node_registry.get_mut(&id) {
+            record.name_op = Some(String::from(name));
+        }
+    }
+
+    pub fn set_node_limit(&self, limit_op: Option<u32>) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.node_limit_op = limit_op;
+    }
+
+    pub fn set_node_limit_handler<F: Fn(u32) + 'static>(&self, handler: F) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.node_limit_handler_op = Some(Rc::new(handler));
+    }
+
+    pub fn set_track_node_sites(&self, track: bool) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.track_node_sites = track;
+    }
+
+    pub fn set_optimize_graph(&self, optimize: bool) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.optimize_graph = optimize;
+    }
+
+    pub fn optimize_graph(&self) -> bool {
+        let self_ = unsafe { &*(*self.data).get() };
+        self_.optimize_graph
+    }
+
+    pub fn optimizer_stats(&self) -> OptimizerStats {
+        let self_ = unsafe { &*(*self.data).get() };
+        self_.optimizer_stats
+    }
+
+    pub fn record_fused_step(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.optimizer_stats.fused_steps += 1;
+    }
+
+    pub fn record_folded_cell(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.optimizer_stats.folded_cells += 1;
+    }
+
+    pub fn live_nodes(&self) -> Vec<(u32,String)> {
+        let self_ = unsafe { &*(*self.data).get() };
+        let mut nodes: Vec<(u32,String)> = self_.node_registry
+            .iter()
+            .map(|(id, record)| (*id, record.name_op.clone().unwrap_or_else(|| record.desc.clone())))
+            .collect();
+        nodes.sort();
+        nodes
+    }
+
+    // Strong listeners that no sink can reach, so they will never fire again.
+    pub fn find_orphan_listeners(&self) -> Vec<(u32,String)> {
+        let self_ = unsafe { &*(*self.data).get() };
+        let mut orphans: Vec<(u32,String)> = self_.keep_alive
+            .iter()
+            .filter(|node| node.is_listener() && !node.reaches_source())
+            .map(|node| (node.id(), node.label()))
+            .collect();
+        orphans.sort();
+        orphans
+    }
+
+    pub fn node_allocation_sites(&self, top: usize) -> Vec<(String,u32)> {
+        let self_ = unsafe { &*(*self.data).get() };
+        let mut counts: HashMap<String,u32> = HashMap::new();
+        for record in self_.node_registry.values() {
+            let site =
+                match record.name_op {
+                    Some(ref name) => name.clone(),
+                    None =>
+                        record.backtrace_op
+                            .as_ref()
+                            .and_then(|backtrace| allocation_site(backtrace))
+                            .unwrap_or_else(|| record.desc.clone())
+                };
+            *counts.entry(site).or_insert(0) += 1;
+        }
+        let mut sites: Vec<(String,u32)> = counts.into_iter().collect();
+        sites.sort_by(|&(ref site1, count1), &(ref site2, count2)| count2.cmp(&count1).then(site1.cmp(site2)));
+        sites.truncate(top);
+        sites
     }
 
     pub fn node_count(&self) -> u32 {
@@ -116,6 +529,330 @@ impl SodiumCtx {
         self_.callback_depth
     }
 
+    pub fn run_listener<F: FnOnce()>(&self, key: u32, name_op: Option<String>, f: F) {
+        let middlewares: Vec<Rc<dyn Fn(&ListenerInfo, &dyn Fn())>>;
+        {
+            let self_ = unsafe { &mut *(*self.data).get() };
+            self_.tx_listeners_fired = self_.tx_listeners_fired + 1;
+            self_.listener_middlewares.retain(|middleware| middleware.upgrade().is_some());
+            middlewares = self_.listener_middlewares.iter().filter_map(|middleware| middleware.upgrade()).collect();
+        }
+        let result =
+            if middlewares.is_empty() {
+                catch_unwind(AssertUnwindSafe(f))
+            } else {
+                let info = ListenerInfo {
+                    listener_id: key,
+                    name: name_op.clone(),
+                    tx_id: self.current_tx_id()
+                };
+                // Middleware only gets a Fn, calling it again after the listener ran does nothing.
+                let f = RefCell::new(Some(f));
+                let listener = || {
+                    let f_op = f.borrow_mut().take();
+                    if let Some(f) = f_op {
+                        f();
+                    }
+                };
+                catch_unwind(AssertUnwindSafe(|| run_middlewares(&middlewares, &info, &listener)))
+            };
+        if let Err(err) = result {
+            let msg =
+                if let Some(msg) = err.downcast_ref::<&'static str>() {
+                    String::from(*msg)
+                } else if let Some(msg) = err.downcast_ref::<String>() {
+                    msg.clone()
+                } else {
+                    String::from("listener panicked")
+                };
+            let msg =
+                match name_op {
+                    Some(name) => format!("listener on '{}': {}", name, msg),
+                    None => msg
+                };
+            let self_ = unsafe { &mut *(*self.data).get() };
+            self_.listener_errors.push(msg);
+        }
+    }
+
+    // While frozen each listener only keeps the last value it was given, thaw() delivers it.
+    pub fn deliver<A: Clone + 'static, CALLBACK: FnMut(&A) + 'static>(&self, key: u32, name_op: Option<String>, callback: &Rc<UnsafeCell<CALLBACK>>, a: &A) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if self_.freeze_depth == 0 {
+            let callback = unsafe { &mut *(**callback).get() };
+            self.run_listener(key, name_op, || callback(a));
+            return;
+        }
+        let sodium_ctx = self.clone();
+        let callback = Rc::downgrade(callback);
+        let a = a.clone();
+        let deliver: Box<dyn FnMut()> = Box::new(move || {
+            if let Some(callback) = callback.upgrade() {
+                let callback = unsafe { &mut *(*callback).get() };
+                sodium_ctx.run_listener(key, name_op.clone(), || callback(&a));
+            }
+        });
+        match self_.frozen_index.get(&key) {
+            Some(&index) => self_.frozen_deliveries[index] = deliver,
+            None => {
+                self_.frozen_index.insert(key, self_.frozen_deliveries.len());
+                self_.frozen_deliveries.push(deliver);
+            }
+        }
+    }
+
+    pub fn freeze(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.freeze_depth = self_.freeze_depth + 1;
+    }
+
+    pub fn thaw(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if self_.freeze_depth == 0 {
+            self.fail(SodiumError::Misuse(String::from("SodiumCtx::thaw called without a matching freeze.")));
+            return;
+        }
+        self_.freeze_depth = self_.freeze_depth - 1;
+        if self_.freeze_depth > 0 {
+            return;
+        }
+        let mut deliveries = Vec::new();
+        swap(&mut self_.frozen_deliveries, &mut deliveries);
+        self_.frozen_index.clear();
+        self.inc_callback_depth();
+        for mut deliver in deliveries {
+            deliver();
+        }
+        self.dec_callback_depth();
+    }
+
+    pub fn take_listener_errors(&self) -> Vec<String> {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let mut errors = Vec::new();
+        swap(&mut self_.listener_errors, &mut errors);
+        errors
+    }
+
+    pub fn on_transaction_end<F: Fn(TxSummary) + 'static>(&self, f: F) -> TxObserver {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let observer: Rc<dyn Fn(TxSummary)> = Rc::new(f);
+        self_.tx_observers.push(Rc::downgrade(&observer));
+        TxObserver {
+            _observer: observer
+        }
+    }
+
+    pub fn add_listener_middleware<F: Fn(&ListenerInfo, &dyn Fn()) + 'static>(&self, f: F) -> ListenerMiddleware {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let middleware: Rc<dyn Fn(&ListenerInfo, &dyn Fn())> = Rc::new(f);
+        self_.listener_middlewares.push(Rc::downgrade(&middleware));
+        ListenerMiddleware {
+            _middleware: middleware
+        }
+    }
+
+    // A constant cell shared with every other asked for with an equal value, for as long as
+    // one of them is still alive.
+    pub fn interned_constant<A: Clone + Eq + Hash + Trace + Finalize + 'static>(&self, value: A) -> Cell<A> {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let key = (TypeId::of::<A>(), hasher.finish());
+        {
+            let self_ = unsafe { &mut *(*self.data).get() };
+            if let Some(entries) = self_.constants.get(&key) {
+                let found_op = entries
+                    .iter()
+                    .filter_map(|entry| entry.as_any().downcast_ref::<InternedConstant<A>>())
+                    .filter_map(|entry| entry.cell.upgrade())
+                    .find(|cell| cell.sample_no_trans() == value);
+                if let Some(cell) = found_op {
+                    return cell;
+                }
+            }
+        }
+        let cell = Cell::new(self, value);
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.constants.retain(|_, entries| {
+            entries.retain(|entry| entry.is_alive());
+            !entries.is_empty()
+        });
+        self_.constants
+            .entry(key)
+            .or_insert_with(Vec::new)
+            .push(Box::new(InternedConstant { cell: cell.downgrade() }));
+        cell
+    }
+
+    pub fn resource<A: Clone + Trace + Finalize + 'static>(&self) -> Cell<Option<A>> {
+        let value = {
+            let self_ = unsafe { &mut *(*self.data).get() };
+            let resource = resource_of::<A>(self_);
+            if let Some(cell) = resource.cell_op.as_ref().and_then(|cell| cell.upgrade()) {
+                return cell;
+            }
+            resource.value.clone()
+        };
+        let sink = StreamSink::new(self);
+        let cell = sink.to_stream().hold(value);
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let resource = resource_of::<A>(self_);
+        resource.sink_op = Some(sink.downgrade());
+        resource.cell_op = Some(cell.downgrade());
+        cell
+    }
+
+    pub fn set_resource<A: Clone + Trace + Finalize + 'static>(&self, value: A) {
+        let sink_op = {
+            let self_ = unsafe { &mut *(*self.data).get() };
+            let resource = resource_of::<A>(self_);
+            resource.value = Some(value.clone());
+            resource.sink_op.as_ref().and_then(|sink| sink.upgrade())
+        };
+        if let Some(sink) = sink_op {
+            sink.send(Some(value));
+        }
+    }
+
+    pub fn record_monitor_event(&self, name: &str, inter_event_op: Option<Duration>) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let metrics = self_.monitors.entry(String::from(name)).or_insert_with(MonitorMetrics::new);
+        metrics.events = metrics.events + 1;
+        if let Some(inter_event) = inter_event_op {
+            metrics.inter_event.record(inter_event);
+        }
+    }
+
+    pub fn record_monitor_processing(&self, name: &str, processing: Duration) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.monitors.entry(String::from(name)).or_insert_with(MonitorMetrics::new).processing.record(processing);
+    }
+
+    pub fn metrics(&self) -> Vec<(String,MonitorMetrics)> {
+        let self_ = unsafe { &*(*self.data).get() };
+        let mut metrics: Vec<(String,MonitorMetrics)> = self_.monitors.iter().map(|(name, metrics)| (name.clone(), metrics.clone())).collect();
+        metrics.sort_by(|&(ref a, _), &(ref b, _)| a.cmp(b));
+        metrics
+    }
+
+    pub fn reset_metrics(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.monitors.clear();
+    }
+
+    // Like on_transaction_end, but lives as long as the context. Replaces the last one.
+    pub fn set_transaction_hook<F: Fn(TxSummary) + 'static>(&self, f: F) {
+        let observer = self.on_transaction_end(f);
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.tx_hook_op = Some(observer);
+    }
+
+    // The allocations that went over the gc memory limit. Allocations made while they are
+    // sent aren't reported, so a graph stuck over the limit can't feed itself.
+    pub fn oom_events(&self) -> Stream<OomEvent> {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if self_.oom_events.sinks.is_empty() {
+            let weak_sodium_ctx = self.downgrade();
+            self_.gc_ctx.set_oom_handler(move |event| {
+                if let Some(sodium_ctx) = weak_sodium_ctx.upgrade() {
+                    sodium_ctx.report(oom_events_of, *event);
+                }
+            });
+        }
+        self.diagnostics(oom_events_of)
+    }
+
+    // With it on, misuse of the library that would panic (looping twice, sending from a
+    // callback, a dependency cycle, ...) is reported on errors() instead. The offending call
+    // does nothing, and if it was made in a transaction the updates that transaction still had
+    // to run are dropped. Updates already run, and post callbacks, still happen.
+    pub fn set_panic_free(&self, panic_free: bool) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.panic_free = panic_free;
+    }
+
+    pub fn errors(&self) -> Stream<SodiumError> {
+        self.diagnostics(errors_of)
+    }
+
+    // Panics with the error, or in panic-free mode reports it and aborts the transaction.
+    pub fn fail(&self, error: SodiumError) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if !self_.panic_free {
+            panic!("{}", error);
+        }
+        if self_.transaction_depth > 0 {
+            self_.aborting = true;
+        }
+        if self_.unread_errors.len() == MAX_UNREAD_ERRORS {
+            self_.unread_errors.pop_front();
+        }
+        self_.unread_errors.push_back(error.clone());
+        self.report(errors_of, error);
+    }
+
+    pub fn take_errors(&self) -> Vec<SodiumError> {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.unread_errors.drain(..).collect()
+    }
+
+    fn diagnostics<A: Clone + Trace + Finalize + 'static>(&self, diagnostics_of: fn(&mut SodiumCtxData) -> &mut Diagnostics<A>) -> Stream<A> {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let sink = StreamSink::new(self);
+        diagnostics_of(self_).sinks.push(sink.downgrade());
+        sink.to_stream()
+    }
+
+    fn report<A: Clone + Trace + Finalize + 'static>(&self, diagnostics_of: fn(&mut SodiumCtxData) -> &mut Diagnostics<A>, a: A) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let diagnostics = diagnostics_of(self_);
+        if diagnostics.delivering || diagnostics.sinks.is_empty() {
+            return;
+        }
+        if diagnostics.pending.is_empty() {
+            let weak_sodium_ctx = self.downgrade();
+            self.after_outer_transaction(move || {
+                if let Some(sodium_ctx) = weak_sodium_ctx.upgrade() {
+                    sodium_ctx.deliver_reports(diagnostics_of);
+                }
+            });
+        }
+        diagnostics.pending.push(a);
+    }
+
+    fn deliver_reports<A: Clone + Trace + Finalize + 'static>(&self, diagnostics_of: fn(&mut SodiumCtxData) -> &mut Diagnostics<A>) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let diagnostics = diagnostics_of(self_);
+        let mut pending = Vec::new();
+        swap(&mut diagnostics.pending, &mut pending);
+        diagnostics.sinks.retain(|sink| sink.upgrade().is_some());
+        let sinks: Vec<StreamSink<A>> = diagnostics.sinks.iter().filter_map(|sink| sink.upgrade()).collect();
+        diagnostics.delivering = true;
+        for a in pending {
+            for sink in &sinks {
+                sink.send(a.clone());
+            }
+        }
+        let self_ = unsafe { &mut *(*self.data).get() };
+        diagnostics_of(self_).delivering = false;
+    }
+
+    fn end_transaction(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let summary = TxSummary {
+            name: self_.tx_name_op,
+            nodes_fired: self_.tx_nodes_fired,
+            listeners_fired: self_.tx_listeners_fired,
+            duration: self_.tx_start_op.take().map(|start| start.elapsed()).unwrap_or(Duration::from_secs(0))
+        };
+        self_.tx_nodes_fired = 0;
+        self_.tx_listeners_fired = 0;
+        self_.tx_observers.retain(|observer| observer.upgrade().is_some());
+        let observers: Vec<Rc<dyn Fn(TxSummary)>> = self_.tx_observers.iter().filter_map(|observer| observer.upgrade()).collect();
+        for observer in observers {
+            observer(summary);
+        }
+    }
+
     pub fn pre<F: FnMut() + 'static>(&self, f: F) {
         self.transaction(|| {
             let self_ = unsafe { &mut *(*self.data).get() };
@@ -130,15 +867,167 @@ impl SodiumCtx {
         });
     }
 
+    // Like post, for a callback its owner holds on to and queues once per transaction.
+    pub fn post_shared(&self, f: &Rc<dyn Fn()>) {
+        self.transaction(|| {
+            let self_ = unsafe { &mut *(*self.data).get() };
+            self_.post_shared_trans.push(f.clone());
+        });
+    }
+
     pub fn transaction<A,CODE:FnOnce()->A>(&self, code: CODE)->A {
+        self.open_transaction();
+        let result = code();
+        self.close_transaction();
+        result
+    }
+
+    // Only the first name counts when named transactions nest.
+    pub fn transaction_named<A,CODE:FnOnce()->A>(&self, name: &'static str, code: CODE)->A {
         let self_ = unsafe { &mut *(*self.data).get() };
+        if self_.tx_name_op.is_none() {
+            self_.tx_name_op = Some(name);
+        }
+        self.transaction(code)
+    }
+
+    fn open_transaction(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if self_.transaction_depth == 0 {
+            self_.transaction_id = self_.transaction_id + 1;
+            if !self_.in_post_trans {
+                self_.outer_transaction_id = self_.outer_transaction_id + 1;
+            }
+            if !self_.tx_observers.is_empty() && self_.tx_start_op.is_none() {
+                self_.tx_start_op = Some(Instant::now());
+            }
+        }
         self_.transaction_depth = self_.transaction_depth + 1;
-        let result = code();
+    }
+
+    fn close_transaction(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
         self_.transaction_depth = self_.transaction_depth - 1;
         if self_.transaction_depth == 0 {
             self.propergate();
+            if !self_.in_post_trans && !self_.running_after_outer_trans {
+                self.run_after_outer_trans();
+            }
         }
-        result
+    }
+
+    // Opens a transaction that stays open until end_batch, so all the sends an event loop
+    // makes in between go out together. A sink sent to more than once keeps the last value, or
+    // combines them if it has a coalescer. Batches don't nest and only start outside any
+    // transaction.
+    // False if it failed in panic-free mode.
+    pub fn begin_batch(&self) -> bool {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if self_.batching {
+            self.fail(SodiumError::Misuse(String::from("SodiumCtx::begin_batch called while a batch was already open.")));
+            return false;
+        }
+        if self_.transaction_depth > 0 || self_.in_post_trans || self_.callback_depth > 0 {
+            self.fail(SodiumError::Misuse(String::from("SodiumCtx::begin_batch can not be called inside a transaction.")));
+            return false;
+        }
+        self_.batching = true;
+        self.open_transaction();
+        true
+    }
+
+    pub fn end_batch(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if !self_.batching {
+            self.fail(SodiumError::Misuse(String::from("SodiumCtx::end_batch called without a matching begin_batch.")));
+            return;
+        }
+        if self_.transaction_depth != 1 || self_.callback_depth > 0 {
+            self.fail(SodiumError::Misuse(String::from("SodiumCtx::end_batch can not be called inside a transaction.")));
+            return;
+        }
+        self_.batching = false;
+        self.close_transaction();
+    }
+
+    pub fn is_batching(&self) -> bool {
+        let self_ = unsafe { &*(*self.data).get() };
+        self_.batching
+    }
+
+    pub fn suspend(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if self_.suspended_sends_op.is_some() {
+            self.fail(SodiumError::Misuse(String::from("SodiumCtx::suspend called while already suspended.")));
+            return;
+        }
+        self_.suspended_sends_op = Some(Vec::new());
+    }
+
+    pub fn resume(&self, drop_elapsed: bool) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        if self_.transaction_depth > 0 || self_.in_post_trans || self_.callback_depth > 0 {
+            self.fail(SodiumError::Misuse(String::from("SodiumCtx::resume can not be called inside a transaction.")));
+            return;
+        }
+        let sends =
+            match self_.suspended_sends_op.take() {
+                Some(sends) => sends,
+                None => {
+                    self.fail(SodiumError::Misuse(String::from("SodiumCtx::resume called without a matching suspend.")));
+                    return;
+                }
+            };
+        self_.resumes = self_.resumes + 1;
+        self_.resumed_dropping_elapsed = drop_elapsed;
+        let mut sends = sends.into_iter().peekable();
+        while let Some((group_op, mut send)) = sends.next() {
+            self.transaction(|| {
+                send();
+                while let Some(&(next_group_op, _)) = sends.peek() {
+                    if group_op.is_none() || next_group_op != group_op {
+                        break;
+                    }
+                    let (_, mut send) = sends.next().unwrap();
+                    send();
+                }
+            });
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        let self_ = unsafe { &*(*self.data).get() };
+        self_.suspended_sends_op.is_some()
+    }
+
+    pub fn resumes(&self) -> (u64,bool) {
+        let self_ = unsafe { &*(*self.data).get() };
+        (self_.resumes, self_.resumed_dropping_elapsed)
+    }
+
+    // Keeps a sink's send for resume(), does nothing unless suspended.
+    pub fn buffer_send<F: FnMut() + 'static>(&self, send: F) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let group_op = if self_.transaction_depth > 0 { Some(self_.outer_transaction_id) } else { None };
+        if let Some(ref mut sends) = self_.suspended_sends_op {
+            sends.push((group_op, Box::new(send)));
+        }
+    }
+
+    fn run_after_outer_trans(&self) {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        self_.running_after_outer_trans = true;
+        loop {
+            let mut after_outer_trans = Vec::new();
+            swap(&mut self_.after_outer_trans, &mut after_outer_trans);
+            if after_outer_trans.is_empty() {
+                break;
+            }
+            for mut f in after_outer_trans {
+                f();
+            }
+        }
+        self_.running_after_outer_trans = false;
     }
 
     pub fn schedule_update_sort(&self) {
@@ -146,6 +1035,13 @@ impl SodiumCtx {
         self_.resort_required = true;
     }
 
+    fn take_spare_trans(&self) -> Vec<Box<dyn FnMut()>> {
+        let self_ = unsafe { &mut *(*self.data).get() };
+        let mut spare_trans = Vec::new();
+        swap(&mut self_.spare_trans, &mut spare_trans);
+        spare_trans
+    }
+
     fn propergate(&self) {
         let self_ = unsafe { &mut *(*self.data).get() };
         if self_.resort_required {
@@ -156,40 +1052,133 @@ impl SodiumCtx {
             self_.resort_required = false;
         }
         loop {
-            let mut pre_trans = Vec::new();
+            let mut pre_trans = self.take_spare_trans();
             swap(&mut self_.pre_trans, &mut pre_trans);
-            for mut f in pre_trans {
+            for mut f in pre_trans.drain(..) {
                 f();
             }
+            self_.spare_trans = pre_trans;
             if self_.pre_trans.is_empty() {
                 break;
             }
         }
         self_.transaction_depth = self_.transaction_depth + 1;
         loop {
+            if self_.aborting {
+                self_.to_be_updated.clear();
+                self_.to_be_updated_set.clear();
+                break;
+            }
             let node_op = self_.to_be_updated.pop();
             match node_op {
                 Some(node) => {
                     self_.to_be_updated_set.remove(&node);
                     let mark_dependents_dirty = node.update();
                     if mark_dependents_dirty {
+                        self_.tx_nodes_fired = self_.tx_nodes_fired + 1;
                         node.mark_dependents_dirty();
                     }
                 },
                 None => break
             }
         }
+        self_.aborting = false;
         self_.transaction_depth = self_.transaction_depth - 1;
+        let in_post_trans = self_.in_post_trans;
+        self_.in_post_trans = true;
         loop {
-            let mut post_trans = Vec::new();
+            while let Some(f) = self_.post_shared_trans.pop() {
+                f();
+            }
+            let mut post_trans = self.take_spare_trans();
             swap(&mut self_.post_trans, &mut post_trans);
-            for mut f in post_trans {
+            for mut f in post_trans.drain(..) {
                 f();
             }
-            if self_.post_trans.is_empty() {
+            self_.spare_trans = post_trans;
+            if self_.post_trans.is_empty() && self_.post_shared_trans.is_empty() {
                 break;
             }
         }
+        self_.in_post_trans = in_post_trans;
+        if !self_.tx_observers.is_empty() {
+            self.end_transaction();
+        }
+        if !in_post_trans {
+            self_.tx_name_op = None;
+        }
+    }
+}
+
+fn resource_of<A: 'static>(data: &mut SodiumCtxData) -> &mut Resource<A> {
+    data.resources
+        .entry(TypeId::of::<A>())
+        .or_insert_with(|| Box::new(Resource::<A> { value: None, sink_op: None, cell_op: None }))
+        .downcast_mut::<Resource<A>>()
+        .unwrap()
+}
+
+fn allocation_site(backtrace: &str) -> Option<String> {
+    backtrace
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| line.starts_with("at "))
+        .map(|line| String::from(&line[3..]))
+        .find(|location| location.contains("src/") && !location.contains("src/sodium/") && !location.contains("/rustc/"))
+}
+
+impl SampleReader {
+    // Reads every cell as of the start of the transaction, or once post callbacks have started
+    // as of its end.
+    pub fn get<A: Clone + Trace + Finalize + 'static>(&self, ca: &Cell<A>) -> A {
+        let value =
+            if self.committed {
+                ca._next_value()
+            } else {
+                ca._value()
+            };
+        let value = unsafe { &*(**value).get() };
+        value.get().clone()
+    }
+}
+
+// Leaves the scope when dropped, so a panic in SodiumScope::run does not leave it entered.
+struct ScopeExit<'a> {
+    sodium_ctx: &'a SodiumCtx
+}
+
+impl<'a> Drop for ScopeExit<'a> {
+    fn drop(&mut self) {
+        let data = unsafe { &mut *(*self.sodium_ctx.data).get() };
+        data.scope_stack.pop();
+    }
+}
+
+impl SodiumScope {
+    pub fn run<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        {
+            let data = unsafe { &mut *(*self.sodium_ctx.data).get() };
+            data.scope_stack.push(self.nodes.clone());
+        }
+        let _exit = ScopeExit { sodium_ctx: &self.sodium_ctx };
+        f()
+    }
+
+    pub fn node_count(&self) -> usize {
+        let nodes = unsafe { &*(*self.nodes).get() };
+        nodes.iter().filter(|node| node.upgrade().is_some()).count()
+    }
+
+    pub fn dispose(self) {
+        let nodes: Vec<Node> = {
+            let nodes = unsafe { &mut *(*self.nodes).get() };
+            let mut nodes2 = Vec::new();
+            swap(nodes, &mut nodes2);
+            nodes2.iter().flat_map(|node| node.upgrade()).collect()
+        };
+        for node in &nodes {
+            node.dispose();
+        }
     }
 }
 
@@ -199,6 +1188,14 @@ impl WeakSodiumCtx {
     }
 }
 
+// The first middleware added is the outermost, the listener itself runs inside the last.
+fn run_middlewares(middlewares: &[Rc<dyn Fn(&ListenerInfo, &dyn Fn())>], info: &ListenerInfo, listener: &dyn Fn()) {
+    match middlewares.split_first() {
+        Some((middleware, rest)) => middleware(info, &|| run_middlewares(rest, info, listener)),
+        None => listener()
+    }
+}
+
 impl Clone for SodiumCtx {
     fn clone(&self) -> Self {
         SodiumCtx {
@@ -14,26 +14,66 @@ use sodium::impl_::MemoLazy;
 use sodium::impl_::Node;
 use sodium::impl_::SodiumCtx;
 use sodium::impl_::StreamLoop;
+use sodium::impl_::StreamSink;
+use sodium::impl_::TxId;
+use sodium::OverflowPolicy;
 use sodium::gc::Finalize;
 use sodium::gc::Gc;
 use sodium::gc::GcDep;
+use sodium::gc::GcWeak;
 use sodium::gc::Trace;
+use std::cell::Cell as StdCell;
 use std::cell::UnsafeCell;
+use std::collections::VecDeque;
 use std::rc::Rc;
+use std::time::Instant;
 
 pub struct Stream<A> {
     pub data: Gc<UnsafeCell<StreamData<A>>>
 }
 
+pub struct WeakStream<A> {
+    data: GcWeak<UnsafeCell<StreamData<A>>>
+}
+
 pub struct StreamData<A> {
     pub value: Gc<UnsafeCell<Option<MemoLazy<A>>>>,
-    pub node: Node
+    pub node: Node,
+    // Set on streams made by replay, listen hands the buffered firings to each new listener.
+    pub replay_op: Option<Gc<UnsafeCell<VecDeque<A>>>>,
+    // Set on map and filter streams made while SodiumCtx::set_optimize_graph is on.
+    pub stage_op: Option<Stage<A>>
+}
+
+// A run of map and filter steps, all done by the node of the last one. rebuild makes the
+// run again on a fresh strong handle on the stream it starts from, for a node that reads
+// straight from that stream. The run itself only holds that stream weakly and its steps
+// hold no Gc values, so nothing here needs tracing.
+pub struct Stage<A> {
+    rebuild: Rc<Rebuild<A>>,
+    steps: usize
+}
+
+type Pull<A> = dyn Fn() -> Option<MemoLazy<A>>;
+
+type Rebuild<A> = dyn Fn() -> Option<(Box<Pull<A>>, Dep, Node)>;
+
+type Step<A,B> = dyn Fn(Option<MemoLazy<A>>) -> Option<MemoLazy<B>>;
+
+impl<A> Clone for Stage<A> {
+    fn clone(&self) -> Self {
+        Stage {
+            rebuild: self.rebuild.clone(),
+            steps: self.steps
+        }
+    }
 }
 
 impl<A: Trace> Trace for StreamData<A> {
     fn trace(&self, f: &mut FnMut(&GcDep)) {
         self.value.trace(f);
         self.node.trace(f);
+        self.replay_op.trace(f);
     }
 }
 
@@ -41,6 +81,7 @@ impl<A: Finalize> Finalize for StreamData<A> {
     fn finalize(&mut self) {
         self.value.finalize();
         self.node.finalize();
+        self.replay_op.finalize();
     }
 }
 
@@ -77,6 +118,44 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<Option<A>> {
     }
 }
 
+impl<A: Clone + Trace + Finalize + 'static, E: Clone + Trace + Finalize + 'static> Stream<Result<A,E>> {
+    // Both outputs read the upstream thunk directly, so the result is only evaluated once.
+    pub fn split(&self) -> (Stream<A>, Stream<E>) {
+        (
+            self._split_branch(|r: &Result<A,E>| r.as_ref().ok().cloned(), "Stream::split_ok"),
+            self._split_branch(|r: &Result<A,E>| r.as_ref().err().cloned(), "Stream::split_err")
+        )
+    }
+
+    fn _split_branch<B: Clone + Trace + Finalize + 'static, F: Fn(&Result<A,E>)->Option<B> + 'static>(&self, f: F, desc: &'static str) -> Stream<B> {
+        let sodium_ctx = self._node().sodium_ctx().clone();
+        let sodium_ctx = &sodium_ctx;
+        let self_ = self.clone();
+        let sodium_ctx2 = sodium_ctx.clone();
+        let update_deps = vec![self.to_dep()];
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    match self_.peek_value() {
+                        Some(thunk) =>
+                            match f(thunk.get()) {
+                                Some(val) => Some(sodium_ctx.new_lazy(move || val.clone())),
+                                None => None
+                            },
+                        None => None
+                    }
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            desc
+        )
+    }
+}
+
 impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
     pub fn new(sodium_ctx: &SodiumCtx) -> Stream<A> {
         Stream::_new(
@@ -95,7 +174,7 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         cleanup: CLEANUP,
         desc: &'static str
     ) -> Stream<A> {
-        let mut gc_ctx = sodium_ctx.gc_ctx();
+        let gc_ctx = sodium_ctx.gc_ctx();
         let init_firing = update.apply();
         let value = gc_ctx.new_gc_with_desc(UnsafeCell::new(init_firing), String::from(desc) + "_value");
         let mut update_deps = update.deps();
@@ -128,7 +207,9 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
                     deps,
                     cleanup,
                     String::from(desc) + "_node"
-                )
+                ),
+                replay_op: None,
+                stage_op: None
             }), String::from(desc))
         }
     }
@@ -161,6 +242,18 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         let self_ = self.clone();
         let f = Rc::new(f);
         let mut update_deps = f.deps();
+        if sodium_ctx.optimize_graph() && update_deps.is_empty() {
+            let sodium_ctx = sodium_ctx.clone();
+            return self._fused_step(
+                Rc::new(move |thunk_op: Option<MemoLazy<A>>| {
+                    thunk_op.map(|thunk| {
+                        let f = f.clone();
+                        sodium_ctx.new_lazy(move || f.apply(thunk.get()))
+                    })
+                }),
+                "Stream::map"
+            );
+        }
         update_deps.push(self.to_dep());
         let sodium_ctx2 = sodium_ctx.clone();
         Stream::_new(
@@ -181,6 +274,29 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         )
     }
 
+    // map for a plain fn, which is copied into the node and each firing's thunk rather than
+    // shared behind an Rc, and can't carry dependencies. The thunk is still allocated.
+    pub fn map_fn<B: Clone + Trace + Finalize + 'static>(&self, f: fn(&A) -> B) -> Stream<B> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let self_ = self.clone();
+        let update_deps = vec![self.to_dep()];
+        let sodium_ctx2 = sodium_ctx.clone();
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    self_.peek_value().map(|thunk| sodium_ctx.new_lazy(move || f(thunk.get())))
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            "Stream::map_fn"
+        )
+    }
+
     pub fn hold(&self, a: A) -> Cell<A> {
         let sodium_ctx = self._node().sodium_ctx();
         let sodium_ctx = &sodium_ctx;
@@ -236,6 +352,12 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         let pred = Rc::new(pred);
         let sodium_ctx2 = sodium_ctx.clone();
         let mut update_deps = pred.deps();
+        if sodium_ctx.optimize_graph() && update_deps.is_empty() {
+            return self._fused_step(
+                Rc::new(move |thunk_op: Option<MemoLazy<A>>| thunk_op.filter(|thunk| pred.apply(thunk.get()))),
+                "Stream::filter"
+            );
+        }
         update_deps.push(self.to_dep());
         Stream::_new(
             sodium_ctx,
@@ -263,6 +385,223 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         )
     }
 
+    // map and filter in one node, f gives None to drop the event. It runs as the event
+    // arrives rather than when the value is first needed, as filtering can't wait.
+    pub fn map_filter<B: Clone + Trace + Finalize + 'static, F: Fn(&A) -> Option<B> + 'static>(&self, f: F, desc: &'static str) -> Stream<B> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        if sodium_ctx.optimize_graph() {
+            let sodium_ctx = sodium_ctx.clone();
+            return self._fused_step(
+                Rc::new(move |thunk_op: Option<MemoLazy<A>>| {
+                    thunk_op
+                        .and_then(|thunk| f(thunk.get()))
+                        .map(|b| sodium_ctx.new_lazy(move || b.clone()))
+                }),
+                desc
+            );
+        }
+        let self_ = self.clone();
+        let update_deps = vec![self.to_dep()];
+        let sodium_ctx2 = sodium_ctx.clone();
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    self_.peek_value()
+                        .and_then(|thunk| f(thunk.get()))
+                        .map(|b| sodium_ctx.new_lazy(move || b.clone()))
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            desc
+        )
+    }
+
+    // The run of steps a new step on this stream joins. That is this stream's own while
+    // nothing consumes its node yet, so the step can read from the start of the run instead.
+    // Otherwise a new run starts here. Also says whether the step joined an existing run.
+    fn _run_for_next_step(&self) -> (Rc<Rebuild<A>>, usize, bool) {
+        let data = unsafe { &*(*self.data).get() };
+        if let Some(ref stage) = data.stage_op {
+            if !self._node().has_dependents() {
+                return (stage.rebuild.clone(), stage.steps, true);
+            }
+        }
+        let start = self.downgrade();
+        let rebuild: Rc<Rebuild<A>> = Rc::new(move || {
+            start.upgrade().map(|start| {
+                let dep = start.to_dep();
+                let node = start._node().clone();
+                let pull: Box<Pull<A>> = Box::new(move || start.peek_value());
+                (pull, dep, node)
+            })
+        });
+        (rebuild, 0, false)
+    }
+
+    // A map or filter step made while optimizing, see Stage.
+    fn _fused_step<B: Clone + Trace + Finalize + 'static>(&self, step: Rc<Step<A,B>>, desc: &'static str) -> Stream<B> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let (rebuild, steps, joined) = self._run_for_next_step();
+        let rebuild: Rc<Rebuild<B>> = Rc::new(move || {
+            rebuild().map(|(pull, dep, node)| {
+                let step = step.clone();
+                let pull: Box<Pull<B>> = Box::new(move || step(pull()));
+                (pull, dep, node)
+            })
+        });
+        // This stream, or a node it reads through, holds the start of the run.
+        let (pull, dep, start) = rebuild().unwrap();
+        if joined {
+            sodium_ctx.record_fused_step();
+        }
+        let stream = Stream::_new(
+            &sodium_ctx,
+            Lambda::new(pull, vec![dep]),
+            vec![start],
+            || {},
+            desc
+        );
+        {
+            let data = unsafe { &mut *(*stream.data).get() };
+            data.stage_op = Some(Stage { rebuild, steps: steps + 1 });
+        }
+        stream
+    }
+
+    // How many map and filter steps this stream's node does, more than one when
+    // SodiumCtx::set_optimize_graph fused them.
+    pub fn unfused_node_count(&self) -> usize {
+        let data = unsafe { &*(*self.data).get() };
+        data.stage_op.as_ref().map(|stage| stage.steps).unwrap_or(1)
+    }
+
+    pub fn filter_fn(&self, pred: fn(&A) -> bool) -> Stream<A> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let self_ = self.clone();
+        let update_deps = vec![self.to_dep()];
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    self_.peek_value().and_then(|val| if pred(val.get()) { Some(val) } else { None })
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            "Stream::filter_fn"
+        )
+    }
+
+    pub fn monitor(&self, name: &str) -> Stream<A> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let self_ = self.clone();
+        let update_deps = vec![self.to_dep()];
+        let sodium_ctx2 = sodium_ctx.clone();
+        let name = String::from(name);
+        let last_op: StdCell<Option<Instant>> = StdCell::new(None);
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    let value_op = self_.peek_value();
+                    if value_op.is_some() {
+                        let now = Instant::now();
+                        sodium_ctx.record_monitor_event(&name, last_op.get().map(|last| now - last));
+                        last_op.set(Some(now));
+                        let sodium_ctx2 = sodium_ctx.clone();
+                        let name = name.clone();
+                        sodium_ctx.post(move || sodium_ctx2.record_monitor_processing(&name, now.elapsed()));
+                    }
+                    value_op
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            "Stream::monitor"
+        )
+    }
+
+    pub fn all_in_transaction(&self) -> Stream<Vec<A>> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let mut gc_ctx = sodium_ctx.gc_ctx();
+        let gc_ctx = &mut gc_ctx;
+        let deps = vec![self._node().clone()];
+        let value: Gc<UnsafeCell<Option<MemoLazy<Vec<A>>>>> = gc_ctx.new_gc_with_desc(UnsafeCell::new(None), String::from("Stream::all_in_transaction_value"));
+        let buffer: Gc<UnsafeCell<Vec<A>>> = gc_ctx.new_gc_with_desc(UnsafeCell::new(Vec::new()), String::from("Stream::all_in_transaction_buffer"));
+        let node2 = Node::new(
+            sodium_ctx,
+            || true,
+            Vec::new(),
+            Vec::new(),
+            || {},
+            String::from("Stream::all_in_transaction_node2")
+        );
+        let result = Stream {
+            data: gc_ctx.new_gc_with_desc(UnsafeCell::new(StreamData {
+                value: value.clone(),
+                node: node2.clone(),
+                replay_op: None,
+                stage_op: None
+            }), String::from("Stream::all_in_transaction"))
+        };
+        let update_deps = vec![self.to_dep(), node2.to_dep(), Dep { gc_dep: value.to_dep() }, Dep { gc_dep: buffer.to_dep() }];
+        let self_ = self.clone();
+        let sodium_ctx2 = sodium_ctx.clone();
+        let node1;
+        {
+            let node2 = node2.clone();
+            node1 = Node::new(
+                sodium_ctx,
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    if let Some(thunk) = self_.peek_value() {
+                        let buffered = unsafe { &mut *(*buffer).get() };
+                        buffered.push(thunk.get().clone());
+                        // The first occurrence of the outer transaction arranges for them all
+                        // to go out together once it is over.
+                        if buffered.len() == 1 {
+                            let sodium_ctx2 = sodium_ctx.clone();
+                            let node2 = node2.clone();
+                            let value = value.clone();
+                            let buffer = buffer.clone();
+                            sodium_ctx.after_outer_transaction(move || {
+                                let sodium_ctx = &sodium_ctx2;
+                                let buffered = ::std::mem::replace(unsafe { &mut *(*buffer).get() }, Vec::new());
+                                let value = value.clone();
+                                sodium_ctx.transaction(|| {
+                                    unsafe { *(*value).get() = Some(sodium_ctx.new_lazy(move || buffered.clone())); }
+                                    node2.mark_dependents_dirty();
+                                    let value = value.clone();
+                                    sodium_ctx.post(move || {
+                                        unsafe { *(*value).get() = None; }
+                                    });
+                                });
+                            });
+                        }
+                    }
+                    false
+                },
+                update_deps,
+                deps,
+                || {},
+                String::from("Stream::all_in_transaction_node1")
+            );
+        }
+        node2.add_dependencies(vec![node1]);
+        result
+    }
+
     pub fn merge<FN:Fn(&A,&A)->A+'static>(&self, sa: Stream<A>, f: FN) -> Stream<A> {
         let sodium_ctx = self._node().sodium_ctx();
         let sodium_ctx = &sodium_ctx;
@@ -345,23 +684,79 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
     }
 
     pub fn once(&self) -> Stream<A> {
+        self.take(1)
+    }
+
+    pub fn take_until<B: Clone + Trace + Finalize + 'static>(&self, sb: &Stream<B>) -> Stream<A> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let done = Rc::new(UnsafeCell::new(sb.peek_value().is_some()));
+        let update;
+        {
+            let self_ = self.clone();
+            let sb2 = sb.clone();
+            let done = done.clone();
+            update = Lambda::new(
+                move || {
+                    let done = unsafe { &mut *(*done).get() };
+                    if sb2.peek_value().is_some() {
+                        *done = true;
+                    }
+                    if *done {
+                        None
+                    } else {
+                        self_.peek_value()
+                    }
+                },
+                vec![self.to_dep(), sb.to_dep()]
+            );
+        }
+        let result = Stream::_new(
+            &sodium_ctx,
+            update,
+            vec![self._node().clone(), sb._node().clone()],
+            || {},
+            "Stream::take_until"
+        );
+        let node = result._node().clone();
+        let weak_node = node.downgrade();
+        let mut released = false;
+        node.after_update(move || {
+            if released || !unsafe { *(*done).get() } {
+                return;
+            }
+            released = true;
+            let weak_node = weak_node.clone();
+            sodium_ctx.post(move || {
+                if let Some(node) = weak_node.upgrade() {
+                    node.dispose();
+                }
+            });
+        });
+        result
+    }
+
+    pub fn take(&self, n: usize) -> Stream<A> {
         let sodium_ctx = self._node().sodium_ctx();
         let sodium_ctx = &sodium_ctx;
+        if n == 0 {
+            return Stream::new(sodium_ctx);
+        }
         let sodium_ctx2 = sodium_ctx.clone();
         sodium_ctx.transaction(|| {
             let sodium_ctx = &sodium_ctx2;
             let mut gc_ctx = sodium_ctx.gc_ctx();
             let gc_ctx = &mut gc_ctx;
             let init_firing = self.peek_value();
-            let init_firing_is_some = init_firing.is_some();
-            let value = gc_ctx.new_gc_with_desc(UnsafeCell::new(init_firing), String::from("Stream::once_value"));
+            let remaining = Rc::new(UnsafeCell::new(if init_firing.is_some() { n - 1 } else { n }));
+            let value = gc_ctx.new_gc_with_desc(UnsafeCell::new(init_firing), String::from("Stream::take_value"));
             let self_ = self.clone();
-            let deps = if init_firing_is_some { Vec::new() } else { vec![self_._node().clone()] };
+            let deps = if unsafe { *(*remaining).get() } == 0 { Vec::new() } else { vec![self_._node().clone()] };
             let node_self: Rc<UnsafeCell<Option<Node>>> = Rc::new(UnsafeCell::new(None));
             let node;
             {
                 let value = value.clone();
                 let node_self = node_self.clone();
+                let remaining = remaining.clone();
                 let sodium_ctx2 = sodium_ctx.clone();
                 node = Node::new(
                     &sodium_ctx,
@@ -371,9 +766,13 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
                             let value = unsafe { &mut *(*value).get() };
                             *value = self_.peek_value();
                             if value.is_some() {
-                                let node_self = unsafe { &*(*node_self).get() };
-                                if let &Some(ref node_self2) = node_self {
-                                    node_self2.remove_all_dependencies();
+                                let remaining = unsafe { &mut *(*remaining).get() };
+                                *remaining = *remaining - 1;
+                                if *remaining == 0 {
+                                    let node_self = unsafe { &*(*node_self).get() };
+                                    if let &Some(ref node_self2) = node_self {
+                                        node_self2.remove_all_dependencies();
+                                    }
                                 }
                             }
                         }
@@ -387,7 +786,7 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
                     Vec::new(),
                     deps,
                     || {},
-                    String::from("Stream::once_node")
+                    String::from("Stream::take_node")
                 );
             }
             {
@@ -398,28 +797,334 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
             Stream {
                 data: gc_ctx.new_gc_with_desc(UnsafeCell::new(StreamData {
                     value,
-                    node
-                }), String::from("Stream::once"))
+                    node,
+                    replay_op: None,
+                    stage_op: None
+                }), String::from("Stream::take"))
             }
         })
     }
 
+    // Every firing shares one memoised value instead of cloning b through a closure.
+    pub fn map_to<B: Clone + Trace + Finalize + 'static>(&self, b: B) -> Stream<B> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let b = sodium_ctx.new_lazy(move || b.clone());
+        let self_ = self.clone();
+        let update_deps = vec![self.to_dep(), b.to_dep()];
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    if self_.peek_value().is_some() {
+                        Some(b.clone())
+                    } else {
+                        None
+                    }
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            "Stream::map_to"
+        )
+    }
+
+    pub fn coincidence_with<B: Clone + Trace + Finalize + 'static>(&self, sb: Stream<B>) -> Stream<(A,B)> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let sa = self.clone();
+        let sb2 = sb.clone();
+        let sodium_ctx2 = sodium_ctx.clone();
+        let update_deps = vec![self.to_dep(), sb.to_dep()];
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    match (sa.peek_value(), sb2.peek_value()) {
+                        (Some(a_thunk), Some(b_thunk)) => Some(sodium_ctx.new_lazy(move || (a_thunk.get().clone(), b_thunk.get().clone()))),
+                        _ => None
+                    }
+                },
+                update_deps
+            ),
+            vec![self._node().clone(), sb._node().clone()],
+            || {},
+            "Stream::coincidence_with"
+        )
+    }
+
+    pub fn alone<B: Clone + Trace + Finalize + 'static>(&self, sb: Stream<B>) -> Stream<A> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let sa = self.clone();
+        let sb2 = sb.clone();
+        let update_deps = vec![self.to_dep(), sb.to_dep()];
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    match (sa.peek_value(), sb2.peek_value()) {
+                        (Some(a_thunk), None) => Some(a_thunk),
+                        _ => None
+                    }
+                },
+                update_deps
+            ),
+            vec![self._node().clone(), sb._node().clone()],
+            || {},
+            "Stream::alone"
+        )
+    }
+
+    pub fn tag_tx(&self) -> Stream<(TxId,A)> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let self_ = self.clone();
+        let sodium_ctx2 = sodium_ctx.clone();
+        let update_deps = vec![self.to_dep()];
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    self_.peek_value().map(|value| {
+                        let tx_id = sodium_ctx.current_tx_id();
+                        sodium_ctx.new_lazy(move || (tx_id, value.get().clone()))
+                    })
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            "Stream::tag_tx"
+        )
+    }
+
+    pub fn switch_map<B: Clone + Trace + Finalize + 'static, F: Fn(&A)->Stream<B> + 'static>(&self, f: F) -> Stream<B> {
+        let sodium_ctx = self._node().sodium_ctx();
+        Cell::switch_s(self.map(f).hold(Stream::new(&sodium_ctx)))
+    }
+
+    pub fn sample_from<B: Clone + Trace + Finalize + 'static>(&self, sb: &Stream<B>) -> Stream<(A,Option<B>)> {
+        let latest = sb.map(|b: &B| Some(b.clone())).hold(None);
+        self.snapshot2(latest, |a: &A, b_op: &Option<B>| (a.clone(), b_op.clone()))
+    }
+
+    pub fn limit_per_transaction(&self, n: usize, policy: OverflowPolicy) -> Stream<A> {
+        struct LimitState<A> {
+            outer_tx_id: Option<TxId>,
+            count: usize,
+            pending: VecDeque<MemoLazy<A>>,
+            flush_scheduled: bool
+        }
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let deferred: StreamSink<A> = StreamSink::new(sodium_ctx);
+        let deferred_stream = deferred.to_stream();
+        let state = Rc::new(UnsafeCell::new(LimitState {
+            outer_tx_id: None,
+            count: 0,
+            pending: VecDeque::new(),
+            flush_scheduled: false
+        }));
+        let self_ = self.clone();
+        let deferred_stream2 = deferred_stream.clone();
+        let sodium_ctx2 = sodium_ctx.clone();
+        let update_deps = vec![self.to_dep(), deferred_stream.to_dep()];
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    let value =
+                        match deferred_stream2.peek_value().or_else(|| self_.peek_value()) {
+                            Some(value) => value,
+                            None => return None
+                        };
+                    let state2 = unsafe { &mut *(*state).get() };
+                    let outer_tx_id = sodium_ctx.outer_tx_id();
+                    if state2.outer_tx_id != Some(outer_tx_id) {
+                        state2.outer_tx_id = Some(outer_tx_id);
+                        state2.count = 0;
+                    }
+                    if state2.count < n {
+                        state2.count = state2.count + 1;
+                        return Some(value);
+                    }
+                    match policy {
+                        OverflowPolicy::Drop => return None,
+                        OverflowPolicy::Latest => {
+                            state2.pending.clear();
+                            state2.pending.push_back(value);
+                        },
+                        OverflowPolicy::Block => state2.pending.push_back(value)
+                    }
+                    if !state2.flush_scheduled {
+                        state2.flush_scheduled = true;
+                        let state = state.clone();
+                        let deferred = deferred.clone();
+                        let sodium_ctx2 = sodium_ctx.clone();
+                        sodium_ctx.after_outer_transaction(move || {
+                            let state = unsafe { &mut *(*state).get() };
+                            state.flush_scheduled = false;
+                            let mut values_op = Some(state.pending.drain(..).map(|value| value.get().clone()).collect::<Vec<A>>());
+                            let deferred = deferred.clone();
+                            // Each deferred event needs its own transaction, started from a post
+                            // callback so they all count towards the same outer transaction.
+                            sodium_ctx2.post(move || {
+                                if let Some(values) = values_op.take() {
+                                    for value in values {
+                                        deferred.send(value);
+                                    }
+                                }
+                            });
+                        });
+                    }
+                    None
+                },
+                update_deps
+            ),
+            vec![self._node().clone(), deferred_stream._node().clone()],
+            || {},
+            "Stream::limit_per_transaction"
+        )
+    }
+
+    pub fn skip(&self, n: usize) -> Stream<A> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let self_ = self.clone();
+        let skipped = Rc::new(UnsafeCell::new(0));
+        let update_deps = vec![self.to_dep()];
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    match self_.peek_value() {
+                        Some(thunk) => {
+                            let skipped = unsafe { &mut *(*skipped).get() };
+                            if *skipped < n {
+                                *skipped = *skipped + 1;
+                                None
+                            } else {
+                                Some(thunk)
+                            }
+                        },
+                        None => None
+                    }
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            "Stream::skip"
+        )
+    }
+
+    // Listeners attached to the result first get the last n firings, oldest first, each in a
+    // transaction of its own that starts once the transaction they attach in has finished.
+    // Live firings are held back until the replay is done. Streams derived from the result don't
+    // replay, only listeners attached to it directly.
+    pub fn replay(&self, n: usize) -> Stream<A> {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let buffer: Gc<UnsafeCell<VecDeque<A>>> = sodium_ctx.gc_ctx().new_gc_with_desc(UnsafeCell::new(VecDeque::new()), String::from("Stream::replay_buffer"));
+        let self_ = self.clone();
+        let buffer2 = buffer.clone();
+        let update_deps = vec![self.to_dep(), Dep { gc_dep: buffer.to_dep() }];
+        let result = Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    let thunk_op = self_.peek_value();
+                    if let Some(ref thunk) = thunk_op {
+                        if n > 0 {
+                            let buffer = unsafe { &mut *(*buffer2).get() };
+                            if buffer.len() == n {
+                                buffer.pop_front();
+                            }
+                            buffer.push_back(thunk.get().clone());
+                        }
+                    }
+                    thunk_op
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            "Stream::replay"
+        );
+        {
+            let data = unsafe { &mut *(*result.data).get() };
+            data.replay_op = Some(buffer);
+        }
+        result
+    }
+
+    pub fn _map_sampling<S,B,SAMPLE,FN>(&self, sample: SAMPLE, f: FN, mut update_deps: Vec<Dep>, desc: &'static str) -> Stream<B>
+        where S: 'static,
+              B: Clone + Trace + Finalize + 'static,
+              SAMPLE: Fn() -> S + 'static,
+              FN: Fn(&A,&S) -> B + 'static
+    {
+        let sodium_ctx = self._node().sodium_ctx();
+        let sodium_ctx = &sodium_ctx;
+        let self_ = self.clone();
+        let f = Rc::new(f);
+        update_deps.push(self.to_dep());
+        let sodium_ctx2 = sodium_ctx.clone();
+        Stream::_new(
+            sodium_ctx,
+            Lambda::new(
+                move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    self_.peek_value().map(|thunk| {
+                        let f = f.clone();
+                        let sampled = sample();
+                        sodium_ctx.new_lazy(move || f(thunk.get(), &sampled))
+                    })
+                },
+                update_deps
+            ),
+            vec![self._node().clone()],
+            || {},
+            desc
+        )
+    }
+
     pub fn snapshot<B>(&self, cb: Cell<B>) -> Stream<B> where B: Trace + Finalize + Clone + 'static {
         let deps = vec![cb.to_dep()];
-        self.map(Lambda::new(move |_a: &A| cb.sample_no_trans(), deps))
+        self._map_sampling(
+            move || cb._value_thunk(),
+            |_a: &A, b: &MemoLazy<B>| b.get().clone(),
+            deps,
+            "Stream::snapshot"
+        )
     }
 
     pub fn snapshot2<B,C,FN:IsLambda2<A,B,C> + 'static>(&self, cb: Cell<B>, f: FN) -> Stream<C> where B: Trace + Finalize + Clone + 'static, C: Trace + Finalize + Clone + 'static {
         let mut deps = f.deps();
         deps.push(cb.to_dep());
-        self.map(Lambda::new(move |a: &A| f.apply(a, &cb.sample_no_trans()), deps))
+        self._map_sampling(
+            move || cb._value_thunk(),
+            move |a: &A, b: &MemoLazy<B>| f.apply(a, b.get()),
+            deps,
+            "Stream::snapshot2"
+        )
     }
 
     pub fn snapshot3<B,C,D,FN:IsLambda3<A,B,C,D> + 'static>(&self, cb: Cell<B>, cc: Cell<C>, f: FN) -> Stream<D> where B: Trace + Finalize + Clone + 'static, C: Trace + Finalize + Clone + 'static, D: Trace + Finalize + Clone + 'static {
         let mut deps = f.deps();
         deps.push(cb.to_dep());
         deps.push(cc.to_dep());
-        self.map(Lambda::new(move |a: &A| f.apply(a, &cb.sample_no_trans(), &cc.sample_no_trans()), deps))
+        self._map_sampling(
+            move || (cb._value_thunk(), cc._value_thunk()),
+            move |a: &A, &(ref b, ref c): &(MemoLazy<B>,MemoLazy<C>)| f.apply(a, b.get(), c.get()),
+            deps,
+            "Stream::snapshot3"
+        )
     }
 
     pub fn snapshot4<B,C,D,E,FN:IsLambda4<A,B,C,D,E> + 'static>(&self, cb: Cell<B>, cc: Cell<C>, cd: Cell<D>, f: FN) -> Stream<E> where B: Trace + Finalize + Clone + 'static, C: Trace + Finalize + Clone + 'static, D: Trace + Finalize + Clone + 'static, E: Trace + Finalize + Clone + 'static {
@@ -427,7 +1132,12 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         deps.push(cb.to_dep());
         deps.push(cc.to_dep());
         deps.push(cd.to_dep());
-        self.map(Lambda::new(move |a: &A| f.apply(a, &cb.sample_no_trans(), &cc.sample_no_trans(), &cd.sample_no_trans()), deps))
+        self._map_sampling(
+            move || (cb._value_thunk(), cc._value_thunk(), cd._value_thunk()),
+            move |a: &A, &(ref b, ref c, ref d): &(MemoLazy<B>,MemoLazy<C>,MemoLazy<D>)| f.apply(a, b.get(), c.get(), d.get()),
+            deps,
+            "Stream::snapshot4"
+        )
     }
 
     pub fn snapshot5<B,C,D,E,F,FN:IsLambda5<A,B,C,D,E,F> + 'static>(&self, cb: Cell<B>, cc: Cell<C>, cd: Cell<D>, ce: Cell<E>, f: FN) -> Stream<F> where B: Trace + Finalize + Clone + 'static, C: Trace + Finalize + Clone + 'static, D: Trace + Finalize + Clone + 'static, E: Trace + Finalize + Clone + 'static, E: Trace + Finalize + Clone + 'static, F: Trace + Finalize + Clone + 'static {
@@ -436,7 +1146,12 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         deps.push(cc.to_dep());
         deps.push(cd.to_dep());
         deps.push(ce.to_dep());
-        self.map(Lambda::new(move |a: &A| f.apply(a, &cb.sample_no_trans(), &cc.sample_no_trans(), &cd.sample_no_trans(), &ce.sample_no_trans()), deps))
+        self._map_sampling(
+            move || (cb._value_thunk(), cc._value_thunk(), cd._value_thunk(), ce._value_thunk()),
+            move |a: &A, &(ref b, ref c, ref d, ref e): &(MemoLazy<B>,MemoLazy<C>,MemoLazy<D>,MemoLazy<E>)| f.apply(a, b.get(), c.get(), d.get(), e.get()),
+            deps,
+            "Stream::snapshot5"
+        )
     }
 
     pub fn snapshot6<B,C,D,E,F,G,FN:IsLambda6<A,B,C,D,E,F,G> + 'static>(&self, cb: Cell<B>, cc: Cell<C>, cd: Cell<D>, ce: Cell<E>, cf: Cell<F>, f: FN) -> Stream<G> where B: Trace + Finalize + Clone + 'static, C: Trace + Finalize + Clone + 'static, D: Trace + Finalize + Clone + 'static, E: Trace + Finalize + Clone + 'static, E: Trace + Finalize + Clone + 'static, F: Trace + Finalize + Clone + 'static, G: Trace + Finalize + Clone + 'static {
@@ -446,13 +1161,26 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         deps.push(cd.to_dep());
         deps.push(ce.to_dep());
         deps.push(cf.to_dep());
-        self.map(Lambda::new(move |a: &A| f.apply(a, &cb.sample_no_trans(), &cc.sample_no_trans(), &cd.sample_no_trans(), &ce.sample_no_trans(), &cf.sample_no_trans()), deps))
+        self._map_sampling(
+            move || (cb._value_thunk(), cc._value_thunk(), cd._value_thunk(), ce._value_thunk(), cf._value_thunk()),
+            move |a: &A, &(ref b, ref c, ref d, ref e, ref f2): &(MemoLazy<B>,MemoLazy<C>,MemoLazy<D>,MemoLazy<E>,MemoLazy<F>)| f.apply(a, b.get(), c.get(), d.get(), e.get(), f2.get()),
+            deps,
+            "Stream::snapshot6"
+        )
     }
 
     pub fn add_cleanup<CLEANUP:IsLambdaMut0<()>+'static>(&self, cleanup: CLEANUP) {
         self._node().add_cleanup(cleanup);
     }
 
+    pub fn set_name(&self, name: &str) {
+        self._node().set_name(name);
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self._node().name()
+    }
+
     pub fn listen<CALLBACK:FnMut(&A)+'static>(
         &self,
         callback: CALLBACK
@@ -467,46 +1195,111 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         self._listen(callback, true)
     }
 
+    pub fn listen_once<CALLBACK:FnMut(&A)+'static>(
+        &self,
+        callback: CALLBACK
+    ) -> Listener {
+        let listener = self._listen(callback, false);
+        listener.unlisten_after(|| true);
+        listener
+    }
+
+    pub fn listen_with_priority<CALLBACK:FnMut(&A)+'static>(
+        &self,
+        priority: i32,
+        callback: CALLBACK
+    ) -> Listener {
+        self._listen_with_priority(callback, false, priority)
+    }
+
     pub fn _listen<CALLBACK:FnMut(&A)+'static>(
         &self,
         callback: CALLBACK,
         weak: bool
+    ) -> Listener {
+        self._listen_with_priority(callback, weak, 0)
+    }
+
+    pub fn _listen_with_priority<CALLBACK:FnMut(&A)+'static>(
+        &self,
+        callback: CALLBACK,
+        weak: bool,
+        priority: i32
     ) -> Listener {
         let sodium_ctx = self._node().sodium_ctx();
         let sodium_ctx = &sodium_ctx;
         let callback = Rc::new(UnsafeCell::new(callback));
+        let key = sodium_ctx.new_id();
         let self_ = self.clone();
-        {
+        // Firings waiting behind a replay, None once the replay is over.
+        let pending_op: Option<Rc<UnsafeCell<Option<VecDeque<A>>>>> =
+            unsafe { &*(*self.data).get() }.replay_op.as_ref().map(|buffer| {
+                let replayed = unsafe { &*(**buffer).get() }.clone();
+                Rc::new(UnsafeCell::new(Some(replayed)))
+            });
+        if let Some(ref pending) = pending_op {
+            let pending = pending.clone();
+            let self_ = self_.clone();
+            let callback = callback.clone();
+            let sodium_ctx2 = sodium_ctx.clone();
+            sodium_ctx.transaction(|| {
+                sodium_ctx.after_outer_transaction(move || {
+                    let sodium_ctx = &sodium_ctx2;
+                    loop {
+                        let a_op = unsafe { &mut *(*pending).get() }.as_mut().and_then(|queue| queue.pop_front());
+                        match a_op {
+                            Some(a) => sodium_ctx.transaction(|| sodium_ctx.deliver(key, self_._node().name(), &callback, &a)),
+                            None => break
+                        }
+                    }
+                    unsafe { *(*pending).get() = None; }
+                });
+            });
+        }
+        let deliver = {
             let self_ = self_.clone();
             let callback = callback.clone();
+            let sodium_ctx = sodium_ctx.clone();
+            Rc::new(move |a: &A| {
+                if let Some(ref pending) = pending_op {
+                    if let Some(ref mut queue) = *unsafe { &mut *(*pending).get() } {
+                        queue.push_back(a.clone());
+                        return;
+                    }
+                }
+                sodium_ctx.deliver(key, self_._node().name(), &callback, a);
+            })
+        };
+        {
             let value_op = self_.peek_value();
             if let Some(value) = value_op {
+                let deliver = deliver.clone();
                 sodium_ctx.pre(move || {
-                    let callback = unsafe { &mut *(*callback).get() };
-                    (*callback)(value.get());
+                    deliver(value.get());
                 });
             }
         }
         let update_deps = vec![self.to_dep()];
-        Listener::new(Node::new(
+        let node = Node::new_with_priority(
             sodium_ctx,
             move || {
-                let callback = unsafe { &mut *(*callback).get() };
                 let value_op = self_.peek_value();
                 if let Some(value) = value_op {
-                    (*callback)(value.get());
+                    deliver(value.get());
                 }
                 return false;
             },
             update_deps,
             vec![self._node().clone()],
             || {},
-            String::from("Stream::listen_node")
-        ), weak)
+            String::from("Stream::listen_node"),
+            priority
+        );
+        Listener::new(node, weak)
     }
 }
 
-impl<A:Clone + 'static> Clone for Stream<A> {
+impl<A> Clone for Stream<A> {
     fn clone(&self) -> Self {
         Stream {
             data: self.data.clone()
@@ -514,6 +1307,28 @@ impl<A:Clone + 'static> Clone for Stream<A> {
     }
 }
 
+impl<A> Stream<A> {
+    pub fn downgrade(&self) -> WeakStream<A> {
+        WeakStream {
+            data: self.data.downgrade()
+        }
+    }
+}
+
+impl<A> WeakStream<A> {
+    pub fn upgrade(&self) -> Option<Stream<A>> {
+        self.data.upgrade().map(|data| Stream { data })
+    }
+}
+
+impl<A> Clone for WeakStream<A> {
+    fn clone(&self) -> Self {
+        WeakStream {
+            data: self.data.clone()
+        }
+    }
+}
+
 impl<A:Trace> Trace for Stream<A> {
     fn trace(&self, f: &mut FnMut(&GcDep)) {
         self.data.trace(f);
@@ -3,6 +3,7 @@ use sodium::impl_::Latch;
 use sodium::impl_::MemoLazy;
 use sodium::impl_::Node;
 use sodium::impl_::SodiumCtx;
+use sodium::impl_::SodiumError;
 use sodium::impl_::Stream;
 use sodium::impl_::gc::Finalize;
 use sodium::impl_::gc::Gc;
@@ -26,7 +27,8 @@ impl<A: Trace + Finalize + Clone + 'static> StreamLoop<A> {
     pub fn loop_(&self, sa: Stream<A>) {
         let looped = unsafe { &mut *(*self.looped).get() };
         if *looped {
-            panic!("StreamLoop looped more than once.");
+            self.stream._node().sodium_ctx().fail(SodiumError::LoopedTwice(self.stream._node().describe("StreamLoop")));
+            return;
         }
         let value = self.stream._value().clone();
         let update_deps = vec![sa.to_dep(), Dep { gc_dep: value.to_dep() }];
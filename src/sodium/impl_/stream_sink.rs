@@ -4,20 +4,63 @@ use sodium::impl_::StreamData;
 use sodium::impl_::MemoLazy;
 use sodium::impl_::Node;
 use sodium::impl_::SodiumCtx;
+use sodium::impl_::SodiumError;
+use sodium::impl_::WeakNode;
 use sodium::gc::Finalize;
 use sodium::gc::Gc;
 use sodium::gc::GcDep;
+use sodium::gc::GcWeak;
 use sodium::gc::Trace;
 use std::cell::UnsafeCell;
 use std::mem::swap;
 use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::channel;
 
 pub struct StreamSink<A> {
     value: Gc<UnsafeCell<Option<MemoLazy<A>>>>,
     next_value: Gc<UnsafeCell<Option<MemoLazy<A>>>>,
     node: Node,
     will_clear: Rc<UnsafeCell<bool>>,
-    coalescer_op: Option<Rc<Fn(&A,&A)->A>>
+    // Queued with post_shared, and hands the cleared thunk to spare when nothing else holds it,
+    // so a steady stream of sends doesn't allocate.
+    clear: Rc<dyn Fn()>,
+    spare: Rc<UnsafeCell<Option<MemoLazy<A>>>>,
+    coalescer_op: Option<Rc<Fn(&A,&A)->A>>,
+    producer_queue: Rc<UnsafeCell<Option<(Sender<A>,Receiver<A>)>>>
+}
+
+// Doesn't keep the sink alive, for senders that should stop once nothing listens.
+pub struct WeakStreamSink<A> {
+    value: GcWeak<UnsafeCell<Option<MemoLazy<A>>>>,
+    next_value: GcWeak<UnsafeCell<Option<MemoLazy<A>>>>,
+    node: WeakNode,
+    will_clear: Rc<UnsafeCell<bool>>,
+    clear: Rc<dyn Fn()>,
+    spare: Rc<UnsafeCell<Option<MemoLazy<A>>>>,
+    coalescer_op: Option<Rc<dyn Fn(&A,&A)->A>>,
+    producer_queue: Rc<UnsafeCell<Option<(Sender<A>,Receiver<A>)>>>
+}
+
+// Can be moved to and cloned across other threads, values queue up until the owning
+// thread calls StreamSink::drain_producers.
+pub struct SinkHandle<A> {
+    sender: Sender<A>
+}
+
+impl<A> SinkHandle<A> {
+    pub fn send(&self, a: A) -> bool {
+        self.sender.send(a).is_ok()
+    }
+}
+
+impl<A> Clone for SinkHandle<A> {
+    fn clone(&self) -> Self {
+        SinkHandle {
+            sender: self.sender.clone()
+        }
+    }
 }
 
 impl<A: Trace + Finalize + Clone + 'static> StreamSink<A> {
@@ -30,11 +73,30 @@ impl<A: Trace + Finalize + Clone + 'static> StreamSink<A> {
     }
 
     pub fn _new(sodium_ctx: &SodiumCtx, coalescer_op: Option<Rc<Fn(&A,&A)->A>>) -> StreamSink<A> {
-        let mut gc_ctx = sodium_ctx.gc_ctx();
-        let value = gc_ctx.new_gc_with_desc(UnsafeCell::new(None), String::from("StreamSink_value"));
+        let gc_ctx = sodium_ctx.gc_ctx();
+        let value: Gc<UnsafeCell<Option<MemoLazy<A>>>> = gc_ctx.new_gc_with_desc(UnsafeCell::new(None), String::from("StreamSink_value"));
         let next_value = gc_ctx.new_gc_with_desc(UnsafeCell::new(None), String::from("StreamSink_next_value"));
         let update_deps = vec![Dep { gc_dep: value.to_dep() }, Dep { gc_dep: next_value.to_dep() }];
-        StreamSink {
+        let will_clear = Rc::new(UnsafeCell::new(false));
+        let spare: Rc<UnsafeCell<Option<MemoLazy<A>>>> = Rc::new(UnsafeCell::new(None));
+        let clear: Rc<dyn Fn()> = {
+            let value = value.downgrade();
+            let will_clear = will_clear.clone();
+            let spare = spare.clone();
+            Rc::new(move || {
+                unsafe { *will_clear.get() = false; }
+                if let Some(value) = value.upgrade() {
+                    let value = unsafe { &mut *(*value).get() };
+                    if let Some(lazy) = value.take() {
+                        if lazy.is_unique() {
+                            lazy.replace(None);
+                            unsafe { *spare.get() = Some(lazy); }
+                        }
+                    }
+                }
+            })
+        };
+        let sink = StreamSink {
             value: value.clone(),
             next_value: next_value.clone(),
             node: Node::new(
@@ -52,27 +114,32 @@ impl<A: Trace + Finalize + Clone + 'static> StreamSink<A> {
                 || {},
                 String::from("StreamSink::new_node")
             ),
-            will_clear: Rc::new(UnsafeCell::new(false)),
-            coalescer_op: coalescer_op
-        }
+            will_clear: will_clear,
+            clear: clear,
+            spare: spare,
+            coalescer_op: coalescer_op,
+            producer_queue: Rc::new(UnsafeCell::new(None))
+        };
+        sink.node.mark_source();
+        sink
     }
 
     pub fn send(&self, value: A) {
         let sodium_ctx = self.node.sodium_ctx();
         if sodium_ctx.callback_depth() > 0 {
-            panic!("StreamSink::send / CellSink::send can not be called from a sodium callback, consider using SodiumCtx::post to send after the end of transaction.")
+            sodium_ctx.fail(SodiumError::SendFromCallback(self.node.describe("StreamSink")));
+            return;
+        }
+        if sodium_ctx.is_suspended() {
+            let self_ = self.clone();
+            sodium_ctx.buffer_send(move || self_.send(value.clone()));
+            return;
         }
         sodium_ctx.transaction(|| {
             let will_clear = unsafe { &mut *(*self.will_clear).get() };
             if !*will_clear {
                 *will_clear = true;
-                let self_ = self.clone();
-                sodium_ctx.post(move || {
-                    let value = unsafe { &mut *(*self_.value).get() };
-                    let will_clear = unsafe { &mut *(*self_.will_clear).get() };
-                    *value = None;
-                    *will_clear = false;
-                });
+                sodium_ctx.post_shared(&self.clear);
             }
             let next_value = unsafe { &mut *(*self.next_value).get() };
             match &self.coalescer_op {
@@ -81,31 +148,117 @@ impl<A: Trace + Finalize + Clone + 'static> StreamSink<A> {
                         match next_value {
                             &mut Some(ref next_value3) => {
                                 let next_value4 = coalescer(next_value3.get(), &value);
-                                Some(sodium_ctx.new_lazy(move || next_value4.clone()))
+                                Some(self.evaluated(next_value4))
                             },
-                            &mut None => Some(sodium_ctx.new_lazy(move || value.clone()))
+                            &mut None => Some(self.evaluated(value))
                         };
                     *next_value = next_value2;
                 },
                 &None => {
-                    *next_value = Some(sodium_ctx.new_lazy(move || value.clone()));
+                    *next_value = Some(self.evaluated(value));
                 }
             };
             self.node.mark_dirty();
         });
     }
 
+    fn evaluated(&self, value: A) -> MemoLazy<A> {
+        let spare = unsafe { &mut *(*self.spare).get() };
+        match spare.take() {
+            Some(lazy) => {
+                lazy.replace(Some(value));
+                lazy
+            },
+            None => MemoLazy::evaluated(&mut self.node.sodium_ctx().gc_ctx(), value)
+        }
+    }
+
+    pub fn multi_producer(&self) -> SinkHandle<A> {
+        let producer_queue = unsafe { &mut *(*self.producer_queue).get() };
+        if producer_queue.is_none() {
+            *producer_queue = Some(channel());
+        }
+        let &(ref sender, _) = producer_queue.as_ref().unwrap();
+        SinkHandle {
+            sender: sender.clone()
+        }
+    }
+
+    pub fn drain_producers(&self) -> usize {
+        self.drain_producers_where(|_a: &A| true)
+    }
+
+    // Values admit turns down are dropped, but still counted as drained.
+    pub fn drain_producers_where<F: FnMut(&A) -> bool>(&self, mut admit: F) -> usize {
+        let producer_queue = unsafe { &*(*self.producer_queue).get() };
+        let mut values: Vec<A> =
+            match producer_queue {
+                &Some((_, ref receiver)) => receiver.try_iter().collect(),
+                &None => Vec::new()
+            };
+        let count = values.len();
+        values.retain(|value| admit(value));
+        if !values.is_empty() {
+            let sodium_ctx = self.node.sodium_ctx();
+            if self.coalescer_op.is_some() {
+                sodium_ctx.transaction(|| {
+                    for value in values {
+                        self.send(value);
+                    }
+                });
+            } else {
+                // Without a coalescer a second send in the same transaction
+                // would replace the first, so each value gets its own.
+                for value in values {
+                    self.send(value);
+                }
+            }
+        }
+        count
+    }
+
+    pub fn downgrade(&self) -> WeakStreamSink<A> {
+        WeakStreamSink {
+            value: self.value.downgrade(),
+            next_value: self.next_value.downgrade(),
+            node: self.node.downgrade(),
+            will_clear: self.will_clear.clone(),
+            clear: self.clear.clone(),
+            spare: self.spare.clone(),
+            coalescer_op: self.coalescer_op.clone(),
+            producer_queue: self.producer_queue.clone()
+        }
+    }
+
     pub fn to_stream(&self) -> Stream<A> {
-        let mut gc_ctx = self.node.sodium_ctx().gc_ctx();
+        let gc_ctx = self.node.sodium_ctx().gc_ctx();
         Stream {
             data: gc_ctx.new_gc_with_desc(UnsafeCell::new(StreamData {
                 value: self.value.clone(),
-                node: self.node.clone()
+                node: self.node.clone(),
+                replay_op: None,
+                stage_op: None
             }), String::from("StreamSink::to_stream"))
         }
     }
 }
 
+impl<A> WeakStreamSink<A> {
+    pub fn upgrade(&self) -> Option<StreamSink<A>> {
+        let node = self.node.upgrade()?;
+        Some(StreamSink {
+            value: self.value.upgrade()?,
+            next_value: self.next_value.upgrade()?,
+            node,
+            will_clear: self.will_clear.clone(),
+            clear: self.clear.clone(),
+            spare: self.spare.clone(),
+            coalescer_op: self.coalescer_op.clone(),
+            producer_queue: self.producer_queue.clone()
+        })
+    }
+}
+
 impl<A: Clone + Trace + Finalize + 'static> Clone for StreamSink<A> {
     fn clone(&self) -> Self {
         StreamSink {
@@ -113,7 +266,10 @@ impl<A: Clone + Trace + Finalize + 'static> Clone for StreamSink<A> {
             next_value: self.next_value.clone(),
             node: self.node.clone(),
             will_clear: self.will_clear.clone(),
-            coalescer_op: self.coalescer_op.clone()
+            clear: self.clear.clone(),
+            spare: self.spare.clone(),
+            coalescer_op: self.coalescer_op.clone(),
+            producer_queue: self.producer_queue.clone()
         }
     }
 }
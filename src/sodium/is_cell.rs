@@ -50,6 +50,14 @@ pub trait IsCell<A: Finalize + Trace + Clone + 'static>: Sized {
         self.to_cell().lift6(cb, cc, cd, ce, cf, f)
     }
 
+    fn set_name(&self, name: &str) {
+        self.to_cell().impl_.set_name(name);
+    }
+
+    fn name(&self) -> Option<String> {
+        self.to_cell().impl_.name()
+    }
+
     fn add_cleanup<CLEANUP:IsLambdaMut0<()>+'static>(&self, cleanup: CLEANUP) {
         self.to_cell().add_cleanup(cleanup);
     }
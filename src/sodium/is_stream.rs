@@ -1,4 +1,8 @@
+use sodium::AsyncRunner;
+use sodium::AsyncStream;
+use sodium::CancellationToken;
 use sodium::Cell;
+use sodium::EventCollector;
 use sodium::IsCell;
 use sodium::IsLambdaMut0;
 use sodium::IsLambda1;
@@ -7,13 +11,22 @@ use sodium::IsLambda3;
 use sodium::IsLambda4;
 use sodium::IsLambda5;
 use sodium::IsLambda6;
+use sodium::IsWeak;
 use sodium::Listener;
+use sodium::Mailbox;
 use sodium::MemoLazy;
+use sodium::OnceStream;
+use sodium::OverflowPolicy;
 use sodium::Stream;
 use sodium::StreamLoop;
 use sodium::StreamSink;
+use sodium::Termination;
+use sodium::TxId;
+use sodium::async_channel;
 use sodium::gc::Finalize;
 use sodium::gc::Trace;
+use std::future::Future;
+use std::sync::Arc;
 
 pub trait IsStream<A: Finalize + Trace + Clone + 'static> {
     fn to_stream(&self) -> Stream<A>;
@@ -25,9 +38,18 @@ pub trait IsStream<A: Finalize + Trace + Clone + 'static> {
         self.to_stream().map(f)
     }
 
+    fn map_fn<B: Clone + Trace + Finalize + 'static>(&self, f: fn(&A) -> B) -> Stream<B> {
+        self.to_stream().map_fn(f)
+    }
+
     fn map_to<B: Clone + Trace + Finalize + 'static>(&self, b: &B) -> Stream<B> {
-        let b = b.clone();
-        self.map(move |_a: &A| b.clone())
+        Stream {
+            impl_: self.to_stream().impl_.map_to(b.clone())
+        }
+    }
+
+    fn to_unit(&self) -> Stream<()> {
+        self.map_to(&())
     }
 
     fn hold(&self, a: A) -> Cell<A> {
@@ -38,6 +60,10 @@ pub trait IsStream<A: Finalize + Trace + Clone + 'static> {
         self.to_stream().filter(pred)
     }
 
+    fn filter_fn(&self, pred: fn(&A) -> bool) -> Stream<A> {
+        self.to_stream().filter_fn(pred)
+    }
+
     fn merge<SA: IsStream<A>, FN: Fn(&A,&A)->A+'static>(&self, sa: SA, f: FN) -> Stream<A> {
         self.to_stream().merge(sa, f)
     }
@@ -64,6 +90,16 @@ pub trait IsStream<A: Finalize + Trace + Clone + 'static> {
         self.to_stream().collect_lazy(init_state, f)
     }
 
+    fn with_previous(&self) -> Stream<(Option<A>,A)> {
+        self.collect(None, |a: &A, prev_op: &Option<A>| ((prev_op.clone(), a.clone()), Some(a.clone())))
+    }
+
+    fn diff_by<B: Clone + Trace + Finalize + 'static, F: Fn(&A,&A)->B + 'static>(&self, f: F) -> Stream<B> {
+        self.with_previous()
+            .map(move |&(ref prev_op, ref a): &(Option<A>,A)| prev_op.as_ref().map(|prev| f(prev, a)))
+            .filter_option()
+    }
+
     fn accum<S,F>(&self, init_state: S, f: F) -> Cell<S>
         where S: Clone + Trace + Finalize + 'static,
               F: IsLambda2<A,S,S> + 'static
@@ -84,6 +120,66 @@ pub trait IsStream<A: Finalize + Trace + Clone + 'static> {
         self.to_stream().once()
     }
 
+    // Stops when the termination stream fires, typically StreamSink::termination(), and then
+    // lets go of its inputs. Events in that same transaction are dropped.
+    fn take_until_closed<E: Clone + Trace + Finalize + 'static, ST: IsStream<Termination<E>>>(&self, termination: ST) -> Stream<A> {
+        self.to_stream().take_until_closed(termination)
+    }
+
+    // Passes events on until stop fires, e.g. when the component it belongs to goes away,
+    // and then lets go of its inputs for good. Events in that same transaction are dropped.
+    fn until<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(&self, stop: SB) -> Stream<A> {
+        self.to_stream().until(stop)
+    }
+
+    fn map_once<B: 'static, F: Fn(&A) -> B + 'static>(&self, f: F) -> OnceStream<B> {
+        self.to_stream().map_once(f)
+    }
+
+    fn take(&self, n: usize) -> Stream<A> {
+        self.to_stream().take(n)
+    }
+
+    // Lets at most n events through per outer transaction, counting the nested transactions
+    // started by Operational::split. Drop discards the rest, Block defers them to the following
+    // transactions in order and Latest defers only the most recent one.
+    fn limit_per_transaction(&self, n: usize, policy: OverflowPolicy) -> Stream<A> {
+        self.to_stream().limit_per_transaction(n, policy)
+    }
+
+    fn skip(&self, n: usize) -> Stream<A> {
+        self.to_stream().skip(n)
+    }
+
+    fn replay(&self, n: usize) -> Stream<A> {
+        self.to_stream().replay(n)
+    }
+
+    fn coincidence_with<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(&self, sb: SB) -> Stream<(A,B)> {
+        self.to_stream().coincidence_with(sb)
+    }
+
+    fn alone<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(&self, sb: SB) -> Stream<A> {
+        self.to_stream().alone(sb)
+    }
+
+    // Pairs each event with the id of the transaction it fired in.
+    fn tag_tx(&self) -> Stream<(TxId,A)> {
+        self.to_stream().tag_tx()
+    }
+
+    // Pairs each event with the latest value of sb, None until sb has fired. Like snapshot,
+    // a value sb fires in the same transaction is not seen until the next one.
+    fn sample_from<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(&self, sb: SB) -> Stream<(A,Option<B>)> {
+        self.to_stream().sample_from(sb)
+    }
+
+    // Each event picks a new inner stream, events from the previous one stop. Like switch_s,
+    // the switch takes effect at the end of the transaction that fired.
+    fn switch_map<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>, F: Fn(&A)->SB + 'static>(&self, f: F) -> Stream<B> {
+        self.to_stream().switch_map(f)
+    }
+
     fn or_else<SA: IsStream<A>>(&self, sa: SA) -> Stream<A> {
         self.merge(sa, |l, _r| l.clone())
     }
@@ -112,6 +208,14 @@ pub trait IsStream<A: Finalize + Trace + Clone + 'static> {
         self.to_stream().snapshot6(cb, cc, cd, ce, cf, f)
     }
 
+    fn set_name(&self, name: &str) {
+        self.to_stream().impl_.set_name(name);
+    }
+
+    fn name(&self) -> Option<String> {
+        self.to_stream().impl_.name()
+    }
+
     fn add_cleanup<CLEANUP:IsLambdaMut0<()>+'static>(&self, cleanup: CLEANUP) {
         self.to_stream().add_cleanup(cleanup);
     }
@@ -129,6 +233,58 @@ pub trait IsStream<A: Finalize + Trace + Clone + 'static> {
     ) -> Listener {
         self.to_stream().listen_weak(callback)
     }
+
+    fn collector(&self) -> EventCollector<A> {
+        EventCollector::new(&self.to_stream())
+    }
+
+    // Calls f with the object while weak can still be upgraded. The first event after it has
+    // been dropped is skipped and removes the listener, so the handle need not be kept.
+    fn listen_bound<T, W: IsWeak<T> + Clone, F: Fn(&T,&A) + 'static>(&self, weak: W, f: F) -> Listener {
+        self.to_stream().listen_bound(weak, f)
+    }
+
+    // The listener removes itself after the first event, there is no need to keep the handle.
+    fn listen_once<CALLBACK:FnMut(&A)+'static>(
+        &self,
+        callback: CALLBACK
+    ) -> Listener {
+        self.to_stream().listen_once(callback)
+    }
+
+    // Listeners on the same stream run highest priority first, then in the order they were added.
+    fn listen_with_priority<CALLBACK:FnMut(&A)+'static>(
+        &self,
+        priority: i32,
+        callback: CALLBACK
+    ) -> Listener {
+        self.to_stream().listen_with_priority(priority, callback)
+    }
+
+    // f runs on whichever thread drains the mailbox instead of inside the transaction.
+    fn listen_on<F: Fn(&A) + Send + Sync + 'static>(&self, mailbox: &Mailbox, f: F) -> Listener where A: Send {
+        let mailbox = mailbox.clone();
+        let f = Arc::new(f);
+        self.listen(move |a: &A| {
+            let a = a.clone();
+            let f = f.clone();
+            mailbox.post(move || f(&a));
+        })
+    }
+
+    fn to_async_stream_bounded(&self, capacity: usize, policy: OverflowPolicy) -> (Listener, AsyncStream<A>) where A: Send {
+        let (sender, stream) = async_channel(capacity, policy);
+        let listener = self.listen(move |a: &A| sender.send(a.clone()));
+        (listener, stream)
+    }
+
+    fn map_async_cancellable<B, F, FUT>(&self, runner: &AsyncRunner, f: F) -> Stream<B>
+        where B: Clone + Trace + Finalize + 'static,
+              F: Fn(&A, CancellationToken) -> FUT + 'static,
+              FUT: Future<Output=B> + 'static
+    {
+        runner.map_async_cancellable(self.to_stream(), f)
+    }
 }
 
 impl<A: Finalize + Trace + Clone + 'static> IsStream<A> for Stream<A> {
@@ -145,7 +301,7 @@ impl<A: Finalize + Trace + Clone + 'static> IsStream<A> for StreamLoop<A> {
     }
 }
 
-impl<A: Finalize + Trace + Clone + 'static> IsStream<A> for StreamSink<A> {
+impl<A: Finalize + Trace + Clone + 'static, E: Finalize + Trace + Clone + 'static> IsStream<A> for StreamSink<A,E> {
     fn to_stream(&self) -> Stream<A> {
         Stream {
             impl_: self.impl_.to_stream()
@@ -0,0 +1,24 @@
+use std::rc;
+use std::sync;
+
+// A weak reference to an object the application owns, for listeners that should stop when
+// it goes away. Implemented for both Rc and Arc weak references.
+pub trait IsWeak<T>: 'static {
+    fn with_upgraded<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R>;
+
+    fn is_dead(&self) -> bool {
+        self.with_upgraded(|_| ()).is_none()
+    }
+}
+
+impl<T: 'static> IsWeak<T> for rc::Weak<T> {
+    fn with_upgraded<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        self.upgrade().map(|t| f(&t))
+    }
+}
+
+impl<T: 'static> IsWeak<T> for sync::Weak<T> {
+    fn with_upgraded<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        self.upgrade().map(|t| f(&t))
+    }
+}
@@ -0,0 +1,246 @@
+use sodium::SodiumCtx;
+use sodium::Stream;
+use sodium::StreamSink;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::cell::Cell as StdCell;
+use std::cell::RefCell;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// How a journalled event is written out. This is not serde's trait, the crate has no
+// dependencies, but a serde backed impl is a few lines, e.g. bincode::serialize(self).
+pub trait Serialize: Sized {
+    fn serialize(&self) -> Vec<u8>;
+
+    fn deserialize(bytes: &[u8]) -> Result<Self,String>;
+}
+
+impl Serialize for Vec<u8> {
+    fn serialize(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Vec<u8>,String> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Serialize for String {
+    fn serialize(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<String,String> {
+        String::from_utf8(bytes.to_vec()).map_err(|err| err.to_string())
+    }
+}
+
+macro_rules! mk_serialize_le_bytes {
+    ($($T:ty),*) => {
+        $(
+            impl Serialize for $T {
+                fn serialize(&self) -> Vec<u8> {
+                    self.to_le_bytes().to_vec()
+                }
+
+                fn deserialize(bytes: &[u8]) -> Result<$T,String> {
+                    let mut buf = [0u8; ::std::mem::size_of::<$T>()];
+                    if bytes.len() != buf.len() {
+                        return Err(format!("expected {} bytes for {}, got {}", buf.len(), stringify!($T), bytes.len()));
+                    }
+                    buf.copy_from_slice(bytes);
+                    Ok(<$T>::from_le_bytes(buf))
+                }
+            }
+        )*
+    }
+}
+
+mk_serialize_le_bytes![i32, i64, u32, u64, f64];
+
+// When appended events are flushed through to the disk. Whatever hasn't been synced can be
+// lost in a crash, but never leaves a torn record that breaks replay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    // Before the event enters the graph.
+    Always,
+    // After every n events, and on JournalSink::sync.
+    Every(u32),
+    // Left to the OS.
+    Never
+}
+
+// Each record is its length and an FNV-1a checksum of the payload, both little endian u32,
+// followed by the payload.
+const HEADER_LEN: usize = 8;
+
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for b in bytes {
+        hash = (hash ^ (*b as u32)).wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(buf)
+}
+
+// The payloads of the complete records, and the length of the file they take up. Anything
+// after that is a write that was cut short.
+fn scan(bytes: &[u8]) -> (Vec<&[u8]>, usize) {
+    let mut records = Vec::new();
+    let mut at = 0;
+    while bytes.len() - at >= HEADER_LEN {
+        let len = u32_at(bytes, at) as usize;
+        let sum = u32_at(bytes, at + 4);
+        let start = at + HEADER_LEN;
+        if bytes.len() - start < len || checksum(&bytes[start..start + len]) != sum {
+            break;
+        }
+        records.push(&bytes[start..start + len]);
+        at = start + len;
+    }
+    (records, at)
+}
+
+// An append-only log of events, for rebuilding state after a restart from everything that
+// was sent before it.
+pub struct Journal {
+    path: PathBuf
+}
+
+impl Journal {
+    // Creates the file if it is missing. A record torn by a crash part way through a write is
+    // cut off here, so new events don't end up behind it.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Journal> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let (_, valid_len) = scan(&bytes);
+        if valid_len < bytes.len() {
+            file.set_len(valid_len as u64)?;
+            file.sync_data()?;
+        }
+        Ok(Journal { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn events<A: Serialize>(&self) -> io::Result<Vec<A>> {
+        let mut bytes = Vec::new();
+        File::open(&self.path)?.read_to_end(&mut bytes)?;
+        let (records, _) = scan(&bytes);
+        records
+            .into_iter()
+            .map(|record| A::deserialize(record).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)))
+            .collect()
+    }
+
+    // Sends every journalled event into sink, each in its own transaction, and returns how
+    // many there were. Use the plain sink here, not a JournalSink, or they get written again.
+    pub fn replay_into<A: Serialize + Clone + Trace + Finalize + 'static>(&self, sink: &StreamSink<A>) -> io::Result<usize> {
+        let events: Vec<A> = self.events()?;
+        for a in &events {
+            sink.send(a);
+        }
+        Ok(events.len())
+    }
+
+    // Appending to the end of the journal.
+    pub fn sink<A: Serialize + Clone + Trace + Finalize + 'static>(&self, sodium_ctx: &SodiumCtx, policy: FsyncPolicy) -> io::Result<JournalSink<A>> {
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        let end = file.seek(SeekFrom::End(0))?;
+        Ok(JournalSink {
+            sink: sodium_ctx.new_stream_sink(),
+            file: Rc::new(RefCell::new(file)),
+            policy,
+            end: Rc::new(StdCell::new(end)),
+            unsynced: Rc::new(StdCell::new(0))
+        })
+    }
+}
+
+// A StreamSink that writes each event to the journal before it enters the graph.
+pub struct JournalSink<A> {
+    sink: StreamSink<A>,
+    file: Rc<RefCell<File>>,
+    policy: FsyncPolicy,
+    // Where the last complete record ends, a failed write is cut back to it.
+    end: Rc<StdCell<u64>>,
+    unsynced: Rc<StdCell<u32>>
+}
+
+impl<A: Serialize + Clone + Trace + Finalize + 'static> JournalSink<A> {
+    // Nothing enters the graph on an error. A failed write is taken back out of the file, but a
+    // failed sync leaves the event journalled, to be seen on the next replay.
+    pub fn send(&self, a: &A) -> io::Result<()> {
+        let payload = a.serialize();
+        // The length has to fit the u32 in the header.
+        if payload.len() > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("a journal record holds at most {} bytes, got {}", u32::MAX, payload.len())));
+        }
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&checksum(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+        {
+            let mut file = self.file.borrow_mut();
+            if let Err(err) = file.write_all(&record) {
+                let _ = file.set_len(self.end.get());
+                let _ = file.seek(SeekFrom::Start(self.end.get()));
+                return Err(err);
+            }
+            self.end.set(self.end.get() + record.len() as u64);
+            self.unsynced.set(self.unsynced.get() + 1);
+            let sync =
+                match self.policy {
+                    FsyncPolicy::Always => true,
+                    FsyncPolicy::Every(n) => self.unsynced.get() >= n,
+                    FsyncPolicy::Never => false
+                };
+            if sync {
+                file.sync_data()?;
+                self.unsynced.set(0);
+            }
+        }
+        self.sink.send(a);
+        Ok(())
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.borrow_mut().sync_data()?;
+        self.unsynced.set(0);
+        Ok(())
+    }
+
+    pub fn to_stream(&self) -> Stream<A> {
+        self.sink.to_stream()
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> Clone for JournalSink<A> {
+    fn clone(&self) -> Self {
+        JournalSink {
+            sink: self.sink.clone(),
+            file: self.file.clone(),
+            policy: self.policy,
+            end: self.end.clone(),
+            unsynced: self.unsynced.clone()
+        }
+    }
+}
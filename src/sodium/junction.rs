@@ -0,0 +1,167 @@
+use sodium::Cell;
+use sodium::CellSink;
+use sodium::IsCell;
+use sodium::IsStream;
+use sodium::SodiumCtx;
+use sodium::Stream;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Sends to the sink, or from a listener where that is not allowed, once the transaction is
+// over. The junction's inputs change from the next transaction either way.
+fn send_when_allowed<A: Clone + Trace + Finalize + 'static>(sodium_ctx: &SodiumCtx, sink: &CellSink<A>, value: A) {
+    if sodium_ctx.in_callback() {
+        let sink = sink.clone();
+        sodium_ctx.post(move || sink.send(&value));
+    } else {
+        sink.send(&value);
+    }
+}
+
+// Identifies one input plugged into a StreamJunction or CellJunction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PlugHandle(u64);
+
+struct Inputs<X> {
+    next_id: u64,
+    plugged: Vec<(PlugHandle,X)>
+}
+
+impl<X: Clone> Inputs<X> {
+    fn new() -> Inputs<X> {
+        Inputs {
+            next_id: 0,
+            plugged: Vec::new()
+        }
+    }
+
+    fn plug(&mut self, x: X) -> PlugHandle {
+        let handle = PlugHandle(self.next_id);
+        self.next_id += 1;
+        self.plugged.push((handle, x));
+        handle
+    }
+
+    fn unplug(&mut self, handle: PlugHandle) -> bool {
+        let len = self.plugged.len();
+        self.plugged.retain(|&(h, _)| h != handle);
+        self.plugged.len() != len
+    }
+
+    fn current(&self) -> Vec<X> {
+        self.plugged.iter().map(|(_, x)| x.clone()).collect()
+    }
+}
+
+// The merge of a set of streams that can be changed at any time, e.g. by plugins coming and
+// going, without touching anything built on stream(). Inputs are merged in the order they
+// were plugged in, with f combining simultaneous events.
+pub struct StreamJunction<A> {
+    sodium_ctx: SodiumCtx,
+    inputs: RefCell<Inputs<Stream<A>>>,
+    merged: CellSink<Stream<A>>,
+    stream: Stream<A>,
+    f: Rc<Combine<A>>
+}
+
+type Combine<A> = dyn Fn(&A,&A) -> A;
+
+impl<A: Clone + Trace + Finalize + 'static> StreamJunction<A> {
+    pub fn new<F: Fn(&A,&A) -> A + 'static>(sodium_ctx: &SodiumCtx, f: F) -> StreamJunction<A> {
+        let merged = sodium_ctx.new_cell_sink(sodium_ctx.never());
+        let stream = Cell::switch_s(merged.to_cell());
+        StreamJunction {
+            sodium_ctx: sodium_ctx.clone(),
+            inputs: RefCell::new(Inputs::new()),
+            merged,
+            stream,
+            f: Rc::new(f)
+        }
+    }
+
+    pub fn stream(&self) -> Stream<A> {
+        self.stream.clone()
+    }
+
+    // Takes effect from the next transaction.
+    pub fn plug<SA: IsStream<A>>(&self, sa: SA) -> PlugHandle {
+        let handle = self.inputs.borrow_mut().plug(sa.to_stream());
+        self.rebuild();
+        handle
+    }
+
+    // False if the handle was not plugged in, e.g. it was already unplugged.
+    pub fn unplug(&self, handle: PlugHandle) -> bool {
+        let unplugged = self.inputs.borrow_mut().unplug(handle);
+        if unplugged {
+            self.rebuild();
+        }
+        unplugged
+    }
+
+    pub fn input_count(&self) -> usize {
+        self.inputs.borrow().plugged.len()
+    }
+
+    fn rebuild(&self) {
+        let mut merged_op: Option<Stream<A>> = None;
+        for sa in self.inputs.borrow().current() {
+            merged_op = Some(
+                match merged_op {
+                    Some(merged) => {
+                        let f = self.f.clone();
+                        merged.merge(sa, move |a1: &A, a2: &A| f(a1, a2))
+                    },
+                    None => sa
+                }
+            );
+        }
+        send_when_allowed(&self.sodium_ctx, &self.merged, merged_op.unwrap_or_else(|| self.sodium_ctx.never()));
+    }
+}
+
+// The current values of a set of cells that can be changed at any time, in the order they
+// were plugged in.
+pub struct CellJunction<A> {
+    sodium_ctx: SodiumCtx,
+    inputs: RefCell<Inputs<Cell<A>>>,
+    cells: CellSink<Vec<Cell<A>>>,
+    cell: Cell<Vec<A>>
+}
+
+impl<A: Clone + Trace + Finalize + 'static> CellJunction<A> {
+    pub fn new(sodium_ctx: &SodiumCtx) -> CellJunction<A> {
+        let cells = sodium_ctx.new_cell_sink(Vec::new());
+        let cell = Cell::switch_vec(cells.to_cell());
+        CellJunction {
+            sodium_ctx: sodium_ctx.clone(),
+            inputs: RefCell::new(Inputs::new()),
+            cells,
+            cell
+        }
+    }
+
+    pub fn cell(&self) -> Cell<Vec<A>> {
+        self.cell.clone()
+    }
+
+    pub fn plug<CA: IsCell<A>>(&self, ca: CA) -> PlugHandle {
+        let handle = self.inputs.borrow_mut().plug(ca.to_cell());
+        send_when_allowed(&self.sodium_ctx, &self.cells, self.inputs.borrow().current());
+        handle
+    }
+
+    pub fn unplug(&self, handle: PlugHandle) -> bool {
+        let unplugged = self.inputs.borrow_mut().unplug(handle);
+        if unplugged {
+            send_when_allowed(&self.sodium_ctx, &self.cells, self.inputs.borrow().current());
+        }
+        unplugged
+    }
+
+    pub fn input_count(&self) -> usize {
+        self.inputs.borrow().plugged.len()
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    Unbounded,
+    // The producer waits for the consumer once this many items are queued.
+    Block(usize),
+    // The oldest queued item is discarded to make room for a new one.
+    DropOldest(usize),
+    // New items are discarded while the queue is full.
+    DropNewest(usize)
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    dropped: u64
+}
+
+pub struct BoundedQueue<T> {
+    state: Mutex<QueueState<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    policy: Backpressure
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(policy: Backpressure) -> BoundedQueue<T> {
+        BoundedQueue {
+            state: Mutex::new(QueueState { items: VecDeque::new(), dropped: 0 }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            policy
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        let mut state = self.state.lock().unwrap();
+        match self.policy {
+            Backpressure::Unbounded => (),
+            Backpressure::Block(capacity) => {
+                while state.items.len() >= capacity.max(1) {
+                    state = self.not_full.wait(state).unwrap();
+                }
+            },
+            Backpressure::DropOldest(capacity) => {
+                while state.items.len() >= capacity.max(1) {
+                    state.items.pop_front();
+                    state.dropped = state.dropped + 1;
+                }
+            },
+            Backpressure::DropNewest(capacity) => {
+                if state.items.len() >= capacity.max(1) {
+                    state.dropped = state.dropped + 1;
+                    return;
+                }
+            }
+        }
+        state.items.push_back(item);
+        self.not_empty.notify_one();
+    }
+
+    pub fn try_pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        let item_op = state.items.pop_front();
+        if item_op.is_some() {
+            self.not_full.notify_one();
+        }
+        item_op
+    }
+
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        if state.items.is_empty() {
+            state = self.not_empty.wait_timeout(state, timeout).unwrap().0;
+        }
+        let item_op = state.items.pop_front();
+        if item_op.is_some() {
+            self.not_full.notify_one();
+        }
+        item_op
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+// A queue of callbacks that some other thread (e.g. the UI thread) runs with run_pending().
+pub struct Mailbox {
+    queue: Arc<BoundedQueue<Box<dyn FnOnce() + Send>>>
+}
+
+impl Mailbox {
+    pub fn new(policy: Backpressure) -> Mailbox {
+        Mailbox {
+            queue: Arc::new(BoundedQueue::new(policy))
+        }
+    }
+
+    pub fn post<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.queue.push(Box::new(f));
+    }
+
+    pub fn run_pending(&self) -> usize {
+        let mut count = 0;
+        while let Some(f) = self.queue.try_pop() {
+            f();
+            count = count + 1;
+        }
+        count
+    }
+
+    pub fn run_one_timeout(&self, timeout: Duration) -> bool {
+        match self.queue.pop_timeout(timeout) {
+            Some(f) => {
+                f();
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped()
+    }
+}
+
+impl Clone for Mailbox {
+    fn clone(&self) -> Self {
+        Mailbox {
+            queue: self.queue.clone()
+        }
+    }
+}
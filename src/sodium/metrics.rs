@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+// Counts of durations in power of two buckets of microseconds, so percentiles come out to
+// within a factor of two of the truth however long the run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    // Bucket 0 holds durations under a microsecond, bucket b those from 2^(b-1) up to 2^b.
+    buckets: Vec<u64>,
+    count: u64,
+    total: Duration,
+    max: Duration
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            buckets: Vec::new(),
+            count: 0,
+            total: Duration::from_secs(0),
+            max: Duration::from_secs(0)
+        }
+    }
+
+    pub fn record(&mut self, d: Duration) {
+        let micros = d.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (64 - micros.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += d;
+        if d > self.max {
+            self.max = d;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let mean = self.total.as_nanos() / self.count as u128;
+        Some(Duration::from_nanos(mean.min(u64::MAX as u128) as u64))
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.max)
+    }
+
+    // The upper edge of the bucket the q'th quantile falls in, q running from 0 to 1, but
+    // never more than the longest duration seen.
+    pub fn percentile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let target = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, n) in self.buckets.iter().enumerate() {
+            seen += *n;
+            if seen >= target {
+                let upper = Duration::from_micros(1u64 << bucket);
+                return Some(if upper < self.max { upper } else { self.max });
+            }
+        }
+        Some(self.max)
+    }
+}
+
+// What Stream::monitor has seen under one name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MonitorMetrics {
+    pub events: u64,
+    // Time from each event to the next.
+    pub inter_event: Histogram,
+    // Time from each event to the end of its transaction's propagation, which covers
+    // everything downstream of the monitor, listeners included.
+    pub processing: Histogram
+}
+
+impl Default for MonitorMetrics {
+    fn default() -> MonitorMetrics {
+        MonitorMetrics::new()
+    }
+}
+
+impl MonitorMetrics {
+    pub fn new() -> MonitorMetrics {
+        MonitorMetrics {
+            events: 0,
+            inter_event: Histogram::new(),
+            processing: Histogram::new()
+        }
+    }
+}
@@ -1,18 +1,66 @@
+pub use self::async_bridge::AsyncRunner;
+pub use self::async_bridge::AsyncSender;
+pub use self::async_bridge::AsyncStream;
+pub use self::async_bridge::CancellationToken;
+pub use self::async_bridge::OverflowPolicy;
+pub use self::async_bridge::async_channel;
+pub use self::binding::Binding;
+pub use self::binding::bind_bidirectional;
 pub use self::cell::Cell;
 pub use self::cell_loop::CellLoop;
+pub use self::cell_mirror::ArcCellMirror;
 pub use self::cell_sink::CellSink;
+pub use self::event_collector::EventCollector;
+pub use self::fused_stream::FusedStream;
+pub use self::graph_builder::GraphBuilder;
 pub use self::is_cell::IsCell;
 pub use self::is_stream::IsStream;
 pub use self::is_stream::IsStreamOption;
+pub use self::is_weak::IsWeak;
+pub use self::junction::CellJunction;
+pub use self::junction::PlugHandle;
+pub use self::junction::StreamJunction;
+pub use self::mailbox::Backpressure;
+pub use self::mailbox::BoundedQueue;
+pub use self::mailbox::Mailbox;
+pub use self::once_stream::OnceStream;
 pub use self::operational::Operational;
+#[cfg(feature = "threads")]
+pub use self::runtime::RemoteCellHandle;
+#[cfg(feature = "threads")]
+pub use self::runtime::RemoteSinkHandle;
+#[cfg(feature = "threads")]
+pub use self::runtime::RuntimeCtx;
+#[cfg(feature = "threads")]
+pub use self::runtime::RuntimeStopped;
+#[cfg(feature = "threads")]
+pub use self::runtime::SodiumRuntime;
+pub use self::sodium_ctx::Batch;
+pub use self::sodium_ctx::PanicPolicy;
+pub use self::sodium_ctx::ResumePolicy;
+pub use self::sodium_ctx::SampleReader;
 pub use self::sodium_ctx::SodiumCtx;
+pub use self::sodium_ctx::SodiumCtxBuildError;
+pub use self::sodium_ctx::SodiumCtxBuilder;
 pub use self::stream::Stream;
+pub use self::stream::WeakStream;
 pub use self::stream_loop::StreamLoop;
 pub use self::stream_sink::StreamSink;
+pub use self::stream_sink::Termination;
 pub use self::impl_::Dep;
+pub use self::impl_::EdgeError;
 pub use self::impl_::Lambda;
 pub use self::impl_::Listener;
+pub use self::impl_::ListenerInfo;
+pub use self::impl_::ListenerMiddleware;
 pub use self::impl_::MemoLazy;
+pub use self::impl_::OptimizerStats;
+pub use self::impl_::SodiumError;
+pub use self::impl_::SodiumScope;
+pub use self::impl_::SinkHandle;
+pub use self::impl_::TxId;
+pub use self::impl_::TxObserver;
+pub use self::impl_::TxSummary;
 pub use self::impl_::IsLambda0;
 pub use self::impl_::IsLambdaMut0;
 pub use self::impl_::IsLambda1;
@@ -23,18 +71,59 @@ pub use self::impl_::IsLambda5;
 pub use self::impl_::IsLambda6;
 pub use self::impl_::gc;
 
+mod async_bridge;
+mod binding;
+pub mod bus;
 mod cell;
 mod cell_loop;
+mod cell_mirror;
 mod cell_sink;
 
+#[cfg(feature = "os")]
+pub mod config;
+
+pub mod delta;
+
+#[cfg(feature = "dsp")]
+pub mod dsp;
+
+mod event_collector;
+pub mod fsm;
+mod fused_stream;
+pub mod gesture;
+mod graph_builder;
+pub mod hot;
 mod is_cell;
 mod is_stream;
+mod is_weak;
+
+#[cfg(feature = "os")]
+pub mod journal;
+
+mod junction;
 
 #[macro_use]
 mod impl_;
 
+mod mailbox;
+pub mod metrics;
+pub mod node;
+mod once_stream;
 mod operational;
+
+#[cfg(feature = "os")]
+pub mod os;
+
+#[cfg(feature = "threads")]
+mod runtime;
+
 mod sodium_ctx;
+pub mod sources;
 mod stream;
 mod stream_loop;
 mod stream_sink;
+pub mod test;
+pub mod time;
+pub mod track;
+pub mod tuple;
+pub mod undo;
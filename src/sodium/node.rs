@@ -0,0 +1,161 @@
+use sodium::Dep;
+use sodium::IsCell;
+use sodium::IsStream;
+use sodium::Lambda;
+use sodium::Stream;
+use sodium::StreamSink;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use sodium::impl_;
+use std::cell::RefCell;
+
+// Building blocks for primitives that can not be expressed with the existing combinators, e.g.
+// a node fed by a hardware driver. Start with SodiumCtx::new_node_builder.
+//
+// The rank of the node is one more than the highest rank of its dependencies, so its update
+// always runs after every dependency has settled for the transaction.
+pub struct NodeBuilder<A> {
+    sodium_ctx: impl_::SodiumCtx,
+    desc: &'static str,
+    deps: Vec<impl_::Node>,
+    update_deps: Vec<Dep>,
+    update_op: Option<Box<Update<A>>>,
+    cleanups: Vec<Box<dyn FnMut()>>
+}
+
+type Update<A> = dyn FnMut(&Inputs) -> Option<A>;
+
+// Read access to the dependencies from inside an update.
+pub struct Inputs {
+    _private: ()
+}
+
+// The built node. Its events are the values returned by the update, plus anything passed to
+// fire() from outside the graph.
+pub struct CustomNode<A> {
+    stream: Stream<A>,
+    input: StreamSink<A>
+}
+
+impl Inputs {
+    // The value the stream fired with in the current transaction, if any.
+    pub fn value<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(&self, sb: &SB) -> Option<B> {
+        sb.to_stream().impl_.peek_value().map(|value| value.get().clone())
+    }
+
+    // The value of the cell as of the start of the current transaction.
+    pub fn sample<B: Clone + Trace + Finalize + 'static, CB: IsCell<B>>(&self, cb: &CB) -> B {
+        cb.to_cell().impl_.sample_no_trans()
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> NodeBuilder<A> {
+    pub fn _new(sodium_ctx: &impl_::SodiumCtx, desc: &'static str) -> NodeBuilder<A> {
+        NodeBuilder {
+            sodium_ctx: sodium_ctx.clone(),
+            desc,
+            deps: Vec::new(),
+            update_deps: Vec::new(),
+            update_op: None,
+            cleanups: Vec::new()
+        }
+    }
+
+    // The update runs in every transaction where one of these fires.
+    pub fn depends_on<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(mut self, sb: &SB) -> NodeBuilder<A> {
+        let sb = sb.to_stream();
+        self.deps.push(sb.impl_._node().clone());
+        self.update_deps.push(sb.to_dep());
+        self
+    }
+
+    pub fn depends_on_cell<B: Clone + Trace + Finalize + 'static, CB: IsCell<B>>(mut self, cb: &CB) -> NodeBuilder<A> {
+        let cb = cb.to_cell();
+        self.deps.push(cb.impl_._node().clone());
+        self.update_deps.push(cb.to_dep());
+        self
+    }
+
+    // Returning Some fires the node. The update is also called once from build(), in case a
+    // dependency is firing in the transaction the node is built in.
+    pub fn on_update<UPDATE: FnMut(&Inputs) -> Option<A> + 'static>(mut self, update: UPDATE) -> NodeBuilder<A> {
+        self.update_op = Some(Box::new(update));
+        self
+    }
+
+    // Runs when the node is freed.
+    pub fn on_cleanup<CLEANUP: FnMut() + 'static>(mut self, cleanup: CLEANUP) -> NodeBuilder<A> {
+        self.cleanups.push(Box::new(cleanup));
+        self
+    }
+
+    pub fn build(self) -> CustomNode<A> {
+        let NodeBuilder { sodium_ctx, desc, mut deps, mut update_deps, update_op, mut cleanups } = self;
+        let input = StreamSink::_new(impl_::StreamSink::new(&sodium_ctx));
+        let input_stream = input.impl_.to_stream();
+        deps.push(input_stream._node().clone());
+        update_deps.push(input_stream.to_dep());
+        let update_op = update_op.map(RefCell::new);
+        let sodium_ctx2 = sodium_ctx.clone();
+        let update = Lambda::new(
+            move || {
+                if let Some(value) = input_stream.peek_value() {
+                    return Some(value);
+                }
+                let update_op = update_op.as_ref()?;
+                let value = (*update_op.borrow_mut())(&Inputs { _private: () })?;
+                Some(sodium_ctx2.new_lazy(move || value.clone()))
+            },
+            update_deps
+        );
+        let stream = Stream {
+            impl_: impl_::Stream::_new(
+                &sodium_ctx,
+                update,
+                deps,
+                move || {
+                    for cleanup in &mut cleanups {
+                        cleanup();
+                    }
+                },
+                desc
+            )
+        };
+        CustomNode {
+            stream,
+            input
+        }
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> CustomNode<A> {
+    // Fires the node in its own transaction, or in the current one when called from inside a
+    // transaction.
+    pub fn fire(&self, a: &A) {
+        self.input.send(a);
+    }
+
+    pub fn stream(&self) -> Stream<A> {
+        self.stream.clone()
+    }
+
+    pub fn rank(&self) -> u32 {
+        self.stream.impl_._node().rank()
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> IsStream<A> for CustomNode<A> {
+    fn to_stream(&self) -> Stream<A> {
+        self.stream.clone()
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> Clone for CustomNode<A> {
+    fn clone(&self) -> Self {
+        CustomNode {
+            stream: self.stream.clone(),
+            input: self.input.clone()
+        }
+    }
+}
+
@@ -0,0 +1,107 @@
+use sodium::Listener;
+use sodium::SodiumError;
+use sodium::Stream;
+use sodium::gc::Finalize;
+use sodium::gc::GcDep;
+use sodium::gc::Trace;
+use sodium::impl_;
+use std::cell::Cell as StdCell;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Carries one payload through the graph without cloning it, whoever takes it first owns it.
+// The payload is not traced, so it must not hold cells or streams.
+pub struct Slot<A> {
+    payload: Rc<RefCell<Option<A>>>
+}
+
+impl<A> Slot<A> {
+    fn new(a_op: Option<A>) -> Slot<A> {
+        Slot {
+            payload: Rc::new(RefCell::new(a_op))
+        }
+    }
+
+    // None when it was already taken, which map passes on and listen reports.
+    fn take(&self) -> Option<A> {
+        self.payload.borrow_mut().take()
+    }
+}
+
+impl<A> Clone for Slot<A> {
+    fn clone(&self) -> Self {
+        Slot {
+            payload: self.payload.clone()
+        }
+    }
+}
+
+impl<A> Trace for Slot<A> {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl<A> Finalize for Slot<A> {
+    fn finalize(&mut self) {}
+}
+
+// A stream whose events need not be Clone, e.g. a File or the sending half of a channel.
+// It moves each payload to its one consumer, so it can be mapped or listened to only once.
+// Doing either a second time fails with SodiumError::Misuse, and in panic-free mode gets a
+// stream that never fires.
+pub struct OnceStream<A> {
+    stream: Stream<Slot<A>>,
+    consumed: StdCell<bool>
+}
+
+impl<A: 'static> OnceStream<A> {
+    pub(crate) fn new(stream: Stream<Slot<A>>) -> OnceStream<A> {
+        OnceStream {
+            stream,
+            consumed: StdCell::new(false)
+        }
+    }
+
+    fn sodium_ctx(&self) -> impl_::SodiumCtx {
+        self.stream.impl_._node().sodium_ctx()
+    }
+
+    // None once it already has a consumer, after failing with SodiumError::Misuse.
+    fn consume(&self) -> Option<&Stream<Slot<A>>> {
+        if self.consumed.replace(true) {
+            self.sodium_ctx().fail(SodiumError::Misuse(String::from("OnceStream already has a consumer.")));
+            return None;
+        }
+        Some(&self.stream)
+    }
+
+    fn never<B: 'static>(&self) -> Stream<Slot<B>> {
+        Stream {
+            impl_: impl_::Stream::new(&self.sodium_ctx())
+        }
+    }
+
+    pub fn map<B: 'static, F: Fn(A) -> B + 'static>(&self, f: F) -> OnceStream<B> {
+        match self.consume() {
+            Some(stream) => OnceStream::new(stream.map(move |slot: &Slot<A>| Slot::new(slot.take().map(&f)))),
+            None => OnceStream::new(self.never())
+        }
+    }
+
+    pub fn listen<CALLBACK: FnMut(A) + 'static>(&self, mut callback: CALLBACK) -> Listener {
+        let sodium_ctx = self.sodium_ctx();
+        let stream = self.consume().cloned().unwrap_or_else(|| self.never());
+        stream.listen(move |slot: &Slot<A>| {
+            match slot.take() {
+                Some(a) => callback(a),
+                None => sodium_ctx.fail(SodiumError::Misuse(String::from("OnceStream payload was already taken.")))
+            }
+        })
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
+    // Maps each event to a payload that is moved rather than cloned, see OnceStream.
+    pub fn map_once<B: 'static, F: Fn(&A) -> B + 'static>(&self, f: F) -> OnceStream<B> {
+        OnceStream::new(self.map(move |a: &A| Slot::new(Some(f(a)))))
+    }
+}
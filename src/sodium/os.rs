@@ -0,0 +1,242 @@
+use sodium::SodiumCtx;
+use sodium::Stream;
+use sodium::StreamSink;
+use sodium::gc::Finalize;
+use sodium::gc::GcDep;
+use sodium::gc::Trace;
+use std::cell::Cell as StdCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Once;
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    Interrupt,
+    Terminate
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FsEvent {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Removed(PathBuf)
+}
+
+impl Trace for Signal {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for Signal {}
+
+impl Trace for FsEvent {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for FsEvent {}
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+// Bumped by the handler for every signal caught, indexed by signal_index. Each OsEvents
+// compares against the counts it last saw, so instances don't take signals from each other.
+static SIGNAL_COUNTS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static INSTALL_HANDLERS: Once = Once::new();
+// The errno installing the handlers failed with, 0 when they are installed.
+static INSTALL_ERROR: AtomicI32 = AtomicI32::new(0);
+
+#[cfg(any(all(target_os = "linux", not(any(target_arch = "mips", target_arch = "mips64"))), target_os = "macos"))]
+extern "C" fn on_signal(signum: i32) {
+    if let Some(index) = signal_index(signum) {
+        SIGNAL_COUNTS[index].fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn signal_index(signum: i32) -> Option<usize> {
+    match signum {
+        SIGINT => Some(0),
+        SIGTERM => Some(1),
+        _ => None
+    }
+}
+
+fn signal_counts() -> [usize; 2] {
+    [SIGNAL_COUNTS[0].load(Ordering::SeqCst), SIGNAL_COUNTS[1].load(Ordering::SeqCst)]
+}
+
+// struct sigaction as glibc and musl lay it out.
+#[cfg(all(target_os = "linux", not(any(target_arch = "mips", target_arch = "mips64"))))]
+mod sys {
+    #[repr(C)]
+    pub struct SigSet([u64; 16]);
+
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_handler: usize,
+        pub sa_mask: SigSet,
+        pub sa_flags: i32,
+        pub sa_restorer: usize
+    }
+
+    pub const SA_RESTART: i32 = 0x1000_0000;
+
+    extern "C" {
+        pub fn sigaction(signum: i32, act: *const SigAction, oldact: *mut SigAction) -> i32;
+        pub fn sigemptyset(set: *mut SigSet) -> i32;
+    }
+
+    pub fn zeroed_action() -> SigAction {
+        SigAction { sa_handler: 0, sa_mask: SigSet([0; 16]), sa_flags: 0, sa_restorer: 0 }
+    }
+}
+
+// struct sigaction as Darwin lays it out.
+#[cfg(target_os = "macos")]
+mod sys {
+    #[repr(C)]
+    pub struct SigSet(u32);
+
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_handler: usize,
+        pub sa_mask: SigSet,
+        pub sa_flags: i32
+    }
+
+    pub const SA_RESTART: i32 = 0x0002;
+
+    extern "C" {
+        pub fn sigaction(signum: i32, act: *const SigAction, oldact: *mut SigAction) -> i32;
+        pub fn sigemptyset(set: *mut SigSet) -> i32;
+    }
+
+    pub fn zeroed_action() -> SigAction {
+        SigAction { sa_handler: 0, sa_mask: SigSet(0), sa_flags: 0 }
+    }
+}
+
+#[cfg(any(all(target_os = "linux", not(any(target_arch = "mips", target_arch = "mips64"))), target_os = "macos"))]
+fn install_handler(signum: i32) -> io::Result<()> {
+    let mut action = sys::zeroed_action();
+    action.sa_handler = on_signal as extern "C" fn(i32) as usize;
+    action.sa_flags = sys::SA_RESTART;
+    unsafe {
+        if sys::sigemptyset(&mut action.sa_mask) != 0 || sys::sigaction(signum, &action, ::std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(all(target_os = "linux", not(any(target_arch = "mips", target_arch = "mips64"))), target_os = "macos")))]
+fn install_handler(_signum: i32) -> io::Result<()> {
+    Err(unsupported())
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "signal handlers are not supported on this target")
+}
+
+fn install_handlers() -> io::Result<()> {
+    INSTALL_HANDLERS.call_once(|| {
+        if let Err(err) = install_handler(SIGINT).and_then(|()| install_handler(SIGTERM)) {
+            INSTALL_ERROR.store(err.raw_os_error().unwrap_or(-1), Ordering::SeqCst);
+        }
+    });
+    match INSTALL_ERROR.load(Ordering::SeqCst) {
+        0 => Ok(()),
+        -1 => Err(unsupported()),
+        errno => Err(io::Error::from_raw_os_error(errno))
+    }
+}
+
+struct FsWatch {
+    path: PathBuf,
+    state_op: Option<(Option<SystemTime>, u64)>,
+    sink: StreamSink<FsEvent>
+}
+
+impl FsWatch {
+    fn read_state(path: &Path) -> Option<(Option<SystemTime>, u64)> {
+        fs::metadata(path).ok().map(|metadata| (metadata.modified().ok(), metadata.len()))
+    }
+
+    fn poll(&mut self) {
+        let state_op = FsWatch::read_state(&self.path);
+        let event_op =
+            match (&self.state_op, &state_op) {
+                (&None, &Some(_)) => Some(FsEvent::Created(self.path.clone())),
+                (&Some(_), &None) => Some(FsEvent::Removed(self.path.clone())),
+                (Some(old), Some(new)) if old != new => Some(FsEvent::Modified(self.path.clone())),
+                _ => None
+            };
+        self.state_op = state_op;
+        if let Some(event) = event_op {
+            self.sink.send(&event);
+        }
+    }
+}
+
+// Signals are caught asynchronously but only delivered into the graph from poll(), so the
+// graph is still driven from a single thread. Call poll() from the application's main loop.
+// Handlers are only installed once signals() is first called, so until then SIGINT and SIGTERM
+// keep their default behaviour. Several signals of one kind between polls arrive as one event.
+// signals() fails if the handlers could not be installed, and keeps failing after that.
+pub struct OsEvents {
+    sodium_ctx: SodiumCtx,
+    signals: StreamSink<Signal>,
+    signals_seen_op: StdCell<Option<[usize; 2]>>,
+    watches: Vec<FsWatch>
+}
+
+impl OsEvents {
+    pub fn new(sodium_ctx: &SodiumCtx) -> OsEvents {
+        OsEvents {
+            sodium_ctx: sodium_ctx.clone(),
+            signals: sodium_ctx.new_stream_sink(),
+            signals_seen_op: StdCell::new(None),
+            watches: Vec::new()
+        }
+    }
+
+    pub fn signals(&self) -> io::Result<Stream<Signal>> {
+        if self.signals_seen_op.get().is_none() {
+            install_handlers()?;
+            self.signals_seen_op.set(Some(signal_counts()));
+        }
+        Ok(self.signals.to_stream())
+    }
+
+    pub fn fs_watch<P: AsRef<Path>>(&mut self, path: P) -> Stream<FsEvent> {
+        let path = path.as_ref().to_path_buf();
+        let sink = self.sodium_ctx.new_stream_sink();
+        let stream = sink.to_stream();
+        self.watches.push(FsWatch {
+            state_op: FsWatch::read_state(&path),
+            path,
+            sink
+        });
+        stream
+    }
+
+    pub fn poll(&mut self) {
+        if let Some(seen) = self.signals_seen_op.get() {
+            let counts = signal_counts();
+            self.signals_seen_op.set(Some(counts));
+            if counts[0] != seen[0] {
+                self.signals.send(&Signal::Interrupt);
+            }
+            if counts[1] != seen[1] {
+                self.signals.send(&Signal::Terminate);
+            }
+        }
+        for watch in &mut self.watches {
+            watch.poll();
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use sodium::Cell;
+use sodium::IsCell;
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::any::Any;
+use std::cell::Cell as StdCell;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::thread::JoinHandle;
+use std::thread::ThreadId;
+
+// Returned once the runtime has shut down, or its thread died from a panic in a job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuntimeStopped;
+
+enum Command {
+    Run(Box<dyn FnOnce(&RuntimeCtx) + Send>),
+    Shutdown
+}
+
+// What jobs see on the runtime thread. Sinks and cells are registered here to get handles
+// that other threads can use, the registration goes once the last clone of its handle has.
+pub struct RuntimeCtx {
+    sodium_ctx: SodiumCtx,
+    commands: Sender<Command>,
+    thread_id: ThreadId,
+    next_id: StdCell<u64>,
+    objects: RefCell<HashMap<u64,Box<dyn Any>>>
+}
+
+impl RuntimeCtx {
+    pub fn sodium_ctx(&self) -> &SodiumCtx {
+        &self.sodium_ctx
+    }
+
+    pub fn remote_sink<A: Clone + Trace + Finalize + Send + 'static>(&self, sink: &StreamSink<A>) -> RemoteSinkHandle<A> {
+        RemoteSinkHandle {
+            remote: Arc::new(self.register(sink.clone())),
+            phantom: ::std::marker::PhantomData
+        }
+    }
+
+    pub fn remote_cell<A: Clone + Trace + Finalize + Send + 'static, CA: IsCell<A>>(&self, ca: CA) -> RemoteCellHandle<A> {
+        RemoteCellHandle {
+            remote: Arc::new(self.register(ca.to_cell())),
+            phantom: ::std::marker::PhantomData
+        }
+    }
+
+    fn register<T: 'static>(&self, object: T) -> Remote {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.objects.borrow_mut().insert(id, Box::new(object));
+        Remote {
+            id,
+            commands: self.commands.clone(),
+            thread_id: self.thread_id
+        }
+    }
+
+    // Cloned out so the registry isn't borrowed while the object runs user code.
+    fn object<T: Clone + 'static>(&self, id: u64) -> Option<T> {
+        self.objects.borrow().get(&id).and_then(|object| object.downcast_ref::<T>()).cloned()
+    }
+}
+
+struct Remote {
+    id: u64,
+    commands: Sender<Command>,
+    thread_id: ThreadId
+}
+
+impl Remote {
+    fn post<F: FnOnce(&RuntimeCtx) + Send + 'static>(&self, f: F) -> Result<(),RuntimeStopped> {
+        self.commands.send(Command::Run(Box::new(f))).map_err(|_| RuntimeStopped)
+    }
+
+    fn call<R: Send + 'static, F: FnOnce(&RuntimeCtx) -> R + Send + 'static>(&self, f: F) -> Result<R,RuntimeStopped> {
+        call(&self.commands, self.thread_id, f)
+    }
+}
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        let id = self.id;
+        let _ = self.post(move |runtime_ctx| {
+            runtime_ctx.objects.borrow_mut().remove(&id);
+        });
+    }
+}
+
+fn call<R: Send + 'static, F: FnOnce(&RuntimeCtx) -> R + Send + 'static>(commands: &Sender<Command>, thread_id: ThreadId, f: F) -> Result<R,RuntimeStopped> {
+    if thread::current().id() == thread_id {
+        panic!("blocking call into the SodiumRuntime from its own thread, this would deadlock");
+    }
+    let (reply_tx, reply_rx) = channel();
+    commands
+        .send(Command::Run(Box::new(move |runtime_ctx| {
+            let _ = reply_tx.send(f(runtime_ctx));
+        })))
+        .map_err(|_| RuntimeStopped)?;
+    reply_rx.recv().map_err(|_| RuntimeStopped)
+}
+
+// Sends into a StreamSink on the runtime thread, each send is a transaction of its own.
+pub struct RemoteSinkHandle<A> {
+    remote: Arc<Remote>,
+    phantom: ::std::marker::PhantomData<fn(A)>
+}
+
+impl<A: Clone + Trace + Finalize + Send + 'static> RemoteSinkHandle<A> {
+    pub fn send(&self, a: A) -> Result<(),RuntimeStopped> {
+        let id = self.remote.id;
+        self.remote.post(move |runtime_ctx| {
+            if let Some(sink) = runtime_ctx.object::<StreamSink<A>>(id) {
+                sink.send(&a);
+            }
+        })
+    }
+}
+
+impl<A> Clone for RemoteSinkHandle<A> {
+    fn clone(&self) -> Self {
+        RemoteSinkHandle {
+            remote: self.remote.clone(),
+            phantom: ::std::marker::PhantomData
+        }
+    }
+}
+
+pub struct RemoteCellHandle<A> {
+    remote: Arc<Remote>,
+    phantom: ::std::marker::PhantomData<fn() -> A>
+}
+
+impl<A: Clone + Trace + Finalize + Send + 'static> RemoteCellHandle<A> {
+    // Blocks until the runtime thread has got through the work queued before it.
+    pub fn sample(&self) -> Result<A,RuntimeStopped> {
+        let id = self.remote.id;
+        self.remote
+            .call(move |runtime_ctx| runtime_ctx.object::<Cell<A>>(id).map(|ca| ca.sample()))?
+            .ok_or(RuntimeStopped)
+    }
+}
+
+impl<A> Clone for RemoteCellHandle<A> {
+    fn clone(&self) -> Self {
+        RemoteCellHandle {
+            remote: self.remote.clone(),
+            phantom: ::std::marker::PhantomData
+        }
+    }
+}
+
+// Owns a SodiumCtx on a thread of its own, the graph is built and driven by jobs sent to it.
+// Jobs run one at a time in the order they were sent. shutdown(), or dropping the runtime,
+// lets the work already queued finish, then drops the registered objects and the context
+// on the runtime thread and joins it.
+pub struct SodiumRuntime {
+    commands: Sender<Command>,
+    thread_id: ThreadId,
+    thread_op: Option<JoinHandle<()>>
+}
+
+impl Default for SodiumRuntime {
+    fn default() -> SodiumRuntime {
+        SodiumRuntime::new()
+    }
+}
+
+impl SodiumRuntime {
+    pub fn new() -> SodiumRuntime {
+        let (commands, receiver) = channel();
+        let commands2 = commands.clone();
+        let thread = thread::Builder::new()
+            .name(String::from("sodium-runtime"))
+            .spawn(move || {
+                let runtime_ctx = RuntimeCtx {
+                    sodium_ctx: SodiumCtx::new(),
+                    commands: commands2,
+                    thread_id: thread::current().id(),
+                    next_id: StdCell::new(0),
+                    objects: RefCell::new(HashMap::new())
+                };
+                for command in receiver.iter() {
+                    match command {
+                        Command::Run(f) => f(&runtime_ctx),
+                        Command::Shutdown => break
+                    }
+                }
+                runtime_ctx.objects.borrow_mut().clear();
+            })
+            .unwrap();
+        SodiumRuntime {
+            commands,
+            thread_id: thread.thread().id(),
+            thread_op: Some(thread)
+        }
+    }
+
+    // Queues f without waiting for it.
+    pub fn post<F: FnOnce(&RuntimeCtx) + Send + 'static>(&self, f: F) -> Result<(),RuntimeStopped> {
+        self.commands.send(Command::Run(Box::new(f))).map_err(|_| RuntimeStopped)
+    }
+
+    // Runs f on the runtime thread and waits for its result.
+    pub fn run<R: Send + 'static, F: FnOnce(&RuntimeCtx) -> R + Send + 'static>(&self, f: F) -> Result<R,RuntimeStopped> {
+        call(&self.commands, self.thread_id, f)
+    }
+
+    // Err if a job panicked and took the runtime thread down with it.
+    pub fn shutdown(mut self) -> Result<(),RuntimeStopped> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<(),RuntimeStopped> {
+        match self.thread_op.take() {
+            Some(thread) => {
+                let _ = self.commands.send(Command::Shutdown);
+                thread.join().map_err(|_| RuntimeStopped)
+            },
+            None => Ok(())
+        }
+    }
+}
+
+impl Drop for SodiumRuntime {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
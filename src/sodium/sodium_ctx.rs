@@ -1,20 +1,191 @@
 use sodium::Cell;
 use sodium::CellLoop;
 use sodium::CellSink;
+use sodium::IsCell;
 use sodium::IsLambda0;
+use sodium::ListenerInfo;
+use sodium::ListenerMiddleware;
 use sodium::MemoLazy;
+use sodium::OptimizerStats;
+use sodium::SodiumError;
+use sodium::SodiumScope;
 use sodium::Stream;
 use sodium::StreamLoop;
 use sodium::StreamSink;
+use sodium::TxId;
+use sodium::TxObserver;
+use sodium::TxSummary;
 use sodium::gc::Finalize;
 use sodium::gc::GcCtx;
+use sodium::gc::GcPolicy;
+use sodium::gc::OomEvent;
 use sodium::gc::Trace;
+use sodium::gc::size_of_node;
 use sodium::impl_;
+use sodium::metrics::MonitorMetrics;
+use sodium::node::NodeBuilder;
+use std::fmt;
+use std::hash::Hash;
 
 pub struct SodiumCtx {
     impl_: impl_::SodiumCtx
 }
 
+pub struct Batch {
+    sodium_ctx: SodiumCtx,
+    // False when begin_batch failed in panic-free mode, so there is nothing to end.
+    opened: bool
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        if self.opened {
+            self.sodium_ctx.end_batch();
+        }
+    }
+}
+
+// What happens on misuse, see SodiumError.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicPolicy {
+    Panic,
+    // Reported on SodiumCtx::errors instead.
+    Report
+}
+
+// What TimerSystem does with the alarms that came due while the context was suspended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResumePolicy {
+    // They all fire on the next poll(), in order, as if it had been late.
+    FastForward,
+    // They are dropped and the time jumps straight to now.
+    DropElapsed
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SodiumCtxBuildError {
+    // A handler was given but no limit for it to handle.
+    NodeLimitHandlerWithoutLimit,
+    // GcPolicy::Deferred(0) would collect on every drop, use GcPolicy::Eager.
+    DeferredGcWithoutRoots,
+    // A memory limit lower than the bookkeeping asked for with initial_capacity.
+    MemoryLimitBelowCapacity
+}
+
+impl fmt::Display for SodiumCtxBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SodiumCtxBuildError::NodeLimitHandlerWithoutLimit => write!(f, "node_limit_handler was set without a node_limit"),
+            SodiumCtxBuildError::DeferredGcWithoutRoots => write!(f, "GcPolicy::Deferred needs at least one root"),
+            SodiumCtxBuildError::MemoryLimitBelowCapacity => write!(f, "memory_limit is too small for initial_capacity nodes")
+        }
+    }
+}
+
+// Every setting of a new context in one place, from SodiumCtx::builder().
+pub struct SodiumCtxBuilder {
+    gc_policy: GcPolicy,
+    node_limit_op: Option<u32>,
+    node_limit_handler_op: Option<Box<dyn Fn(u32)>>,
+    memory_limit_op: Option<usize>,
+    panic_policy: PanicPolicy,
+    transaction_hook_op: Option<Box<dyn Fn(TxSummary)>>,
+    track_node_sites: bool,
+    optimize_graph: bool,
+    initial_capacity: usize
+}
+
+impl SodiumCtxBuilder {
+    pub fn gc_policy(mut self, policy: GcPolicy) -> SodiumCtxBuilder {
+        self.gc_policy = policy;
+        self
+    }
+
+    pub fn node_limit(mut self, limit: u32) -> SodiumCtxBuilder {
+        self.node_limit_op = Some(limit);
+        self
+    }
+
+    pub fn node_limit_handler<F: Fn(u32) + 'static>(mut self, handler: F) -> SodiumCtxBuilder {
+        self.node_limit_handler_op = Some(Box::new(handler));
+        self
+    }
+
+    pub fn memory_limit(mut self, limit: usize) -> SodiumCtxBuilder {
+        self.memory_limit_op = Some(limit);
+        self
+    }
+
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> SodiumCtxBuilder {
+        self.panic_policy = policy;
+        self
+    }
+
+    // Called at the end of every transaction for as long as the context lives.
+    pub fn transaction_hook<F: Fn(TxSummary) + 'static>(mut self, hook: F) -> SodiumCtxBuilder {
+        self.transaction_hook_op = Some(Box::new(hook));
+        self
+    }
+
+    pub fn track_node_sites(mut self, track: bool) -> SodiumCtxBuilder {
+        self.track_node_sites = track;
+        self
+    }
+
+    pub fn optimize_graph(mut self, optimize: bool) -> SodiumCtxBuilder {
+        self.optimize_graph = optimize;
+        self
+    }
+
+    // How many gc values the collector's tables start with room for, see GcCtx::reserve.
+    pub fn initial_capacity(mut self, capacity: usize) -> SodiumCtxBuilder {
+        self.initial_capacity = capacity;
+        self
+    }
+
+    pub fn build(self) -> Result<SodiumCtx,SodiumCtxBuildError> {
+        if self.node_limit_handler_op.is_some() && self.node_limit_op.is_none() {
+            return Err(SodiumCtxBuildError::NodeLimitHandlerWithoutLimit);
+        }
+        if self.gc_policy == GcPolicy::Deferred(0) {
+            return Err(SodiumCtxBuildError::DeferredGcWithoutRoots);
+        }
+        if let Some(limit) = self.memory_limit_op {
+            if limit < self.initial_capacity.saturating_mul(size_of_node()) {
+                return Err(SodiumCtxBuildError::MemoryLimitBelowCapacity);
+            }
+        }
+        let sodium_ctx = SodiumCtx::new();
+        {
+            let gc_ctx = sodium_ctx.impl_.gc_ctx();
+            gc_ctx.set_policy(self.gc_policy);
+            gc_ctx.reserve(self.initial_capacity);
+            gc_ctx.set_memory_limit(self.memory_limit_op);
+        }
+        sodium_ctx.impl_.set_node_limit(self.node_limit_op);
+        if let Some(handler) = self.node_limit_handler_op {
+            sodium_ctx.impl_.set_node_limit_handler(handler);
+        }
+        sodium_ctx.impl_.set_panic_free(self.panic_policy == PanicPolicy::Report);
+        if let Some(hook) = self.transaction_hook_op {
+            sodium_ctx.impl_.set_transaction_hook(hook);
+        }
+        sodium_ctx.impl_.set_track_node_sites(self.track_node_sites);
+        sodium_ctx.impl_.set_optimize_graph(self.optimize_graph);
+        Ok(sodium_ctx)
+    }
+}
+
+pub struct SampleReader<'a> {
+    impl_: &'a impl_::SampleReader
+}
+
+impl<'a> SampleReader<'a> {
+    pub fn get<A: Clone + Trace + Finalize + 'static, CA: IsCell<A>>(&self, ca: &CA) -> A {
+        self.impl_.get(&ca.to_cell().impl_)
+    }
+}
+
 impl SodiumCtx {
     pub fn new() -> SodiumCtx {
         SodiumCtx {
@@ -22,6 +193,20 @@ impl SodiumCtx {
         }
     }
 
+    pub fn builder() -> SodiumCtxBuilder {
+        SodiumCtxBuilder {
+            gc_policy: GcPolicy::Eager,
+            node_limit_op: None,
+            node_limit_handler_op: None,
+            memory_limit_op: None,
+            panic_policy: PanicPolicy::Panic,
+            transaction_hook_op: None,
+            track_node_sites: false,
+            optimize_graph: false,
+            initial_capacity: 0
+        }
+    }
+
     pub fn new_lazy<A: Trace + Finalize + Clone + 'static,THUNK: IsLambda0<A> + 'static>(&self, thunk: THUNK) -> MemoLazy<A> {
         self.impl_.new_lazy(thunk)
     }
@@ -38,6 +223,46 @@ impl SodiumCtx {
         }
     }
 
+    pub fn new_node_builder<A: Clone + Trace + Finalize + 'static>(&self, desc: &'static str) -> NodeBuilder<A> {
+        NodeBuilder::_new(&self.impl_, desc)
+    }
+
+    // The same as new_cell, named for reading graphs, it is not a cheaper kind of node. Always
+    // a cell of its own, see interned_constant for sharing them.
+    pub fn constant<A: Clone + Trace + Finalize + 'static>(&self, value: A) -> Cell<A> {
+        self.new_cell(value)
+    }
+
+    // Calls with equal values get the same node back while it is alive, so a graph full of
+    // repeated constants only holds one of each. A name given to it applies to them all, use
+    // constant for one that has to stand alone. The context only keeps a weak handle on each,
+    // so nothing in the value is kept alive past the last cell.
+    pub fn interned_constant<A: Clone + Eq + Hash + Trace + Finalize + 'static>(&self, value: A) -> Cell<A> {
+        Cell {
+            impl_: self.impl_.interned_constant(value)
+        }
+    }
+
+    // A value shared across the whole context by its type, e.g. the theme or locale, so it
+    // does not have to be passed to everything that needs it. None until set_resource is first
+    // called for the type. The context keeps the last value set outside of any Gc so it can
+    // remake the cell, so A must not hold Gc handles, they would never be collected.
+    pub fn resource<A: Clone + Trace + Finalize + 'static>(&self) -> Cell<Option<A>> {
+        Cell {
+            impl_: self.impl_.resource()
+        }
+    }
+
+    pub fn set_resource<A: Clone + Trace + Finalize + 'static>(&self, value: A) {
+        self.impl_.set_resource(value);
+    }
+
+    // The same as new_stream, which never fires as nothing can send to it. Named for reading
+    // graphs, it is not a cheaper kind of node.
+    pub fn never<A: Clone + Trace + Finalize + 'static>(&self) -> Stream<A> {
+        self.new_stream()
+    }
+
     pub fn new_cell_loop<A: Clone + Trace + Finalize + 'static>(&self) -> CellLoop<A> {
         CellLoop {
             impl_: impl_::CellLoop::new(&self.impl_)
@@ -57,15 +282,20 @@ impl SodiumCtx {
     }
 
     pub fn new_stream_sink<A: Clone + Trace + Finalize + 'static>(&self) -> StreamSink<A> {
-        StreamSink {
-            impl_: impl_::StreamSink::new(&self.impl_)
-        }
+        StreamSink::_new(impl_::StreamSink::new(&self.impl_))
+    }
+
+    // A sink that can fail with an E, see StreamSink::fail.
+    pub fn new_stream_sink_with_error<A: Clone + Trace + Finalize + 'static, E: Clone + Trace + Finalize + 'static>(&self) -> StreamSink<A,E> {
+        StreamSink::_new(impl_::StreamSink::new(&self.impl_))
     }
 
     pub fn new_stream_sink_with_coalescer<A: Clone + Trace + Finalize + 'static, FN: Fn(&A,&A)->A+'static>(&self, coalescer: FN) -> StreamSink<A> {
-        StreamSink {
-            impl_: impl_::StreamSink::new_with_coalescer(&self.impl_, coalescer)
-        }
+        StreamSink::_new(impl_::StreamSink::new_with_coalescer(&self.impl_, coalescer))
+    }
+
+    pub fn create_scope(&self) -> SodiumScope {
+        self.impl_.create_scope()
     }
 
     pub fn gc_ctx(&self) -> GcCtx {
@@ -77,13 +307,191 @@ impl SodiumCtx {
         self.impl_.transaction(|| code(&sodium_ctx))
     }
 
+    // Like transaction, with a name that TxSummary reports for it, e.g. to tell which user
+    // action a slow transaction came from.
+    pub fn transaction_named<A,CODE:FnOnce(&SodiumCtx)->A>(&self, name: &'static str, code: CODE) -> A {
+        let sodium_ctx = self.clone();
+        self.impl_.transaction_named(name, || code(&sodium_ctx))
+    }
+
     pub fn post<F: FnMut() + 'static>(&self, f: F) {
         self.impl_.post(f);
     }
 
+    // Reads several cells so that they all agree on which transactions have happened.
+    pub fn sample_consistent<R, F: FnOnce(&SampleReader) -> R>(&self, f: F) -> R {
+        self.impl_.sample_consistent(|impl_| f(&SampleReader { impl_ }))
+    }
+
+    // Listeners can call this to correlate their effects with the transaction that caused them.
+    pub fn current_tx_id(&self) -> TxId {
+        self.impl_.current_tx_id()
+    }
+
+    pub fn outer_tx_id(&self) -> TxId {
+        self.impl_.outer_tx_id()
+    }
+
     pub fn node_count(&self) -> u32 {
         self.impl_.node_count()
     }
+
+    pub fn set_node_limit(&self, limit_op: Option<u32>) {
+        self.impl_.set_node_limit(limit_op);
+    }
+
+    pub fn set_node_limit_handler<F: Fn(u32) + 'static>(&self, handler: F) {
+        self.impl_.set_node_limit_handler(handler);
+    }
+
+    // Bytes of gc memory the graph may hold, see GcCtx::set_memory_limit. Only the try_
+    // variants of the combinators fail on it, everything else shows up on oom_events.
+    pub fn set_memory_limit(&self, limit_op: Option<usize>) {
+        self.impl_.gc_ctx().set_memory_limit(limit_op);
+    }
+
+    pub fn memory_in_use(&self) -> usize {
+        self.impl_.gc_ctx().memory_in_use()
+    }
+
+    pub fn oom_events(&self) -> Stream<OomEvent> {
+        Stream {
+            impl_: self.impl_.oom_events()
+        }
+    }
+
+    // Records a backtrace of where each node made from now on was made, so
+    // node_allocation_sites can group unnamed nodes by call site. Off by default.
+    pub fn set_track_node_sites(&self, track: bool) {
+        self.impl_.set_track_node_sites(track);
+    }
+
+    pub fn live_nodes(&self) -> Vec<(u32,String)> {
+        self.impl_.live_nodes()
+    }
+
+    // From now on, a map, filter or map_filter on a stream whose node nothing else consumes
+    // yet is fused into the node that stream would read from, so map(f).map(g).filter(p) is
+    // one node. The streams in between still work if listened to later, they just run their
+    // steps separately. Cell::map and lift2 of cells made by new_cell are folded into another
+    // constant. Steps given as Lambdas with dependencies are left alone. Off by default.
+    pub fn set_optimize_graph(&self, optimize: bool) {
+        self.impl_.set_optimize_graph(optimize);
+    }
+
+    pub fn optimizer_stats(&self) -> OptimizerStats {
+        self.impl_.optimizer_stats()
+    }
+
+    pub fn node_allocation_sites(&self, top: usize) -> Vec<(String,u32)> {
+        self.impl_.node_allocation_sites(top)
+    }
+
+    pub fn find_orphan_listeners(&self) -> Vec<(u32,String)> {
+        self.impl_.find_orphan_listeners()
+    }
+
+    // Listeners stop firing until the matching thaw(), which then fires each of them once with
+    // the last value it would have seen. Propagation carries on as normal, so samples taken in
+    // between are up to date. Calls nest.
+    pub fn freeze(&self) {
+        self.impl_.freeze();
+    }
+
+    pub fn thaw(&self) {
+        self.impl_.thaw();
+    }
+
+    // See Batch.
+    pub fn begin_batch(&self) {
+        self.impl_.begin_batch();
+    }
+
+    pub fn end_batch(&self) {
+        self.impl_.end_batch();
+    }
+
+    pub fn is_batching(&self) -> bool {
+        self.impl_.is_batching()
+    }
+
+    // For when the app is in the background. Sends to sinks are kept until resume() and
+    // TimerSystem::poll does nothing, so time stands still and no alarms fire. Does not nest.
+    pub fn suspend(&self) {
+        self.impl_.suspend();
+    }
+
+    // Replays the kept sends in the order they were made, each in its own transaction unless
+    // they were made in the same one. Timers catch up on their next poll() per policy.
+    pub fn resume(&self, policy: ResumePolicy) {
+        self.impl_.resume(policy == ResumePolicy::DropElapsed);
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.impl_.is_suspended()
+    }
+
+    // True while a listener or other callback is running, when sinks can not be sent to.
+    pub(crate) fn in_callback(&self) -> bool {
+        self.impl_.callback_depth() > 0
+    }
+
+    // How many times resume() has been called and the last policy it was given, so that
+    // timers can tell they were suspended since they last looked.
+    pub fn resumes(&self) -> (u64,ResumePolicy) {
+        let (resumes, drop_elapsed) = self.impl_.resumes();
+        (resumes, if drop_elapsed { ResumePolicy::DropElapsed } else { ResumePolicy::FastForward })
+    }
+
+    // See SodiumError.
+    pub fn set_panic_free(&self, panic_free: bool) {
+        self.impl_.set_panic_free(panic_free);
+    }
+
+    pub fn errors(&self) -> Stream<SodiumError> {
+        Stream {
+            impl_: self.impl_.errors()
+        }
+    }
+
+    // Every error reported since the last call, including those reported before anything
+    // listened to errors(). Only the most recent 256 are kept.
+    pub fn take_errors(&self) -> Vec<SodiumError> {
+        self.impl_.take_errors()
+    }
+
+    // Merges every send made while the Batch is alive into one transaction, which runs when
+    // it is dropped.
+    pub fn batch(&self) -> Batch {
+        Batch {
+            sodium_ctx: self.clone(),
+            opened: self.impl_.begin_batch()
+        }
+    }
+
+    // What each Stream::monitor has recorded, by name.
+    pub fn metrics(&self) -> Vec<(String,MonitorMetrics)> {
+        self.impl_.metrics()
+    }
+
+    pub fn reset_metrics(&self) {
+        self.impl_.reset_metrics();
+    }
+
+    pub fn take_listener_errors(&self) -> Vec<String> {
+        self.impl_.take_listener_errors()
+    }
+
+    pub fn on_transaction_end<F: Fn(TxSummary) + 'static>(&self, f: F) -> TxObserver {
+        self.impl_.on_transaction_end(f)
+    }
+
+    // Runs f around every listener call, with the listener as the function to call, e.g. to
+    // time or log listeners or check which thread they run on. f may skip the call. The
+    // first added wraps the others, and it stays in place while the returned handle lives.
+    pub fn add_listener_middleware<F: Fn(&ListenerInfo, &dyn Fn()) + 'static>(&self, f: F) -> ListenerMiddleware {
+        self.impl_.add_listener_middleware(f)
+    }
 }
 
 impl Clone for SodiumCtx {
@@ -0,0 +1,77 @@
+use sodium::Cell;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use sodium::time::TimerSystem;
+#[cfg(feature = "os")]
+use std::cell::RefCell;
+#[cfg(feature = "os")]
+use std::fs;
+use std::rc::Rc;
+use std::time::Duration;
+#[cfg(feature = "os")]
+use std::time::Instant;
+
+// A cell over something that can only be polled, e.g. a system metric. f is called now and
+// then every period as the timer system is polled.
+pub fn interval_poll<A, F>(period: Duration, f: F, timer_system: &TimerSystem) -> Cell<A>
+    where A: Clone + Trace + Finalize + 'static,
+          F: Fn() -> A + 'static
+{
+    let f = Rc::new(f);
+    let initial = f();
+    timer_system.every(period).map(move |_: &Duration| f()).hold(initial)
+}
+
+// Resident memory of this process in bytes, None where /proc/self/status can't be read.
+#[cfg(feature = "os")]
+pub fn process_memory(period: Duration, timer_system: &TimerSystem) -> Cell<Option<u64>> {
+    interval_poll(period, read_resident_bytes, timer_system)
+}
+
+// CPU time used by this process since the previous poll, as a fraction of the wall time in
+// between, so a process keeping two cores busy reads 2.0. The first poll has nothing to
+// compare with and reads 0.0, None where /proc/self/stat can't be read.
+#[cfg(feature = "os")]
+pub fn cpu_usage(period: Duration, timer_system: &TimerSystem) -> Cell<Option<f64>> {
+    let last: RefCell<Option<(Instant,Duration)>> = RefCell::new(None);
+    interval_poll(
+        period,
+        move || {
+            let cpu = read_cpu_time()?;
+            let now = Instant::now();
+            let usage =
+                match last.borrow_mut().replace((now, cpu)) {
+                    Some((then, last_cpu)) => {
+                        let wall = now.duration_since(then).as_secs_f64();
+                        if wall > 0.0 { cpu.saturating_sub(last_cpu).as_secs_f64() / wall } else { 0.0 }
+                    },
+                    None => 0.0
+                };
+            Some(usage)
+        },
+        timer_system
+    )
+}
+
+#[cfg(feature = "os")]
+fn read_resident_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line["VmRSS:".len()..].trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+// The kernel reports these in USER_HZ, which is 100 for every Linux ABI.
+#[cfg(feature = "os")]
+const USER_HZ: u64 = 100;
+
+#[cfg(feature = "os")]
+fn read_cpu_time() -> Option<Duration> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    // The command name is in parentheses and may hold spaces, the fields count from after it.
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let ticks = utime + stime;
+    Some(Duration::from_secs(ticks / USER_HZ) + Duration::from_millis((ticks % USER_HZ) * 1000 / USER_HZ))
+}
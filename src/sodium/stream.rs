@@ -8,17 +8,28 @@ use sodium::IsLambda3;
 use sodium::IsLambda4;
 use sodium::IsLambda5;
 use sodium::IsLambda6;
+use sodium::IsWeak;
 use sodium::Listener;
 use sodium::MemoLazy;
+use sodium::OverflowPolicy;
+use sodium::Termination;
+use sodium::TxId;
 use sodium::gc::Finalize;
 use sodium::gc::GcDep;
+use sodium::gc::OomError;
 use sodium::gc::Trace;
 use sodium::impl_;
+use std::collections::HashMap;
+use std::hash::Hash;
 
 pub struct Stream<A> {
     pub impl_: impl_::Stream<A>
 }
 
+pub struct WeakStream<A> {
+    impl_: impl_::WeakStream<A>
+}
+
 impl<A: Clone + Trace + Finalize + 'static> Stream<Option<A>> {
     pub fn filter_option(&self) -> Stream<A> {
         Stream {
@@ -27,12 +38,61 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<Option<A>> {
     }
 }
 
+impl<A: Clone + Trace + Finalize + 'static, E: Clone + Trace + Finalize + 'static> Stream<Result<A,E>> {
+    pub fn split(&self) -> (Stream<A>, Stream<E>) {
+        let (sa, se) = self.impl_.split();
+        (Stream { impl_: sa }, Stream { impl_: se })
+    }
+}
+
+impl<K: Clone + Eq + Hash + Trace + Finalize + 'static, A: Clone + Trace + Finalize + 'static> Stream<(K,A)> {
+    // The latest value for each key seen so far.
+    pub fn hold_by_key(&self) -> Cell<HashMap<K,A>> {
+        let never: Stream<K> = Stream { impl_: impl_::Stream::new(&self.impl_._node().sodium_ctx()) };
+        self.hold_by_key_removing(never)
+    }
+
+    // Like hold_by_key, with keys taken out again when they fire on removals. A key set and
+    // removed in the same transaction ends up removed.
+    pub fn hold_by_key_removing<SK: IsStream<K>>(&self, removals: SK) -> Cell<HashMap<K,A>> {
+        let sets = self.map(|&(ref k, ref a): &(K,A)| vec![(k.clone(), Some(a.clone()))]);
+        let removes = removals.to_stream().map(|k: &K| vec![(k.clone(), None)]);
+        sets
+            .merge(removes, |sets: &Vec<(K,Option<A>)>, removes: &Vec<(K,Option<A>)>| {
+                let mut changes = sets.clone();
+                changes.extend(removes.iter().cloned());
+                changes
+            })
+            .accum(HashMap::new(), |changes: &Vec<(K,Option<A>)>, map: &HashMap<K,A>| {
+                let mut map = map.clone();
+                for &(ref k, ref a_op) in changes {
+                    match *a_op {
+                        Some(ref a) => { map.insert(k.clone(), a.clone()); },
+                        None => { map.remove(k); }
+                    }
+                }
+                map
+            })
+    }
+}
+
 impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
 
     pub fn to_dep(&self) -> Dep {
         self.impl_.to_dep()
     }
 
+    // Listeners attached to this stream itself, not to streams derived from it.
+    pub fn listener_count(&self) -> usize {
+        self.impl_._node().listener_count()
+    }
+
+    // How many nodes this stream would have taken without SodiumCtx::set_optimize_graph,
+    // counting its own and the map and filter steps fused into it.
+    pub fn unfused_node_count(&self) -> usize {
+        self.impl_.unfused_node_count()
+    }
+
     pub fn map<B: Clone + Trace + Finalize + 'static,F:IsLambda1<A,B> + 'static>(
         &self,
         f: F
@@ -42,6 +102,14 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         }
     }
 
+    // For a function that captures nothing, e.g. a generated graph's, it saves the Rc map puts
+    // the function in, once per node. Each firing still allocates its lazy value like map's.
+    pub fn map_fn<B: Clone + Trace + Finalize + 'static>(&self, f: fn(&A) -> B) -> Stream<B> {
+        Stream {
+            impl_: self.impl_.map_fn(f)
+        }
+    }
+
     pub fn hold(&self, a: A) -> Cell<A> {
         Cell {
             impl_: self.impl_.hold(a)
@@ -54,12 +122,56 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         }
     }
 
+    pub fn filter_fn(&self, pred: fn(&A) -> bool) -> Stream<A> {
+        Stream {
+            impl_: self.impl_.filter_fn(pred)
+        }
+    }
+
+    // Passes events straight through, recording how far apart they come and how long the
+    // graph takes to deal with each in SodiumCtx::metrics under name. Monitors sharing a name
+    // add to the same figures.
+    pub fn monitor(&self, name: &str) -> Stream<A> {
+        Stream {
+            impl_: self.impl_.monitor(name)
+        }
+    }
+
+    // Every occurrence in the outer transaction, e.g. from split(), in one event fired in a
+    // transaction of its own straight after.
+    pub fn all_in_transaction(&self) -> Stream<Vec<A>> {
+        Stream {
+            impl_: self.impl_.all_in_transaction()
+        }
+    }
+
     pub fn merge<SA:IsStream<A>, FN:Fn(&A,&A)->A+'static>(&self, sa: SA, f: FN) -> Stream<A> {
         Stream {
             impl_: self.impl_.merge(sa.to_stream().impl_, f)
         }
     }
 
+    // The try_ variants fail with the first allocation over the memory limit set with
+    // SodiumCtx::set_memory_limit, instead of going over it.
+    pub fn try_map<B: Clone + Trace + Finalize + 'static,F:IsLambda1<A,B> + 'static>(
+        &self,
+        f: F
+    ) -> Result<Stream<B>,OomError> {
+        self.impl_._node().sodium_ctx().gc_ctx().fallible(|| self.map(f))
+    }
+
+    pub fn try_hold(&self, a: A) -> Result<Cell<A>,OomError> {
+        self.impl_._node().sodium_ctx().gc_ctx().fallible(|| self.hold(a))
+    }
+
+    pub fn try_filter<PRED:IsLambda1<A,bool> + 'static>(&self, pred: PRED) -> Result<Stream<A>,OomError> {
+        self.impl_._node().sodium_ctx().gc_ctx().fallible(|| self.filter(pred))
+    }
+
+    pub fn try_merge<SA:IsStream<A>, FN:Fn(&A,&A)->A+'static>(&self, sa: SA, f: FN) -> Result<Stream<A>,OomError> {
+        self.impl_._node().sodium_ctx().gc_ctx().fallible(|| self.merge(sa, f))
+    }
+
     pub fn gate<CA:IsCell<bool>>(&self, ca: CA) -> Stream<A> {
         Stream {
             impl_: self.impl_.gate(ca.to_cell().impl_)
@@ -91,6 +203,72 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
         }
     }
 
+    pub fn take_until_closed<E: Clone + Trace + Finalize + 'static, ST: IsStream<Termination<E>>>(&self, termination: ST) -> Stream<A> {
+        Stream {
+            impl_: self.impl_.take_until(&termination.to_stream().impl_)
+        }
+    }
+
+    pub fn until<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(&self, stop: SB) -> Stream<A> {
+        Stream {
+            impl_: self.impl_.take_until(&stop.to_stream().impl_)
+        }
+    }
+
+    pub fn take(&self, n: usize) -> Stream<A> {
+        Stream {
+            impl_: self.impl_.take(n)
+        }
+    }
+
+    pub fn limit_per_transaction(&self, n: usize, policy: OverflowPolicy) -> Stream<A> {
+        Stream {
+            impl_: self.impl_.limit_per_transaction(n, policy)
+        }
+    }
+
+    pub fn skip(&self, n: usize) -> Stream<A> {
+        Stream {
+            impl_: self.impl_.skip(n)
+        }
+    }
+
+    pub fn replay(&self, n: usize) -> Stream<A> {
+        Stream {
+            impl_: self.impl_.replay(n)
+        }
+    }
+
+    pub fn coincidence_with<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(&self, sb: SB) -> Stream<(A,B)> {
+        Stream {
+            impl_: self.impl_.coincidence_with(sb.to_stream().impl_)
+        }
+    }
+
+    pub fn alone<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(&self, sb: SB) -> Stream<A> {
+        Stream {
+            impl_: self.impl_.alone(sb.to_stream().impl_)
+        }
+    }
+
+    pub fn tag_tx(&self) -> Stream<(TxId,A)> {
+        Stream {
+            impl_: self.impl_.tag_tx()
+        }
+    }
+
+    pub fn sample_from<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>>(&self, sb: SB) -> Stream<(A,Option<B>)> {
+        Stream {
+            impl_: self.impl_.sample_from(&sb.to_stream().impl_)
+        }
+    }
+
+    pub fn switch_map<B: Clone + Trace + Finalize + 'static, SB: IsStream<B>, F: Fn(&A)->SB + 'static>(&self, f: F) -> Stream<B> {
+        Stream {
+            impl_: self.impl_.switch_map(move |a: &A| f(a).to_stream().impl_)
+        }
+    }
+
     pub fn snapshot<B,CB:IsCell<B>>(&self, cb: CB) -> Stream<B> where B: Trace + Finalize + Clone + 'static {
         Stream {
             impl_: self.impl_.snapshot(cb.to_cell().impl_)
@@ -140,9 +318,35 @@ impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
     ) -> Listener {
         self.impl_.listen_weak(callback)
     }
+
+    pub fn listen_bound<T, W: IsWeak<T> + Clone, F: Fn(&T,&A) + 'static>(&self, weak: W, f: F) -> Listener {
+        let listener;
+        {
+            let weak = weak.clone();
+            listener = self.impl_.listen(move |a: &A| { weak.with_upgraded(|t| f(t, a)); });
+        }
+        listener.unlisten_after(move || weak.is_dead());
+        listener
+    }
+
+    pub fn listen_once<CALLBACK:FnMut(&A)+'static>(
+        &self,
+        callback: CALLBACK
+    ) -> Listener {
+        self.impl_.listen_once(callback)
+    }
+
+    pub fn listen_with_priority<CALLBACK:FnMut(&A)+'static>(
+        &self,
+        priority: i32,
+        callback: CALLBACK
+    ) -> Listener {
+        self.impl_.listen_with_priority(priority, callback)
+    }
 }
 
-impl<A: Clone + Trace + Finalize + 'static> Clone for Stream<A> {
+// Cloning a handle only bumps a reference count, both handles share the same graph node.
+impl<A> Clone for Stream<A> {
     fn clone(&self) -> Self {
         Stream {
             impl_: self.impl_.clone()
@@ -150,6 +354,30 @@ impl<A: Clone + Trace + Finalize + 'static> Clone for Stream<A> {
     }
 }
 
+impl<A> Stream<A> {
+    // A handle that does not keep the stream alive, e.g. for a cache of derived streams.
+    pub fn downgrade(&self) -> WeakStream<A> {
+        WeakStream {
+            impl_: self.impl_.downgrade()
+        }
+    }
+}
+
+impl<A> WeakStream<A> {
+    // None once the stream has been freed.
+    pub fn upgrade(&self) -> Option<Stream<A>> {
+        self.impl_.upgrade().map(|impl_| Stream { impl_ })
+    }
+}
+
+impl<A> Clone for WeakStream<A> {
+    fn clone(&self) -> Self {
+        WeakStream {
+            impl_: self.impl_.clone()
+        }
+    }
+}
+
 impl<A: Clone + Trace + Finalize + 'static> Finalize for Stream<A> {
     fn finalize(&mut self) {}
 }
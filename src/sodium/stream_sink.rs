@@ -1,16 +1,150 @@
+use sodium::SinkHandle;
+use sodium::SodiumError;
 use sodium::Stream;
 use sodium::gc::Finalize;
 use sodium::gc::GcDep;
 use sodium::gc::Trace;
 use sodium::impl_;
+use std::cell::RefCell;
+use std::cell::UnsafeCell;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::rc::Rc;
 
-pub struct StreamSink<A> {
-    pub impl_: impl_::StreamSink<A>
+// E is the error a sink can fail with, sinks that only ever close can leave it as ().
+pub struct StreamSink<A, E = ()> {
+    pub impl_: impl_::StreamSink<A>,
+    termination: Rc<UnsafeCell<TerminationState<E>>>,
+    // Set by dedup_by_key, says whether an event should go in.
+    admit_op: Rc<RefCell<Option<Box<dyn FnMut(&A) -> bool>>>>
 }
 
-impl<A: Clone + Trace + Finalize + 'static> StreamSink<A> {
+#[derive(Clone, Debug, PartialEq)]
+pub enum Termination<E> {
+    Closed,
+    Failed(E)
+}
+
+struct TerminationState<E> {
+    sink_op: Option<impl_::StreamSink<Termination<E>>>,
+    terminated_op: Option<Termination<E>>
+}
+
+impl<A: Clone + Trace + Finalize + 'static, E: Clone + Trace + Finalize + 'static> StreamSink<A,E> {
+    pub fn _new(impl_: impl_::StreamSink<A>) -> StreamSink<A,E> {
+        StreamSink {
+            impl_,
+            termination: Rc::new(UnsafeCell::new(TerminationState {
+                sink_op: None,
+                terminated_op: None
+            })),
+            admit_op: Rc::new(RefCell::new(None))
+        }
+    }
+
     pub fn send(&self, a: &A) {
+        if let Err(termination) = self.try_send(a) {
+            let node = self.impl_.to_stream()._node().clone();
+            let describe = node.describe("StreamSink");
+            let msg =
+                match termination {
+                    Termination::Closed => format!("{}::send called after the sink was closed.", describe),
+                    Termination::Failed(_) => format!("{}::send called after the sink failed.", describe)
+                };
+            node.sodium_ctx().fail(SodiumError::Misuse(msg));
+        }
+    }
+
+    // Like send, but hands back how the sink ended instead of panicking once it has.
+    pub fn try_send(&self, a: &A) -> Result<(),Termination<E>> {
+        let termination = unsafe { &*self.termination.get() };
+        if let Some(ref terminated) = termination.terminated_op {
+            return Err(terminated.clone());
+        }
+        if let Some(ref mut admit) = *self.admit_op.borrow_mut() {
+            if !admit(a) {
+                return Ok(());
+            }
+        }
         self.impl_.send(a.clone());
+        Ok(())
+    }
+
+    // For sources that redeliver, e.g. message queues: an event whose key is among the last
+    // window keys sent is dropped before it enters the graph. Applies to every clone of the
+    // sink, and to events from a multi_producer handle as they are drained.
+    pub fn dedup_by_key<K: Eq + Hash + Clone + 'static, F: Fn(&A) -> K + 'static>(self, f: F, window: usize) -> StreamSink<A,E> {
+        let mut seen: HashSet<K> = HashSet::new();
+        let mut order: VecDeque<K> = VecDeque::new();
+        *self.admit_op.borrow_mut() = Some(Box::new(move |a: &A| {
+            let key = f(a);
+            if seen.contains(&key) {
+                return false;
+            }
+            if window > 0 {
+                if order.len() == window {
+                    if let Some(oldest) = order.pop_front() {
+                        seen.remove(&oldest);
+                    }
+                }
+                seen.insert(key.clone());
+                order.push_back(key);
+            }
+            true
+        }));
+        self
+    }
+
+    pub fn close(&self) {
+        self.terminate(Termination::Closed);
+    }
+
+    pub fn fail(&self, e: E) {
+        self.terminate(Termination::Failed(e));
+    }
+
+    fn terminate(&self, terminated: Termination<E>) {
+        let termination = unsafe { &mut *self.termination.get() };
+        if termination.terminated_op.is_some() {
+            let node = self.impl_.to_stream()._node().clone();
+            node.sodium_ctx().fail(SodiumError::Misuse(format!("{} was already closed or failed.", node.describe("StreamSink"))));
+            return;
+        }
+        termination.terminated_op = Some(terminated.clone());
+        if let Some(ref sink) = termination.sink_op {
+            sink.send(terminated);
+        }
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        let termination = unsafe { &*self.termination.get() };
+        termination.terminated_op.is_some()
+    }
+
+    // Fires once when the sink is closed or fails. Asked for after that, it never fires.
+    pub fn termination(&self) -> Stream<Termination<E>> {
+        let termination = unsafe { &mut *self.termination.get() };
+        if termination.sink_op.is_none() {
+            termination.sink_op = Some(impl_::StreamSink::new(&self.impl_.to_stream()._node().sodium_ctx()));
+        }
+        Stream {
+            impl_: termination.sink_op.as_ref().unwrap().to_stream()
+        }
+    }
+
+    pub fn multi_producer(&self) -> SinkHandle<A> {
+        self.impl_.multi_producer()
+    }
+
+    pub fn drain_producers(&self) -> usize {
+        let admit_op = self.admit_op.clone();
+        self.impl_.drain_producers_where(move |a: &A| {
+            match *admit_op.borrow_mut() {
+                Some(ref mut admit) => admit(a),
+                None => true
+            }
+        })
     }
 
     pub fn to_stream(&self) -> Stream<A> {
@@ -20,22 +154,44 @@ impl<A: Clone + Trace + Finalize + 'static> StreamSink<A> {
     }
 }
 
-impl<A: Clone + Trace + Finalize + 'static> Clone for StreamSink<A> {
+impl<A: Clone + Trace + Finalize + 'static, E> Clone for StreamSink<A,E> {
     fn clone(&self) -> Self {
         StreamSink {
-            impl_: self.impl_.clone()
+            impl_: self.impl_.clone(),
+            termination: self.termination.clone(),
+            admit_op: self.admit_op.clone()
         }
     }
 }
 
-impl<A: Clone + Trace + Finalize + 'static> Finalize for StreamSink<A> {
+impl<A: Clone + Trace + Finalize + 'static, E> Finalize for StreamSink<A,E> {
     fn finalize(&mut self) {
         self.impl_.finalize()
     }
 }
 
-impl<A: Clone + Trace + Finalize + 'static> Trace for StreamSink<A> {
+impl<A: Clone + Trace + Finalize + 'static, E: Clone + Trace + Finalize + 'static> Trace for StreamSink<A,E> {
     fn trace(&self, f: &mut FnMut(&GcDep)) {
-        self.impl_.trace(f)
+        self.impl_.trace(f);
+        let termination = unsafe { &*self.termination.get() };
+        if let Some(ref sink) = termination.sink_op {
+            sink.trace(f);
+        }
+    }
+}
+
+impl<E: Trace> Trace for Termination<E> {
+    fn trace(&self, f: &mut dyn FnMut(&GcDep)) {
+        if let &Termination::Failed(ref e) = self {
+            e.trace(f);
+        }
+    }
+}
+
+impl<E: Finalize> Finalize for Termination<E> {
+    fn finalize(&mut self) {
+        if let &mut Termination::Failed(ref mut e) = self {
+            e.finalize();
+        }
     }
 }
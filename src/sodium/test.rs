@@ -0,0 +1,374 @@
+use sodium::CellSink;
+use sodium::IsCell;
+use sodium::IsStream;
+use sodium::Listener;
+use sodium::Operational;
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::TxId;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+// The nodes alive in a context at one point in time, for asserting on what an operation
+// creates or frees.
+pub struct GraphSnapshot {
+    nodes: BTreeMap<u32,String>
+}
+
+pub struct GraphDiff {
+    pub created: Vec<(u32,String)>,
+    pub destroyed: Vec<(u32,String)>
+}
+
+fn is_listener(desc: &str) -> bool {
+    desc.ends_with("::listen_node")
+}
+
+impl GraphSnapshot {
+    pub fn capture(sodium_ctx: &SodiumCtx) -> GraphSnapshot {
+        GraphSnapshot {
+            nodes: sodium_ctx.live_nodes().into_iter().collect()
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn diff(&self, later: &GraphSnapshot) -> GraphDiff {
+        GraphDiff {
+            created: later.nodes.iter().filter(|&(id, _)| !self.nodes.contains_key(id)).map(|(id, desc)| (*id, desc.clone())).collect(),
+            destroyed: self.nodes.iter().filter(|&(id, _)| !later.nodes.contains_key(id)).map(|(id, desc)| (*id, desc.clone())).collect()
+        }
+    }
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.destroyed.is_empty()
+    }
+
+    pub fn created_nodes(&self) -> usize {
+        self.created.iter().filter(|&(_, desc)| !is_listener(desc)).count()
+    }
+
+    pub fn created_listeners(&self) -> usize {
+        self.created.iter().filter(|&(_, desc)| is_listener(desc)).count()
+    }
+
+    pub fn destroyed_nodes(&self) -> usize {
+        self.destroyed.iter().filter(|&(_, desc)| !is_listener(desc)).count()
+    }
+
+    pub fn destroyed_listeners(&self) -> usize {
+        self.destroyed.iter().filter(|&(_, desc)| is_listener(desc)).count()
+    }
+}
+
+impl fmt::Debug for GraphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "GraphDiff {{")?;
+        for &(id, ref desc) in &self.created {
+            writeln!(f, "  + #{} {}", id, desc)?;
+        }
+        for &(id, ref desc) in &self.destroyed {
+            writeln!(f, "  - #{} {}", id, desc)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+// Records what a set of named streams and cells fire with so a whole run can be checked
+// against a golden file. Values are written with {:?} and grouped under the transaction they
+// fired in, transactions being numbered from 1 in the order the transcript first saw them so
+// that setup done before recording does not shift the numbers.
+pub struct Transcript {
+    lines: Rc<RefCell<Vec<(TxId,String,String)>>>,
+    listeners: Vec<Listener>
+}
+
+impl Default for Transcript {
+    fn default() -> Transcript {
+        Transcript::new()
+    }
+}
+
+impl Transcript {
+    pub fn new() -> Transcript {
+        Transcript {
+            lines: Rc::new(RefCell::new(Vec::new())),
+            listeners: Vec::new()
+        }
+    }
+
+    pub fn record<A: Clone + fmt::Debug + Trace + Finalize + 'static, SA: IsStream<A>>(&mut self, name: &str, sa: SA) {
+        let lines = self.lines.clone();
+        let name = String::from(name);
+        self.listeners.push(
+            sa.to_stream().tag_tx().listen(move |&(tx_id, ref a): &(TxId,A)| {
+                lines.borrow_mut().push((tx_id, name.clone(), format!("{:?}", a)));
+            })
+        );
+    }
+
+    // Records the cell's updates, not its value at the time recording starts.
+    pub fn record_cell<A: Clone + fmt::Debug + Trace + Finalize + 'static, CA: IsCell<A>>(&mut self, name: &str, ca: CA) {
+        self.record(name, Operational::updates(ca.to_cell()));
+    }
+
+    pub fn unlisten(&self) {
+        for listener in &self.listeners {
+            listener.unlisten();
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut last_op: Option<TxId> = None;
+        let mut tx_count = 0;
+        for &(tx_id, ref name, ref value) in self.lines.borrow().iter() {
+            if last_op != Some(tx_id) {
+                tx_count += 1;
+                out.push_str(&format!("tx {}\n", tx_count));
+                last_op = Some(tx_id);
+            }
+            out.push_str(&format!("  {}: {}\n", name, value));
+        }
+        out
+    }
+
+    // A line diff against the expected text, None when they are the same. Lines only in the
+    // expected text start with '-', lines only in this transcript with '+'.
+    pub fn diff(&self, expected: &str) -> Option<String> {
+        let actual = self.render();
+        if actual == expected {
+            return None;
+        }
+        Some(line_diff(expected, &actual))
+    }
+
+    // Compares against the golden file at path and panics with a diff when they differ, or
+    // when the file does not exist. With SODIUM_UPDATE_TRANSCRIPTS set the file is written
+    // instead, so new and intentionally changed transcripts can be reviewed in version
+    // control. That is ignored when CI is set, so a CI run can never accept a transcript.
+    pub fn assert_golden<P: AsRef<Path>>(&self, path: P) {
+        let update = env::var_os("SODIUM_UPDATE_TRANSCRIPTS").is_some() && env::var_os("CI").is_none();
+        self.check_golden(path.as_ref(), update);
+    }
+
+    pub(crate) fn check_golden(&self, path: &Path, update: bool) {
+        if update {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).unwrap_or_else(|err| panic!("can not create {}: {}", dir.display(), err));
+            }
+            fs::write(path, self.render()).unwrap_or_else(|err| panic!("can not write {}: {}", path.display(), err));
+            return;
+        }
+        match fs::read_to_string(path) {
+            Ok(expected) => {
+                if let Some(diff) = self.diff(&expected) {
+                    panic!("transcript does not match {}, set SODIUM_UPDATE_TRANSCRIPTS to accept it:\n{}", path.display(), diff);
+                }
+            },
+            Err(err) => panic!("can not read {}: {}, set SODIUM_UPDATE_TRANSCRIPTS to write it", path.display(), err)
+        }
+    }
+}
+
+// A small xorshift generator, so a fuzz run is the same on every machine for a given seed.
+pub struct FuzzRng {
+    state: u64
+}
+
+impl FuzzRng {
+    pub fn new(seed: u64) -> FuzzRng {
+        // Zero is a fixed point of xorshift, so the one seed that maps to it gets another state.
+        let state = seed ^ 0x9e37_79b9_7f4a_7c15;
+        FuzzRng { state: if state == 0 { 0x2545_f491_4f6c_dd1d } else { state } }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x = x ^ (x << 13);
+        x = x ^ (x >> 7);
+        x = x ^ (x << 17);
+        self.state = x;
+        x
+    }
+
+    // Uniform enough in 0..n for test inputs, n must not be 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+}
+
+// Sends a random value to one sink and returns it written with {:?}.
+type SendInput = Box<dyn Fn(&mut FuzzRng) -> String>;
+
+// The sinks a fuzz run may send to, each with how to make a random value for it.
+pub struct InputModel {
+    inputs: Vec<(String, SendInput)>
+}
+
+impl InputModel {
+    fn new() -> InputModel {
+        InputModel {
+            inputs: Vec::new()
+        }
+    }
+
+    pub fn stream<A, F>(&mut self, name: &str, sink: &StreamSink<A>, gen: F)
+        where A: Clone + fmt::Debug + Trace + Finalize + 'static,
+              F: Fn(&mut FuzzRng) -> A + 'static
+    {
+        let sink = sink.clone();
+        self.inputs.push((String::from(name), Box::new(move |rng: &mut FuzzRng| {
+            let a = gen(rng);
+            sink.send(&a);
+            format!("{:?}", a)
+        })));
+    }
+
+    pub fn cell<A, F>(&mut self, name: &str, sink: &CellSink<A>, gen: F)
+        where A: Clone + fmt::Debug + Trace + Finalize + 'static,
+              F: Fn(&mut FuzzRng) -> A + 'static
+    {
+        let sink = sink.clone();
+        self.inputs.push((String::from(name), Box::new(move |rng: &mut FuzzRng| {
+            let a = gen(rng);
+            sink.send(&a);
+            format!("{:?}", a)
+        })));
+    }
+}
+
+// How much fuzzing to do. Run i uses seed + i, so a failure is reproduced by running again
+// with the failure's seed and runs set to 1.
+#[derive(Clone, Debug)]
+pub struct FuzzConfig {
+    pub seed: u64,
+    pub runs: usize,
+    pub transactions: usize,
+    // The most sends made in one transaction, at least one is always made. Each sink is sent
+    // to at most once per transaction, so a sink that does not coalesce never drops a value.
+    pub max_sends: usize
+}
+
+impl FuzzConfig {
+    pub fn new(seed: u64) -> FuzzConfig {
+        FuzzConfig {
+            seed,
+            runs: 100,
+            transactions: 50,
+            max_sends: 3
+        }
+    }
+}
+
+// The first invariant that did not hold, with the seed of its run and every transaction
+// leading up to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FuzzFailure {
+    pub seed: u64,
+    // Each transaction's sends as (sink name, value written with {:?}), the last one broke it.
+    pub transactions: Vec<Vec<(String,String)>>,
+    pub message: String
+}
+
+impl fmt::Display for FuzzFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "invariant failed after {} transactions (seed {}): {}", self.transactions.len(), self.seed, self.message)?;
+        for (i, sends) in self.transactions.iter().enumerate() {
+            writeln!(f, "tx {}", i + 1)?;
+            for (name, value) in sends {
+                writeln!(f, "  {}: {}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Builds the graph afresh in a new context for every run, declaring the sinks it can be
+// driven through on the input model, then makes random transactions of sends to them and
+// calls check after each one. check gets what graph_builder returned and reports a broken
+// invariant as Err.
+pub fn fuzz_harness<G, B, C>(config: &FuzzConfig, graph_builder: B, check: C) -> Result<(),FuzzFailure>
+    where B: Fn(&SodiumCtx, &mut InputModel) -> G,
+          C: Fn(&G) -> Result<(),String>
+{
+    for run in 0..config.runs {
+        let seed = config.seed.wrapping_add(run as u64);
+        let mut rng = FuzzRng::new(seed);
+        let sodium_ctx = SodiumCtx::new();
+        let mut input_model = InputModel::new();
+        let graph = graph_builder(&sodium_ctx, &mut input_model);
+        if input_model.inputs.is_empty() {
+            panic!("fuzz_harness: graph_builder declared no inputs");
+        }
+        let mut transactions = Vec::new();
+        for _ in 0..config.transactions {
+            let input_count = input_model.inputs.len();
+            let send_count = (1 + rng.below(config.max_sends.max(1) as u64) as usize).min(input_count);
+            let mut order: Vec<usize> = (0..input_count).collect();
+            let mut sends = Vec::new();
+            sodium_ctx.transaction(|_| {
+                for j in 0..send_count {
+                    let k = j + rng.below((input_count - j) as u64) as usize;
+                    order.swap(j, k);
+                    let (ref name, ref send) = input_model.inputs[order[j]];
+                    sends.push((name.clone(), send(&mut rng)));
+                }
+            });
+            transactions.push(sends);
+            if let Err(message) = check(&graph) {
+                return Err(FuzzFailure { seed, transactions, message });
+            }
+        }
+    }
+    Ok(())
+}
+
+// Longest common subsequence over lines, fine for transcripts of a few thousand lines.
+fn line_diff(expected: &str, actual: &str) -> String {
+    let a: Vec<&str> = expected.lines().collect();
+    let b: Vec<&str> = actual.lines().collect();
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] =
+                if a[i] == b[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+        }
+    }
+    let mut out = String::new();
+    let mut i = 0;
+    let mut j = 0;
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push_str(&format!("  {}\n", a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("- {}\n", a[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+ {}\n", b[j]));
+            j += 1;
+        }
+    }
+    out
+}
@@ -0,0 +1,832 @@
+use sodium::Cell;
+use sodium::CellSink;
+use sodium::IsCell;
+use sodium::IsStream;
+use sodium::Operational;
+use sodium::ResumePolicy;
+use sodium::SodiumCtx;
+use sodium::Stream;
+use sodium::StreamSink;
+use sodium::gc::Finalize;
+use sodium::gc::GcDep;
+use sodium::gc::Trace;
+use std::cell::Cell as StdCell;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+#[cfg(feature = "threads")]
+use std::sync::Arc;
+#[cfg(feature = "threads")]
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "threads")]
+use std::sync::atomic::Ordering;
+#[cfg(feature = "threads")]
+use std::sync::mpsc::Receiver;
+#[cfg(feature = "threads")]
+use std::sync::mpsc::channel;
+#[cfg(feature = "threads")]
+use std::thread;
+#[cfg(feature = "threads")]
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+pub struct FrameClock {
+    ticks: StreamSink<Duration>,
+    elapsed: Cell<f64>
+}
+
+impl FrameClock {
+    pub fn new(sodium_ctx: &SodiumCtx) -> FrameClock {
+        let ticks = sodium_ctx.new_stream_sink();
+        let elapsed = ticks.accum(0.0, |dt: &Duration, t: &f64| *t + dt.as_secs_f64());
+        FrameClock {
+            ticks,
+            elapsed
+        }
+    }
+
+    pub fn tick(&self, dt: Duration) {
+        self.ticks.send(&dt);
+    }
+
+    pub fn ticks(&self) -> Stream<Duration> {
+        self.ticks.to_stream()
+    }
+
+    pub fn elapsed(&self) -> Cell<f64> {
+        self.elapsed.clone()
+    }
+
+    pub fn integrate<CA: IsCell<f64>>(&self, rate: CA) -> Cell<f64> {
+        self.ticks
+            .snapshot2(rate, |dt: &Duration, rate: &f64| dt.as_secs_f64() * *rate)
+            .accum(0.0, |dx: &f64, x: &f64| *x + *dx)
+    }
+
+    // Fires on each tick that completes at least one period. A stream fires at most once per
+    // transaction, so a tick spanning several periods fires once with how many it completed.
+    pub fn every(&self, period: Duration) -> Stream<u32> {
+        if period == Duration::from_secs(0) {
+            panic!("FrameClock::every requires a non-zero period.");
+        }
+        self.ticks
+            .collect(Duration::from_secs(0), move |dt: &Duration, acc: &Duration| {
+                let mut acc = *acc + *dt;
+                let mut periods = 0;
+                while acc >= period {
+                    acc -= period;
+                    periods += 1;
+                }
+                if periods > 0 {
+                    (Some(periods), acc)
+                } else {
+                    (None, acc)
+                }
+            })
+            .filter_option()
+    }
+}
+
+impl Clone for FrameClock {
+    fn clone(&self) -> Self {
+        FrameClock {
+            ticks: self.ticks.clone(),
+            elapsed: self.elapsed.clone()
+        }
+    }
+}
+
+// Where a TimerSystem reads the time from. The time is measured from an arbitrary origin and
+// must never go backwards.
+pub trait Clock {
+    fn now(&self) -> Duration;
+
+    // The Instant that now() counts from.
+    fn origin(&self) -> Instant;
+}
+
+// Wall clock time since the clock was created.
+pub struct SystemClock {
+    start: Instant
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now()
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn origin(&self) -> Instant {
+        self.start
+    }
+}
+
+// A clock that only moves when told to, for tests and simulations.
+pub struct ManualClock {
+    origin: Instant,
+    now: Rc<StdCell<Duration>>
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            origin: Instant::now(),
+            now: Rc::new(StdCell::new(Duration::from_secs(0)))
+        }
+    }
+
+    pub fn set(&self, now: Duration) {
+        if now < self.now.get() {
+            panic!("ManualClock::set can not move the clock backwards.");
+        }
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, dt: Duration) {
+        self.now.set(self.now.get() + dt);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    fn origin(&self) -> Instant {
+        self.origin
+    }
+}
+
+impl Clone for ManualClock {
+    fn clone(&self) -> Self {
+        ManualClock {
+            origin: self.origin,
+            now: self.now.clone()
+        }
+    }
+}
+
+struct Alarm {
+    time: Option<Duration>,
+    sink: StreamSink<Duration>
+}
+
+struct Alarms {
+    next_id: u64,
+    alarms: BTreeMap<u64,Alarm>
+}
+
+impl Alarms {
+    fn set(&mut self, id: u64, time: Option<Duration>) {
+        if let Some(alarm) = self.alarms.get_mut(&id) {
+            alarm.time = time;
+        }
+    }
+
+    // An alarm that is only set from inside the graph, see correlate.
+    fn add(&mut self, sink: StreamSink<Duration>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.alarms.insert(id, Alarm { time: None, sink });
+        id
+    }
+
+    fn earliest(&self) -> Option<(u64,Duration)> {
+        self.alarms
+            .iter()
+            .filter_map(|(id, alarm)| alarm.time.map(|time| (*id, time)))
+            .min_by_key(|&(id, time)| (time, id))
+    }
+}
+
+// Turns a Clock into FRP time. Nothing happens on its own, call poll() from the main loop
+// (next_alarm() says how long it may sleep) and each alarm that has come due fires in its own
+// transaction, with time() set to the alarm time.
+pub struct TimerSystem {
+    sodium_ctx: SodiumCtx,
+    clock: Rc<dyn Clock>,
+    time: CellSink<Duration>,
+    alarms: Rc<RefCell<Alarms>>,
+    // SodiumCtx::resumes as of the last poll().
+    resumes_seen: Rc<StdCell<u64>>
+}
+
+impl TimerSystem {
+    pub fn new<CLOCK: Clock + 'static>(sodium_ctx: &SodiumCtx, clock: CLOCK) -> TimerSystem {
+        let time = sodium_ctx.new_cell_sink(clock.now());
+        TimerSystem {
+            sodium_ctx: sodium_ctx.clone(),
+            clock: Rc::new(clock),
+            time,
+            alarms: Rc::new(RefCell::new(Alarms {
+                next_id: 0,
+                alarms: BTreeMap::new()
+            })),
+            resumes_seen: Rc::new(StdCell::new(sodium_ctx.resumes().0))
+        }
+    }
+
+    // The time as of the last alarm or poll().
+    pub fn time(&self) -> Cell<Duration> {
+        self.time.to_cell()
+    }
+
+    // Fires once when the time reaches the alarm, then again each time the alarm is set to a
+    // new value. An alarm already in the past fires on the next poll().
+    pub fn at<CA: IsCell<Option<Duration>>>(&self, alarm: CA) -> Stream<Duration> {
+        let alarm = alarm.to_cell();
+        let sink: StreamSink<Duration> = self.sodium_ctx.new_stream_sink();
+        let id;
+        {
+            let mut alarms = self.alarms.borrow_mut();
+            id = alarms.next_id;
+            alarms.next_id += 1;
+            alarms.alarms.insert(id, Alarm { time: alarm.sample(), sink: sink.clone() });
+        }
+        let fired = sink.to_stream();
+        let updates = Operational::updates(&alarm);
+        let alarms = self.alarms.clone();
+        let alarms2 = self.alarms.clone();
+        self.sodium_ctx
+            .new_node_builder("TimerSystem::at")
+            .depends_on(&fired)
+            .depends_on(&updates)
+            .on_update(move |inputs| {
+                if let Some(time) = inputs.value(&updates) {
+                    alarms.borrow_mut().set(id, time);
+                }
+                inputs.value(&fired)
+            })
+            .on_cleanup(move || {
+                alarms2.borrow_mut().alarms.remove(&id);
+            })
+            .build()
+            .stream()
+    }
+
+    // The time an Instant corresponds to, instants before the clock's origin map to zero.
+    pub fn time_of(&self, at: Instant) -> Duration {
+        at.saturating_duration_since(self.clock.origin())
+    }
+
+    pub fn instant_of(&self, time: Duration) -> Instant {
+        self.clock.origin() + time
+    }
+
+    // The earliest pending alarm, None while the context is suspended as nothing fires then.
+    pub fn next_alarm(&self) -> Option<Duration> {
+        if self.sodium_ctx.is_suspended() {
+            return None;
+        }
+        self.alarms.borrow().earliest().map(|(_, time)| time)
+    }
+
+    pub fn poll(&self) {
+        if self.sodium_ctx.is_suspended() {
+            return;
+        }
+        let now = self.clock.now();
+        let (resumes, policy) = self.sodium_ctx.resumes();
+        if resumes != self.resumes_seen.get() {
+            self.resumes_seen.set(resumes);
+            if policy == ResumePolicy::DropElapsed {
+                for alarm in self.alarms.borrow_mut().alarms.values_mut() {
+                    if alarm.time.is_some_and(|time| time <= now) {
+                        alarm.time = None;
+                    }
+                }
+            }
+        }
+        loop {
+            let due;
+            {
+                let mut alarms = self.alarms.borrow_mut();
+                match alarms.earliest() {
+                    Some((id, time)) if time <= now => {
+                        alarms.set(id, None);
+                        due = (alarms.alarms[&id].sink.clone(), time);
+                    },
+                    _ => break
+                }
+            }
+            let (sink, time) = due;
+            let time = ::std::cmp::max(time, self.time.sample());
+            self.sodium_ctx.transaction(|_| {
+                self.time.send(&time);
+                sink.send(&time);
+            });
+        }
+        if now > self.time.sample() {
+            self.time.send(&now);
+        }
+    }
+
+    // Fires every period from now, with the time it was due. Periods that went by while
+    // nothing polled are skipped rather than fired back to back.
+    pub fn every(&self, period: Duration) -> Stream<Duration> {
+        if period == Duration::from_secs(0) {
+            panic!("TimerSystem::every requires a non-zero period.");
+        }
+        let sink: StreamSink<Duration> = self.sodium_ctx.new_stream_sink();
+        let alarm_id;
+        {
+            let mut alarms = self.alarms.borrow_mut();
+            alarm_id = alarms.add(sink.clone());
+            alarms.set(alarm_id, Some(self.time.sample() + period));
+        }
+        let fired = sink.to_stream();
+        let clock = self.clock.clone();
+        let alarms = self.alarms.clone();
+        let alarms2 = self.alarms.clone();
+        self.sodium_ctx
+            .new_node_builder("TimerSystem::every")
+            .depends_on(&fired)
+            .on_update(move |inputs| {
+                let t_op = inputs.value(&fired);
+                if let Some(t) = t_op {
+                    let now = clock.now();
+                    let mut next = t + period;
+                    while next <= now {
+                        next += period;
+                    }
+                    alarms.borrow_mut().set(alarm_id, Some(next));
+                }
+                t_op
+            })
+            .on_cleanup(move || {
+                alarms2.borrow_mut().alarms.remove(&alarm_id);
+            })
+            .build()
+            .stream()
+    }
+
+    // Pairs each event with the time since the previous one, or since timed() was called for
+    // the first.
+    pub fn timed<A: Clone + Trace + Finalize + 'static, SA: IsStream<A>>(&self, sa: SA) -> Stream<(Duration,A)> {
+        sa.to_stream()
+            .snapshot2(&self.time, |a: &A, t: &Duration| (a.clone(), *t))
+            .collect(self.time.sample(), |&(ref a, t): &(A,Duration), last: &Duration| ((t - *last, a.clone()), t))
+    }
+
+    // Total time spent between start and stop events, including the current run while
+    // started. If both fire in the same transaction the start wins.
+    pub fn stopwatch<SSTART: IsStream<()>, SSTOP: IsStream<()>>(&self, start: SSTART, stop: SSTOP) -> Cell<Duration> {
+        let running = start.to_stream().map(|_: &()| true).or_else(stop.to_stream().map(|_: &()| false));
+        let zero = Duration::from_secs(0);
+        let state = running
+            .snapshot2(&self.time, |running: &bool, t: &Duration| (*running, *t))
+            .accum((zero, None), |&(running, t): &(bool,Duration), &(total, since): &(Duration,Option<Duration>)| {
+                match (running, since) {
+                    (true, None) => (total, Some(t)),
+                    (false, Some(since)) => (total + (t - since), None),
+                    _ => (total, since)
+                }
+            });
+        state.lift2(&self.time, |&(total, since): &(Duration,Option<Duration>), t: &Duration| {
+            match since {
+                Some(since) => total + (*t - since),
+                None => total
+            }
+        })
+    }
+}
+
+impl Clone for TimerSystem {
+    fn clone(&self) -> Self {
+        TimerSystem {
+            sodium_ctx: self.sodium_ctx.clone(),
+            clock: self.clock.clone(),
+            time: self.time.clone(),
+            alarms: self.alarms.clone(),
+            resumes_seen: self.resumes_seen.clone()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackoffPolicy {
+    pub initial_delay: Duration,
+    pub multiplier: f64,
+    pub max_delay: Duration,
+    // None retries for ever.
+    pub max_attempts: Option<u32>
+}
+
+impl BackoffPolicy {
+    // Doubles the delay after each failure, up to max_delay.
+    pub fn exponential(initial_delay: Duration, max_delay: Duration) -> BackoffPolicy {
+        BackoffPolicy {
+            initial_delay,
+            multiplier: 2.0,
+            max_delay,
+            max_attempts: None
+        }
+    }
+
+    pub fn with_max_attempts(self, max_attempts: u32) -> BackoffPolicy {
+        BackoffPolicy {
+            max_attempts: Some(max_attempts),
+            ..self
+        }
+    }
+
+    // The wait before the given retry, counting from zero for the first. A multiplier that
+    // makes it negative waits for nothing, one that makes it infinite or NaN waits max_delay.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let secs = self.initial_delay.as_secs_f64() * self.multiplier.powi(retry.min(i32::MAX as u32) as i32);
+        if secs.is_nan() || secs >= self.max_delay.as_secs_f64() {
+            self.max_delay
+        } else if secs <= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(secs)
+        }
+    }
+}
+
+impl Trace for BackoffPolicy {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for BackoffPolicy {}
+
+// Fires with the attempt number, starting at 1, each time the request that produced results
+// should be sent again. An error schedules the next retry on the timer, counting from time() as
+// of the error, and a success resets the count. Once max_attempts retries have failed it stops.
+// Wire the output into whatever sends the request, e.g. retries.listen(|_| request.send(&())).
+pub fn retry_with_backoff<A, E, SR>(results: SR, policy: BackoffPolicy, timer_system: &TimerSystem) -> Stream<u32>
+    where A: Clone + Trace + Finalize + 'static,
+          E: Clone + Trace + Finalize + 'static,
+          SR: IsStream<Result<A,E>>
+{
+    let scheduled = results
+        .to_stream()
+        .snapshot2(timer_system.time(), |result: &Result<A,E>, t: &Duration| (result.is_err(), *t))
+        .accum((0, None), move |&(failed, t): &(bool,Duration), &(retries, _): &(u32,Option<Duration>)| {
+            if !failed {
+                (0, None)
+            } else if policy.max_attempts.is_some_and(|max_attempts| retries >= max_attempts) {
+                (retries, None)
+            } else {
+                (retries + 1, Some(t + policy.delay(retries)))
+            }
+        });
+    timer_system
+        .at(scheduled.map(|&(_, at): &(u32,Option<Duration>)| at))
+        .snapshot2(&scheduled, |_: &Duration, &(retries, _): &(u32,Option<Duration>)| retries)
+}
+
+// Pairs each response with the outstanding request of the same id, which has timeout from
+// time() as of the request to get one. The second stream fires with the id of each request
+// that ran out of time, a response that turns up after that is dropped, as is one for an id
+// nobody asked for. A request reusing an id that is still outstanding replaces it.
+pub fn correlate<Id, Req, Resp, SREQ, SRESP>(requests: SREQ, responses: SRESP, timeout: Duration, timer_system: &TimerSystem) -> (Stream<(Req,Resp)>, Stream<Id>)
+    where Id: Clone + Eq + Hash + Trace + Finalize + 'static,
+          Req: Clone + Trace + Finalize + 'static,
+          Resp: Clone + Trace + Finalize + 'static,
+          SREQ: IsStream<(Id,Req)>,
+          SRESP: IsStream<(Id,Resp)>
+{
+    let requests = requests
+        .to_stream()
+        .snapshot2(timer_system.time(), |(id, req): &(Id,Req), t: &Duration| (id.clone(), req.clone(), *t));
+    let responses = responses.to_stream();
+    // A single alarm, kept at the earliest deadline. Deadlines that come due together expire
+    // one per firing, in the order they were requested.
+    let sink: StreamSink<Duration> = timer_system.sodium_ctx.new_stream_sink();
+    let alarm_id = timer_system.alarms.borrow_mut().add(sink.clone());
+    let fired = sink.to_stream();
+    let mut outstanding: HashMap<Id,(u64,Duration,Req)> = HashMap::new();
+    let mut next_seq: u64 = 0;
+    let alarms = timer_system.alarms.clone();
+    let alarms2 = timer_system.alarms.clone();
+    let out = timer_system
+        .sodium_ctx
+        .new_node_builder("correlate")
+        .depends_on(&requests)
+        .depends_on(&responses)
+        .depends_on(&fired)
+        .on_update(move |inputs| {
+            if let Some((id, req, t)) = inputs.value(&requests) {
+                outstanding.insert(id, (next_seq, t + timeout, req));
+                next_seq += 1;
+            }
+            let matched_op = inputs
+                .value(&responses)
+                .and_then(|(id, resp)| outstanding.remove(&id).map(|(_, _, req)| (req, resp)));
+            let expired_op = inputs.value(&fired).and_then(|now| {
+                let id_op = outstanding
+                    .iter()
+                    .filter(|&(_, &(_, deadline, _))| deadline <= now)
+                    .min_by_key(|&(_, &(seq, deadline, _))| (deadline, seq))
+                    .map(|(id, _)| id.clone());
+                if let Some(ref id) = id_op {
+                    outstanding.remove(id);
+                }
+                id_op
+            });
+            let next_deadline = outstanding.values().map(|&(_, deadline, _)| deadline).min();
+            alarms.borrow_mut().set(alarm_id, next_deadline);
+            if matched_op.is_none() && expired_op.is_none() {
+                return None;
+            }
+            Some((matched_op, expired_op))
+        })
+        .on_cleanup(move || {
+            alarms2.borrow_mut().alarms.remove(&alarm_id);
+        })
+        .build()
+        .stream();
+    (
+        out.map(|(matched_op, _): &(Option<(Req,Resp)>,Option<Id>)| matched_op.clone()).filter_option(),
+        out.map(|(_, expired_op): &(Option<(Req,Resp)>,Option<Id>)| expired_op.clone()).filter_option()
+    )
+}
+
+impl<A: Clone + Trace + Finalize + 'static> Stream<A> {
+    // Drops an event when one with the same key got through less than window ago, by the
+    // timer's time(). Dropped events do not extend the window. Keys are forgotten as their
+    // windows run out, on an alarm, so the table only holds keys seen recently.
+    pub fn distinct_within<K: Eq + Hash + 'static, F: Fn(&A) -> K + 'static>(&self, window: Duration, key: F, timer_system: &TimerSystem) -> Stream<A> {
+        let events = self.snapshot2(timer_system.time(), |a: &A, t: &Duration| (a.clone(), *t));
+        let sink: StreamSink<Duration> = timer_system.sodium_ctx.new_stream_sink();
+        let alarm_id = timer_system.alarms.borrow_mut().add(sink.clone());
+        let fired = sink.to_stream();
+        let mut expiries: HashMap<K,Duration> = HashMap::new();
+        let alarms = timer_system.alarms.clone();
+        let alarms2 = timer_system.alarms.clone();
+        timer_system
+            .sodium_ctx
+            .new_node_builder("Stream::distinct_within")
+            .depends_on(&events)
+            .depends_on(&fired)
+            .on_update(move |inputs| {
+                if let Some(now) = inputs.value(&fired) {
+                    expiries.retain(|_, expiry| *expiry > now);
+                }
+                let passed_op = inputs.value(&events).and_then(|(a, t)| {
+                    let k = key(&a);
+                    if expiries.get(&k).is_some_and(|expiry| *expiry > t) {
+                        return None;
+                    }
+                    expiries.insert(k, t + window);
+                    Some(a)
+                });
+                alarms.borrow_mut().set(alarm_id, expiries.values().min().cloned());
+                passed_op
+            })
+            .on_cleanup(move || {
+                alarms2.borrow_mut().alarms.remove(&alarm_id);
+            })
+            .build()
+            .stream()
+    }
+}
+
+// Values that can be blended, t runs from 0 at self to 1 at to.
+pub trait Lerp {
+    fn lerp(&self, to: &Self, t: f64) -> Self;
+}
+
+impl Lerp for f64 {
+    fn lerp(&self, to: &f64, t: f64) -> f64 {
+        *self + (*to - *self) * t
+    }
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, to: &f32, t: f64) -> f32 {
+        *self + (*to - *self) * (t as f32)
+    }
+}
+
+impl<A: Lerp, B: Lerp> Lerp for (A,B) {
+    fn lerp(&self, to: &(A,B), t: f64) -> (A,B) {
+        (self.0.lerp(&to.0, t), self.1.lerp(&to.1, t))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut
+}
+
+impl Easing {
+    // Maps the fraction of the duration gone to how far along the blend is, both 0 to 1.
+    pub fn apply(&self, t: f64) -> f64 {
+        match *self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut =>
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    -1.0 + (4.0 - 2.0 * t) * t
+                }
+        }
+    }
+}
+
+impl<A: Lerp + Clone + Trace + Finalize + 'static> Cell<A> {
+    // Cross-fades from this cell to target over duration, starting at the timer's time now.
+    // Both ends keep following their cells while it runs, afterwards it is just target. The
+    // blend moves on every poll() of the timer, with an alarm set for the end so a main loop
+    // sleeping until next_alarm() still lands on it.
+    pub fn interpolate_to<CA: IsCell<A>>(&self, target: CA, duration: Duration, easing: Easing, timer_system: &TimerSystem) -> Cell<A> {
+        let start = timer_system.time().sample();
+        let end = timer_system.sodium_ctx.new_cell(Some(start + duration));
+        let done = timer_system.at(&end).map(|_: &Duration| true).hold(duration == Duration::from_secs(0));
+        let progress = timer_system.time().lift2(&done, move |t: &Duration, done: &bool| {
+            if *done {
+                1.0
+            } else {
+                let gone = t.checked_sub(start).unwrap_or(Duration::from_secs(0)).as_secs_f64() / duration.as_secs_f64();
+                if gone < 1.0 { gone } else { 1.0 }
+            }
+        });
+        self.lift3(target, &progress, move |from: &A, to: &A, progress: &f64| from.lerp(to, easing.apply(*progress)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameInfo {
+    // Counts from zero for the first frame.
+    pub index: u64,
+    pub time: Duration,
+    // Time since the previous frame, zero for the first.
+    pub dt: Duration
+}
+
+impl Trace for FrameInfo {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for FrameInfo {}
+
+// Owns a TimerSystem and decides when frames are due.
+pub trait Driver {
+    fn timer(&self) -> TimerSystem;
+
+    // Fires the TimerSystem time once per frame.
+    fn frame_times(&self) -> Stream<Duration>;
+}
+
+pub fn animation_frames<D: Driver>(driver: &D) -> Stream<FrameInfo> {
+    driver
+        .frame_times()
+        .collect(None, |time: &Duration, last: &Option<FrameInfo>| {
+            let frame =
+                match *last {
+                    Some(last) => FrameInfo { index: last.index + 1, time: *time, dt: *time - last.time },
+                    None => FrameInfo { index: 0, time: *time, dt: Duration::from_secs(0) }
+                };
+            (frame, Some(frame))
+        })
+}
+
+// Fires once, on the first poll at or after the deadline.
+pub fn deadline<D: Driver>(driver: &D, at: Instant) -> Stream<()> {
+    let timer = driver.timer();
+    let at = timer.sodium_ctx.new_cell(Some(timer.time_of(at)));
+    timer.at(&at).map(|_: &Duration| ())
+}
+
+// Frames and time only move when told to, for tests.
+pub struct ManualDriver {
+    clock: ManualClock,
+    timer: TimerSystem,
+    frames: StreamSink<Duration>
+}
+
+impl ManualDriver {
+    pub fn new(sodium_ctx: &SodiumCtx) -> ManualDriver {
+        let clock = ManualClock::new();
+        ManualDriver {
+            timer: TimerSystem::new(sodium_ctx, clock.clone()),
+            clock,
+            frames: sodium_ctx.new_stream_sink()
+        }
+    }
+
+    // Moves the clock forward and fires any alarms that came due.
+    pub fn advance(&self, dt: Duration) {
+        self.clock.advance(dt);
+        self.timer.poll();
+    }
+
+    pub fn frame(&self) {
+        self.frames.send(&self.timer.time().sample());
+    }
+}
+
+impl Driver for ManualDriver {
+    fn timer(&self) -> TimerSystem {
+        self.timer.clone()
+    }
+
+    fn frame_times(&self) -> Stream<Duration> {
+        self.frames.to_stream()
+    }
+}
+
+// A background thread wakes the main loop once per frame period. The graph itself stays on
+// the thread that owns the SodiumCtx, which has to call run_once() in a loop.
+#[cfg(feature = "threads")]
+pub struct ThreadDriver {
+    timer: TimerSystem,
+    frames: StreamSink<Duration>,
+    wakeups: Receiver<()>,
+    running: Arc<AtomicBool>,
+    thread_op: Option<JoinHandle<()>>
+}
+
+#[cfg(feature = "threads")]
+impl ThreadDriver {
+    pub fn new(sodium_ctx: &SodiumCtx, frame_period: Duration) -> ThreadDriver {
+        let (sender, wakeups) = channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread;
+        {
+            let running = running.clone();
+            thread = thread::spawn(move || {
+                while running.load(Ordering::SeqCst) {
+                    thread::sleep(frame_period);
+                    if sender.send(()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        ThreadDriver {
+            timer: TimerSystem::new(sodium_ctx, SystemClock::new()),
+            frames: sodium_ctx.new_stream_sink(),
+            wakeups,
+            running,
+            thread_op: Some(thread)
+        }
+    }
+
+    // Blocks until the next frame or alarm is due, then fires it. Frames missed while the
+    // main loop was busy are dropped rather than fired back to back.
+    pub fn run_once(&self) {
+        let clock_now = self.timer.clock.now();
+        let woken =
+            match self.timer.next_alarm() {
+                Some(alarm) if alarm <= clock_now => self.wakeups.try_recv().is_ok(),
+                Some(alarm) => self.wakeups.recv_timeout(alarm - clock_now).is_ok(),
+                None => self.wakeups.recv().is_ok()
+            };
+        while self.wakeups.try_recv().is_ok() {}
+        self.timer.poll();
+        if woken && !self.timer.sodium_ctx.is_suspended() {
+            self.frames.send(&self.timer.time().sample());
+        }
+    }
+}
+
+#[cfg(feature = "threads")]
+impl Driver for ThreadDriver {
+    fn timer(&self) -> TimerSystem {
+        self.timer.clone()
+    }
+
+    fn frame_times(&self) -> Stream<Duration> {
+        self.frames.to_stream()
+    }
+}
+
+#[cfg(feature = "threads")]
+impl Drop for ThreadDriver {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread_op.take() {
+            let _ = thread.join();
+        }
+    }
+}
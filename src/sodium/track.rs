@@ -0,0 +1,76 @@
+use sodium::Cell;
+use sodium::CellLoop;
+use sodium::CellSink;
+use sodium::Listener;
+use sodium::Operational;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::cell::Cell as StdCell;
+use std::rc::Rc;
+
+// A cell with its value type erased, for code that only cares when it changes.
+pub trait AnyCell {
+    fn listen_changes(&self, callback: Box<dyn FnMut()>) -> Listener;
+}
+
+impl<A: Clone + Trace + Finalize + 'static> AnyCell for Cell<A> {
+    fn listen_changes(&self, mut callback: Box<dyn FnMut()>) -> Listener {
+        Operational::updates(self).listen(move |_: &A| callback())
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> AnyCell for CellSink<A> {
+    fn listen_changes(&self, callback: Box<dyn FnMut()>) -> Listener {
+        self.to_cell().listen_changes(callback)
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static> AnyCell for CellLoop<A> {
+    fn listen_changes(&self, callback: Box<dyn FnMut()>) -> Listener {
+        self.to_cell().listen_changes(callback)
+    }
+}
+
+// Set whenever any of the cells is updated, e.g. once per frame:
+//
+//   if dirty.take() { redraw(); }
+//
+// Starts clear. An update to the same value still counts.
+pub struct DirtyFlag {
+    dirty: Rc<StdCell<bool>>,
+    listeners: Vec<Listener>
+}
+
+impl DirtyFlag {
+    pub fn new(cells: &[&dyn AnyCell]) -> DirtyFlag {
+        let dirty = Rc::new(StdCell::new(false));
+        let listeners = cells
+            .iter()
+            .map(|ca| {
+                let dirty = dirty.clone();
+                ca.listen_changes(Box::new(move || dirty.set(true)))
+            })
+            .collect();
+        DirtyFlag {
+            dirty,
+            listeners
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    // Whether anything changed since the last take().
+    pub fn take(&self) -> bool {
+        self.dirty.replace(false)
+    }
+}
+
+impl Drop for DirtyFlag {
+    fn drop(&mut self) {
+        for listener in &self.listeners {
+            listener.unlisten();
+        }
+    }
+}
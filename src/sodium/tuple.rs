@@ -0,0 +1,109 @@
+use sodium::Cell;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+
+// lift_tuple!((&a, &b, &c)) is a.lift3(&b, &c, ...) that just collects the values, for two to
+// six cells.
+#[macro_export]
+macro_rules! lift_tuple {
+    (($a:expr, $b:expr $(,)*)) => {
+        $crate::sodium::IsCell::lift2($a, $b, $crate::sodium::tuple::tuple2)
+    };
+    (($a:expr, $b:expr, $c:expr $(,)*)) => {
+        $crate::sodium::IsCell::lift3($a, $b, $c, $crate::sodium::tuple::tuple3)
+    };
+    (($a:expr, $b:expr, $c:expr, $d:expr $(,)*)) => {
+        $crate::sodium::IsCell::lift4($a, $b, $c, $d, $crate::sodium::tuple::tuple4)
+    };
+    (($a:expr, $b:expr, $c:expr, $d:expr, $e:expr $(,)*)) => {
+        $crate::sodium::IsCell::lift5($a, $b, $c, $d, $e, $crate::sodium::tuple::tuple5)
+    };
+    (($a:expr, $b:expr, $c:expr, $d:expr, $e:expr, $f:expr $(,)*)) => {
+        $crate::sodium::IsCell::lift6($a, $b, $c, $d, $e, $f, $crate::sodium::tuple::tuple6)
+    }
+}
+
+// What split_tuple returns for five and six cells.
+pub type Cells5<A,B,C,D,E> = (Cell<A>, Cell<B>, Cell<C>, Cell<D>, Cell<E>);
+pub type Cells6<A,B,C,D,E,F> = (Cell<A>, Cell<B>, Cell<C>, Cell<D>, Cell<E>, Cell<F>);
+
+#[doc(hidden)]
+pub fn tuple2<A: Clone, B: Clone>(a: &A, b: &B) -> (A,B) {
+    (a.clone(), b.clone())
+}
+
+#[doc(hidden)]
+pub fn tuple3<A: Clone, B: Clone, C: Clone>(a: &A, b: &B, c: &C) -> (A,B,C) {
+    (a.clone(), b.clone(), c.clone())
+}
+
+#[doc(hidden)]
+pub fn tuple4<A: Clone, B: Clone, C: Clone, D: Clone>(a: &A, b: &B, c: &C, d: &D) -> (A,B,C,D) {
+    (a.clone(), b.clone(), c.clone(), d.clone())
+}
+
+#[doc(hidden)]
+pub fn tuple5<A: Clone, B: Clone, C: Clone, D: Clone, E: Clone>(a: &A, b: &B, c: &C, d: &D, e: &E) -> (A,B,C,D,E) {
+    (a.clone(), b.clone(), c.clone(), d.clone(), e.clone())
+}
+
+#[doc(hidden)]
+pub fn tuple6<A: Clone, B: Clone, C: Clone, D: Clone, E: Clone, F: Clone>(a: &A, b: &B, c: &C, d: &D, e: &E, f: &F) -> (A,B,C,D,E,F) {
+    (a.clone(), b.clone(), c.clone(), d.clone(), e.clone(), f.clone())
+}
+
+impl<A: Clone + Trace + Finalize + 'static, B: Clone + Trace + Finalize + 'static> Cell<(A,B)> {
+    // Each component is a map of this cell, so they all share its node.
+    pub fn split_tuple(&self) -> (Cell<A>, Cell<B>) {
+        (
+            self.map(|t: &(A,B)| t.0.clone()),
+            self.map(|t: &(A,B)| t.1.clone())
+        )
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static, B: Clone + Trace + Finalize + 'static, C: Clone + Trace + Finalize + 'static> Cell<(A,B,C)> {
+    pub fn split_tuple(&self) -> (Cell<A>, Cell<B>, Cell<C>) {
+        (
+            self.map(|t: &(A,B,C)| t.0.clone()),
+            self.map(|t: &(A,B,C)| t.1.clone()),
+            self.map(|t: &(A,B,C)| t.2.clone())
+        )
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static, B: Clone + Trace + Finalize + 'static, C: Clone + Trace + Finalize + 'static, D: Clone + Trace + Finalize + 'static> Cell<(A,B,C,D)> {
+    pub fn split_tuple(&self) -> (Cell<A>, Cell<B>, Cell<C>, Cell<D>) {
+        (
+            self.map(|t: &(A,B,C,D)| t.0.clone()),
+            self.map(|t: &(A,B,C,D)| t.1.clone()),
+            self.map(|t: &(A,B,C,D)| t.2.clone()),
+            self.map(|t: &(A,B,C,D)| t.3.clone())
+        )
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static, B: Clone + Trace + Finalize + 'static, C: Clone + Trace + Finalize + 'static, D: Clone + Trace + Finalize + 'static, E: Clone + Trace + Finalize + 'static> Cell<(A,B,C,D,E)> {
+    pub fn split_tuple(&self) -> Cells5<A,B,C,D,E> {
+        (
+            self.map(|t: &(A,B,C,D,E)| t.0.clone()),
+            self.map(|t: &(A,B,C,D,E)| t.1.clone()),
+            self.map(|t: &(A,B,C,D,E)| t.2.clone()),
+            self.map(|t: &(A,B,C,D,E)| t.3.clone()),
+            self.map(|t: &(A,B,C,D,E)| t.4.clone())
+        )
+    }
+}
+
+impl<A: Clone + Trace + Finalize + 'static, B: Clone + Trace + Finalize + 'static, C: Clone + Trace + Finalize + 'static, D: Clone + Trace + Finalize + 'static, E: Clone + Trace + Finalize + 'static, F: Clone + Trace + Finalize + 'static> Cell<(A,B,C,D,E,F)> {
+    pub fn split_tuple(&self) -> Cells6<A,B,C,D,E,F> {
+        (
+            self.map(|t: &(A,B,C,D,E,F)| t.0.clone()),
+            self.map(|t: &(A,B,C,D,E,F)| t.1.clone()),
+            self.map(|t: &(A,B,C,D,E,F)| t.2.clone()),
+            self.map(|t: &(A,B,C,D,E,F)| t.3.clone()),
+            self.map(|t: &(A,B,C,D,E,F)| t.4.clone()),
+            self.map(|t: &(A,B,C,D,E,F)| t.5.clone())
+        )
+    }
+}
@@ -0,0 +1,184 @@
+use sodium::Cell;
+use sodium::CellSink;
+use sodium::IsStream;
+use sodium::Listener;
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::TxId;
+use sodium::gc::Finalize;
+use sodium::gc::Trace;
+use std::cell::Cell as StdCell;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// One recorded command, and how to send it or its inverse back through its sink.
+struct Step {
+    undo: Box<dyn Fn()>,
+    redo: Box<dyn Fn()>
+}
+
+// Everything recorded in one transaction, undone and redone as a unit.
+struct Entry {
+    tx_id: TxId,
+    steps: Vec<Step>
+}
+
+struct History {
+    done: Vec<Entry>,
+    undone: Vec<Entry>
+}
+
+// Records the commands that flow through registered streams so they can be undone and redone.
+// Undoing sends the inverse of every command recorded in one transaction back through the
+// sinks they were registered with, in reverse order and in a single transaction, and redoing
+// sends the commands again. Commands recorded after an undo clear what could be redone.
+pub struct UndoManager {
+    sodium_ctx: SodiumCtx,
+    history: Rc<RefCell<History>>,
+    replaying: Rc<StdCell<bool>>,
+    can_undo: CellSink<bool>,
+    can_redo: CellSink<bool>,
+    undo: StreamSink<()>,
+    redo: StreamSink<()>,
+    listeners: Vec<Listener>
+}
+
+impl UndoManager {
+    pub fn new(sodium_ctx: &SodiumCtx) -> UndoManager {
+        let mut undo_manager = UndoManager {
+            sodium_ctx: sodium_ctx.clone(),
+            history: Rc::new(RefCell::new(History { done: Vec::new(), undone: Vec::new() })),
+            replaying: Rc::new(StdCell::new(false)),
+            can_undo: sodium_ctx.new_cell_sink(false),
+            can_redo: sodium_ctx.new_cell_sink(false),
+            undo: sodium_ctx.new_stream_sink(),
+            redo: sodium_ctx.new_stream_sink(),
+            listeners: Vec::new()
+        };
+        let undo_listener = undo_manager.replay_on(true);
+        let redo_listener = undo_manager.replay_on(false);
+        undo_manager.listeners.push(undo_listener);
+        undo_manager.listeners.push(redo_listener);
+        undo_manager
+    }
+
+    // Records each event on commands. Usually commands is the sink's own stream or something
+    // fed by it. Events sent while undoing or redoing are not recorded again.
+    pub fn register<A, SA, F>(&mut self, commands: SA, sink: &StreamSink<A>, invert: F)
+        where A: Clone + Trace + Finalize + 'static,
+              SA: IsStream<A>,
+              F: Fn(&A) -> A + 'static
+    {
+        let sodium_ctx = self.sodium_ctx.clone();
+        let history = self.history.clone();
+        let replaying = self.replaying.clone();
+        let sink = sink.clone();
+        let invert = Rc::new(invert);
+        let can_undo = self.can_undo.clone();
+        let can_redo = self.can_redo.clone();
+        let listener = commands.to_stream().listen(move |a: &A| {
+            if replaying.get() {
+                return;
+            }
+            let step = {
+                let undo_sink = sink.clone();
+                let redo_sink = sink.clone();
+                let inverse = invert(a);
+                let a = a.clone();
+                Step {
+                    undo: Box::new(move || undo_sink.send(&inverse)),
+                    redo: Box::new(move || redo_sink.send(&a))
+                }
+            };
+            let tx_id = sodium_ctx.outer_tx_id();
+            let mut history = history.borrow_mut();
+            history.undone.clear();
+            let same_tx = history.done.last().is_some_and(|entry| entry.tx_id == tx_id);
+            if same_tx {
+                history.done.last_mut().unwrap().steps.push(step);
+            } else {
+                history.done.push(Entry { tx_id, steps: vec![step] });
+            }
+            let can_undo = can_undo.clone();
+            let can_redo = can_redo.clone();
+            sodium_ctx.post(move || {
+                can_undo.send(&true);
+                can_redo.send(&false);
+            });
+        });
+        self.listeners.push(listener);
+    }
+
+    pub fn can_undo(&self) -> Cell<bool> {
+        self.can_undo.to_cell()
+    }
+
+    pub fn can_redo(&self) -> Cell<bool> {
+        self.can_redo.to_cell()
+    }
+
+    // Send () to undo the last recorded transaction, nothing happens when there is none.
+    pub fn undo(&self) -> StreamSink<()> {
+        self.undo.clone()
+    }
+
+    pub fn redo(&self) -> StreamSink<()> {
+        self.redo.clone()
+    }
+
+    fn replay_on(&self, undo: bool) -> Listener {
+        let trigger = if undo { self.undo.clone() } else { self.redo.clone() };
+        let sodium_ctx = self.sodium_ctx.clone();
+        let history = self.history.clone();
+        let replaying = self.replaying.clone();
+        let can_undo = self.can_undo.clone();
+        let can_redo = self.can_redo.clone();
+        trigger.to_stream().listen(move |_: &()| {
+            let sodium_ctx2 = sodium_ctx.clone();
+            let history = history.clone();
+            let replaying = replaying.clone();
+            let can_undo = can_undo.clone();
+            let can_redo = can_redo.clone();
+            // Sinks can not be sent to from a listener, so the replay waits for the end of
+            // the transaction.
+            sodium_ctx.post(move || {
+                let entry_op = {
+                    let mut history = history.borrow_mut();
+                    if undo { history.done.pop() } else { history.undone.pop() }
+                };
+                let entry = match entry_op {
+                    Some(entry) => entry,
+                    None => return
+                };
+                replaying.set(true);
+                sodium_ctx2.transaction(|_| {
+                    if undo {
+                        for step in entry.steps.iter().rev() {
+                            (step.undo)();
+                        }
+                    } else {
+                        for step in &entry.steps {
+                            (step.redo)();
+                        }
+                    }
+                });
+                replaying.set(false);
+                let (done, undone) = {
+                    let mut history = history.borrow_mut();
+                    if undo { history.undone.push(entry); } else { history.done.push(entry); }
+                    (!history.done.is_empty(), !history.undone.is_empty())
+                };
+                can_undo.send(&done);
+                can_redo.send(&undone);
+            });
+        })
+    }
+}
+
+impl Drop for UndoManager {
+    fn drop(&mut self) {
+        for listener in &self.listeners {
+            listener.unlisten();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use sodium::AsyncRunner;
+use sodium::CancellationToken;
+use sodium::IsStream;
+use sodium::OverflowPolicy;
+use sodium::SodiumCtx;
+use tests::assert_memory_freed;
+use std::cell::Cell as StdCell;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Wake;
+use std::thread;
+use std::thread::Thread;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(mut future: F) -> F::Output {
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park()
+        }
+    }
+}
+
+fn drain_with_policy(policy: OverflowPolicy) -> (Vec<i32>, u64) {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    let result;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let (l, stream) = s.to_async_stream_bounded(2, policy);
+        for i in 1..6 {
+            s.send(&i);
+        }
+        l.unlisten();
+        drop(l);
+        let mut out = Vec::new();
+        while let Some(a) = block_on(stream.next()) {
+            out.push(a);
+        }
+        result = (out, stream.dropped());
+    }
+    assert_memory_freed(sodium_ctx);
+    result
+}
+
+#[test]
+fn to_async_stream_bounded_drop() {
+    assert_eq!((vec![1, 2], 3), drain_with_policy(OverflowPolicy::Drop));
+}
+
+#[test]
+fn to_async_stream_bounded_latest() {
+    assert_eq!((vec![4, 5], 3), drain_with_policy(OverflowPolicy::Latest));
+}
+
+#[test]
+fn to_async_stream_bounded_block() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let (l, stream) = s.to_async_stream_bounded(1, OverflowPolicy::Block);
+        let consumer = thread::spawn(move || {
+            let mut out = Vec::new();
+            while let Some(a) = block_on(stream.next()) {
+                out.push(a);
+            }
+            (out, stream.dropped())
+        });
+        for i in 0..10 {
+            s.send(&i);
+        }
+        l.unlisten();
+        drop(l);
+        assert_eq!(((0..10).collect::<Vec<i32>>(), 0), consumer.join().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+struct Reply {
+    slot: Rc<StdCell<Option<i32>>>
+}
+
+impl Future for Reply {
+    type Output = i32;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<i32> {
+        match self.slot.take() {
+            Some(reply) => Poll::Ready(reply),
+            None => Poll::Pending
+        }
+    }
+}
+
+#[test]
+fn map_async_cancellable() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let runner = AsyncRunner::new(sodium_ctx);
+        let s = sodium_ctx.new_stream_sink();
+        let requests: Rc<RefCell<Vec<(i32,Rc<StdCell<Option<i32>>>,CancellationToken)>>> = Rc::new(RefCell::new(Vec::new()));
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let requests = requests.clone();
+            let out = out.clone();
+            l = s
+                .map_async_cancellable(&runner, move |a: &i32, token: CancellationToken| {
+                    let slot = Rc::new(StdCell::new(None));
+                    requests.borrow_mut().push((*a, slot.clone(), token));
+                    Reply { slot }
+                })
+                .listen(move |reply: &i32| out.borrow_mut().push(*reply));
+        }
+        s.send(&1);
+        s.send(&2);
+        assert!(runner.is_woken());
+        assert_eq!(1, runner.poll());
+        let cancelled = requests.borrow()[1].2.cell();
+        {
+            let requests = requests.borrow();
+            assert!(requests[0].2.is_cancelled());
+            assert!(!requests[1].2.is_cancelled());
+            requests[0].1.set(Some(10));
+            requests[1].1.set(Some(20));
+        }
+        assert_eq!(0, runner.poll());
+        assert_eq!(vec![20], *out.borrow());
+        assert!(!cancelled.sample());
+        s.send(&3);
+        assert!(cancelled.sample());
+        requests.borrow()[2].1.set(Some(30));
+        assert_eq!(0, runner.poll());
+        l.unlisten();
+        assert_eq!(vec![20, 30], *out.borrow());
+        drop(cancelled);
+        requests.borrow_mut().clear();
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,48 @@
+use sodium::SodiumCtx;
+use sodium::bind_bidirectional;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn bind_bidirectional_suppresses_echo() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let c = sodium_ctx.new_cell_sink(1);
+        let external = Rc::new(RefCell::new(0));
+        let sets = Rc::new(RefCell::new(Vec::new()));
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = c.to_cell().listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        let binding;
+        {
+            let external_get = external.clone();
+            let external_set = external.clone();
+            let sets = sets.clone();
+            binding = bind_bidirectional(
+                &c,
+                move || *external_get.borrow(),
+                move |a: &i32| {
+                    sets.borrow_mut().push(*a);
+                    *external_set.borrow_mut() = *a;
+                },
+                |a: &i32, b: &i32| a == b
+            );
+        }
+        assert_eq!(1, *external.borrow());
+        c.send(&5);
+        assert_eq!(5, *external.borrow());
+        *external.borrow_mut() = 7;
+        binding.pull();
+        binding.pull();
+        drop(binding);
+        l.unlisten();
+        assert_eq!(vec![1, 5, 7], *out.borrow());
+        assert_eq!(vec![1, 5], *sets.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
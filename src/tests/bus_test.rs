@@ -0,0 +1,64 @@
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::bus::Bus;
+use sodium::bus::BusError;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn publish_and_subscribe() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let bus = Bus::new(sodium_ctx);
+        let early = bus.subscribe::<i32>("clicks").unwrap();
+        let s1: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let s2: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let p1 = bus.publish("clicks", &s1).unwrap();
+        let p2 = bus.publish("clicks", &s2).unwrap();
+        let late = bus.subscribe::<i32>("clicks").unwrap();
+        assert_eq!(2, bus.publisher_count("clicks"));
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut listeners = Vec::new();
+        for &(tag, ref sa) in &[("early", early), ("late", late)] {
+            let out = out.clone();
+            listeners.push(sa.listen(move |a: &i32| out.borrow_mut().push((tag, *a))));
+        }
+        s1.send(&1);
+        s2.send(&2);
+        drop(p1);
+        s1.send(&3);
+        s2.send(&4);
+        assert_eq!(1, bus.publisher_count("clicks"));
+        drop(p2);
+        for l in listeners {
+            l.unlisten();
+        }
+        let out = out.borrow();
+        for tag in &["early", "late"] {
+            let values: Vec<i32> = out.iter().filter(|&&(t, _)| t == *tag).map(|&(_, a)| a).collect();
+            assert_eq!(vec![1, 2, 4], values);
+        }
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn type_mismatch() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let bus = Bus::new(sodium_ctx);
+        let s: StreamSink<String> = sodium_ctx.new_stream_sink();
+        let _p = bus.publish("name", &s).unwrap();
+        let err = bus.subscribe::<i32>("name").err().unwrap();
+        assert_eq!(
+            BusError::TypeMismatch { topic: String::from("name"), expected: "alloc::string::String", found: "i32" },
+            err
+        );
+        assert_eq!("topic 'name' carries alloc::string::String, not i32", format!("{}", err));
+        assert!(bus.publish("name", s.to_stream().map(|a: &String| a.len() as i32)).is_err());
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -1,10 +1,23 @@
 use sodium::Cell;
 use sodium::CellSink;
 use sodium::IsCell;
+use sodium::IsStream;
 use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::gc::Finalize;
+use sodium::gc::GcDep;
+use sodium::gc::NoGc;
+use sodium::gc::Trace;
+use lift_tuple;
 use tests::assert_memory_freed;
 use std::cell::RefCell;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
 
 #[test]
 fn constant_cell() {
@@ -219,3 +232,505 @@ fn lift_cells_in_switch_c() {
   };
 
 }*/
+
+#[test]
+fn switch_vec() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let a = sodium_ctx.new_cell_sink(1);
+        let b = sodium_ctx.new_cell_sink(2);
+        let c = sodium_ctx.new_cell_sink(3);
+        let cas = sodium_ctx.new_cell_sink(vec![a.to_cell(), b.to_cell()]);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = Cell::switch_vec(&cas).listen(move |xs: &Vec<i32>| out.borrow_mut().push(xs.clone()));
+        }
+        a.send(&10);
+        cas.send(&vec![b.to_cell(), c.to_cell()]);
+        a.send(&11);
+        c.send(&30);
+        sodium_ctx.transaction(|_| {
+            cas.send(&vec![c.to_cell(), a.to_cell()]);
+            a.send(&12);
+        });
+        l.unlisten();
+        assert_eq!(vec![vec![1, 2], vec![10, 2], vec![2, 3], vec![2, 30], vec![30, 12]], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn apply() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let f1: Rc<dyn Fn(&i32)->String> = Rc::new(|a: &i32| format!("1 {}", a));
+        let cf = sodium_ctx.new_cell_sink(NoGc::new(f1));
+        let ca = sodium_ctx.new_cell_sink(5);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = ca.apply(&cf).listen(move |a: &String| out.borrow_mut().push(a.clone()));
+        }
+        let f2: Rc<dyn Fn(&i32)->String> = Rc::new(|a: &i32| format!("12 {}", a));
+        cf.send(&NoGc::new(f2));
+        ca.send(&6);
+        l.unlisten();
+        assert_eq!(vec![String::from("1 5"), String::from("12 5"), String::from("12 6")], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[cfg(feature = "debug-history")]
+#[test]
+fn record_history() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let c = sodium_ctx.new_cell_sink(0);
+        let m = c.map(|a: &i32| *a * 10);
+        let l = m.listen(|_: &i32| {});
+        m.record_history(3);
+        let mut ids = vec![sodium_ctx.current_tx_id()];
+        for i in 1..4 {
+            c.send(&i);
+            ids.push(sodium_ctx.current_tx_id());
+        }
+        l.unlisten();
+        assert_eq!(vec![(ids[1], 10), (ids[2], 20), (ids[3], 30)], m.history());
+        assert_eq!(ids[3].0, ids[2].0 + 1);
+        m.record_history(1);
+        assert_eq!(vec![(ids[3], 30)], m.history());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn sample_consistent() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let a = s.hold(0);
+        let b = s.map(|x: &i32| *x * 10).hold(0);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let sodium_ctx2 = sodium_ctx.clone();
+            let a = a.clone();
+            let b = b.clone();
+            let out = out.clone();
+            l = s.listen(move |_: &i32| {
+                out.borrow_mut().push(sodium_ctx2.sample_consistent(|r| (r.get(&a), r.get(&b))));
+                let sodium_ctx3 = sodium_ctx2.clone();
+                let a = a.clone();
+                let b = b.clone();
+                let out = out.clone();
+                sodium_ctx2.post(move || {
+                    out.borrow_mut().push(sodium_ctx3.sample_consistent(|r| (r.get(&a), r.get(&b))));
+                });
+            });
+        }
+        s.send(&1);
+        out.borrow_mut().push(sodium_ctx.sample_consistent(|r| (r.get(&a), r.get(&b))));
+        l.unlisten();
+        assert_eq!(vec![(0, 0), (1, 10), (1, 10)], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn lift_all() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let cells: Vec<CellSink<i32>> = (1..4).map(|i| sodium_ctx.new_cell_sink(i)).collect();
+        let calls = Rc::new(RefCell::new(0));
+        let sum;
+        {
+            let calls = calls.clone();
+            sum = Cell::lift_all(&cells, move |values: &[i32]| {
+                *calls.borrow_mut() += 1;
+                values.iter().sum::<i32>()
+            });
+        }
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = sum.listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        cells[0].send(&10);
+        sodium_ctx.transaction(|_| {
+            cells[1].send(&20);
+            cells[2].send(&30);
+        });
+        l.unlisten();
+        assert_eq!(vec![6, 15, 60], *out.borrow());
+        assert_eq!(3, *calls.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn bool_logic() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let cells: Vec<CellSink<bool>> = (0..3).map(|_| sodium_ctx.new_cell_sink(false)).collect();
+        let all = Cell::all(&cells);
+        let any = Cell::any(&cells);
+        let a_and_not_b = cells[0].to_cell().and(cells[1].to_cell().not());
+        let a_or_b = cells[0].to_cell().or(&cells[1]);
+        let outs: Vec<Rc<RefCell<Vec<bool>>>> = (0..4).map(|_| Rc::new(RefCell::new(Vec::new()))).collect();
+        let mut listeners = Vec::new();
+        for (c, out) in [all, any, a_and_not_b, a_or_b].iter().zip(outs.iter()) {
+            let out = out.clone();
+            listeners.push(c.listen(move |a: &bool| out.borrow_mut().push(*a)));
+        }
+        cells[0].send(&true);
+        cells[2].send(&true);
+        sodium_ctx.transaction(|_| {
+            cells[1].send(&true);
+            cells[2].send(&true);
+        });
+        cells[0].send(&false);
+        for l in listeners {
+            l.unlisten();
+        }
+        assert_eq!(vec![false, true, false], *outs[0].borrow());
+        assert_eq!(vec![false, true], *outs[1].borrow());
+        assert_eq!(vec![false, true, false], *outs[2].borrow());
+        assert_eq!(vec![false, true], *outs[3].borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn when_true_and_changes_to() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let c = sodium_ctx.new_cell_sink(false);
+        let n = sodium_ctx.new_cell_sink(1);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut listeners = Vec::new();
+        for &(tag, ref s) in &[("t", c.to_cell().when_true()), ("f", c.to_cell().when_false()), ("3", n.to_cell().changes_to(3))] {
+            let out = out.clone();
+            listeners.push(s.listen(move |_: &()| out.borrow_mut().push(tag)));
+        }
+        c.send(&false);
+        c.send(&true);
+        c.send(&true);
+        n.send(&3);
+        n.send(&3);
+        c.send(&false);
+        n.send(&2);
+        n.send(&3);
+        for l in listeners {
+            l.unlisten();
+        }
+        assert_eq!(vec!["t", "3", "f", "3"], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn freeze_and_thaw() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let c = sodium_ctx.new_cell_sink(1);
+        let s: StreamSink<char> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        let l2;
+        let l3;
+        {
+            let out = out.clone();
+            l = c.to_cell().map(|x: &i32| *x * 10).listen(move |x: &i32| out.borrow_mut().push(format!("c{}", x)));
+        }
+        {
+            let out = out.clone();
+            l2 = s.listen(move |x: &char| out.borrow_mut().push(format!("s{}", x)));
+        }
+        {
+            let out = out.clone();
+            l3 = s.listen(move |x: &char| out.borrow_mut().push(format!("gone{}", x)));
+        }
+        sodium_ctx.freeze();
+        c.send(&2);
+        s.send(&'a');
+        c.send(&3);
+        s.send(&'b');
+        l3.unlisten();
+        assert_eq!(vec!["c10"], *out.borrow());
+        assert_eq!(3, c.sample());
+        sodium_ctx.thaw();
+        c.send(&4);
+        l.unlisten();
+        l2.unlisten();
+        assert_eq!(vec!["c10", "c30", "sb", "c40"], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn map_memo() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let c = sodium_ctx.new_cell_sink(1);
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let squared;
+        {
+            let calls = calls.clone();
+            squared = c.to_cell().map_memo(move |x: &i32| { calls.borrow_mut().push(*x); *x * *x }, 2);
+        }
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = squared.listen(move |x: &i32| out.borrow_mut().push(*x));
+        }
+        c.send(&2);
+        c.send(&1);
+        c.send(&2);
+        c.send(&3);
+        c.send(&1);
+        c.send(&3);
+        l.unlisten();
+        assert_eq!(vec![1, 4, 1, 4, 9, 1, 9], *out.borrow());
+        assert_eq!(vec![1, 2, 3, 1], *calls.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn lift_tuple_and_split_tuple() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let name = sodium_ctx.new_cell_sink(String::from("Ada"));
+        let age = sodium_ctx.new_cell_sink(36);
+        let admin = sodium_ctx.new_cell_sink(false);
+        let person: Cell<(String,i32,bool)> = lift_tuple!((&name, &age, &admin));
+        assert_eq!((String::from("Ada"), 36, false), person.sample());
+        let (name2, age2, admin2) = person.split_tuple();
+        sodium_ctx.transaction(|_| {
+            age.send(&37);
+            admin.send(&true);
+        });
+        assert_eq!(String::from("Ada"), name2.sample());
+        assert_eq!(37, age2.sample());
+        assert!(admin2.sample());
+        let pair: Cell<(i32,bool)> = lift_tuple!((&age, &admin,));
+        assert_eq!((37, true), pair.sample());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn mirror_atomic() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let c = sodium_ctx.new_cell_sink(1);
+        let doubled = c.map(|a: &i32| *a * 2);
+        let (mirror, l) = doubled.mirror_atomic();
+        assert_eq!((2, 0), (mirror.get(), mirror.version()));
+        sodium_ctx.transaction(|_| {
+            c.send(&2);
+            c.send(&3);
+        });
+        let mirror2 = mirror.clone();
+        assert_eq!((6, 1), thread::spawn(move || (mirror2.get(), mirror2.version())).join().unwrap());
+        l.unlisten();
+        c.send(&4);
+        assert_eq!(6, mirror.get());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn mirror_atomic_concurrent_reads() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let c = sodium_ctx.new_cell_sink((false, 'a'));
+        let (mirror, l) = c.to_cell().mirror_atomic();
+        let done = Arc::new(AtomicBool::new(false));
+        let reader;
+        {
+            let mirror = mirror.clone();
+            let done = done.clone();
+            reader = thread::spawn(move || {
+                while !done.load(Ordering::Acquire) {
+                    let (flag, letter) = mirror.get();
+                    assert!((!flag && letter == 'a') || (flag && letter == 'b'));
+                }
+            });
+        }
+        for i in 0..10000 {
+            c.send(&if i % 2 == 0 { (true, 'b') } else { (false, 'a') });
+        }
+        done.store(true, Ordering::Release);
+        reader.join().unwrap();
+        l.unlisten();
+        assert_eq!(10000, mirror.version());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn interned_constant() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let node_count = sodium_ctx.node_count();
+        let a = sodium_ctx.interned_constant(42);
+        let b = sodium_ctx.interned_constant(42);
+        let c = sodium_ctx.interned_constant(String::from("42"));
+        assert_eq!(node_count + 2, sodium_ctx.node_count());
+        let d = sodium_ctx.constant(42);
+        assert_eq!(node_count + 3, sodium_ctx.node_count());
+        assert_eq!((42, 42, String::from("42"), 42), (a.sample(), b.sample(), c.sample(), d.sample()));
+        drop(a);
+        drop(b);
+        assert_eq!(node_count + 2, sodium_ctx.node_count());
+        let e = sodium_ctx.interned_constant(42);
+        assert_eq!(node_count + 3, sodium_ctx.node_count());
+        assert_eq!(42, e.sample());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+// Equal by key only, the marker counts how many copies are still alive.
+#[derive(Clone)]
+struct Keyed(i32, Rc<()>);
+
+impl PartialEq for Keyed {
+    fn eq(&self, other: &Keyed) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Keyed {}
+
+impl Hash for Keyed {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl Trace for Keyed {
+    fn trace(&self, _f: &mut dyn FnMut(&GcDep)) {}
+}
+
+impl Finalize for Keyed {}
+
+#[test]
+fn interned_constant_keeps_no_value() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let marker = Rc::new(());
+        let a = sodium_ctx.interned_constant(Keyed(1, marker.clone()));
+        let b = sodium_ctx.interned_constant(Keyed(1, Rc::new(())));
+        assert!(Rc::ptr_eq(&marker, &b.sample().1));
+        drop(a);
+        drop(b);
+        assert_eq!(1, Rc::strong_count(&marker));
+        let c = sodium_ctx.interned_constant(Keyed(2, Rc::new(())));
+        assert_eq!(2, c.sample().0);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn from_fn() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let source = Rc::new(RefCell::new(1));
+        let calls = Rc::new(RefCell::new(0));
+        let invalidate = sodium_ctx.new_stream_sink();
+        let c;
+        {
+            let source = source.clone();
+            let calls = calls.clone();
+            c = Cell::from_fn(move || { *calls.borrow_mut() += 1; *source.borrow() * 10 }, &invalidate.to_stream());
+        }
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = c.listen(move |x: &i32| out.borrow_mut().push(*x));
+        }
+        *source.borrow_mut() = 2;
+        assert_eq!(10, c.sample());
+        invalidate.send(&());
+        *source.borrow_mut() = 3;
+        *source.borrow_mut() = 4;
+        invalidate.send(&());
+        l.unlisten();
+        assert_eq!(vec![10, 20, 40], *out.borrow());
+        assert_eq!(3, *calls.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn resource() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let theme = sodium_ctx.resource::<&'static str>();
+        assert_eq!(None, theme.sample());
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = theme.listen(move |a: &Option<&'static str>| out.borrow_mut().push(*a));
+        }
+        sodium_ctx.set_resource("dark");
+        sodium_ctx.set_resource(42);
+        assert_eq!(Some("dark"), sodium_ctx.resource::<&'static str>().sample());
+        assert_eq!(Some(42), sodium_ctx.resource::<i32>().sample());
+        l.unlisten();
+        drop(theme);
+        sodium_ctx.set_resource("light");
+        assert_eq!(Some("light"), sodium_ctx.resource::<&'static str>().sample());
+        assert_eq!(vec![None, Some("dark")], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn apply_plain_closures() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let f1: fn(&i32)->i32 = |a: &i32| *a + 1;
+        let cf = sodium_ctx.new_cell_sink(NoGc::new(f1));
+        let ca = sodium_ctx.new_cell_sink(5);
+        let offset = 100;
+        let cg = sodium_ctx.new_cell(NoGc::new(move |a: &i32| *a + offset));
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = ca.apply(&cf).lift2(&ca.apply(&cg), |a: &i32, b: &i32| (*a, *b)).listen(move |a: &(i32,i32)| out.borrow_mut().push(*a));
+        }
+        let f2: fn(&i32)->i32 = |a: &i32| *a * 2;
+        cf.send(&NoGc::new(f2));
+        ca.send(&6);
+        l.unlisten();
+        assert_eq!(vec![(6, 105), (10, 105), (12, 106)], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
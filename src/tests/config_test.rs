@@ -0,0 +1,56 @@
+use sodium::SodiumCtx;
+use sodium::config::ConfigError;
+use sodium::config::cell_from_file;
+use sodium::os::OsEvents;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::process;
+use std::rc::Rc;
+
+#[test]
+fn cell_from_file_reloads() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let path = env::temp_dir().join(format!("sodium_config_test_{}", process::id()));
+        fs::write(&path, "1").unwrap();
+        let mut os_events = OsEvents::new(sodium_ctx);
+        let (config, errors) = cell_from_file(&mut os_events, &path, |s: &str| s.trim().parse::<i32>().map_err(|err| err.to_string())).unwrap();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let out_errors = Rc::new(RefCell::new(Vec::new()));
+        let l1;
+        let l2;
+        {
+            let out = out.clone();
+            l1 = config.listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        {
+            let out_errors = out_errors.clone();
+            l2 = errors.listen(move |err: &ConfigError| out_errors.borrow_mut().push(err.clone()));
+        }
+        fs::write(&path, "22").unwrap();
+        os_events.poll();
+        fs::write(&path, "oops").unwrap();
+        os_events.poll();
+        fs::write(&path, "333").unwrap();
+        os_events.poll();
+        fs::remove_file(&path).unwrap();
+        os_events.poll();
+        l1.unlisten();
+        l2.unlisten();
+        assert_eq!(vec![1, 22, 333], *out.borrow());
+        let out_errors = out_errors.borrow();
+        assert_eq!(2, out_errors.len());
+        match out_errors[0] {
+            ConfigError::Parse(ref err_path, _) => assert_eq!(path, *err_path),
+            ref err => panic!("unexpected error {:?}", err)
+        }
+        match out_errors[1] {
+            ConfigError::Io(ref err_path, _) => assert_eq!(path, *err_path),
+            ref err => panic!("unexpected error {:?}", err)
+        }
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,60 @@
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::delta::Diff;
+use sodium::delta::MapPatch;
+use sodium::delta::VecPatch;
+use sodium::delta::cell_to_deltas;
+use sodium::delta::deltas_to_cell;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+#[test]
+fn vec_diff_and_apply() {
+    let old = vec![1, 2, 3, 4];
+    let new = vec![1, 5, 6, 4];
+    let patch = old.diff(&new).unwrap();
+    assert_eq!(VecPatch { start: 1, removed: 2, inserted: vec![5, 6] }, patch);
+    assert_eq!(new, old.apply(&patch));
+    assert_eq!(None, old.diff(&old.clone()));
+    assert_eq!(vec![1, 2, 3], vec![1, 3].apply(&vec![1, 3].diff(&vec![1, 2, 3]).unwrap()));
+    assert_eq!(vec![2, 2], vec![2, 2, 2].apply(&vec![2, 2, 2].diff(&vec![2, 2]).unwrap()));
+}
+
+#[test]
+fn round_trip() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let patches: StreamSink<MapPatch<&'static str,i32>> = sodium_ctx.new_stream_sink();
+        let map = deltas_to_cell(BTreeMap::new(), &patches);
+        let values = sodium_ctx.new_cell_sink(BTreeMap::new());
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = cell_to_deltas(&values).listen(move |patch: &MapPatch<&'static str,i32>| out.borrow_mut().push(patch.clone()));
+        }
+        patches.send(&MapPatch { inserted: vec![("a", 1), ("b", 2)], removed: vec![] });
+        patches.send(&MapPatch { inserted: vec![("a", 3)], removed: vec!["b"] });
+        let mut expected = BTreeMap::new();
+        expected.insert("a", 3);
+        assert_eq!(expected, map.sample());
+        values.send(&map.sample());
+        values.send(&map.sample());
+        let mut next = map.sample();
+        next.insert("c", 4);
+        next.remove("a");
+        values.send(&next);
+        l.unlisten();
+        assert_eq!(
+            vec![
+                MapPatch { inserted: vec![("a", 3)], removed: vec![] },
+                MapPatch { inserted: vec![("c", 4)], removed: vec!["a"] }
+            ],
+            *out.borrow()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,95 @@
+use sodium::IsStream;
+use sodium::SodiumCtx;
+use sodium::dsp::BlockDriver;
+use sodium::dsp::BlockStream;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn map_block_and_mix() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s1 = sodium_ctx.new_stream_sink();
+        let s2 = sodium_ctx.new_stream_sink();
+        let b1: BlockStream<f32, 4> = BlockStream::new(&s1);
+        let b2: BlockStream<f32, 4> = BlockStream::new(&s2);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = b1
+                .map_samples(|x: f32| x * 2.0)
+                .mix(&b2)
+                .listen(move |block: &[f32; 4]| out.borrow_mut().push(*block));
+        }
+        s1.send(&[1.0, 2.0, 3.0, 4.0]);
+        sodium_ctx.transaction(|_| {
+            s1.send(&[1.0, 1.0, 1.0, 1.0]);
+            s2.send(&[0.5, 0.5, 0.5, 0.5]);
+        });
+        s2.send(&[0.25, 0.25, 0.25, 0.25]);
+        l.unlisten();
+        assert_eq!(
+            vec![
+                [2.0, 4.0, 6.0, 8.0],
+                [2.5, 2.5, 2.5, 2.5],
+                [0.25, 0.25, 0.25, 0.25]
+            ],
+            *out.borrow()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn map_samples_empty_block() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let b: BlockStream<f32, 0> = BlockStream::new(&s);
+        let out = Rc::new(RefCell::new(0));
+        let l;
+        {
+            let out = out.clone();
+            l = b
+                .map_samples(|x: f32| x * 2.0)
+                .listen(move |_: &[f32; 0]| *out.borrow_mut() += 1);
+        }
+        s.send(&[]);
+        l.unlisten();
+        assert_eq!(1, *out.borrow());
+        let driver: BlockDriver<0> = BlockDriver::new(sodium_ctx, |tick| {
+            BlockStream::new(tick.map(|_: &()| [])).map_samples(|x: f32| x * 2.0)
+        });
+        let mut out = [1.0f32; 3];
+        driver.render(&mut out);
+        assert_eq!([0.0, 0.0, 0.0], out);
+        driver.render(&mut []);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn block_driver_render() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let gain = sodium_ctx.new_cell_sink(0.5f32);
+        {
+            let driver: BlockDriver<2> = BlockDriver::new(sodium_ctx, |tick| {
+                let ticks: BlockStream<(), 2> = BlockStream::new(tick.map(|_: &()| [(); 2]));
+                ticks.hold_to_block(&gain)
+            });
+            let mut out = [1.0f32; 5];
+            driver.render(&mut out);
+            assert_eq!([0.5, 0.5, 0.5, 0.5, 0.5], out);
+            gain.send(&0.25);
+            driver.render(&mut out[..2]);
+            assert_eq!([0.25, 0.25, 0.5, 0.5, 0.5], out);
+        }
+    }
+    assert_memory_freed(sodium_ctx);
+}
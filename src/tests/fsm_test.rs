@@ -0,0 +1,52 @@
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::fsm::StateMachine;
+use sodium::fsm::Transition;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn door() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let open: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let close: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let lock: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let (open_s, close_s, lock_s) = (open.to_stream(), close.to_stream(), lock.to_stream());
+        let door = StateMachine::new(
+            "closed",
+            &[
+                ("closed", &open_s, |_: &i32, _: &&'static str| "open"),
+                ("open", &close_s, |_: &i32, _: &&'static str| "closed"),
+                ("closed", &lock_s, |_: &i32, _: &&'static str| "locked"),
+                // Only the right code unlocks it, anything else leaves it locked.
+                ("locked", &open_s, |code: &i32, state: &&'static str| if *code == 1234 { "closed" } else { *state })
+            ]
+        );
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = door.transitions().listen(move |t: &Transition<&'static str>| out.borrow_mut().push((t.from, t.to)));
+        }
+        open.send(&0);
+        lock.send(&0);
+        close.send(&0);
+        lock.send(&0);
+        open.send(&1);
+        open.send(&1234);
+        sodium_ctx.transaction(|_| {
+            open.send(&0);
+            lock.send(&0);
+        });
+        l.unlisten();
+        assert_eq!("open", door.state().sample());
+        assert_eq!(
+            vec![("closed", "open"), ("open", "closed"), ("closed", "locked"), ("locked", "locked"), ("locked", "closed"), ("closed", "open")],
+            *out.borrow()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,104 @@
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::gesture::ButtonEvent;
+use sodium::gesture::Drag;
+use sodium::gesture::KeyEvent;
+use sodium::gesture::Point;
+use sodium::gesture::double_click;
+use sodium::gesture::drag_threshold;
+use sodium::gesture::key_chord;
+use sodium::time::ManualClock;
+use sodium::time::TimerSystem;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[test]
+fn double_click_within_window() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let clicks: StreamSink<u32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = double_click(&clicks, &timer, Duration::from_millis(300)).listen(move |a: &u32| out.borrow_mut().push(*a));
+        }
+        let click_at = |ms: u64, a: u32| {
+            clock.set(Duration::from_millis(ms));
+            timer.poll();
+            clicks.send(&a);
+        };
+        click_at(0, 1);
+        click_at(200, 2);
+        click_at(300, 3);
+        click_at(1000, 4);
+        click_at(1200, 5);
+        l.unlisten();
+        assert_eq!(vec![2, 5], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn drag_past_threshold() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let moves: StreamSink<Point> = sodium_ctx.new_stream_sink();
+        let presses: StreamSink<ButtonEvent> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = drag_threshold(&moves, &presses, 5.0).listen(move |drag: &Drag| out.borrow_mut().push(drag.current.x));
+        }
+        moves.send(&Point::new(20.0, 0.0));
+        presses.send(&ButtonEvent::Down(Point::new(0.0, 0.0)));
+        moves.send(&Point::new(3.0, 0.0));
+        moves.send(&Point::new(6.0, 0.0));
+        moves.send(&Point::new(2.0, 0.0));
+        presses.send(&ButtonEvent::Up(Point::new(2.0, 0.0)));
+        moves.send(&Point::new(30.0, 0.0));
+        sodium_ctx.transaction(|_| {
+            presses.send(&ButtonEvent::Down(Point::new(30.0, 0.0)));
+            moves.send(&Point::new(31.0, 0.0));
+        });
+        moves.send(&Point::new(40.0, 0.0));
+        l.unlisten();
+        assert_eq!(vec![6.0, 2.0, 40.0], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn key_chord_fires_on_completion() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let keys: StreamSink<KeyEvent<char>> = sodium_ctx.new_stream_sink();
+        let count = Rc::new(RefCell::new(0));
+        let l;
+        {
+            let count = count.clone();
+            l = key_chord(&keys, &['c', 'k']).listen(move |_: &()| *count.borrow_mut() += 1);
+        }
+        keys.send(&KeyEvent::Down('k'));
+        keys.send(&KeyEvent::Down('c'));
+        keys.send(&KeyEvent::Down('x'));
+        keys.send(&KeyEvent::Down('c'));
+        assert_eq!(1, *count.borrow());
+        keys.send(&KeyEvent::Up('c'));
+        keys.send(&KeyEvent::Down('c'));
+        keys.send(&KeyEvent::Up('k'));
+        keys.send(&KeyEvent::Up('c'));
+        keys.send(&KeyEvent::Down('c'));
+        l.unlisten();
+        assert_eq!(2, *count.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,70 @@
+use sodium::GraphBuilder;
+use sodium::IsStream;
+use sodium::SodiumCtx;
+use sodium::Stream;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn graph_builder_steps() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let streams: Rc<RefCell<Vec<Stream<i32>>>> = Rc::new(RefCell::new(Vec::new()));
+        let mut builder = GraphBuilder::new(sodium_ctx);
+        let progress = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let progress = progress.clone();
+            l = builder.progress().listen(move |p: &f32| progress.borrow_mut().push(*p));
+        }
+        for i in 0..5 {
+            let s = s.clone();
+            let streams = streams.clone();
+            builder.add(move |_sodium_ctx: &SodiumCtx| {
+                streams.borrow_mut().push(s.map(move |a: &i32| *a + i));
+            });
+        }
+        assert_eq!(0.0, builder.fraction());
+        assert!(!builder.step(2));
+        assert_eq!(2, streams.borrow().len());
+        assert!(!builder.step(2));
+        builder.abort();
+        assert!(builder.is_done());
+        assert_eq!(4, streams.borrow().len());
+        l.unlisten();
+        assert_eq!(vec![1.0, 0.4, 0.8, 1.0], *progress.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn graph_builder_add_from_listener() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let builder = Rc::new(RefCell::new(GraphBuilder::new(sodium_ctx)));
+        let added = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let builder = builder.clone();
+            let added = added.clone();
+            l = s.listen(move |a: &i32| {
+                let a = *a;
+                let added = added.clone();
+                builder.borrow_mut().add(move |_sodium_ctx: &SodiumCtx| added.borrow_mut().push(a));
+            });
+        }
+        s.send(&1);
+        s.send(&2);
+        l.unlisten();
+        assert_eq!(0.0, builder.borrow().fraction());
+        assert!(builder.borrow_mut().step(5));
+        assert_eq!(vec![1, 2], *added.borrow());
+        assert_eq!(1.0, builder.borrow().progress().sample());
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,66 @@
+use sodium::SodiumCtx;
+use sodium::hot::Functions;
+use sodium::hot::GraphSpec;
+use sodium::hot::HotGraph;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn functions() -> Functions {
+    Functions::new()
+        .unary("double", |a| a * 2.0)
+        .unary("triple", |a| a * 3.0)
+        .binary("add", |a, b| a + b)
+}
+
+#[test]
+fn patch_keeps_unchanged_state() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let spec = GraphSpec::new()
+            .input("clicks")
+            .accum("count", 0.0, "add", "clicks")
+            .map("shown", "double", "count");
+        let mut graph = HotGraph::new(sodium_ctx, functions(), spec).unwrap();
+        let clicks = graph.input("clicks").unwrap();
+        let shown = graph.cell("shown").unwrap();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = shown.listen(move |a: &f64| out.borrow_mut().push(*a));
+        }
+        clicks.send(&1.0);
+        clicks.send(&1.0);
+        let spec2 = GraphSpec::parse(&graph.spec().to_string().replace("double", "triple")).unwrap();
+        let diff = graph.patch(spec2).unwrap();
+        assert_eq!(vec![String::from("shown")], diff.changed);
+        assert_eq!(vec![String::from("clicks"), String::from("count")], diff.unchanged);
+        clicks.send(&1.0);
+        let spec3 = GraphSpec::parse("clicks = input\ncount = accum 10 add clicks\nshown = map triple count").unwrap();
+        graph.patch(spec3).unwrap();
+        clicks.send(&1.0);
+        assert!(graph.patch(GraphSpec::new().input("clicks")).is_err());
+        clicks.send(&1.0);
+        l.unlisten();
+        assert_eq!(vec![0.0, 2.0, 4.0, 6.0, 9.0, 30.0, 33.0, 36.0], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn spec_errors() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        assert!(GraphSpec::parse("count = accum zero add clicks").is_err());
+        let undefined = GraphSpec::new().map("shown", "double", "count");
+        assert!(HotGraph::new(sodium_ctx, functions(), undefined).is_err());
+        let unknown = GraphSpec::new().input("clicks").map("shown", "halve", "clicks");
+        assert!(HotGraph::new(sodium_ctx, functions(), unknown).is_err());
+        let kind = GraphSpec::new().constant("one", 1.0).hold("held", 0.0, "one");
+        assert!(HotGraph::new(sodium_ctx, functions(), kind).is_err());
+    }
+    assert_memory_freed(sodium_ctx);
+}
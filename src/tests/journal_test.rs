@@ -0,0 +1,54 @@
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::journal::FsyncPolicy;
+use sodium::journal::Journal;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process;
+use std::rc::Rc;
+
+#[test]
+fn journal_replay_after_torn_write() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let path = env::temp_dir().join(format!("sodium_journal_test_{}", process::id()));
+        let _ = fs::remove_file(&path);
+        {
+            let journal = Journal::open(&path).unwrap();
+            let sink = journal.sink(sodium_ctx, FsyncPolicy::Every(2)).unwrap();
+            let out = Rc::new(RefCell::new(Vec::new()));
+            let l;
+            {
+                let out = out.clone();
+                l = sink.to_stream().listen(move |a: &String| out.borrow_mut().push(a.clone()));
+            }
+            sink.send(&String::from("a")).unwrap();
+            sink.send(&String::from("bc")).unwrap();
+            sink.sync().unwrap();
+            l.unlisten();
+            assert_eq!(vec![String::from("a"), String::from("bc")], *out.borrow());
+        }
+        // What a crash part way through appending a record leaves behind.
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[5, 0, 0, 0, 1, 2]).unwrap();
+        let journal = Journal::open(&path).unwrap();
+        let sink = journal.sink(sodium_ctx, FsyncPolicy::Always).unwrap();
+        sink.send(&String::from("d")).unwrap();
+        let replayed: StreamSink<String> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = replayed.to_stream().listen(move |a: &String| out.borrow_mut().push(a.clone()));
+        }
+        assert_eq!(3, journal.replay_into(&replayed).unwrap());
+        l.unlisten();
+        assert_eq!(vec![String::from("a"), String::from("bc"), String::from("d")], *out.borrow());
+        fs::remove_file(&path).unwrap();
+    }
+    assert_memory_freed(sodium_ctx);
+}
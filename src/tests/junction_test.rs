@@ -0,0 +1,106 @@
+use sodium::CellJunction;
+use sodium::SodiumCtx;
+use sodium::StreamJunction;
+use sodium::StreamSink;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn stream_junction() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let junction = StreamJunction::new(sodium_ctx, |a: &i32, b: &i32| *a + *b);
+        let s1: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let s2: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = junction.stream().listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        s1.send(&1);
+        let h1 = junction.plug(&s1);
+        s1.send(&2);
+        let h2 = junction.plug(&s2);
+        sodium_ctx.transaction(|_| {
+            s1.send(&10);
+            s2.send(&20);
+        });
+        assert!(junction.unplug(h1));
+        assert!(!junction.unplug(h1));
+        s1.send(&3);
+        s2.send(&4);
+        assert!(junction.unplug(h2));
+        s2.send(&5);
+        assert_eq!(0, junction.input_count());
+        l.unlisten();
+        assert_eq!(vec![2, 30, 4], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn cell_junction() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let junction = CellJunction::new(sodium_ctx);
+        let c1 = sodium_ctx.new_cell_sink(1);
+        let c2 = sodium_ctx.new_cell_sink(2);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = junction.cell().listen(move |a: &Vec<i32>| out.borrow_mut().push(a.clone()));
+        }
+        let h1 = junction.plug(&c1);
+        junction.plug(&c2);
+        c1.send(&3);
+        junction.unplug(h1);
+        c1.send(&4);
+        l.unlisten();
+        assert_eq!(vec![vec![], vec![1], vec![1, 2], vec![3, 2], vec![2]], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn plug_from_listener() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let junction = Rc::new(StreamJunction::new(sodium_ctx, |a: &i32, b: &i32| *a + *b));
+        let cells = Rc::new(CellJunction::new(sodium_ctx));
+        let plugins: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let c = sodium_ctx.new_cell_sink(7);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l1;
+        let l2;
+        {
+            let out = out.clone();
+            l1 = junction.stream().listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        {
+            let junction = junction.clone();
+            let cells = cells.clone();
+            let s = s.clone();
+            let c = c.clone();
+            l2 = plugins.to_stream().listen(move |_: &i32| {
+                junction.plug(&s);
+                cells.plug(&c);
+            });
+        }
+        s.send(&1);
+        plugins.send(&0);
+        assert_eq!(1, junction.input_count());
+        s.send(&2);
+        assert_eq!(vec![7], cells.cell().sample());
+        l2.unlisten();
+        l1.unlisten();
+        assert_eq!(vec![2], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,73 @@
+use sodium::Backpressure;
+use sodium::IsStream;
+use sodium::Mailbox;
+use sodium::SodiumCtx;
+use tests::assert_memory_freed;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn listen_on_drop_oldest() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let mailbox = Mailbox::new(Backpressure::DropOldest(2));
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen_on(&mailbox, move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        for i in 1..6 {
+            s.send(&i);
+        }
+        l.unlisten();
+        let consumer;
+        {
+            let mailbox = mailbox.clone();
+            consumer = thread::spawn(move || mailbox.run_pending());
+        }
+        assert_eq!(2, consumer.join().unwrap());
+        assert_eq!(vec![4, 5], *out.lock().unwrap());
+        assert_eq!(3, mailbox.dropped());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn listen_on_block() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let mailbox = Mailbox::new(Backpressure::Block(1));
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let consumer;
+        {
+            let mailbox = mailbox.clone();
+            consumer = thread::spawn(move || {
+                let mut count = 0;
+                while count < 10 && mailbox.run_one_timeout(Duration::from_secs(5)) {
+                    count = count + 1;
+                }
+                count
+            });
+        }
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen_on(&mailbox, move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        for i in 0..10 {
+            s.send(&i);
+        }
+        l.unlisten();
+        assert_eq!(10, consumer.join().unwrap());
+        assert_eq!((0..10).collect::<Vec<i32>>(), *out.lock().unwrap());
+        assert_eq!(0, mailbox.dropped());
+    }
+    assert_memory_freed(sodium_ctx);
+}
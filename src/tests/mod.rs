@@ -1,7 +1,32 @@
 pub use self::memory_check::assert_memory_freed;
 
+mod async_bridge_test;
+mod binding_test;
+mod bus_test;
 mod cell_test;
 mod cell_loop_test;
+#[cfg(feature = "os")]
+mod config_test;
+mod delta_test;
+#[cfg(feature = "dsp")]
+mod dsp_test;
+mod fsm_test;
 mod gc_test;
+mod gesture_test;
+mod graph_builder_test;
+mod hot_test;
+#[cfg(feature = "os")]
+mod journal_test;
+mod junction_test;
+mod mailbox_test;
 mod memory_check;
+mod node_test;
+#[cfg(feature = "os")]
+mod os_test;
+#[cfg(feature = "threads")]
+mod runtime_test;
+mod sources_test;
 mod stream_test;
+mod time_test;
+mod track_test;
+mod undo_test;
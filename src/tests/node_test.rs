@@ -0,0 +1,45 @@
+use sodium::IsStream;
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn custom_node() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let a: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let b = sodium_ctx.new_cell_sink(100);
+        let cleaned_up = Rc::new(RefCell::new(false));
+        let node;
+        {
+            let a2 = a.clone();
+            let b2 = b.clone();
+            let cleaned_up = cleaned_up.clone();
+            node = sodium_ctx.new_node_builder("adder")
+                .depends_on(&a)
+                .depends_on_cell(&b)
+                .on_update(move |inputs| inputs.value(&a2).map(|a| a + inputs.sample(&b2)))
+                .on_cleanup(move || *cleaned_up.borrow_mut() = true)
+                .build();
+        }
+        assert!(node.rank() > a.to_stream().impl_._node().rank());
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = node.listen(move |x: &i32| out.borrow_mut().push(*x));
+        }
+        a.send(&1);
+        b.send(&200);
+        a.send(&2);
+        node.fire(&-1);
+        l.unlisten();
+        assert_eq!(vec![101, 202, -1], *out.borrow());
+        drop(node);
+        assert!(*cleaned_up.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,76 @@
+use sodium::SodiumCtx;
+use sodium::os::FsEvent;
+use sodium::os::OsEvents;
+use sodium::os::Signal;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::process;
+use std::rc::Rc;
+
+#[test]
+fn fs_watch() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let path = env::temp_dir().join(format!("sodium_os_test_{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut os_events = OsEvents::new(sodium_ctx);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = os_events.fs_watch(&path).listen(move |event: &FsEvent| out.borrow_mut().push(event.clone()));
+        }
+        os_events.poll();
+        fs::write(&path, "a").unwrap();
+        os_events.poll();
+        fs::write(&path, "abc").unwrap();
+        os_events.poll();
+        os_events.poll();
+        fs::remove_file(&path).unwrap();
+        os_events.poll();
+        l.unlisten();
+        assert_eq!(
+            vec![FsEvent::Created(path.clone()), FsEvent::Modified(path.clone()), FsEvent::Removed(path.clone())],
+            *out.borrow()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn signals() {
+    extern "C" {
+        fn raise(signum: i32) -> i32;
+    }
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let mut os_events = OsEvents::new(sodium_ctx);
+        let mut os_events2 = OsEvents::new(sodium_ctx);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let out2 = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        let l2;
+        {
+            let out = out.clone();
+            l = os_events.signals().unwrap().listen(move |signal: &Signal| out.borrow_mut().push(*signal));
+        }
+        {
+            let out2 = out2.clone();
+            l2 = os_events2.signals().unwrap().listen(move |signal: &Signal| out2.borrow_mut().push(*signal));
+        }
+        unsafe { raise(15); }
+        os_events.poll();
+        os_events.poll();
+        os_events2.poll();
+        l.unlisten();
+        l2.unlisten();
+        assert_eq!(vec![Signal::Terminate], *out.borrow());
+        assert_eq!(vec![Signal::Terminate], *out2.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,61 @@
+use sodium::IsStream;
+use sodium::RuntimeStopped;
+use sodium::SodiumRuntime;
+use sodium::StreamSink;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+#[test]
+fn remote_sink_and_cell() {
+    let runtime = SodiumRuntime::new();
+    let (sink, total) = runtime.run(|runtime_ctx| {
+        let s: StreamSink<i32> = runtime_ctx.sodium_ctx().new_stream_sink();
+        let total = s.accum(0, |a: &i32, total: &i32| *a + *total);
+        (runtime_ctx.remote_sink(&s), runtime_ctx.remote_cell(&total))
+    }).unwrap();
+    let producers: Vec<thread::JoinHandle<()>> = (0..4).map(|_| {
+        let sink = sink.clone();
+        thread::spawn(move || {
+            for i in 1..11 {
+                sink.send(i).unwrap();
+            }
+        })
+    }).collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    assert_eq!(Ok(220), total.sample());
+    assert_eq!(Ok(()), runtime.shutdown());
+    assert_eq!(Err(RuntimeStopped), sink.send(1));
+    assert_eq!(Err(RuntimeStopped), total.sample());
+}
+
+#[test]
+fn shutdown_finishes_queued_work() {
+    let runtime = SodiumRuntime::new();
+    let out = Arc::new(Mutex::new(Vec::new()));
+    let sink;
+    {
+        let out = out.clone();
+        sink = runtime.run(move |runtime_ctx| {
+            let s: StreamSink<i32> = runtime_ctx.sodium_ctx().new_stream_sink();
+            s.listen(move |a: &i32| out.lock().unwrap().push(*a));
+            runtime_ctx.remote_sink(&s)
+        }).unwrap();
+    }
+    for i in 1..4 {
+        sink.send(i).unwrap();
+    }
+    drop(runtime);
+    assert_eq!(vec![1, 2, 3], *out.lock().unwrap());
+    assert_eq!(Err(RuntimeStopped), sink.send(4));
+}
+
+#[test]
+fn panicking_job_stops_the_runtime() {
+    let runtime = SodiumRuntime::new();
+    runtime.post(|_| panic!("job failed")).unwrap();
+    assert_eq!(Err(RuntimeStopped), runtime.run(|_| ()));
+    assert_eq!(Err(RuntimeStopped), runtime.shutdown());
+}
@@ -0,0 +1,69 @@
+use sodium::SodiumCtx;
+use sodium::sources::interval_poll;
+#[cfg(feature = "os")]
+use sodium::sources::cpu_usage;
+#[cfg(feature = "os")]
+use sodium::sources::process_memory;
+use sodium::time::ManualClock;
+use sodium::time::TimerSystem;
+use tests::assert_memory_freed;
+use std::cell::Cell as StdCell;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[test]
+fn interval_poll_skips_missed_periods() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let calls = Rc::new(StdCell::new(0));
+        let polled;
+        {
+            let calls = calls.clone();
+            polled = interval_poll(Duration::from_millis(100), move || { calls.set(calls.get() + 1); calls.get() }, &timer);
+        }
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = polled.listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        clock.advance(Duration::from_millis(50));
+        timer.poll();
+        clock.advance(Duration::from_millis(50));
+        timer.poll();
+        // Three periods go by before the next poll, f is only called once for them.
+        clock.advance(Duration::from_millis(350));
+        timer.poll();
+        clock.advance(Duration::from_millis(100));
+        timer.poll();
+        l.unlisten();
+        assert_eq!(vec![1, 2, 3, 4], *out.borrow());
+        assert_eq!(Some(Duration::from_millis(600)), timer.next_alarm());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[cfg(feature = "os")]
+#[test]
+fn process_metrics() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let memory = process_memory(Duration::from_millis(100), &timer);
+        let cpu = cpu_usage(Duration::from_millis(100), &timer);
+        if cfg!(target_os = "linux") {
+            assert!(memory.sample().unwrap() > 0);
+            assert_eq!(Some(0.0), cpu.sample());
+            clock.advance(Duration::from_millis(100));
+            timer.poll();
+            assert!(cpu.sample().unwrap() >= 0.0);
+        }
+    }
+    assert_memory_freed(sodium_ctx);
+}
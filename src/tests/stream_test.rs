@@ -1,21 +1,47 @@
 use sodium::Cell;
 use sodium::CellLoop;
 use sodium::CellSink;
+use sodium::EdgeError;
 use sodium::IsCell;
 use sodium::IsStream;
 use sodium::IsStreamOption;
 use sodium::Lambda;
+use sodium::ListenerInfo;
 use sodium::Operational;
+use sodium::OverflowPolicy;
+use sodium::PanicPolicy;
 use sodium::SodiumCtx;
+use sodium::SodiumCtxBuildError;
+use sodium::SodiumError;
 use sodium::Stream;
 use sodium::StreamLoop;
 use sodium::StreamSink;
+use sodium::Termination;
+use sodium::TxId;
 use sodium::gc::Finalize;
 use sodium::gc::GcDep;
+use sodium::gc::GcPolicy;
+use sodium::gc::OomEvent;
 use sodium::gc::Trace;
+use sodium::test::FuzzConfig;
+use sodium::test::FuzzRng;
+use sodium::test::GraphSnapshot;
+use sodium::test::InputModel;
+use sodium::test::fuzz_harness;
+use sodium::test::Transcript;
 use tests::assert_memory_freed;
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::panic;
+use std::panic::AssertUnwindSafe;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
 
 #[test]
 fn gc_crash_test() {
@@ -218,6 +244,41 @@ fn filter() {
     assert_memory_freed(sodium_ctx);
 }
 
+fn is_small(a: &u32) -> bool {
+    *a < 10
+}
+
+fn describe(a: &u32) -> String {
+    format!("#{}", a)
+}
+
+#[test]
+fn map_fn_and_filter_fn() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s
+                .filter_fn(is_small)
+                .map_fn(describe)
+                .listen(
+                    move |a: &String|
+                        out.borrow_mut().push(a.clone())
+                );
+        }
+        s.send(&2);
+        s.send(&16);
+        s.send(&9);
+        assert_eq!(vec![String::from("#2"), String::from("#9")], *out.borrow());
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
 #[test]
 fn filter_option() {
     let mut sodium_ctx = SodiumCtx::new();
@@ -460,6 +521,66 @@ fn defer() {
     assert_memory_freed(sodium_ctx);
 }
 
+#[test]
+fn all_in_transaction() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<Vec<i32>> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = Operational::split(&s)
+                .map(|a: &i32| *a * 10)
+                .all_in_transaction()
+                .listen(move |a: &Vec<i32>| out.borrow_mut().push(a.clone()));
+        }
+        s.send(&vec![1, 2, 3]);
+        s.send(&vec![]);
+        s.send(&vec![4]);
+        l.unlisten();
+        assert_eq!(vec![vec![10, 20, 30], vec![40]], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn monitor() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s.to_stream().monitor("clicks").listen(move |a: &i32| {
+                thread::sleep(Duration::from_millis(2));
+                out.borrow_mut().push(*a);
+            });
+        }
+        s.send(&1);
+        s.send(&2);
+        s.send(&3);
+        l.unlisten();
+        assert_eq!(vec![1, 2, 3], *out.borrow());
+        let metrics = sodium_ctx.metrics();
+        assert_eq!(1, metrics.len());
+        let (ref name, ref clicks) = metrics[0];
+        assert_eq!("clicks", name);
+        assert_eq!(3, clicks.events);
+        assert_eq!(2, clicks.inter_event.count());
+        assert_eq!(3, clicks.processing.count());
+        assert!(clicks.processing.mean().unwrap() >= Duration::from_millis(2));
+        assert!(clicks.inter_event.percentile(0.5).unwrap() >= Duration::from_millis(2));
+        assert!(clicks.processing.percentile(1.0).unwrap() <= clicks.processing.max().unwrap());
+        sodium_ctx.reset_metrics();
+        assert!(sodium_ctx.metrics().is_empty());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
 #[test]
 fn hold() {
     let mut sodium_ctx = SodiumCtx::new();
@@ -513,6 +634,56 @@ fn hold_is_delayed() {
     assert_memory_freed(sodium_ctx);
 }
 
+#[test]
+fn snapshot_samples_when_fired() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let c = sodium_ctx.new_cell_sink(1);
+        // Nothing forces the snapshot until after c has moved on.
+        let h = s.snapshot2(&c, |a: &i32, b: &i32| *a * 10 + *b).hold(0);
+        s.send(&5);
+        c.send(&2);
+        assert_eq!(51, h.sample());
+        sodium_ctx.transaction(|_| {
+            s.send(&6);
+            c.send(&3);
+        });
+        assert_eq!(62, h.sample());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn node_order_breaks_ties_on_id() {
+    use std::collections::BinaryHeap;
+    use std::collections::HashSet;
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let a: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let b: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let a = a.to_stream().impl_._node().clone();
+        let b = b.to_stream().impl_._node().clone();
+        assert_eq!(a.rank(), b.rank());
+        assert!(a.id() < b.id());
+        // Equal rank is not equal nodes, or to_be_updated would drop one of them.
+        assert!(a != b);
+        let mut set = HashSet::new();
+        set.insert(a.clone());
+        set.insert(b.clone());
+        assert_eq!(2, set.len());
+        // Among equal ranks the older node is updated first.
+        let mut heap = BinaryHeap::new();
+        heap.push(b.clone());
+        heap.push(a.clone());
+        assert_eq!(heap.pop().map(|node| node.id()), Some(a.id()));
+        assert_eq!(heap.pop().map(|node| node.id()), Some(b.id()));
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
 #[test]
 fn switch_c() {
     let mut sodium_ctx = SodiumCtx::new();
@@ -752,3 +923,1449 @@ fn loop_cell() {
     }
     assert_memory_freed(sodium_ctx);
 }
+
+#[test]
+fn listener_panic() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l1 = s.listen(|a: &i32| {
+            if *a == 2 {
+                panic!("bad value {}", a);
+            }
+        });
+        let l2;
+        {
+            let out = out.clone();
+            l2 = s.map(|a: &i32| *a * 10).listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        s.send(&1);
+        s.send(&2);
+        s.send(&3);
+        l1.unlisten();
+        l2.unlisten();
+        assert_eq!(vec![10, 20, 30], *out.borrow());
+        assert_eq!(vec![String::from("bad value 2")], sodium_ctx.take_listener_errors());
+        assert!(sodium_ctx.take_listener_errors().is_empty());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn transaction_observer() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let l = s.map(|a: &i32| *a + 1).listen(|_: &i32| {});
+        let summaries = Rc::new(RefCell::new(Vec::new()));
+        let observer;
+        {
+            let summaries = summaries.clone();
+            observer = sodium_ctx.on_transaction_end(move |summary| summaries.borrow_mut().push((summary.nodes_fired, summary.listeners_fired)));
+        }
+        s.send(&1);
+        drop(observer);
+        s.send(&2);
+        l.unlisten();
+        assert_eq!(vec![(2, 1)], *summaries.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn transaction_named() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let observer;
+        {
+            let names = names.clone();
+            observer = sodium_ctx.on_transaction_end(move |summary| names.borrow_mut().push(summary.name));
+        }
+        sodium_ctx.transaction_named("load_document", |sodium_ctx| {
+            sodium_ctx.transaction_named("inner", |_| s.send(&1));
+        });
+        s.send(&2);
+        drop(observer);
+        assert_eq!(vec![Some("load_document"), None], *names.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn node_limit() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let exceeded = Rc::new(RefCell::new(Vec::new()));
+        {
+            let exceeded = exceeded.clone();
+            sodium_ctx.set_node_limit_handler(move |node_count| exceeded.borrow_mut().push(node_count));
+        }
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let limit = sodium_ctx.node_count() + 2;
+        sodium_ctx.set_node_limit(Some(limit));
+        sodium_ctx.set_track_node_sites(true);
+        let mut s2 = Vec::new();
+        for _ in 0..3 {
+            s2.push(s.map(|a: &i32| *a + 1));
+        }
+        assert_eq!(vec![limit + 1], *exceeded.borrow());
+        let sites = sodium_ctx.node_allocation_sites(1);
+        assert_eq!(1, sites.len());
+        assert!(sites[0].0.contains("stream_test.rs"));
+        assert_eq!(3, sites[0].1);
+        sodium_ctx.set_node_limit(None);
+        sodium_ctx.set_track_node_sites(false);
+        drop(s2);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn try_map_over_memory_limit() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let oom_events = sodium_ctx.oom_events();
+        let l = {
+            let events = events.clone();
+            oom_events.listen(move |event: &OomEvent| events.borrow_mut().push(event.refused))
+        };
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let sa = s.to_stream();
+        let in_use = sodium_ctx.memory_in_use();
+        sodium_ctx.set_memory_limit(Some(in_use));
+        let node_count = sodium_ctx.node_count();
+        assert!(sa.try_map(|a: &i32| *a + 1).is_err());
+        assert_eq!(node_count, sodium_ctx.node_count());
+        assert!(events.borrow().is_empty());
+        sodium_ctx.transaction(|_| {});
+        assert!(!events.borrow().is_empty());
+        assert!(events.borrow().iter().all(|refused| *refused));
+        sodium_ctx.set_memory_limit(None);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let s2 = sa.try_map(|a: &i32| *a + 1).unwrap();
+        let l2 = {
+            let out = out.clone();
+            s2.listen(move |a: &i32| out.borrow_mut().push(*a))
+        };
+        s.send(&1);
+        l2.unlisten();
+        l.unlisten();
+        assert_eq!(vec![2], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn hold_by_key() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let sets: StreamSink<(String,i32)> = sodium_ctx.new_stream_sink();
+        let removals: StreamSink<String> = sodium_ctx.new_stream_sink();
+        let latest = sets.to_stream().hold_by_key_removing(&removals);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = latest.listen(move |map: &HashMap<String,i32>| {
+                let mut entries: Vec<(String,i32)> = map.iter().map(|(k, a)| (k.clone(), *a)).collect();
+                entries.sort();
+                out.borrow_mut().push(entries);
+            });
+        }
+        sets.send(&(String::from("a"), 1));
+        sets.send(&(String::from("b"), 2));
+        sets.send(&(String::from("a"), 3));
+        removals.send(&String::from("b"));
+        sodium_ctx.transaction(|_| {
+            sets.send(&(String::from("c"), 4));
+            removals.send(&String::from("c"));
+        });
+        l.unlisten();
+        let a = |k: &str, a: i32| (String::from(k), a);
+        assert_eq!(
+            vec![
+                vec![],
+                vec![a("a", 1)],
+                vec![a("a", 1), a("b", 2)],
+                vec![a("a", 3), a("b", 2)],
+                vec![a("a", 3)],
+                vec![a("a", 3)]
+            ],
+            *out.borrow()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn batch_merges_sends() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let sa: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let sb: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = sa.to_stream().merge(&sb, |a: &i32, b: &i32| *a + *b).listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        {
+            let _batch = sodium_ctx.batch();
+            sa.send(&1);
+            sb.send(&10);
+            assert!(out.borrow().is_empty());
+        }
+        sodium_ctx.begin_batch();
+        sa.send(&2);
+        sodium_ctx.end_batch();
+        sa.send(&3);
+        sb.send(&30);
+        l.unlisten();
+        assert_eq!(vec![11, 2, 3, 30], *out.borrow());
+        assert!(!sodium_ctx.is_batching());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+#[should_panic(expected = "SodiumCtx::begin_batch called while a batch was already open.")]
+fn batch_does_not_nest() {
+    let sodium_ctx = SodiumCtx::new();
+    let _batch = sodium_ctx.batch();
+    sodium_ctx.begin_batch();
+}
+
+#[test]
+fn builder() {
+    assert_eq!(
+        Some(SodiumCtxBuildError::NodeLimitHandlerWithoutLimit),
+        SodiumCtx::builder().node_limit_handler(|_| {}).build().err()
+    );
+    assert_eq!(
+        Some(SodiumCtxBuildError::DeferredGcWithoutRoots),
+        SodiumCtx::builder().gc_policy(GcPolicy::Deferred(0)).build().err()
+    );
+    assert_eq!(
+        Some(SodiumCtxBuildError::MemoryLimitBelowCapacity),
+        SodiumCtx::builder().initial_capacity(1000).memory_limit(1000).build().err()
+    );
+    let transactions = Rc::new(RefCell::new(0));
+    let mut sodium_ctx = {
+        let transactions = transactions.clone();
+        SodiumCtx::builder()
+            .gc_policy(GcPolicy::Deferred(64))
+            .node_limit(100)
+            .panic_policy(PanicPolicy::Report)
+            .transaction_hook(move |_| *transactions.borrow_mut() += 1)
+            .initial_capacity(100)
+            .build()
+            .unwrap()
+    };
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        assert_eq!(GcPolicy::Deferred(64), sodium_ctx.gc_ctx().policy());
+        let errors = Rc::new(RefCell::new(0));
+        let l1 = {
+            let errors = errors.clone();
+            sodium_ctx.errors().listen(move |_: &SodiumError| *errors.borrow_mut() += 1)
+        };
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let l2 = s.to_stream().listen(|_: &i32| {});
+        let before = *transactions.borrow();
+        s.send(&1);
+        assert_eq!(before + 1, *transactions.borrow());
+        s.close();
+        s.send(&2);
+        sodium_ctx.transaction(|_| {});
+        l1.unlisten();
+        l2.unlisten();
+        assert_eq!(1, *errors.borrow());
+    }
+    sodium_ctx.gc_ctx().collect_cycles();
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn panic_free_reports_errors() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        sodium_ctx.set_panic_free(true);
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let l1 = {
+            let errors = errors.clone();
+            sodium_ctx.errors().listen(move |err: &SodiumError| errors.borrow_mut().push(err.clone()))
+        };
+        let sl: StreamLoop<i32> = sodium_ctx.new_stream_loop();
+        sl.loop_(sodium_ctx.new_stream());
+        sl.loop_(sodium_ctx.new_stream());
+        let sa: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let sb: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l2 = {
+            let sb = sb.clone();
+            sa.listen(move |a: &i32| sb.send(a))
+        };
+        let l3 = {
+            let out = out.clone();
+            sb.listen(move |b: &i32| out.borrow_mut().push(*b))
+        };
+        sa.send(&1);
+        sb.send(&2);
+        l1.unlisten();
+        l2.unlisten();
+        l3.unlisten();
+        assert_eq!(vec![2], *out.borrow());
+        let errors = errors.borrow();
+        assert_eq!(2, errors.len());
+        match errors[0] {
+            SodiumError::LoopedTwice(_) => (),
+            ref err => panic!("unexpected error {:?}", err)
+        }
+        match errors[1] {
+            SodiumError::SendFromCallback(_) => (),
+            ref err => panic!("unexpected error {:?}", err)
+        }
+        assert!(format!("{}", errors[1]).contains("::send can not be called from a sodium callback"));
+        assert_eq!(*errors, sodium_ctx.take_errors());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn take_errors_without_listener() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        sodium_ctx.set_panic_free(true);
+        let sl: StreamLoop<i32> = sodium_ctx.new_stream_loop();
+        sl.loop_(sodium_ctx.new_stream());
+        sl.loop_(sodium_ctx.new_stream());
+        let errors = sodium_ctx.take_errors();
+        assert_eq!(1, errors.len());
+        match errors[0] {
+            SodiumError::LoopedTwice(_) => (),
+            ref err => panic!("unexpected error {:?}", err)
+        }
+        assert!(sodium_ctx.take_errors().is_empty());
+        for _ in 0..300 {
+            sl.loop_(sodium_ctx.new_stream());
+        }
+        assert_eq!(256, sodium_ctx.take_errors().len());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn with_previous() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let ea = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let out2 = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        let l2;
+        {
+            let out = out.clone();
+            l = ea.with_previous().listen(move |a: &(Option<i32>,i32)| out.borrow_mut().push(*a));
+        }
+        {
+            let out2 = out2.clone();
+            l2 = ea.diff_by(|prev: &i32, a: &i32| *a - *prev).listen(move |a: &i32| out2.borrow_mut().push(*a));
+        }
+        ea.send(&5);
+        ea.send(&7);
+        ea.send(&3);
+        l.unlisten();
+        l2.unlisten();
+        assert_eq!(vec![(None, 5), (Some(5), 7), (Some(7), 3)], *out.borrow());
+        assert_eq!(vec![2, -4], *out2.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn set_name() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let doubled = s.map(|a: &i32| *a * 2);
+        assert_eq!(None, doubled.name());
+        doubled.set_name("doubled");
+        assert_eq!(Some(String::from("doubled")), doubled.name());
+        let l = doubled.listen(|a: &i32| {
+            if *a == 4 {
+                panic!("bad value {}", a);
+            }
+        });
+        s.send(&2);
+        l.unlisten();
+        assert_eq!(vec![String::from("listener on 'doubled': bad value 4")], sodium_ctx.take_listener_errors());
+        assert!(sodium_ctx.node_allocation_sites(usize::max_value()).iter().any(|&(ref site, count)| site == "doubled" && count == 1));
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn multi_producer() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink_with_coalescer(|a: &i32, b: &i32| *a + *b);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        let handle = s.multi_producer();
+        let threads: Vec<_> = (1..5).map(|i| {
+            let handle = handle.clone();
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    handle.send(i);
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(40, s.drain_producers());
+        assert_eq!(0, s.drain_producers());
+        l.unlisten();
+        assert_eq!(vec![100], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn multi_producer_without_coalescer() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        let handle = s.multi_producer();
+        thread::spawn(move || {
+            for i in 0..5 {
+                handle.send(i);
+            }
+        }).join().unwrap();
+        assert_eq!(5, s.drain_producers());
+        l.unlisten();
+        assert_eq!(vec![0, 1, 2, 3, 4], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn split() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<Result<i32,String>> = sodium_ctx.new_stream_sink();
+        let evaluations = Rc::new(RefCell::new(0));
+        let parsed;
+        {
+            let evaluations = evaluations.clone();
+            parsed = s.map(move |r: &Result<i32,String>| {
+                *evaluations.borrow_mut() += 1;
+                r.clone().map(|a| a * 10)
+            });
+        }
+        let (oks, errs) = parsed.split();
+        let out_ok = Rc::new(RefCell::new(Vec::new()));
+        let out_err = Rc::new(RefCell::new(Vec::new()));
+        let l1;
+        let l2;
+        {
+            let out_ok = out_ok.clone();
+            l1 = oks.listen(move |a: &i32| out_ok.borrow_mut().push(*a));
+        }
+        {
+            let out_err = out_err.clone();
+            l2 = errs.listen(move |e: &String| out_err.borrow_mut().push(e.clone()));
+        }
+        s.send(&Ok(1));
+        s.send(&Err(String::from("bad")));
+        s.send(&Ok(3));
+        l1.unlisten();
+        l2.unlisten();
+        assert_eq!(vec![10, 30], *out_ok.borrow());
+        assert_eq!(vec![String::from("bad")], *out_err.borrow());
+        assert_eq!(3, *evaluations.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn take_and_skip() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out_take = Rc::new(RefCell::new(Vec::new()));
+        let out_skip = Rc::new(RefCell::new(Vec::new()));
+        let out_none = Rc::new(RefCell::new(Vec::new()));
+        let l1;
+        let l2;
+        let l3;
+        {
+            let out_take = out_take.clone();
+            l1 = s.take(2).listen(move |a: &i32| out_take.borrow_mut().push(*a));
+        }
+        {
+            let out_skip = out_skip.clone();
+            l2 = s.skip(2).listen(move |a: &i32| out_skip.borrow_mut().push(*a));
+        }
+        {
+            let out_none = out_none.clone();
+            l3 = s.take(0).listen(move |a: &i32| out_none.borrow_mut().push(*a));
+        }
+        s.send(&1);
+        s.send(&2);
+        s.send(&3);
+        s.send(&4);
+        l1.unlisten();
+        l2.unlisten();
+        l3.unlisten();
+        assert_eq!(vec![1, 2], *out_take.borrow());
+        assert_eq!(vec![3, 4], *out_skip.borrow());
+        assert!(out_none.borrow().is_empty());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn scope_dispose() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let node_count_before = sodium_ctx.node_count();
+        let scope = sodium_ctx.create_scope();
+        scope.run(|| {
+            let out = out.clone();
+            let total = s.accum(0, |a: &i32, total: &i32| *a + *total);
+            total.listen(move |a: &i32| out.borrow_mut().push(*a));
+        });
+        assert!(scope.node_count() > 0);
+        s.send(&1);
+        s.send(&2);
+        scope.dispose();
+        assert_eq!(node_count_before, sodium_ctx.node_count());
+        s.send(&3);
+        assert_eq!(vec![0, 1, 3], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn scope_run_panics() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let scope = sodium_ctx.create_scope();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| scope.run(|| {
+            let _doubled = s.map(|a: &i32| *a * 2);
+            panic!("building failed");
+        })));
+        assert!(result.is_err());
+        // Nodes made after the panic are no longer put in the scope.
+        let kept = s.map(|a: &i32| *a + 1);
+        assert_eq!(0, scope.node_count());
+        scope.dispose();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = kept.listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        s.send(&1);
+        l.unlisten();
+        assert_eq!(vec![2], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn coincidence_and_alone() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let ctrl: StreamSink<()> = sodium_ctx.new_stream_sink();
+        let key: StreamSink<char> = sodium_ctx.new_stream_sink();
+        let out_chord = Rc::new(RefCell::new(Vec::new()));
+        let out_alone = Rc::new(RefCell::new(Vec::new()));
+        let l1;
+        let l2;
+        {
+            let out_chord = out_chord.clone();
+            l1 = key.coincidence_with(&ctrl).listen(move |&(k, _): &(char, ())| out_chord.borrow_mut().push(k));
+        }
+        {
+            let out_alone = out_alone.clone();
+            l2 = key.alone(&ctrl).listen(move |k: &char| out_alone.borrow_mut().push(*k));
+        }
+        key.send(&'a');
+        sodium_ctx.transaction(|_| {
+            ctrl.send(&());
+            key.send(&'c');
+        });
+        ctrl.send(&());
+        key.send(&'d');
+        l1.unlisten();
+        l2.unlisten();
+        assert_eq!(vec!['c'], *out_chord.borrow());
+        assert_eq!(vec!['a', 'd'], *out_alone.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn map_to_and_constants() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let never: Stream<i32> = sodium_ctx.never();
+        let c = sodium_ctx.constant(String::from("c"));
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let units = Rc::new(RefCell::new(0));
+        let l1;
+        let l2;
+        {
+            let out = out.clone();
+            l1 = s.or_else(&never).map_to(&String::from("x")).snapshot2(&c, |x: &String, c: &String| format!("{}{}", x, c))
+                .listen(move |a: &String| out.borrow_mut().push(a.clone()));
+        }
+        {
+            let units = units.clone();
+            l2 = s.to_unit().listen(move |_: &()| *units.borrow_mut() += 1);
+        }
+        s.send(&1);
+        s.send(&2);
+        l1.unlisten();
+        l2.unlisten();
+        assert_eq!(vec![String::from("xc"), String::from("xc")], *out.borrow());
+        assert_eq!(2, *units.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn graph_snapshot_diff() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let before = GraphSnapshot::capture(sodium_ctx);
+        let l = s.map(|a: &i32| *a + 1).filter(|a: &i32| *a > 1).listen(|_: &i32| {});
+        let during = GraphSnapshot::capture(sodium_ctx);
+        let diff = before.diff(&during);
+        assert_eq!(2, diff.created_nodes(), "{:?}", diff);
+        assert_eq!(1, diff.created_listeners(), "{:?}", diff);
+        assert!(diff.destroyed.is_empty());
+        l.unlisten();
+        drop(l);
+        let after = GraphSnapshot::capture(sodium_ctx);
+        assert!(before.diff(&after).is_empty(), "{:?}", before.diff(&after));
+        assert_eq!(3, during.diff(&after).destroyed.len());
+        let leaked = s.map(|a: &i32| *a + 1);
+        assert_eq!(1, after.diff(&GraphSnapshot::capture(sodium_ctx)).created_nodes());
+        drop(leaked);
+        assert!(after.diff(&GraphSnapshot::capture(sodium_ctx)).is_empty());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn listen_with_priority() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut listeners = Vec::new();
+        for &(tag, priority) in &[("a", 0), ("b", 5), ("c", 0), ("d", -1), ("e", 5)] {
+            let out = out.clone();
+            listeners.push(s.listen_with_priority(priority, move |_: &i32| out.borrow_mut().push(tag)));
+        }
+        s.send(&1);
+        s.send(&2);
+        for l in listeners {
+            l.unlisten();
+        }
+        assert_eq!(vec!["b", "e", "a", "c", "d", "b", "e", "a", "c", "d"], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn switch_map() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let requests: StreamSink<char> = sodium_ctx.new_stream_sink();
+        let a: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let b: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            let a = a.clone();
+            let b = b.clone();
+            l = requests
+                .switch_map(move |r: &char| if *r == 'a' { a.to_stream() } else { b.to_stream() })
+                .listen(move |x: &i32| out.borrow_mut().push(*x));
+        }
+        a.send(&1);
+        requests.send(&'a');
+        a.send(&2);
+        b.send(&3);
+        requests.send(&'b');
+        a.send(&4);
+        b.send(&5);
+        l.unlisten();
+        assert_eq!(vec![2, 5], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn sample_from() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let ticks: StreamSink<char> = sodium_ctx.new_stream_sink();
+        let readings: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = ticks
+                .sample_from(&readings)
+                .listen(move |&(c, reading): &(char,Option<i32>)| out.borrow_mut().push((c, reading)));
+        }
+        ticks.send(&'a');
+        readings.send(&1);
+        readings.send(&2);
+        ticks.send(&'b');
+        sodium_ctx.transaction(|_| {
+            readings.send(&3);
+            ticks.send(&'c');
+        });
+        ticks.send(&'d');
+        l.unlisten();
+        assert_eq!(vec![('a', None), ('b', Some(2)), ('c', Some(2)), ('d', Some(3))], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn limit_per_transaction() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<Vec<i32>> = sodium_ctx.new_stream_sink();
+        let events: Stream<i32> = Operational::split(&s);
+        let mut outs = Vec::new();
+        let mut listeners = Vec::new();
+        for policy in vec![OverflowPolicy::Drop, OverflowPolicy::Latest, OverflowPolicy::Block] {
+            let out = Rc::new(RefCell::new(Vec::new()));
+            {
+                let out = out.clone();
+                let sodium_ctx = sodium_ctx.clone();
+                listeners.push(events.limit_per_transaction(2, policy).listen(move |a: &i32| {
+                    out.borrow_mut().push((sodium_ctx.outer_tx_id(), *a))
+                }));
+            }
+            outs.push(out);
+        }
+        s.send(&vec![1, 2, 3, 4, 5]);
+        s.send(&vec![6]);
+        for l in listeners {
+            l.unlisten();
+        }
+        let values = |i: usize| outs[i].borrow().iter().map(|&(_, a)| a).collect::<Vec<i32>>();
+        let outer_ids = |i: usize| outs[i].borrow().iter().map(|&(t, _)| t).collect::<Vec<TxId>>();
+        assert_eq!(vec![1, 2, 6], values(0));
+        assert_eq!(vec![1, 2, 5, 6], values(1));
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], values(2));
+        let t = outer_ids(2);
+        assert!(t[0] == t[1] && t[1] < t[2] && t[2] == t[3] && t[3] < t[4] && t[4] < t[5], "{:?}", t);
+        let t = outer_ids(1);
+        assert!(t[0] == t[1] && t[1] < t[2] && t[2] < t[3], "{:?}", t);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn listen_once_and_unlisten_after() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let once_out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let once_out = once_out.clone();
+            s.listen_once(move |a: &i32| once_out.borrow_mut().push(*a));
+        }
+        let out = Rc::new(RefCell::new(Vec::new()));
+        {
+            let out2 = out.clone();
+            let l = s.listen(move |a: &i32| out2.borrow_mut().push(*a));
+            let out = out.clone();
+            l.unlisten_after(move || out.borrow().len() == 3);
+        }
+        for i in 1..6 {
+            s.send(&i);
+        }
+        assert_eq!(vec![1], *once_out.borrow());
+        assert_eq!(vec![1, 2, 3], *out.borrow());
+        assert_eq!(1, Rc::strong_count(&once_out));
+        assert_eq!(1, Rc::strong_count(&out));
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn collector() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let collector = s.map(|a: &i32| *a * 2).collector();
+        let mut frame = Vec::new();
+        s.send(&1);
+        s.send(&2);
+        assert_eq!(2, collector.len());
+        collector.drain_into(&mut frame);
+        assert_eq!(vec![2, 4], frame);
+        assert!(collector.is_empty());
+        frame.clear();
+        collector.drain_into(&mut frame);
+        assert!(frame.is_empty());
+        s.send(&3);
+        frame.push(0);
+        collector.drain_into(&mut frame);
+        assert_eq!(vec![0, 6], frame);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn tag_tx() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            let sodium_ctx = sodium_ctx.clone();
+            l = s.tag_tx().listen(move |&(tx_id, a): &(TxId, i32)| {
+                assert_eq!(tx_id, sodium_ctx.current_tx_id());
+                out.borrow_mut().push((tx_id, a));
+            });
+        }
+        s.send(&1);
+        let first = sodium_ctx.current_tx_id();
+        s.send(&2);
+        l.unlisten();
+        assert_eq!(vec![(first, 1), (TxId(first.0 + 1), 2)], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "instantaneous dependency cycle: Stream::new_node 'sb' -> Stream::map_node 'doubled' -> Stream::new_node 'sb'")]
+fn loop_without_delay_panics() {
+    let sodium_ctx = SodiumCtx::new();
+    sodium_ctx.transaction(|sodium_ctx| {
+        let sb: StreamLoop<i32> = sodium_ctx.new_stream_loop();
+        sb.set_name("sb");
+        let doubled = sb.map(|x: &i32| *x * 2);
+        doubled.set_name("doubled");
+        sb.loop_(&doubled);
+    });
+}
+
+#[test]
+fn invalid_edges() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        sodium_ctx.set_panic_free(true);
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let l = {
+            let errors = errors.clone();
+            sodium_ctx.errors().listen(move |err: &SodiumError| errors.borrow_mut().push(err.clone()))
+        };
+        let sl: StreamLoop<i32> = sodium_ctx.new_stream_loop();
+        sl.loop_(&sl);
+        let other_ctx = SodiumCtx::new();
+        let sl2: StreamLoop<i32> = sodium_ctx.new_stream_loop();
+        sl2.loop_(other_ctx.new_stream());
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let doubled = s.map(|a: &i32| *a * 2);
+        let (doubled_node, s_node) = (doubled.impl_._node().clone(), s.to_stream().impl_._node().clone());
+        assert_eq!(Err(EdgeError::Duplicate), doubled_node.try_add_dependency(s_node));
+        sodium_ctx.transaction(|_| {});
+        l.unlisten();
+        let errors: Vec<Option<EdgeError>> = errors.borrow().iter().map(|err| match *err { SodiumError::InvalidEdge(error, _) => Some(error), _ => None }).collect();
+        assert_eq!(vec![Some(EdgeError::SelfEdge), Some(EdgeError::CrossContext)], errors);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn cycle_edge() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        sodium_ctx.set_panic_free(true);
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let l = {
+            let errors = errors.clone();
+            sodium_ctx.errors().listen(move |err: &SodiumError| errors.borrow_mut().push(err.clone()))
+        };
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let doubled = s.map(|a: &i32| *a * 2);
+        let (doubled_node, s_node) = (doubled.impl_._node().clone(), s.to_stream().impl_._node().clone());
+        assert_eq!(Err(EdgeError::Cycle), s_node.try_add_dependency(doubled_node.clone()));
+        assert!(s_node.dependencies().is_empty());
+        sodium_ctx.transaction(|_| {});
+        l.unlisten();
+        let cycles = errors.borrow().iter().filter(|err| match **err { SodiumError::DependencyCycle(_) => true, _ => false }).count();
+        assert_eq!(1, cycles);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn clone_and_downgrade() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let doubled = s.map(|x: &i32| *x * 2);
+        let node_count = sodium_ctx.node_count();
+        let doubled2 = doubled.clone();
+        assert_eq!(node_count, sodium_ctx.node_count());
+        let weak = doubled.downgrade();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = weak.upgrade().unwrap().listen(move |x: &i32| out.borrow_mut().push(*x));
+        }
+        s.send(&1);
+        l.unlisten();
+        assert_eq!(vec![2], *out.borrow());
+        drop(doubled);
+        drop(doubled2);
+        assert!(weak.upgrade().is_none());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn close_and_fail() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32,String> = sodium_ctx.new_stream_sink_with_error();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let terminations = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        let l2;
+        {
+            let out = out.clone();
+            l = s.take_until_closed(s.termination()).listen(move |x: &i32| out.borrow_mut().push(*x));
+        }
+        {
+            let terminations = terminations.clone();
+            l2 = s.termination().listen(move |t: &Termination<String>| terminations.borrow_mut().push(t.clone()));
+        }
+        s.send(&1);
+        s.send(&2);
+        assert!(!s.is_terminated());
+        s.fail(String::from("disconnected"));
+        assert!(s.is_terminated());
+        assert_eq!(Err(Termination::Failed(String::from("disconnected"))), s.try_send(&3));
+        let closed: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        closed.close();
+        assert_eq!(Err(Termination::Closed), closed.try_send(&4));
+        l.unlisten();
+        l2.unlisten();
+        assert_eq!(vec![1, 2], *out.borrow());
+        assert_eq!(vec![Termination::Failed(String::from("disconnected"))], *terminations.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn replay() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let r = s.replay(2);
+        s.send(&1);
+        s.send(&2);
+        s.send(&3);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            let sodium_ctx2 = sodium_ctx.clone();
+            l = r.listen(move |x: &i32| out.borrow_mut().push((*x, sodium_ctx2.current_tx_id())));
+        }
+        s.send(&4);
+        let out2 = Rc::new(RefCell::new(Vec::new()));
+        let l2 = sodium_ctx.transaction(|_sodium_ctx| {
+            let l2;
+            {
+                let out2 = out2.clone();
+                l2 = r.listen(move |x: &i32| out2.borrow_mut().push(*x));
+            }
+            s.send(&5);
+            assert!(out2.borrow().is_empty());
+            l2
+        });
+        l.unlisten();
+        l2.unlisten();
+        let values: Vec<i32> = out.borrow().iter().map(|&(x, _)| x).collect();
+        assert_eq!(vec![2, 3, 4, 5], values);
+        let mut tx_ids: Vec<TxId> = out.borrow().iter().map(|&(_, tx_id)| tx_id).collect();
+        tx_ids.dedup();
+        assert_eq!(4, tx_ids.len());
+        assert_eq!(vec![3, 4, 5], *out2.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn dedup_by_key() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<(u32,&'static str)> = sodium_ctx.new_stream_sink().dedup_by_key(|msg: &(u32,&'static str)| msg.0, 2);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen(move |msg: &(u32,&'static str)| out.borrow_mut().push(msg.1));
+        }
+        s.send(&(1, "a"));
+        s.send(&(1, "a again"));
+        let s2 = s.clone();
+        s2.send(&(2, "b"));
+        assert_eq!(Ok(()), s2.try_send(&(2, "b again")));
+        s.send(&(3, "c"));
+        s.send(&(1, "a after the window"));
+        let handle = s.multi_producer();
+        thread::spawn(move || {
+            handle.send((1, "a from a handle"));
+            handle.send((4, "d"));
+            handle.send((4, "d again"));
+        }).join().unwrap();
+        assert_eq!(3, s.drain_producers());
+        l.unlisten();
+        assert_eq!(vec!["a", "b", "c", "a after the window", "d"], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn listener_count_and_orphans() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let doubled = s.map(|a: &i32| *a * 2);
+        let held = doubled.hold(0);
+        let l1 = s.listen(|_: &i32| {});
+        let l2 = doubled.listen(|_: &i32| {});
+        let l3 = held.listen(|_: &i32| {});
+        assert_eq!(1, s.to_stream().listener_count());
+        assert_eq!(1, doubled.listener_count());
+        assert_eq!(1, held.listener_count());
+        assert!(sodium_ctx.find_orphan_listeners().is_empty());
+        let never: Stream<i32> = sodium_ctx.never();
+        let l4 = never.listen(|_: &i32| {});
+        let l5 = sodium_ctx.new_cell(1).listen(|_: &i32| {});
+        assert_eq!(2, sodium_ctx.find_orphan_listeners().len());
+        l1.unlisten();
+        l2.unlisten();
+        l3.unlisten();
+        l4.unlisten();
+        l5.unlisten();
+        assert_eq!(0, s.to_stream().listener_count());
+        assert_eq!(0, held.listener_count());
+        assert!(sodium_ctx.find_orphan_listeners().is_empty());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn transcript() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<&'static str> = sodium_ctx.new_stream_sink();
+        let total = s.map(|a: &&'static str| a.len()).hold(0);
+        s.send(&"before recording");
+        let mut transcript = Transcript::new();
+        transcript.record("s", s.to_stream());
+        transcript.record_cell("total", total.clone());
+        s.send(&"ab");
+        sodium_ctx.transaction(|_| {
+            s.send(&"cde");
+        });
+        transcript.unlisten();
+        let expected = "tx 1\n  s: \"ab\"\n  total: 2\ntx 2\n  s: \"cde\"\n  total: 3\n";
+        assert_eq!(expected, transcript.render());
+        assert_eq!(None, transcript.diff(expected));
+        assert_eq!(
+            Some(String::from("  tx 1\n    s: \"ab\"\n-   total: 1\n+   total: 2\n  tx 2\n    s: \"cde\"\n    total: 3\n")),
+            transcript.diff(&expected.replace("total: 2", "total: 1"))
+        );
+        let path = env::temp_dir().join(format!("sodium-transcript-{}", std::process::id())).join("golden.txt");
+        let missing = panic::catch_unwind(AssertUnwindSafe(|| transcript.check_golden(&path, false)));
+        assert!(missing.is_err());
+        assert!(!path.exists());
+        transcript.check_golden(&path, true);
+        assert_eq!(expected, fs::read_to_string(&path).unwrap());
+        transcript.check_golden(&path, false);
+        fs::write(&path, "tx 1\n").unwrap();
+        let mismatch = panic::catch_unwind(AssertUnwindSafe(|| transcript.check_golden(&path, false)));
+        assert!(mismatch.is_err());
+        transcript.check_golden(&path, true);
+        assert_eq!(expected, fs::read_to_string(&path).unwrap());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn until() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let stop: StreamSink<()> = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s.map(|a: &i32| *a * 10).until(&stop).listen(move |a: &i32| out.borrow_mut().push(*a));
+        }
+        let node_count = sodium_ctx.node_count();
+        s.send(&1);
+        s.send(&2);
+        sodium_ctx.transaction(|_| {
+            s.send(&3);
+            stop.send(&());
+        });
+        s.send(&4);
+        assert_eq!(vec![10, 20], *out.borrow());
+        // The map in front of it is freed as it has nothing else downstream.
+        assert_eq!(node_count - 1, sodium_ctx.node_count());
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn listen_bound() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let node_count = sodium_ctx.node_count();
+        let view = Rc::new(RefCell::new(Vec::new()));
+        s.listen_bound(Rc::downgrade(&view), |view: &RefCell<Vec<i32>>, a: &i32| view.borrow_mut().push(*a));
+        let shared = Arc::new(AtomicUsize::new(0));
+        let l = s.listen_bound(Arc::downgrade(&shared), |total: &AtomicUsize, a: &i32| { total.fetch_add(*a as usize, Ordering::SeqCst); });
+        s.send(&1);
+        s.send(&2);
+        assert_eq!(vec![1, 2], *view.borrow());
+        drop(view);
+        s.send(&3);
+        assert_eq!(6, shared.load(Ordering::SeqCst));
+        assert_eq!(node_count + 1, sodium_ctx.node_count());
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+// Deliberately not Clone, only one consumer can hold it.
+struct Token(i32);
+
+#[test]
+fn map_once() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let tokens = s.map_once(|a: &i32| Token(*a));
+        let doubled = tokens.map(|t: Token| Token(t.0 * 2));
+        let out: Rc<RefCell<Vec<Token>>> = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = doubled.listen(move |t: Token| out.borrow_mut().push(t));
+        }
+        s.send(&1);
+        s.send(&2);
+        l.unlisten();
+        assert_eq!(vec![2, 4], out.borrow().iter().map(|t| t.0).collect::<Vec<i32>>());
+        let second = panic::catch_unwind(AssertUnwindSafe(|| { tokens.listen(|_: Token| {}); }));
+        assert!(second.is_err());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn map_once_reuse_panic_free() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        sodium_ctx.set_panic_free(true);
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let l1 = {
+            let errors = errors.clone();
+            sodium_ctx.errors().listen(move |err: &SodiumError| errors.borrow_mut().push(err.clone()))
+        };
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let tokens = s.map_once(|a: &i32| Token(*a));
+        let out: Rc<RefCell<Vec<i32>>> = Rc::new(RefCell::new(Vec::new()));
+        let l2;
+        {
+            let out = out.clone();
+            l2 = tokens.listen(move |t: Token| out.borrow_mut().push(t.0));
+        }
+        let l3 = tokens.listen(|_: Token| {});
+        let l4 = tokens.map(|t: Token| Token(t.0 + 1)).listen(|_: Token| {});
+        s.send(&1);
+        l4.unlisten();
+        l3.unlisten();
+        l2.unlisten();
+        l1.unlisten();
+        assert_eq!(vec![1], *out.borrow());
+        assert_eq!(2, errors.borrow().len());
+        assert!(errors.borrow().iter().all(|err| *err == SodiumError::Misuse(String::from("OnceStream already has a consumer."))));
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn fuzz_harness_finds_and_reproduces() {
+    let build = |sodium_ctx: &SodiumCtx, inputs: &mut InputModel| {
+        let a = sodium_ctx.new_cell_sink(0);
+        let b: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        inputs.cell("a", &a, |rng: &mut FuzzRng| rng.below(10) as i32);
+        inputs.stream("b", &b, |rng: &mut FuzzRng| rng.below(10) as i32);
+        let total = b.to_stream().accum(0, |b: &i32, total: &i32| *total + *b);
+        let sum = a.to_cell().lift2(&total, |a: &i32, total: &i32| *a + *total);
+        (a, total, sum)
+    };
+    let config = FuzzConfig::new(7);
+    let holds = fuzz_harness(&config, &build, |&(ref a, ref total, ref sum)| {
+        if sum.sample() == a.sample() + total.sample() { Ok(()) } else { Err(String::from("sum is stale")) }
+    });
+    assert_eq!(Ok(()), holds);
+    let broken = fuzz_harness(&config, &build, |&(_, ref total, _)| {
+        if total.sample() < 30 { Ok(()) } else { Err(format!("total is {}", total.sample())) }
+    });
+    let failure = broken.unwrap_err();
+    assert!(failure.message.starts_with("total is "));
+    // Every recorded send was delivered, so replaying the log gives the total that failed.
+    let mut sent = 0;
+    for sends in &failure.transactions {
+        assert!(sends.len() <= 2);
+        assert!(sends.len() < 2 || sends[0].0 != sends[1].0);
+        sent += sends.iter().filter(|send| send.0 == "b").map(|send| send.1.parse::<i32>().unwrap()).sum::<i32>();
+    }
+    assert_eq!(format!("total is {}", sent), failure.message);
+    let mut again = FuzzConfig::new(failure.seed);
+    again.runs = 1;
+    let reproduced = fuzz_harness(&again, &build, |&(_, ref total, _)| {
+        if total.sample() < 30 { Ok(()) } else { Err(format!("total is {}", total.sample())) }
+    });
+    assert_eq!(Err(failure), reproduced);
+    let mut rng = FuzzRng::new(0x9e37_79b9_7f4a_7c15);
+    assert_ne!(0, rng.next_u64());
+}
+
+#[test]
+fn fuse() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let before = sodium_ctx.node_count();
+        let unfused = s.map(|a: &i32| *a + 1).map(|a: &i32| *a * 10).filter(|a: &i32| *a % 20 == 0);
+        let unfused_nodes = sodium_ctx.node_count() - before;
+        let chain = s.to_stream().fuse().map(|a: &i32| *a + 1).map(|a: &i32| *a * 10).filter(|a: &i32| *a % 20 == 0);
+        let before = sodium_ctx.node_count();
+        let fused = chain.build();
+        assert_eq!(3, unfused_nodes);
+        assert_eq!(3, chain.unfused_node_count());
+        assert_eq!(1, sodium_ctx.node_count() - before);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut listeners = Vec::new();
+        for &(tag, ref sa) in &[("unfused", unfused), ("fused", fused)] {
+            let out = out.clone();
+            listeners.push(sa.listen(move |a: &i32| out.borrow_mut().push((tag, *a))));
+        }
+        for a in 0..4 {
+            s.send(&a);
+        }
+        for l in listeners {
+            l.unlisten();
+        }
+        let out = out.borrow();
+        let values = |tag: &str| out.iter().filter(|&&(t, _)| t == tag).map(|&(_, a)| a).collect::<Vec<i32>>();
+        assert_eq!(vec![20, 40], values("unfused"));
+        assert_eq!(values("unfused"), values("fused"));
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn optimize_graph_fuses_steps() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let unfused = s.map(|a: &i32| *a + 1).map(|a: &i32| *a * 10).filter(|a: &i32| *a % 20 == 0);
+        sodium_ctx.set_optimize_graph(true);
+        let before = sodium_ctx.node_count();
+        let fused = s.map(|a: &i32| *a + 1).map(|a: &i32| *a * 10).filter(|a: &i32| *a % 20 == 0);
+        assert_eq!(1, sodium_ctx.node_count() - before);
+        assert_eq!(1, unfused.unfused_node_count());
+        assert_eq!(3, fused.unfused_node_count());
+        assert_eq!(2, sodium_ctx.optimizer_stats().fused_steps);
+        let plus_one = s.map(|a: &i32| *a + 1);
+        let times_ten = plus_one.map(|a: &i32| *a * 10);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut listeners = Vec::new();
+        for &(tag, ref sa) in &[("unfused", unfused), ("fused", fused), ("plus_one", plus_one.clone()), ("times_ten", times_ten)] {
+            let out = out.clone();
+            listeners.push(sa.listen(move |a: &i32| out.borrow_mut().push((tag, *a))));
+        }
+        // plus_one now has a listener, so a step on it can't be fused past it.
+        let plus_two = plus_one.map(|a: &i32| *a + 1);
+        assert_eq!(1, plus_two.unfused_node_count());
+        assert_eq!(3, sodium_ctx.optimizer_stats().fused_steps);
+        for a in 0..4 {
+            s.send(&a);
+        }
+        for l in listeners {
+            l.unlisten();
+        }
+        let out = out.borrow();
+        let values = |tag: &str| out.iter().filter(|&&(t, _)| t == tag).map(|&(_, a)| a).collect::<Vec<i32>>();
+        assert_eq!(vec![20, 40], values("unfused"));
+        assert_eq!(values("unfused"), values("fused"));
+        assert_eq!(vec![1, 2, 3, 4], values("plus_one"));
+        assert_eq!(vec![10, 20, 30, 40], values("times_ten"));
+        sodium_ctx.set_optimize_graph(false);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn optimize_graph_folds_constants() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let c = sodium_ctx.new_cell(2);
+        let cs = sodium_ctx.new_cell_sink(5);
+        let cl: CellLoop<i32> = sodium_ctx.new_cell_loop();
+        let unfolded = c.map(|a: &i32| *a * 3);
+        sodium_ctx.set_optimize_graph(true);
+        let folded = c.map(|a: &i32| *a * 3).lift2(sodium_ctx.new_cell(1), |a: &i32, b: &i32| *a + *b);
+        let from_sink = cs.map(|a: &i32| *a * 3);
+        let from_loop = cl.map(|a: &i32| *a * 3);
+        cl.loop_(&cs);
+        assert_eq!(2, sodium_ctx.optimizer_stats().folded_cells);
+        assert_eq!((6, 7), (unfolded.sample(), folded.sample()));
+        cs.send(&6);
+        assert_eq!((18, 18), (from_sink.sample(), from_loop.sample()));
+        sodium_ctx.set_optimize_graph(false);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn listener_middleware() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let muted = s.map(|a: &i32| *a * 10);
+        muted.set_name("muted");
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let outer;
+        {
+            let log = log.clone();
+            outer = sodium_ctx.add_listener_middleware(move |info: &ListenerInfo, listener: &dyn Fn()| {
+                log.borrow_mut().push(format!("outer {:?}", info.name));
+                listener();
+                log.borrow_mut().push(String::from("outer done"));
+            });
+        }
+        // Calling the listener twice only runs it once.
+        let inner = sodium_ctx.add_listener_middleware(move |info: &ListenerInfo, listener: &dyn Fn()| {
+            if info.name.as_ref().map(|name| name.as_str()) == Some("muted") {
+                return;
+            }
+            listener();
+            listener();
+        });
+        let mut listeners = Vec::new();
+        for sa in &[s.to_stream(), muted] {
+            let log = log.clone();
+            listeners.push(sa.listen(move |a: &i32| log.borrow_mut().push(format!("got {}", a))));
+        }
+        s.send(&1);
+        drop(inner);
+        drop(outer);
+        s.send(&2);
+        for l in listeners {
+            l.unlisten();
+        }
+        assert_eq!(
+            vec!["outer None", "got 1", "outer done", "outer Some(\"muted\")", "outer done", "got 2", "got 20"],
+            *log.borrow()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
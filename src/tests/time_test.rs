@@ -0,0 +1,419 @@
+use sodium::ResumePolicy;
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::time::BackoffPolicy;
+use sodium::time::Driver;
+use sodium::time::Easing;
+use sodium::time::FrameClock;
+use sodium::time::FrameInfo;
+use sodium::time::ManualDriver;
+use sodium::time::ManualClock;
+#[cfg(feature = "threads")]
+use sodium::time::ThreadDriver;
+use sodium::time::TimerSystem;
+use sodium::time::animation_frames;
+use sodium::time::correlate;
+use sodium::time::deadline;
+use sodium::time::retry_with_backoff;
+use tests::assert_memory_freed;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+use std::time::Instant;
+
+#[test]
+fn frame_clock_elapsed_and_integrate() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = FrameClock::new(sodium_ctx);
+        let rate = sodium_ctx.new_cell_sink(2.0);
+        let position = clock.integrate(&rate);
+        clock.tick(Duration::from_millis(500));
+        rate.send(&-1.0);
+        clock.tick(Duration::from_millis(250));
+        assert_eq!(0.75, clock.elapsed().sample());
+        assert_eq!(0.75, position.sample());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn frame_clock_every() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = FrameClock::new(sodium_ctx);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            let elapsed = clock.elapsed();
+            l = clock
+                .every(Duration::from_millis(100))
+                .snapshot2(&elapsed, |periods: &u32, t: &f64| (*periods, (*t * 1000.0).round() as u32))
+                .listen(move |a: &(u32,u32)| out.borrow_mut().push(*a));
+        }
+        for _ in 0..7 {
+            clock.tick(Duration::from_millis(40));
+        }
+        clock.tick(Duration::from_millis(250));
+        l.unlisten();
+        assert_eq!(vec![(1, 80), (1, 160), (3, 280)], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn timer_system_at() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let alarm = sodium_ctx.new_cell_sink(Some(Duration::from_millis(100)));
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = timer.at(&alarm).listen(move |t: &Duration| out.borrow_mut().push(t.as_millis()));
+        }
+        assert_eq!(Some(Duration::from_millis(100)), timer.next_alarm());
+        clock.advance(Duration::from_millis(60));
+        timer.poll();
+        clock.advance(Duration::from_millis(60));
+        timer.poll();
+        assert_eq!(120, timer.time().sample().as_millis());
+        alarm.send(&Some(Duration::from_millis(130)));
+        alarm.send(&None);
+        clock.advance(Duration::from_millis(60));
+        timer.poll();
+        alarm.send(&Some(Duration::from_millis(150)));
+        timer.poll();
+        l.unlisten();
+        assert_eq!(vec![100, 180], *out.borrow());
+        assert_eq!(None, timer.next_alarm());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn suspend_and_resume() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let alarm = sodium_ctx.new_cell_sink(Some(Duration::from_millis(100)));
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let c = sodium_ctx.new_cell_sink(0);
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        let l2;
+        {
+            let fired = fired.clone();
+            l = timer.at(&alarm).listen(move |t: &Duration| fired.borrow_mut().push(t.as_millis()));
+            let out = out.clone();
+            l2 = s.to_stream().snapshot2(&c, |a: &i32, c: &i32| (*a, *c)).listen(move |a: &(i32,i32)| out.borrow_mut().push(*a));
+        }
+        sodium_ctx.suspend();
+        s.send(&1);
+        sodium_ctx.transaction(|_| {
+            c.send(&5);
+            s.send(&2);
+        });
+        clock.advance(Duration::from_millis(150));
+        timer.poll();
+        assert_eq!(0, timer.time().sample().as_millis());
+        assert_eq!(None, timer.next_alarm());
+        assert!(out.borrow().is_empty());
+        sodium_ctx.resume(ResumePolicy::FastForward);
+        // Replayed together, so the snapshot still sees c from before the transaction.
+        assert_eq!(vec![(1, 0), (2, 0)], *out.borrow());
+        timer.poll();
+        assert_eq!(vec![100], *fired.borrow());
+        alarm.send(&Some(Duration::from_millis(200)));
+        sodium_ctx.suspend();
+        clock.advance(Duration::from_millis(150));
+        sodium_ctx.resume(ResumePolicy::DropElapsed);
+        timer.poll();
+        assert_eq!(vec![100], *fired.borrow());
+        assert_eq!(300, timer.time().sample().as_millis());
+        l.unlisten();
+        l2.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn timer_system_timed_and_stopwatch() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let sa = sodium_ctx.new_stream_sink();
+        let start = sodium_ctx.new_stream_sink();
+        let stop = sodium_ctx.new_stream_sink();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = timer.timed(&sa).listen(move |&(dt, c): &(Duration,char)| out.borrow_mut().push((dt.as_millis(), c)));
+        }
+        let stopwatch = timer.stopwatch(&start, &stop);
+        let step = |ms: u64| {
+            clock.advance(Duration::from_millis(ms));
+            timer.poll();
+        };
+        step(10);
+        sa.send(&'a');
+        start.send(&());
+        step(25);
+        sa.send(&'b');
+        assert_eq!(25, stopwatch.sample().as_millis());
+        stop.send(&());
+        step(100);
+        start.send(&());
+        step(5);
+        sa.send(&'c');
+        l.unlisten();
+        assert_eq!(30, stopwatch.sample().as_millis());
+        assert_eq!(vec![(10, 'a'), (25, 'b'), (105, 'c')], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn manual_driver_frames_and_deadline() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let driver = ManualDriver::new(sodium_ctx);
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let timeouts = Rc::new(RefCell::new(0));
+        let l;
+        let l2;
+        {
+            let frames = frames.clone();
+            l = animation_frames(&driver).listen(move |frame: &FrameInfo| {
+                frames.borrow_mut().push((frame.index, frame.time.as_millis(), frame.dt.as_millis()))
+            });
+        }
+        {
+            let timeouts = timeouts.clone();
+            let at = driver.timer().instant_of(Duration::from_millis(30));
+            l2 = deadline(&driver, at).listen(move |_: &()| *timeouts.borrow_mut() += 1);
+        }
+        driver.frame();
+        driver.advance(Duration::from_millis(16));
+        driver.frame();
+        assert_eq!(0, *timeouts.borrow());
+        driver.advance(Duration::from_millis(16));
+        assert_eq!(1, *timeouts.borrow());
+        driver.frame();
+        driver.advance(Duration::from_millis(16));
+        l.unlisten();
+        l2.unlisten();
+        assert_eq!(vec![(0, 0, 0), (1, 16, 16), (2, 32, 16)], *frames.borrow());
+        assert_eq!(1, *timeouts.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+#[cfg(feature = "threads")]
+fn thread_driver_deadline() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let driver = ThreadDriver::new(sodium_ctx, Duration::from_millis(2));
+        let frames = Rc::new(RefCell::new(0));
+        let fired = Rc::new(RefCell::new(None));
+        let l;
+        let l2;
+        {
+            let frames = frames.clone();
+            l = animation_frames(&driver).listen(move |_: &FrameInfo| *frames.borrow_mut() += 1);
+        }
+        let start = Instant::now();
+        {
+            let fired = fired.clone();
+            l2 = deadline(&driver, start + Duration::from_millis(10)).listen(move |_: &()| *fired.borrow_mut() = Some(Instant::now()));
+        }
+        for _ in 0..1000 {
+            if fired.borrow().is_some() {
+                break;
+            }
+            driver.run_once();
+        }
+        l.unlisten();
+        l2.unlisten();
+        assert!(fired.borrow().unwrap() >= start + Duration::from_millis(10));
+        assert!(*frames.borrow() > 0);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn retry_with_backoff_schedules_retries() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let results: StreamSink<Result<u32,String>> = sodium_ctx.new_stream_sink();
+        let policy = BackoffPolicy::exponential(Duration::from_millis(100), Duration::from_secs(1)).with_max_attempts(2);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = retry_with_backoff(&results, policy, &timer).listen(move |attempt: &u32| out.borrow_mut().push(*attempt));
+        }
+        results.send(&Err(String::from("timeout")));
+        assert_eq!(Some(Duration::from_millis(100)), timer.next_alarm());
+        clock.advance(Duration::from_millis(100));
+        timer.poll();
+        results.send(&Err(String::from("timeout")));
+        assert_eq!(Some(Duration::from_millis(300)), timer.next_alarm());
+        clock.advance(Duration::from_millis(200));
+        timer.poll();
+        results.send(&Err(String::from("timeout")));
+        assert_eq!(None, timer.next_alarm());
+        results.send(&Ok(1));
+        results.send(&Err(String::from("timeout")));
+        assert_eq!(Some(Duration::from_millis(400)), timer.next_alarm());
+        clock.advance(Duration::from_millis(100));
+        timer.poll();
+        l.unlisten();
+        assert_eq!(vec![1, 2, 1], *out.borrow());
+        assert_eq!(Duration::from_secs(1), policy.delay(10));
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn backoff_policy_odd_multipliers() {
+    let policy = BackoffPolicy::exponential(Duration::from_millis(100), Duration::from_secs(1));
+    let negative = BackoffPolicy { multiplier: -2.0, ..policy };
+    assert_eq!(Duration::from_secs(0), negative.delay(1));
+    assert_eq!(Duration::from_millis(400), negative.delay(2));
+    let nan = BackoffPolicy { multiplier: f64::NAN, ..policy };
+    assert_eq!(Duration::from_secs(1), nan.delay(1));
+    let infinite = BackoffPolicy { multiplier: f64::INFINITY, ..policy };
+    assert_eq!(Duration::from_secs(1), infinite.delay(1));
+    let zero_times_infinite = BackoffPolicy { initial_delay: Duration::from_secs(0), multiplier: f64::INFINITY, ..policy };
+    assert_eq!(Duration::from_secs(1), zero_times_infinite.delay(1));
+    assert_eq!(Duration::from_secs(1), policy.delay(u32::MAX));
+}
+
+#[test]
+fn correlate_matches_and_times_out() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let requests: StreamSink<(u32,String)> = sodium_ctx.new_stream_sink();
+        let responses: StreamSink<(u32,i32)> = sodium_ctx.new_stream_sink();
+        let (matched, timed_out) = correlate(&requests, &responses, Duration::from_millis(100), &timer);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let out2 = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        let l2;
+        {
+            let out = out.clone();
+            let out2 = out2.clone();
+            l = matched.listen(move |&(ref req, resp): &(String,i32)| out.borrow_mut().push((req.clone(), resp)));
+            l2 = timed_out.listen(move |id: &u32| out2.borrow_mut().push(*id));
+        }
+        requests.send(&(1, String::from("a")));
+        requests.send(&(2, String::from("b")));
+        requests.send(&(3, String::from("c")));
+        assert_eq!(Some(Duration::from_millis(100)), timer.next_alarm());
+        clock.advance(Duration::from_millis(50));
+        timer.poll();
+        responses.send(&(2, 20));
+        responses.send(&(4, 40));
+        requests.send(&(4, String::from("d")));
+        clock.advance(Duration::from_millis(50));
+        timer.poll();
+        assert_eq!(Some(Duration::from_millis(150)), timer.next_alarm());
+        responses.send(&(1, 10));
+        responses.send(&(4, 40));
+        clock.advance(Duration::from_millis(100));
+        timer.poll();
+        assert_eq!(None, timer.next_alarm());
+        l.unlisten();
+        l2.unlisten();
+        assert_eq!(vec![(String::from("b"), 20), (String::from("d"), 40)], *out.borrow());
+        assert_eq!(vec![1, 3], *out2.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn distinct_within() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let s: StreamSink<(&'static str,i32)> = sodium_ctx.new_stream_sink();
+        let distinct = s.to_stream().distinct_within(Duration::from_millis(100), |&(key, _): &(&'static str,i32)| key, &timer);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = distinct.listen(move |&(_, n): &(&'static str,i32)| out.borrow_mut().push(n));
+        }
+        s.send(&("a", 1));
+        s.send(&("b", 2));
+        s.send(&("a", 3));
+        clock.advance(Duration::from_millis(60));
+        timer.poll();
+        s.send(&("a", 4));
+        assert_eq!(Some(Duration::from_millis(100)), timer.next_alarm());
+        clock.advance(Duration::from_millis(40));
+        timer.poll();
+        assert_eq!(None, timer.next_alarm());
+        s.send(&("a", 5));
+        s.send(&("b", 6));
+        l.unlisten();
+        assert_eq!(vec![1, 2, 5, 6], *out.borrow());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn interpolate_to() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let clock = ManualClock::new();
+        let timer = TimerSystem::new(sodium_ctx, clock.clone());
+        let from = sodium_ctx.new_cell(0.0);
+        let to = sodium_ctx.new_cell_sink(10.0);
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let faded = from.interpolate_to(&to, Duration::from_millis(100), Easing::Linear, &timer);
+        let l;
+        {
+            let out = out.clone();
+            l = faded.listen(move |a: &f64| out.borrow_mut().push(*a));
+        }
+        assert_eq!(Some(Duration::from_millis(100)), timer.next_alarm());
+        clock.advance(Duration::from_millis(25));
+        timer.poll();
+        to.send(&20.0);
+        clock.advance(Duration::from_millis(100));
+        timer.poll();
+        to.send(&30.0);
+        l.unlisten();
+        assert_eq!(vec![0.0, 2.5, 5.0, 20.0, 20.0, 30.0], *out.borrow());
+        assert_eq!(None, timer.next_alarm());
+        assert_eq!(0.25, Easing::EaseIn.apply(0.5));
+        assert_eq!(0.75, Easing::EaseOut.apply(0.5));
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,29 @@
+use sodium::CellSink;
+use sodium::IsCell;
+use sodium::SodiumCtx;
+use sodium::track::DirtyFlag;
+use tests::assert_memory_freed;
+
+#[test]
+fn dirty_flag() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let width: CellSink<u32> = sodium_ctx.new_cell_sink(640);
+        let title: CellSink<String> = sodium_ctx.new_cell_sink(String::from("untitled"));
+        let area = width.map(|w: &u32| *w * 480);
+        let dirty = DirtyFlag::new(&[&area, &title]);
+        assert!(!dirty.take());
+        width.send(&800);
+        assert!(dirty.is_dirty());
+        assert!(dirty.take());
+        assert!(!dirty.take());
+        sodium_ctx.transaction(|_| {
+            width.send(&1024);
+            title.send(&String::from("drawing"));
+        });
+        assert!(dirty.take());
+        assert!(!dirty.take());
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,45 @@
+use sodium::IsStream;
+use sodium::SodiumCtx;
+use sodium::StreamSink;
+use sodium::undo::UndoManager;
+use tests::assert_memory_freed;
+
+#[test]
+fn undo_and_redo() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let moves: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let renames: StreamSink<(String,String)> = sodium_ctx.new_stream_sink();
+        let position = moves.accum(0, |d: &i32, x: &i32| *x + *d);
+        let name = renames.map(|&(_, ref to): &(String,String)| to.clone()).hold(String::from("a"));
+        let mut undo_manager = UndoManager::new(sodium_ctx);
+        undo_manager.register(&moves, &moves, |d: &i32| -*d);
+        undo_manager.register(&renames, &renames, |&(ref from, ref to): &(String,String)| (to.clone(), from.clone()));
+        assert!(!undo_manager.can_undo().sample());
+        moves.send(&5);
+        sodium_ctx.transaction(|_| {
+            moves.send(&3);
+            renames.send(&(String::from("a"), String::from("b")));
+        });
+        assert_eq!((8, String::from("b")), (position.sample(), name.sample()));
+        assert!(undo_manager.can_undo().sample());
+        undo_manager.undo().send(&());
+        assert_eq!((5, String::from("a")), (position.sample(), name.sample()));
+        assert!(undo_manager.can_redo().sample());
+        undo_manager.undo().send(&());
+        undo_manager.undo().send(&());
+        assert_eq!(0, position.sample());
+        assert!(!undo_manager.can_undo().sample());
+        undo_manager.redo().send(&());
+        assert_eq!(5, position.sample());
+        moves.send(&10);
+        assert_eq!(15, position.sample());
+        assert!(!undo_manager.can_redo().sample());
+        undo_manager.redo().send(&());
+        assert_eq!(15, position.sample());
+        undo_manager.undo().send(&());
+        assert_eq!((5, String::from("a")), (position.sample(), name.sample()));
+    }
+    assert_memory_freed(sodium_ctx);
+}